@@ -0,0 +1,63 @@
+//! DMs the members with match alerts on when someone adds a result that matches them well.
+//! Members who can't be DMed have their alerts turned off, and are told the next time they run a
+//! command.
+
+use std::sync::Arc;
+
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::{
+    logic::{self, Invoker},
+    GlobalState,
+};
+
+/// Checks the newest result of `who` (or their headmate's) against everyone with alerts on, and
+/// DMs the ones it matches well.
+pub async fn notify(
+    ctx: serenity::Context,
+    state: Arc<GlobalState>,
+    who: Invoker,
+    headmate: Option<String>,
+    subject: String,
+) {
+    let alerts = {
+        let data = state.data.read().await;
+        logic::match_alerts(&data, &state.api, &state.cache, who, &headmate, &subject).await
+    };
+    let alerts = match alerts {
+        Ok(alerts) => alerts,
+        Err(e) => {
+            warn!("Could not check match alerts: {e}");
+            return;
+        }
+    };
+
+    let guild = who
+        .guild_id
+        .name(&ctx.cache)
+        .unwrap_or_else(|| "a server".to_string());
+    let mut failed = Vec::new();
+    for alert in alerts {
+        let message =
+            serenity::CreateMessage::new().content(format!("In {guild}: {}", alert.message));
+        if let Err(e) = alert.user_id.direct_message(&ctx, message).await {
+            info!(user = %alert.user_id, "Could not DM match alert, turning it off: {e:#}");
+            failed.push(alert.user_id);
+        }
+    }
+    if failed.is_empty() {
+        return;
+    }
+    let mut data = state.data.write().await;
+    for user_id in failed {
+        logic::fail_match_alert(
+            &mut data,
+            Invoker {
+                guild_id: who.guild_id,
+                user_id,
+            },
+        );
+    }
+    state.saves.request();
+}
@@ -0,0 +1,602 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::config::ApiConfig;
+
+#[derive(Debug, Deserialize)]
+struct MatchResult {
+    score: u32,
+    #[allow(unused)]
+    partner: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(unused)]
+pub struct GetResultScore {
+    pub id: u32,
+    pub name: String,
+    pub pairdesc: String,
+    pub description: String,
+    pub score: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(unused)]
+pub struct GetResultResult {
+    pub langfile: String,
+    pub date: String,
+    pub version: u32,
+    pub gender: String,
+    pub auth: bool,
+    pub scores: Vec<GetResultScore>,
+}
+
+impl GetResultResult {
+    /// When the test was taken, read from `date`. bdsmtest.org sends a plain `YYYY-MM-DD`,
+    /// sometimes with a time after it, and `None` means it sent something else.
+    pub fn taken(&self) -> Option<DateTime<Utc>> {
+        let date = self.date.trim();
+        if let Ok(at) = DateTime::parse_from_rfc3339(date) {
+            return Some(at.to_utc());
+        }
+        if let Ok(at) = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S") {
+            return Some(at.and_utc());
+        }
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+        Some(day.and_hms_opt(0, 0, 0)?.and_utc())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MatchRequest {
+    #[serde(rename = "rauth[rid]")]
+    pub person: String,
+    pub partner: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct GetResultRequest<'a> {
+    #[serde(rename = "rauth[rid]")]
+    person: String,
+    #[serde(rename = "uauth[uid]")]
+    uid: &'static str,
+    #[serde(rename = "uauth[salt]")]
+    salt: &'static str,
+    #[serde(rename = "uauth[authsig]")]
+    authsig: &'a str,
+}
+
+/// A response as bdsmtest.org sent it, before anything was checked or parsed.
+#[derive(Clone, Debug)]
+pub struct RawResponse {
+    pub status: reqwest::StatusCode,
+    /// From sending the request (after any throttling) until the whole body arrived.
+    pub elapsed: Duration,
+    pub body: String,
+}
+
+impl RawResponse {
+    /// Parses the body the way [`BdsmApi::get_result`] does.
+    pub fn parse_result(&self) -> Result<GetResultResult, serde_json::Error> {
+        serde_json::from_str(&self.body)
+    }
+}
+
+/// The bdsmtest.org operations the bot depends on.
+#[async_trait]
+pub trait BdsmApi: Send + Sync {
+    async fn get_result(&self, id: &str) -> Result<GetResultResult, anyhow::Error>;
+
+    async fn get_match(&self, request: &MatchRequest) -> Result<u32, anyhow::Error>;
+}
+
+/// Whether `e` means bdsmtest.org doesn't know the result, as opposed to being unreachable. It
+/// answers unknown IDs with either a 404 or a body that isn't a result.
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.status() == Some(reqwest::StatusCode::NOT_FOUND) || e.is_decode())
+}
+
+/// Whether `e` might go away if the request is sent again: bdsmtest.org couldn't be reached, took
+/// too long, had a server error or asked for fewer requests.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect()
+        || e.is_timeout()
+        || e.status()
+            .is_some_and(|s| s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// `e` with the URL that was called. Being rate limited is spelled out, since it's the reason
+/// scores go missing when the bot is busy.
+fn describe(e: reqwest::Error, url: &str) -> anyhow::Error {
+    let limited = e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let e = anyhow::Error::from(e).context(format!("while calling {url}"));
+    if limited {
+        e.context("bdsmtest.org is rate limiting the bot, try again in a few minutes")
+    } else {
+        e
+    }
+}
+
+/// How many times a request is sent before its error is given up on.
+const ATTEMPTS: u32 = 3;
+
+/// Spaces requests out by at least `interval`. Callers queue up behind each other in order.
+struct Throttle {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(interval: Duration) -> Self {
+        Throttle {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now() + self.interval;
+    }
+}
+
+/// Client for the bdsmtest.org ajax endpoints. Every request goes through a shared throttle and
+/// a limit on how many are in flight at once, and ones that fail for a passing reason are retried.
+pub struct BdsmClient {
+    client: reqwest::Client,
+    result_url: String,
+    match_url: String,
+    authsig: String,
+    throttle: Throttle,
+    in_flight: Semaphore,
+    retry_backoff: Duration,
+}
+
+impl BdsmClient {
+    pub fn new(client: reqwest::Client, config: &ApiConfig) -> Self {
+        let base_url = config.base_url.trim_end_matches('/');
+        BdsmClient {
+            client,
+            result_url: format!("{base_url}/ajax/getresult"),
+            match_url: format!("{base_url}/ajax/match"),
+            authsig: config.authsig.clone(),
+            throttle: Throttle::new(Duration::from_millis(config.request_interval_ms)),
+            in_flight: Semaphore::new(config.max_in_flight),
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+        }
+    }
+
+    /// The one HTTP client every request shares, with the timeouts from `config`.
+    pub fn http_client(config: &ApiConfig) -> reqwest::Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+    }
+
+    /// Sends the request `build` makes and parses the answer, retrying after a pause that doubles
+    /// each time while [`is_transient`] holds, up to [`ATTEMPTS`] times in all.
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> reqwest::Result<T> {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            let outcome = {
+                let _permit = self.permit().await;
+                self.throttle.wait().await;
+                match build().send().await.and_then(|r| r.error_for_status()) {
+                    Ok(response) => response.json().await,
+                    Err(e) => Err(e),
+                }
+            };
+            match outcome {
+                Err(e) if attempt < ATTEMPTS && is_transient(&e) => {
+                    warn!(attempt, "Retrying a bdsmtest.org request: {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Waits for a request to be allowed in flight.
+    async fn permit(&self) -> tokio::sync::SemaphorePermit<'_> {
+        // The semaphore is never closed.
+        self.in_flight
+            .acquire()
+            .await
+            .expect("request semaphore closed")
+    }
+}
+
+impl BdsmClient {
+    fn result_request(&self, id: &str) -> reqwest::RequestBuilder {
+        self.client.post(&self.result_url).form(&GetResultRequest {
+            person: id.to_string(),
+            uid: "0",
+            salt: "",
+            authsig: &self.authsig,
+        })
+    }
+
+    /// Looks up a result like [`BdsmApi::get_result`], but hands back whatever came back, even
+    /// error statuses. Only transport failures are errors.
+    pub async fn get_result_raw(&self, id: &str) -> Result<RawResponse, anyhow::Error> {
+        let _permit = self.permit().await;
+        self.throttle.wait().await;
+        let start = Instant::now();
+        let response = self.result_request(id).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        Ok(RawResponse {
+            status,
+            elapsed: start.elapsed(),
+            body,
+        })
+    }
+}
+
+#[async_trait]
+impl BdsmApi for BdsmClient {
+    async fn get_result(&self, id: &str) -> Result<GetResultResult, anyhow::Error> {
+        self.fetch(|| self.result_request(id))
+            .await
+            .map_err(|e| describe(e, &self.result_url))
+    }
+
+    async fn get_match(&self, request: &MatchRequest) -> Result<u32, anyhow::Error> {
+        let result: MatchResult = self
+            .fetch(|| self.client.post(&self.match_url).form(request))
+            .await
+            .map_err(|e| describe(e, &self.match_url))?;
+        Ok(result.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_string, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    const RESULT_FORM: &str = "rauth%5Brid%5D=abc123&uauth%5Buid%5D=0&uauth%5Bsalt%5D=\
+        &uauth%5Bauthsig%5D=814a69afc15258000678f00526b0c107ac271b5ea997beb4f7c1e81c861c972b";
+    const MATCH_FORM: &str = "rauth%5Brid%5D=abc123&partner=def456";
+
+    fn result_body() -> serde_json::Value {
+        json!({
+            "langfile": "en",
+            "date": "2024-05-01",
+            "version": 3,
+            "gender": "",
+            "auth": false,
+            "scores": [
+                {"id": 1, "name": "Rigger", "pairdesc": "", "description": "", "score": 95},
+                {"id": 2, "name": "Switch", "pairdesc": "", "description": "", "score": 71},
+            ],
+        })
+    }
+
+    fn match_request() -> MatchRequest {
+        MatchRequest {
+            person: "abc123".into(),
+            partner: "def456".into(),
+        }
+    }
+
+    async fn setup() -> (MockServer, BdsmClient) {
+        let server = MockServer::start().await;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        let config = ApiConfig {
+            base_url: server.uri(),
+            retry_backoff_ms: 1,
+            ..Default::default()
+        };
+        let api = BdsmClient::new(client, &config);
+        (server, api)
+    }
+
+    async fn mount(server: &MockServer, endpoint: &str, response: ResponseTemplate) {
+        mount_times(server, endpoint, response, 1).await;
+    }
+
+    async fn mount_times(
+        server: &MockServer,
+        endpoint: &str,
+        response: ResponseTemplate,
+        times: u64,
+    ) {
+        Mock::given(method("POST"))
+            .and(path(endpoint))
+            .respond_with(response)
+            .expect(times)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn get_result_sends_exact_form() {
+        let (server, api) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/ajax/getresult"))
+            .and(header("content-type", "application/x-www-form-urlencoded"))
+            .and(body_string(RESULT_FORM))
+            .respond_with(ResponseTemplate::new(200).set_body_json(result_body()))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = api.get_result("abc123").await.unwrap();
+        assert_eq!(result.date, "2024-05-01");
+        assert_eq!(result.scores.len(), 2);
+        assert_eq!(result.scores[0].name, "Rigger");
+        assert_eq!(result.scores[0].score, 95);
+    }
+
+    #[test]
+    fn taken_reads_the_formats_bdsmtest_sends() {
+        let taken = |date: &str| {
+            GetResultResult {
+                date: date.into(),
+                ..serde_json::from_value(result_body()).unwrap()
+            }
+            .taken()
+        };
+        let day: DateTime<Utc> = "2024-05-01T00:00:00Z".parse().unwrap();
+        assert_eq!(taken("2024-05-01"), Some(day));
+        assert_eq!(
+            taken("2024-05-01 13:30:00"),
+            Some(day + chrono::Duration::minutes(13 * 60 + 30))
+        );
+        assert_eq!(taken("2024-05-01T02:00:00+02:00"), Some(day));
+        assert_eq!(taken("May 1st"), None);
+        assert_eq!(taken(""), None);
+    }
+
+    #[tokio::test]
+    async fn get_result_not_found() {
+        let (server, api) = setup().await;
+        mount(&server, "/ajax/getresult", ResponseTemplate::new(404)).await;
+
+        assert!(is_not_found(&api.get_result("abc123").await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn get_result_server_error() {
+        let (server, api) = setup().await;
+        mount_times(&server, "/ajax/getresult", ResponseTemplate::new(500), 3).await;
+
+        let err = api.get_result("abc123").await.unwrap_err();
+        assert!(!is_not_found(&err));
+        assert!(format!("{err:#}")
+            .starts_with(&format!("while calling {}/ajax/getresult", server.uri())));
+    }
+
+    #[tokio::test]
+    async fn get_result_retries_a_passing_server_error() {
+        let (server, api) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/ajax/getresult"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        mount(
+            &server,
+            "/ajax/getresult",
+            ResponseTemplate::new(200).set_body_json(result_body()),
+        )
+        .await;
+
+        assert_eq!(api.get_result("abc123").await.unwrap().scores.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_match_says_when_rate_limited() {
+        let (server, api) = setup().await;
+        mount_times(&server, "/ajax/match", ResponseTemplate::new(429), 3).await;
+
+        let request = MatchRequest {
+            person: "abc123".into(),
+            partner: "def456".into(),
+        };
+        let err = api.get_match(&request).await.unwrap_err();
+        assert!(!is_not_found(&err));
+        assert_eq!(
+            err.to_string(),
+            "bdsmtest.org is rate limiting the bot, try again in a few minutes"
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_are_limited() {
+        let server = MockServer::start().await;
+        let config = ApiConfig {
+            base_url: server.uri(),
+            request_interval_ms: 0,
+            max_in_flight: 2,
+            ..Default::default()
+        };
+        let api = BdsmClient::new(reqwest::Client::new(), &config);
+        let delay = Duration::from_millis(100);
+        mount_times(
+            &server,
+            "/ajax/getresult",
+            ResponseTemplate::new(200)
+                .set_body_json(result_body())
+                .set_delay(delay),
+            4,
+        )
+        .await;
+
+        let start = Instant::now();
+        let lookups = (0..4).map(|_| api.get_result("abc123"));
+        for result in poise::serenity_prelude::futures::future::join_all(lookups).await {
+            result.unwrap();
+        }
+        // Two at a time, so the four take two rounds.
+        assert!(start.elapsed() >= delay * 2);
+    }
+
+    #[tokio::test]
+    async fn get_result_malformed_json() {
+        let (server, api) = setup().await;
+        mount(
+            &server,
+            "/ajax/getresult",
+            ResponseTemplate::new(200).set_body_string("<html>not json</html>"),
+        )
+        .await;
+
+        assert!(is_not_found(&api.get_result("abc123").await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn get_result_timeout() {
+        let (server, api) = setup().await;
+        mount_times(
+            &server,
+            "/ajax/getresult",
+            ResponseTemplate::new(200)
+                .set_body_json(result_body())
+                .set_delay(Duration::from_secs(2)),
+            3,
+        )
+        .await;
+
+        let err = api.get_result("abc123").await.unwrap_err();
+        assert!(err
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout));
+        assert!(!is_not_found(&err));
+    }
+
+    #[tokio::test]
+    async fn get_result_raw_keeps_errors_and_bad_bodies() {
+        let (server, api) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/ajax/getresult"))
+            .and(body_string(RESULT_FORM))
+            .respond_with(ResponseTemplate::new(500).set_body_string("<html>oops</html>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let raw = api.get_result_raw("abc123").await.unwrap();
+        assert_eq!(raw.status, reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(raw.body, "<html>oops</html>");
+        assert!(raw.parse_result().is_err());
+    }
+
+    #[tokio::test]
+    async fn get_result_raw_parses_like_get_result() {
+        let (server, api) = setup().await;
+        mount(
+            &server,
+            "/ajax/getresult",
+            ResponseTemplate::new(200).set_body_json(result_body()),
+        )
+        .await;
+
+        let raw = api.get_result_raw("abc123").await.unwrap();
+        assert_eq!(raw.status, reqwest::StatusCode::OK);
+        assert_eq!(raw.parse_result().unwrap().scores.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_match_sends_exact_form() {
+        let (server, api) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/ajax/match"))
+            .and(header("content-type", "application/x-www-form-urlencoded"))
+            .and(body_string(MATCH_FORM))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"score": 87, "partner": "def456"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(api.get_match(&match_request()).await.unwrap(), 87);
+    }
+
+    #[tokio::test]
+    async fn get_match_not_found() {
+        let (server, api) = setup().await;
+        mount(&server, "/ajax/match", ResponseTemplate::new(404)).await;
+
+        assert!(api.get_match(&match_request()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn get_match_server_error() {
+        let (server, api) = setup().await;
+        mount_times(&server, "/ajax/match", ResponseTemplate::new(500), 3).await;
+
+        assert!(api.get_match(&match_request()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn get_match_malformed_json() {
+        let (server, api) = setup().await;
+        mount(
+            &server,
+            "/ajax/match",
+            ResponseTemplate::new(200).set_body_json(json!({"partner": "def456"})),
+        )
+        .await;
+
+        assert!(api.get_match(&match_request()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn get_match_timeout() {
+        let (server, api) = setup().await;
+        mount_times(
+            &server,
+            "/ajax/match",
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"score": 87, "partner": "def456"}))
+                .set_delay(Duration::from_secs(2)),
+            3,
+        )
+        .await;
+
+        let err = api.get_match(&match_request()).await.unwrap_err();
+        assert!(err
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout));
+    }
+
+    #[tokio::test]
+    async fn throttle_spaces_out_requests() {
+        let throttle = Throttle::new(Duration::from_millis(50));
+        let start = Instant::now();
+        for _ in 0..3 {
+            throttle.wait().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
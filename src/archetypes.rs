@@ -0,0 +1,272 @@
+//! The archetypes bdsmtest.org reports scores for.
+//!
+//! The bundled list is extended with any other archetype a fetched result turns out to have, so
+//! new ones can be picked before the list here is updated.
+
+use std::{collections::BTreeMap, sync::RwLock};
+
+use crate::logic::MAX_CHOICES;
+
+/// Input shorter than this is never matched loosely.
+const MIN_LOOSE_LEN: usize = 3;
+/// How many typos [`resolve_loosely`] forgives.
+const MAX_TYPOS: usize = 2;
+
+/// Archetypes seen in fetched results that aren't in [`ARCHETYPES`], in the order they were seen.
+/// They are leaked, which is bounded by how rarely bdsmtest.org adds archetypes.
+static LEARNED: RwLock<Vec<&'static str>> = RwLock::new(Vec::new());
+
+pub const ARCHETYPES: &[&str] = &[
+    "Ageplayer",
+    "Boy/Girl",
+    "Brat",
+    "Brat tamer",
+    "Daddy/Mommy",
+    "Degradee",
+    "Degrader",
+    "Dominant",
+    "Exhibitionist",
+    "Experimentalist",
+    "Little",
+    "Masochist",
+    "Master/Mistress",
+    "Non-monogamist",
+    "Owner",
+    "Pet",
+    "Primal (Hunter)",
+    "Primal (Prey)",
+    "Rigger",
+    "Rope bunny",
+    "Sadist",
+    "Slave",
+    "Submissive",
+    "Switch",
+    "Vanilla",
+    "Voyeur",
+];
+
+/// Archetypes that complement each other. Anything not listed here complements itself.
+pub const COMPLEMENTS: &[(&str, &str)] = &[
+    ("Brat tamer", "Brat"),
+    ("Daddy/Mommy", "Little"),
+    ("Degrader", "Degradee"),
+    ("Dominant", "Submissive"),
+    ("Exhibitionist", "Voyeur"),
+    ("Master/Mistress", "Slave"),
+    ("Owner", "Pet"),
+    ("Primal (Hunter)", "Primal (Prey)"),
+    ("Rigger", "Rope bunny"),
+    ("Sadist", "Masochist"),
+];
+
+/// The archetype a partner would ideally score highly on to match a high score on `name`.
+pub fn complement(name: &str) -> &str {
+    COMPLEMENTS
+        .iter()
+        .find_map(|&(a, b)| {
+            if a.eq_ignore_ascii_case(name) {
+                Some(b)
+            } else if b.eq_ignore_ascii_case(name) {
+                Some(a)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(name)
+}
+
+/// Adds `name` to the known archetypes. Returns false if it was already known.
+pub fn learn(name: &str) -> bool {
+    let name = name.trim();
+    if name.is_empty() || resolve(name).is_some() {
+        return false;
+    }
+    let mut learned = LEARNED.write().unwrap_or_else(|e| e.into_inner());
+    // Checked again now that nothing else can add it.
+    if learned.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+        return false;
+    }
+    learned.push(Box::leak(name.to_string().into_boxed_str()));
+    true
+}
+
+/// The archetypes learned from fetched results so far.
+pub fn learned() -> Vec<&'static str> {
+    LEARNED.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Every known archetype, bundled ones first.
+fn all() -> Vec<&'static str> {
+    ARCHETYPES.iter().copied().chain(learned()).collect()
+}
+
+/// The canonical spelling of `name`, ignoring case and surrounding whitespace.
+pub fn resolve(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    all().into_iter().find(|a| a.eq_ignore_ascii_case(name))
+}
+
+/// Lowercase letters and digits only, so punctuation and spacing don't matter.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The number of single character edits between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Like [`resolve`], but also accepts typed input that is only close: spelled without its
+/// punctuation, a part of a single archetype's name, or a couple of typos away from a single
+/// archetype. Ambiguous input resolves to nothing.
+pub fn resolve_loosely(name: &str) -> Option<&'static str> {
+    if let Some(archetype) = resolve(name) {
+        return Some(archetype);
+    }
+    let wanted = normalize(name);
+    if wanted.len() < MIN_LOOSE_LEN {
+        return None;
+    }
+    let all = all();
+    let unique = |matches: Vec<&'static str>| match matches[..] {
+        [only] => Some(only),
+        _ => None,
+    };
+    if let Some(&archetype) = all.iter().find(|a| normalize(a) == wanted) {
+        return Some(archetype);
+    }
+    let containing: Vec<_> = all
+        .iter()
+        .copied()
+        .filter(|a| normalize(a).contains(&wanted))
+        .collect();
+    if !containing.is_empty() {
+        return unique(containing);
+    }
+    let distances: Vec<_> = all
+        .iter()
+        .map(|a| (edit_distance(&normalize(a), &wanted), *a))
+        .collect();
+    let closest = distances.iter().map(|&(d, _)| d).min()?;
+    if closest > MAX_TYPOS {
+        return None;
+    }
+    unique(
+        distances
+            .into_iter()
+            .filter(|&(d, _)| d == closest)
+            .map(|(_, a)| a)
+            .collect(),
+    )
+}
+
+/// What `aliases`, keyed by canonical name, calls the archetype bdsmtest.org calls `name`.
+/// Archetypes without an alias keep their name.
+pub fn display<'a>(aliases: &'a BTreeMap<String, String>, name: &'a str) -> &'a str {
+    resolve(name)
+        .and_then(|canonical| aliases.get(canonical))
+        .map_or(name, String::as_str)
+}
+
+/// Autocomplete choices for `partial`: every archetype containing it, ignoring case.
+pub fn matching(partial: &str) -> Vec<&'static str> {
+    let partial = partial.trim().to_lowercase();
+    all()
+        .into_iter()
+        .filter(|a| a.to_lowercase().contains(&partial))
+        .take(MAX_CHOICES)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_uses_aliases_by_canonical_name() {
+        let aliases = BTreeMap::from([("Rope bunny".to_string(), "Knot enjoyer".to_string())]);
+        assert_eq!(display(&aliases, "rope bunny"), "Knot enjoyer");
+        assert_eq!(display(&aliases, "Rigger"), "Rigger");
+        assert_eq!(display(&aliases, "Unheard of"), "Unheard of");
+    }
+
+    #[test]
+    fn resolve_ignores_case() {
+        assert_eq!(resolve(" rope BUNNY "), Some("Rope bunny"));
+        assert_eq!(resolve("Rope"), None);
+        assert_eq!(matching("rope"), ["Rope bunny"]);
+        assert_eq!(matching("").len(), MAX_CHOICES);
+    }
+
+    #[test]
+    fn resolve_loosely_accepts_close_input() {
+        assert_eq!(resolve_loosely("rope BUNNY"), Some("Rope bunny"));
+        assert_eq!(resolve_loosely("ropebunny"), Some("Rope bunny"));
+        assert_eq!(resolve_loosely("Rope"), Some("Rope bunny"));
+        assert_eq!(resolve_loosely("boy girl"), Some("Boy/Girl"));
+        assert_eq!(resolve_loosely("Riggr"), Some("Rigger"));
+        assert_eq!(resolve_loosely("exibitionst"), Some("Exhibitionist"));
+        // Both primals, and both Degrader and Degradee.
+        assert_eq!(resolve_loosely("primal"), None);
+        assert_eq!(resolve_loosely("Degrade"), None);
+        assert_eq!(resolve_loosely("Degradr"), Some("Degrader"));
+        assert_eq!(resolve_loosely("ro"), None);
+        assert_eq!(resolve_loosely("Woof"), None);
+    }
+
+    #[test]
+    fn learned_archetypes_are_known() {
+        assert!(!learn("switch"));
+        assert!(!learn(" "));
+        assert!(learn("Tickler"));
+        assert!(!learn("tickler"));
+        assert!(learned().contains(&"Tickler"));
+        assert_eq!(resolve("TICKLER"), Some("Tickler"));
+        assert_eq!(resolve_loosely("tickle"), Some("Tickler"));
+        assert_eq!(matching("tick"), ["Tickler"]);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("rigger", "rigger"), 0);
+        assert_eq!(edit_distance("rigger", "riggr"), 1);
+        assert_eq!(edit_distance("slave", "sklave"), 1);
+        assert_eq!(edit_distance("", "pet"), 3);
+    }
+
+    #[test]
+    fn complements_are_symmetric() {
+        for &(a, b) in COMPLEMENTS {
+            assert_eq!(resolve(a), Some(a));
+            assert_eq!(resolve(b), Some(b));
+            assert_eq!(complement(a), b);
+            assert_eq!(complement(b), a);
+        }
+        assert_eq!(complement("Switch"), "Switch");
+    }
+
+    #[test]
+    fn archetypes_have_at_most_one_complement() {
+        for archetype in ARCHETYPES {
+            let pairs = COMPLEMENTS
+                .iter()
+                .filter(|&&(a, b)| a == *archetype || b == *archetype)
+                .count();
+            assert!(pairs <= 1, "{archetype} is in {pairs} pairs");
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use poise::serenity_prelude as serenity;
+use tracing::{error, warn};
+
+use crate::{
+    data::{self, GlobalData, HeadmateData},
+    format::format_timestamp,
+};
+
+const TIERS: [&str; 4] = ["history", "hourly", "daily", "monthly"];
+
+/// A copy of the registry saved by [`data::persist`].
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub taken: DateTime<Utc>,
+}
+
+/// Every snapshot in the backup tiers under `root`, newest first.
+pub fn snapshots<P: AsRef<Path>>(root: P) -> std::io::Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    for tier in TIERS {
+        let dir = root.as_ref().join(tier);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            snapshots.push(Snapshot {
+                path: entry.path(),
+                taken: entry.metadata()?.modified()?.into(),
+            });
+        }
+    }
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.taken));
+    Ok(snapshots)
+}
+
+/// Reads the registry under `root`. If it can't be read or parsed, say because the process died
+/// while writing it, the newest snapshot in the history tier that can be is used instead. A
+/// registry that doesn't exist yet, with no snapshots either, is a fresh deployment and starts
+/// out empty. Otherwise the registry's own error is returned when no snapshot can be read.
+pub fn load_registry(root: &Path) -> Result<GlobalData, anyhow::Error> {
+    let registry = root.join(data::REGISTRY);
+    let broken = match data::load(&registry) {
+        Ok(data) => return Ok(data),
+        Err(e) => e,
+    };
+    let fresh = !registry.exists();
+    let history = root.join(data::BACKUP_DIR).join(TIERS[0]);
+    let mut snapshots: Vec<_> = std::fs::read_dir(&history)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect();
+    // Named after when they were taken, so the newest sorts last.
+    snapshots.sort();
+    for path in snapshots.iter().rev() {
+        match data::load(path) {
+            Ok(data) => {
+                error!(
+                    backup = %path.display(),
+                    "Could not load the registry, loaded the newest backup instead: {broken:#}"
+                );
+                return Ok(data);
+            }
+            Err(e) => warn!("Skipping unreadable backup: {e:#}"),
+        }
+    }
+    if fresh && snapshots.is_empty() {
+        return Ok(GlobalData::default());
+    }
+    Err(broken)
+}
+
+/// The (primary or headmate) entry of a single user to look for in the backups.
+#[derive(Clone, Debug)]
+pub struct RestoreTarget {
+    pub guild_id: serenity::GuildId,
+    pub user_id: serenity::UserId,
+    pub headmate: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub snapshot: Snapshot,
+    pub data: HeadmateData,
+}
+
+/// Searches `snapshots` in order and returns the first one where `target` has any results.
+/// Snapshots that can't be read are skipped.
+pub fn find(snapshots: &[Snapshot], target: &RestoreTarget) -> Option<Found> {
+    snapshots.iter().find_map(|snapshot| {
+        let backup = match data::load(&snapshot.path) {
+            Ok(backup) => backup,
+            Err(e) => {
+                warn!(
+                    "Skipping unreadable snapshot {}: {e:#}",
+                    snapshot.path.display()
+                );
+                return None;
+            }
+        };
+        let headmate = backup
+            .guild(target.guild_id)?
+            .users
+            .get(&target.user_id)?
+            .headmate(&target.headmate)?;
+        (!headmate.results.is_empty()).then(|| Found {
+            snapshot: snapshot.clone(),
+            data: headmate.clone(),
+        })
+    })
+}
+
+/// The results in `restored` that `live` doesn't already have, either under the same timestamp
+/// or under a different one.
+pub fn missing<'a>(
+    live: Option<&HeadmateData>,
+    restored: &'a HeadmateData,
+) -> Vec<(&'a DateTime<Utc>, &'a String)> {
+    restored
+        .results
+        .iter()
+        .filter(|(at, id)| {
+            live.is_none_or(|live| {
+                !live.results.contains_key(at) && !live.results.values().any(|v| v == *id)
+            })
+        })
+        .collect()
+}
+
+/// Adds the [`missing`] results to `live`, along with what was stored about each: whether it was
+/// hidden, its manual scores, when it expires and the copy fetched from bdsmtest.org. Everything
+/// already there is left untouched. Returns the number of results added.
+pub fn merge(live: &mut HeadmateData, restored: &HeadmateData) -> usize {
+    let missing: Vec<_> = missing(Some(live), restored)
+        .into_iter()
+        .map(|(at, id)| (*at, id.clone()))
+        .collect();
+    let count = missing.len();
+    for (_, id) in &missing {
+        if let Some(&visibility) = restored.result_visibility.get(id) {
+            live.result_visibility.insert(id.clone(), visibility);
+        }
+        if let Some(scores) = restored.manual.get(id) {
+            live.manual.insert(id.clone(), scores.clone());
+        }
+        if let Some(&expires) = restored.expires.get(id) {
+            live.expires.insert(id.clone(), expires);
+        }
+        if let Some(fetched) = restored.fetched.get(id) {
+            live.fetched.insert(id.clone(), fetched.clone());
+        }
+    }
+    live.results.extend(missing);
+    count
+}
+
+/// A summary of what restoring `found` on top of `live` would do, with dates in `tz`.
+pub fn describe(found: &Found, live: Option<&HeadmateData>, tz: Tz) -> String {
+    let mut description = format!(
+        "Found {} result(s) in {} (taken {}):\n",
+        found.data.results.len(),
+        found.snapshot.path.display(),
+        format_timestamp(&found.snapshot.taken, tz),
+    );
+    let missing = missing(live, &found.data);
+    for (at, id) in &missing {
+        description += &format!("- {} {id}\n", format_timestamp(at, tz));
+    }
+    description += &match missing.len() {
+        0 => "All of these are already present, nothing would be restored".to_string(),
+        n => format!("{n} result(s) would be restored"),
+    };
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        data::{GlobalData, Visibility},
+        testutil::at,
+    };
+
+    fn headmate(results: &[(u32, &str)]) -> HeadmateData {
+        HeadmateData {
+            results: results
+                .iter()
+                .map(|&(day, id)| (at(day), id.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn target(headmate: Option<&str>) -> RestoreTarget {
+        RestoreTarget {
+            guild_id: serenity::GuildId::new(1),
+            user_id: serenity::UserId::new(2),
+            headmate: headmate.map(String::from),
+        }
+    }
+
+    fn write_snapshot(dir: &Path, name: &str, primary: Option<HeadmateData>) -> Snapshot {
+        let mut data = GlobalData::default();
+        data.guild_mut(serenity::GuildId::new(1))
+            .users
+            .entry(serenity::UserId::new(2))
+            .or_default()
+            .primary = primary;
+        let path = dir.join(name);
+        std::fs::write(&path, serde_json::to_string(&data).unwrap()).unwrap();
+        Snapshot {
+            path,
+            taken: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn find_uses_first_snapshot_with_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let bad = dir.path().join("corrupt.json");
+        std::fs::write(&bad, "{").unwrap();
+        let snapshots = [
+            write_snapshot(dir.path(), "newest.json", None),
+            Snapshot {
+                path: bad,
+                taken: Utc::now(),
+            },
+            write_snapshot(
+                dir.path(),
+                "middle.json",
+                Some(headmate(&[(1, "a"), (2, "b")])),
+            ),
+            write_snapshot(dir.path(), "oldest.json", Some(headmate(&[(1, "a")]))),
+        ];
+
+        let found = find(&snapshots, &target(None)).unwrap();
+        assert_eq!(found.snapshot.path, snapshots[2].path);
+        assert_eq!(found.data.results.len(), 2);
+        assert!(find(&snapshots, &target(Some("Ash"))).is_none());
+    }
+
+    fn registry_with(id: &str) -> GlobalData {
+        let mut data = GlobalData::default();
+        data.guild_mut(serenity::GuildId::new(1))
+            .users
+            .entry(serenity::UserId::new(2))
+            .or_default()
+            .primary = Some(headmate(&[(1, id)]));
+        data
+    }
+
+    fn stored_id(data: &GlobalData) -> &str {
+        let user = &data.guilds[&serenity::GuildId::new(1)].users[&serenity::UserId::new(2)];
+        &user.primary.as_ref().unwrap().results[&at(1)]
+    }
+
+    #[test]
+    fn truncated_registry_falls_back_to_newest_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join(data::BACKUP_DIR).join("history");
+        std::fs::create_dir_all(&history).unwrap();
+        for (name, id) in [
+            ("registry-1714000000", "old"),
+            ("registry-1714500000", "new"),
+        ] {
+            let json = serde_json::to_string(&registry_with(id)).unwrap();
+            std::fs::write(history.join(format!("{name}.json")), json).unwrap();
+        }
+        std::fs::write(history.join("registry-1714900000.json"), "{\"guil").unwrap();
+
+        // Killed between opening the registry and writing it.
+        let registry = dir.path().join(data::REGISTRY);
+        std::fs::write(&registry, "").unwrap();
+        assert_eq!(stored_id(&load_registry(dir.path()).unwrap()), "new");
+
+        // Killed partway through writing it.
+        std::fs::write(&registry, "{\"guilds\": {\"1\": {").unwrap();
+        assert_eq!(stored_id(&load_registry(dir.path()).unwrap()), "new");
+
+        std::fs::remove_dir_all(&history).unwrap();
+        assert!(load_registry(dir.path()).is_err());
+    }
+
+    #[test]
+    fn empty_data_dir_starts_an_empty_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_registry(dir.path()).unwrap().guilds.is_empty());
+
+        // A registry that went missing with only broken backups left is not a fresh start.
+        let history = dir.path().join(data::BACKUP_DIR).join("history");
+        std::fs::create_dir_all(&history).unwrap();
+        std::fs::write(history.join("registry-1714000000.json"), "{").unwrap();
+        assert!(load_registry(dir.path()).is_err());
+    }
+
+    #[test]
+    fn persist_replaces_the_registry_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = data::Storage {
+            root: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        // Left behind by a write that never finished.
+        let temp = dir.path().join(format!("{}.tmp", data::REGISTRY));
+        std::fs::write(&temp, "{").unwrap();
+
+        data::persist_in(&storage, &registry_with("a")).unwrap();
+        data::persist_in(&storage, &registry_with("b")).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(stored_id(&load_registry(dir.path()).unwrap()), "b");
+    }
+
+    #[test]
+    fn merge_keeps_newer_results() {
+        let mut live = headmate(&[(3, "c"), (4, "b")]);
+        let restored = headmate(&[(1, "a"), (2, "b"), (3, "x")]);
+        assert_eq!(missing(Some(&live), &restored).len(), 1);
+        assert_eq!(merge(&mut live, &restored), 1);
+        assert_eq!(
+            live.results,
+            headmate(&[(1, "a"), (3, "c"), (4, "b")]).results
+        );
+    }
+
+    #[test]
+    fn merge_keeps_restored_results_hidden() {
+        let mut live = headmate(&[(3, "c")]);
+        let mut restored = headmate(&[(1, "a"), (2, "b"), (3, "c")]);
+        restored
+            .result_visibility
+            .insert("a".into(), Visibility::HiddenFromOthers);
+        assert_eq!(merge(&mut live, &restored), 2);
+        assert!(!live.is_result_visible("a"));
+        assert!(live.is_result_visible("b"));
+        assert_eq!(live.most_recent_visible(), Some(&"c".to_string()));
+    }
+
+    #[test]
+    fn merge_brings_back_what_was_stored_about_each_result() {
+        let mut live = headmate(&[(3, "c")]);
+        let mut restored = headmate(&[(1, "manual-1"), (2, "guest"), (3, "c")]);
+        let scores = BTreeMap::from([("Rigger".to_string(), 80)]);
+        restored.manual.insert("manual-1".into(), scores.clone());
+        restored.expires.insert("guest".into(), at(9));
+        restored.expires.insert("c".into(), at(9));
+        assert_eq!(merge(&mut live, &restored), 2);
+        assert_eq!(
+            live.manual,
+            BTreeMap::from([("manual-1".to_string(), scores)])
+        );
+        assert!(live.is_temporary("guest"));
+        // What the live data says about results it already had wins.
+        assert!(!live.is_temporary("c"));
+    }
+
+    #[test]
+    fn missing_without_live_data_is_everything() {
+        let restored = headmate(&[(1, "a"), (2, "b")]);
+        assert_eq!(missing(None, &restored).len(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+//! Timing benchmarks for persistence and list formatting. These are ignored by default, run them
+//! with `cargo test --release bench -- --ignored --nocapture --test-threads=1`.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    data::{persist_in, GlobalData, Storage},
+    format::{format_compat_list, CompatListOptions},
+    testutil::{synthetic_data, synthetic_entries, Shape},
+};
+
+const SIZES: [u64; 3] = [10, 100, 1000];
+const ITERATIONS: usize = 10;
+
+fn shape(users: u64) -> Shape {
+    Shape {
+        guilds: 1,
+        users,
+        headmates: 3,
+        results: 4,
+    }
+}
+
+/// Runs `f` [`ITERATIONS`] times and reports the median time to stdout.
+fn time<T>(label: &str, mut f: impl FnMut() -> T) {
+    let mut samples: Vec<Duration> = (0..ITERATIONS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(f());
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    println!("{label:<40} median {:>10.3?}", samples[ITERATIONS / 2]);
+}
+
+#[test]
+#[ignore]
+fn bench_persist() {
+    for users in SIZES {
+        let data = synthetic_data(shape(users));
+        let dir = tempfile::tempdir().unwrap();
+        time(&format!("persist/{users} users"), || {
+            let storage = Storage {
+                root: dir.path().to_path_buf(),
+                ..Default::default()
+            };
+            persist_in(&storage, &data).unwrap()
+        });
+        time(&format!("serialize/{users} users"), || {
+            serde_json::to_vec_pretty(&data).unwrap()
+        });
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_migrate() {
+    for users in SIZES {
+        let json = serde_json::to_string(&synthetic_data(shape(users))).unwrap();
+        time(&format!("parse+migrate/{users} users"), || {
+            let mut data: GlobalData = serde_json::from_str(&json).unwrap();
+            data.migrate();
+            data
+        });
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_format_compat_list() {
+    for users in SIZES {
+        let shape = shape(users);
+        let entries = synthetic_entries((shape.users * (1 + shape.headmates)) as usize);
+        time(
+            &format!("format_compat_list/{} entries", entries.len()),
+            || format_compat_list("Member 0", &entries, &CompatListOptions::default()),
+        );
+    }
+}
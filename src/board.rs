@@ -0,0 +1,200 @@
+//! The compatibility board: a pinned message per guild that the bot edits as the registry changes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use poise::serenity_prelude as serenity;
+use tracing::info;
+
+use crate::{
+    cache::Cache, commands::resolve_member_names, data::GuildData, digest::top_pairings,
+    format::MESSAGE_LIMIT, GlobalState,
+};
+
+const MAX_MEMBERS: usize = 50;
+const TOP_PAIRINGS: usize = 5;
+
+/// Builds the board for `guild`. Only listed entries are shown.
+pub fn compose(
+    guild: &GuildData,
+    cache: &Cache,
+    member_names: &BTreeMap<serenity::UserId, String>,
+    show_pairings: bool,
+) -> String {
+    let members: BTreeSet<_> = guild
+        .listed_entries()
+        .filter(|e| !e.data.results.is_empty())
+        .map(|e| e.user_id)
+        .collect();
+    let mut names: Vec<_> = members
+        .iter()
+        .map(|user_id| {
+            member_names
+                .get(user_id)
+                .cloned()
+                .unwrap_or_else(|| "**Deleted User**".to_string())
+        })
+        .collect();
+    names.sort();
+
+    let mut board = format!(
+        "**Compatibility board**\nRegistered members ({}):\n",
+        names.len()
+    );
+    if names.is_empty() {
+        board += "Nobody yet, use /add_bdsm_result to be the first\n";
+    } else {
+        board += &names
+            .iter()
+            .take(MAX_MEMBERS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if names.len() > MAX_MEMBERS {
+            board += &format!(" and {} more", names.len() - MAX_MEMBERS);
+        }
+        board += "\n";
+    }
+
+    if show_pairings {
+        board += "\n**Top pairings**\n";
+        let pairings = top_pairings(guild, cache, member_names);
+        if pairings.is_empty() {
+            board += "No scores have been calculated yet, run /list_compatibility to get started\n";
+        }
+        for (rank, (score, a, b)) in pairings.into_iter().take(TOP_PAIRINGS).enumerate() {
+            board += &format!("{}. {a} & {b}: {score:02}%\n", rank + 1);
+        }
+    }
+
+    if board.chars().count() > MESSAGE_LIMIT {
+        board = board.chars().take(MESSAGE_LIMIT - 1).collect::<String>() + "…";
+    }
+    board
+}
+
+/// Fetches everything the board for `guild_id` shows and renders it.
+pub async fn render(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+    guild_id: serenity::GuildId,
+    show_pairings: bool,
+) -> String {
+    let users: Vec<_> = state
+        .data
+        .read()
+        .await
+        .guild(guild_id)
+        .iter()
+        .flat_map(|g| g.display_names())
+        .collect();
+    let member_names = resolve_member_names(ctx, guild_id, users).await;
+
+    let data = state.data.read().await;
+    let empty = GuildData::default();
+    compose(
+        data.guild(guild_id).unwrap_or(&empty),
+        &*state.cache.lock().await,
+        &member_names,
+        show_pairings,
+    )
+}
+
+/// Whether `e` is Discord rejecting a request with `status`.
+pub fn is_http_status(e: &serenity::Error, status: serenity::StatusCode) -> bool {
+    matches!(
+        e,
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response))
+            if response.status_code == status
+    )
+}
+
+/// Edits the board of `guild_id` to match the registry. A board whose message was deleted is
+/// forgotten.
+pub async fn update(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+    guild_id: serenity::GuildId,
+) -> Result<(), anyhow::Error> {
+    let Some(board) = state
+        .data
+        .read()
+        .await
+        .guild(guild_id)
+        .and_then(|g| g.config.board.clone())
+    else {
+        return Ok(());
+    };
+    let content = render(ctx, state, guild_id, board.show_pairings).await;
+
+    match board
+        .channel
+        .edit_message(
+            ctx,
+            board.message,
+            serenity::EditMessage::new()
+                .content(content)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) if is_http_status(&e, serenity::StatusCode::NOT_FOUND) => {
+            info!(%guild_id, "Board message was deleted, no longer updating it");
+            let mut data = state.data.write().await;
+            let config = &mut data.guild_mut(guild_id).config;
+            if config.board.as_ref().map(|b| b.message) == Some(board.message) {
+                config.board = None;
+                state.saves.request();
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::Matchup,
+        data::{UserData, Visibility},
+    };
+
+    #[test]
+    fn compose_respects_visibility() {
+        let mut guild = GuildData::default();
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        for (id, result) in [(1, "a"), (2, "b"), (3, "c")] {
+            let mut user = UserData::default();
+            user.headmate_mut(&None).results.insert(at, result.into());
+            guild.users.insert(serenity::UserId::new(id), user);
+        }
+        guild
+            .users
+            .get_mut(&serenity::UserId::new(3))
+            .unwrap()
+            .visibility = Visibility::HiddenFromOthers;
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("a".into(), "b".into()), 80);
+        cache.insert(Matchup::new("a".into(), "c".into()), 99);
+        let names = BTreeMap::from([
+            (serenity::UserId::new(1), "**Alex**".to_string()),
+            (serenity::UserId::new(2), "**Sam**".to_string()),
+            (serenity::UserId::new(3), "**Kit**".to_string()),
+        ]);
+
+        assert_eq!(
+            compose(&guild, &cache, &names, true),
+            "**Compatibility board**\n\
+             Registered members (2):\n\
+             **Alex**, **Sam**\n\
+             \n\
+             **Top pairings**\n\
+             1. **Alex** & **Sam**: 80%\n"
+        );
+        assert_eq!(
+            compose(&guild, &cache, &names, false),
+            "**Compatibility board**\nRegistered members (2):\n**Alex**, **Sam**\n"
+        );
+    }
+}
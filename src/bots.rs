@@ -0,0 +1,54 @@
+//! Flags registered users that turn out to be bots, once at startup. Bots can't take the test, so
+//! any results stored for them are left out of everything. Nothing is deleted, an owner can still
+//! look at what was stored.
+
+use std::sync::Arc;
+
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::GlobalState;
+
+/// Looks up every registered user not flagged yet, and flags the ones that are bots. Users that
+/// can't be looked up are left alone.
+pub async fn sweep(ctx: serenity::Context, state: Arc<GlobalState>) {
+    let users: Vec<_> = state
+        .data
+        .read()
+        .await
+        .guilds
+        .iter()
+        .flat_map(|(&guild_id, guild)| {
+            guild
+                .users
+                .iter()
+                .filter(|(_, user)| !user.bot)
+                .map(move |(&user_id, _)| (guild_id, user_id))
+        })
+        .collect();
+
+    let mut bots = Vec::new();
+    for (guild_id, user_id) in users {
+        match user_id.to_user(&ctx).await {
+            Ok(user) if user.bot => bots.push((guild_id, user_id)),
+            Ok(_) => {}
+            Err(e) => warn!(%user_id, "Could not check whether a user is a bot: {e:#}"),
+        }
+    }
+    if bots.is_empty() {
+        return;
+    }
+
+    let mut data = state.data.write().await;
+    for &(guild_id, user_id) in &bots {
+        info!(%guild_id, %user_id, "Flagged a registered bot account");
+        if let Some(user) = data.guild_mut(guild_id).users.get_mut(&user_id) {
+            user.bot = true;
+        }
+    }
+    state.saves.request();
+    drop(data);
+    for (guild_id, _) in bots {
+        state.refresh.request(guild_id);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api::{GetResultResult, MatchRequest},
+    archetypes,
+};
+
+/// An unordered pair of result IDs. `Matchup::new(a, b)` and `Matchup::new(b, a)` are the same key.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Matchup(String, String);
+
+impl Matchup {
+    pub fn new(a: String, b: String) -> Matchup {
+        if a < b {
+            Matchup(a, b)
+        } else {
+            Matchup(b, a)
+        }
+    }
+
+    /// Whether both result IDs satisfy `f`.
+    pub fn both(&self, mut f: impl FnMut(&str) -> bool) -> bool {
+        f(&self.0) && f(&self.1)
+    }
+
+    pub fn request(&self) -> MatchRequest {
+        MatchRequest {
+            person: self.0.clone(),
+            partner: self.1.clone(),
+        }
+    }
+}
+
+impl From<MatchRequest> for Matchup {
+    fn from(value: MatchRequest) -> Self {
+        Matchup::new(value.person, value.partner)
+    }
+}
+
+/// A cached match score, and when it was fetched.
+#[derive(Clone, Copy, Debug)]
+struct CachedMatch {
+    score: u32,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Match scores and results that have already been fetched from bdsmtest.org. Results never
+/// change once published, so they are never invalidated. Match scores are re-fetched now and
+/// then in case bdsmtest.org changes how it scores.
+#[derive(Default)]
+pub struct Cache {
+    matches: HashMap<Matchup, CachedMatch>,
+    results: HashMap<String, GetResultResult>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::default()
+    }
+
+    pub fn get(&self, matchup: &Matchup) -> Option<u32> {
+        self.matches.get(matchup).map(|m| m.score)
+    }
+
+    pub fn insert(&mut self, matchup: Matchup, score: u32) {
+        self.insert_at(matchup, score, Utc::now());
+    }
+
+    pub fn insert_at(&mut self, matchup: Matchup, score: u32, fetched_at: DateTime<Utc>) {
+        self.matches
+            .insert(matchup, CachedMatch { score, fetched_at });
+    }
+
+    /// The `count` matchups fetched longest ago, oldest first.
+    pub fn oldest_matches(&self, count: usize) -> Vec<Matchup> {
+        let mut matches: Vec<_> = self.matches.iter().collect();
+        matches.sort_by_key(|(_, m)| m.fetched_at);
+        matches
+            .into_iter()
+            .take(count)
+            .map(|(matchup, _)| matchup.clone())
+            .collect()
+    }
+
+    /// Drops the match scores of any result that isn't `stored`. Returns how many were dropped.
+    pub fn evict_matches(&mut self, mut stored: impl FnMut(&str) -> bool) -> usize {
+        let before = self.matches.len();
+        self.matches.retain(|matchup, _| matchup.both(&mut stored));
+        before - self.matches.len()
+    }
+
+    pub fn get_result(&self, id: &str) -> Option<&GetResultResult> {
+        self.results.get(id)
+    }
+
+    /// Drops the cached results that aren't `stored`, along with their match scores. Returns how
+    /// many results were dropped.
+    pub fn evict(&mut self, mut stored: impl FnMut(&str) -> bool) -> usize {
+        self.evict_matches(&mut stored);
+        let before = self.results.len();
+        self.results.retain(|id, _| stored(id));
+        before - self.results.len()
+    }
+
+    /// Drops everything. Returns how many match scores and results were dropped.
+    pub fn clear(&mut self) -> (usize, usize) {
+        let dropped = (self.matches.len(), self.results.len());
+        self.matches.clear();
+        self.results.clear();
+        dropped
+    }
+
+    /// Like [`Cache::evict`], but also returns how many match scores were dropped, as
+    /// `(matches, results)`.
+    pub fn prune(&mut self, mut stored: impl FnMut(&str) -> bool) -> (usize, usize) {
+        let matches = self.evict_matches(&mut stored);
+        (matches, self.evict(stored))
+    }
+
+    /// Caches a fetched result. Any archetype it has that isn't known yet is learned.
+    pub fn insert_result(&mut self, id: String, result: GetResultResult) {
+        for score in &result.scores {
+            archetypes::learn(&score.name);
+        }
+        self.results.insert(id, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn hash_of(m: &Matchup) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        m.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn oldest_matches_first_and_evicts_unstored() {
+        let at =
+            |day: u32| -> DateTime<Utc> { format!("2024-01-{day:02}T00:00:00Z").parse().unwrap() };
+        let mut cache = Cache::new();
+        cache.insert_at(Matchup::new("a".into(), "b".into()), 10, at(3));
+        cache.insert_at(Matchup::new("a".into(), "c".into()), 20, at(1));
+        cache.insert_at(Matchup::new("b".into(), "gone".into()), 30, at(2));
+        assert_eq!(
+            cache.oldest_matches(2),
+            [
+                Matchup::new("a".into(), "c".into()),
+                Matchup::new("b".into(), "gone".into())
+            ]
+        );
+
+        assert_eq!(cache.evict_matches(|id| id != "gone"), 1);
+        assert_eq!(cache.get(&Matchup::new("b".into(), "gone".into())), None);
+        cache.insert_at(Matchup::new("c".into(), "a".into()), 25, at(4));
+        assert_eq!(
+            cache.oldest_matches(1),
+            [Matchup::new("a".into(), "b".into())]
+        );
+    }
+
+    #[test]
+    fn prune_counts_matches_and_results() {
+        let result = GetResultResult {
+            langfile: "en".into(),
+            date: "2024-05-01".into(),
+            version: 3,
+            gender: String::new(),
+            auth: false,
+            scores: Vec::new(),
+        };
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("a".into(), "b".into()), 10);
+        cache.insert(Matchup::new("a".into(), "gone".into()), 20);
+        cache.insert(Matchup::new("gone".into(), "old".into()), 30);
+        cache.insert_result("a".into(), result.clone());
+        cache.insert_result("gone".into(), result);
+        assert_eq!(cache.prune(|id| id == "a" || id == "b"), (2, 1));
+        assert_eq!(cache.get(&Matchup::new("a".into(), "b".into())), Some(10));
+        assert_eq!(cache.clear(), (1, 1));
+        assert!(cache.get_result("a").is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn matchup_is_symmetric(a: String, b: String) {
+            prop_assert_eq!(Matchup::new(a.clone(), b.clone()), Matchup::new(b, a));
+        }
+
+        #[test]
+        fn matchup_hash_agrees_with_eq(a: String, b: String, c: String, d: String) {
+            let x = Matchup::new(a, b);
+            let y = Matchup::new(c, d);
+            if x == y {
+                prop_assert_eq!(hash_of(&x), hash_of(&y));
+            }
+            let swapped = Matchup::new(x.1.clone(), x.0.clone());
+            prop_assert_eq!(hash_of(&x), hash_of(&swapped));
+        }
+
+        #[test]
+        fn matchup_from_request_matches_new(person: String, partner: String) {
+            let request = MatchRequest {
+                person: person.clone(),
+                partner: partner.clone(),
+            };
+            prop_assert_eq!(Matchup::from(request), Matchup::new(partner, person));
+        }
+
+        #[test]
+        fn cache_round_trips(a: String, b: String, score: u32) {
+            let mut cache = Cache::new();
+            cache.insert(Matchup::new(a.clone(), b.clone()), score);
+            prop_assert_eq!(cache.get(&Matchup::new(a.clone(), b.clone())), Some(score));
+            prop_assert_eq!(cache.get(&Matchup::new(b, a)), Some(score));
+        }
+
+        #[test]
+        fn cache_keeps_latest_score(a: String, b: String, first: u32, second: u32) {
+            let mut cache = Cache::new();
+            cache.insert(Matchup::new(a.clone(), b.clone()), first);
+            cache.insert(Matchup::new(b.clone(), a.clone()), second);
+            prop_assert_eq!(cache.get(&Matchup::new(a, b)), Some(second));
+        }
+    }
+}
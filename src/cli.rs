@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+use poise::serenity_prelude as serenity;
+
+use crate::data::{self, GlobalData, GuildData};
+
+#[derive(Parser)]
+#[command(about = "Discord bot for comparing bdsmtest.org results")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the Discord bot (the default when no subcommand is given)
+    Run,
+    /// Print per-guild counts and anything that looks wrong in a registry
+    Inspect {
+        /// Path to the registry file
+        #[arg(default_value = data::REGISTRY)]
+        path: PathBuf,
+    },
+    /// Check that a registry parses and migrates cleanly
+    Validate {
+        /// Path to the registry file
+        #[arg(default_value = data::REGISTRY)]
+        path: PathBuf,
+        /// Write the migrated registry back to `path`
+        #[arg(long)]
+        write: bool,
+    },
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GuildReport {
+    pub users: usize,
+    pub headmates: usize,
+    pub results: usize,
+    pub anomalies: Vec<String>,
+}
+
+fn inspect_guild(guild: &GuildData) -> GuildReport {
+    let mut report = GuildReport {
+        users: guild.users.len(),
+        ..Default::default()
+    };
+    for (user_id, user) in &guild.users {
+        report.headmates += user.headmates.len();
+        if user.primary.is_none() && user.headmates.is_empty() && user.tombstones.is_empty() {
+            report
+                .anomalies
+                .push(format!("user {user_id} has no entries"));
+        }
+        if let Some(primary) = &user.primary {
+            report.results += primary.results.len();
+            if primary.results.is_empty() {
+                report.anomalies.push(format!(
+                    "user {user_id} has a primary entry with no results"
+                ));
+            }
+        }
+
+        let mut folded: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (name, headmate) in &user.headmates {
+            report.results += headmate.results.len();
+            if headmate.results.is_empty() {
+                report.anomalies.push(format!(
+                    "user {user_id} has headmate {name:?} with no results"
+                ));
+            }
+            folded.entry(name.to_lowercase()).or_default().push(name);
+        }
+        for names in folded.values().filter(|names| names.len() > 1) {
+            report.anomalies.push(format!(
+                "user {user_id} has headmates differing only by case: {names:?}"
+            ));
+        }
+    }
+    report
+}
+
+pub fn inspect(data: &GlobalData) -> BTreeMap<serenity::GuildId, GuildReport> {
+    data.guilds
+        .iter()
+        .map(|(&guild_id, guild)| (guild_id, inspect_guild(guild)))
+        .collect()
+}
+
+/// Prints an [`inspect`] report for the registry at `path`.
+pub fn inspect_registry(path: &Path) -> Result<(), anyhow::Error> {
+    let data = data::load(path)?;
+    let reports = inspect(&data);
+    println!("{} guild(s) in {}", reports.len(), path.display());
+    for (guild_id, report) in reports {
+        println!(
+            "guild {guild_id}: {} user(s), {} headmate(s), {} result(s)",
+            report.users, report.headmates, report.results
+        );
+        for anomaly in report.anomalies {
+            println!("  - {anomaly}");
+        }
+    }
+    Ok(())
+}
+
+/// Loads and migrates the registry at `path`, only writing the result back if `write` is set.
+pub fn validate_registry(path: &Path, write: bool) -> Result<(), anyhow::Error> {
+    let data = data::load(path)?;
+    println!(
+        "{} is valid ({} guild(s))",
+        path.display(),
+        data.guilds.len()
+    );
+    if write {
+        let output = std::fs::File::create(path)
+            .with_context(|| format!("while opening {}", path.display()))?;
+        serde_json::to_writer_pretty(output, &data).context("while formatting json")?;
+        println!("wrote migrated registry to {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::UserData;
+
+    #[test]
+    fn inspect_reports_counts_and_anomalies() {
+        let mut data = GlobalData::default();
+        let guild = data.guild_mut(serenity::GuildId::new(1));
+
+        let mut user = UserData::default();
+        user.headmate_mut(&None)
+            .results
+            .insert(chrono::Utc::now(), "abc".into());
+        user.headmate_mut(&Some("Ash".into()));
+        user.headmate_mut(&Some("ash".into()))
+            .results
+            .insert(chrono::Utc::now(), "def".into());
+        guild.users.insert(serenity::UserId::new(2), user);
+        guild
+            .users
+            .insert(serenity::UserId::new(3), UserData::default());
+
+        let reports = inspect(&data);
+        assert_eq!(
+            reports[&serenity::GuildId::new(1)],
+            GuildReport {
+                users: 2,
+                headmates: 2,
+                results: 2,
+                anomalies: vec![
+                    "user 2 has headmate \"Ash\" with no results".into(),
+                    "user 2 has headmates differing only by case: [\"Ash\", \"ash\"]".into(),
+                    "user 3 has no entries".into(),
+                ],
+            }
+        );
+    }
+}
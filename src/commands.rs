@@ -0,0 +1,1921 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Context as _;
+use chrono::Utc;
+use poise::serenity_prelude::{self as serenity, futures::StreamExt as _, Mentionable as _};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    alerts, archetypes,
+    cache::Cache,
+    compare_button,
+    data::GlobalData,
+    format, graph, heatmap, jobs,
+    logic::{self, Invoker},
+    scan::CancelToken,
+    share, share_button, Context,
+};
+
+pub mod admin;
+pub mod owner;
+pub mod settings;
+
+fn invoker(ctx: Context<'_>) -> Result<Invoker, anyhow::Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
+    Ok(Invoker {
+        guild_id,
+        user_id: ctx.author().id,
+    })
+}
+
+/// Refuses to store results for bot accounts.
+fn ensure_human(user: &serenity::User) -> Result<(), logic::CommandError> {
+    if user.bot {
+        return Err(logic::CommandError::BotAccount(user.id));
+    }
+    Ok(())
+}
+
+/// The name the invoker shows up as in this guild, preferring their display name override.
+fn author_display_name(ctx: Context<'_>, data: &GlobalData) -> String {
+    if let Some(name) = ctx.guild_id().and_then(|g| {
+        data.guild(g)?
+            .users
+            .get(&ctx.author().id)?
+            .display_name
+            .clone()
+    }) {
+        return name;
+    }
+    match &ctx.author().member {
+        Some(m) => serenity::Member::from(serenity::PartialMember::clone(m.as_ref()))
+            .display_name()
+            .to_string(),
+        None => ctx
+            .author()
+            .global_name
+            .clone()
+            .unwrap_or(ctx.author().name.clone()),
+    }
+}
+
+/// The bolded display name of a guild member, for use in listings. A `display_name` override
+/// chosen by the member is used as-is without looking them up.
+pub async fn member_name(
+    cache_http: impl serenity::CacheHttp,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+    display_name: Option<&str>,
+) -> String {
+    if let Some(name) = display_name {
+        return format!("**{name}**");
+    }
+    match guild_id.member(cache_http, user_id).await {
+        Ok(user) => format!("**{}**", user.display_name()),
+        Err(_) if user_id.get() == 1 => "".to_string(),
+        Err(_) => "**Deleted User**".to_string(),
+    }
+}
+
+/// How many members [`resolve_member_names`] looks up at once.
+const MEMBER_LOOKUPS: usize = 8;
+
+/// The names of `users` in `guild_id`, as [`member_name`] gives them. Overrides and members in
+/// the guild's cache are resolved first, all at once, and only the rest are looked up, a few at
+/// a time.
+#[instrument(skip_all, fields(%guild_id, members = users.len(), looked_up))]
+pub async fn resolve_member_names(
+    cache_http: impl serenity::CacheHttp + Copy,
+    guild_id: serenity::GuildId,
+    users: Vec<(serenity::UserId, Option<String>)>,
+) -> BTreeMap<serenity::UserId, String> {
+    let started = std::time::Instant::now();
+    let mut member_names = BTreeMap::new();
+    let mut missing = Vec::new();
+    {
+        let guild = cache_http
+            .cache()
+            .and_then(|cache| guild_id.to_guild_cached(cache));
+        for (user_id, display_name) in users {
+            let cached = guild
+                .as_ref()
+                .and_then(|g| Some(g.members.get(&user_id)?.display_name().to_string()));
+            match display_name.or(cached) {
+                Some(name) => {
+                    member_names.insert(user_id, format!("**{name}**"));
+                }
+                None => missing.push(user_id),
+            }
+        }
+    }
+
+    tracing::Span::current().record("looked_up", missing.len());
+    let looked_up: Vec<_> = serenity::futures::stream::iter(missing)
+        .map(|user_id| async move {
+            (
+                user_id,
+                member_name(cache_http, guild_id, user_id, None).await,
+            )
+        })
+        .buffer_unordered(MEMBER_LOOKUPS)
+        .collect()
+        .await;
+    member_names.extend(looked_up);
+    info!(
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Resolved member names"
+    );
+    member_names
+}
+
+/// Resolves the names of every registered user in the invoker's guild for use in listings.
+async fn member_names(
+    ctx: Context<'_>,
+    data: &GlobalData,
+    guild_id: serenity::GuildId,
+) -> Result<BTreeMap<serenity::UserId, String>, anyhow::Error> {
+    let users = data
+        .guild(guild_id)
+        .iter()
+        .flat_map(|g| g.display_names())
+        .collect();
+    Ok(resolve_member_names(ctx, guild_id, users).await)
+}
+
+/// Tells the invoker, once, that their match alerts were turned off because they couldn't be
+/// DMed. Runs after every command.
+pub async fn report_match_alert_failure(ctx: Context<'_>) {
+    let Ok(who) = invoker(ctx) else {
+        return;
+    };
+    let failed = {
+        let mut data = ctx.data().data.write().await;
+        let failed = logic::take_match_alert_failure(&mut data, who);
+        if failed {
+            ctx.data().saves.request();
+        }
+        failed
+    };
+    if failed {
+        let note = poise::CreateReply::default()
+            .content(
+                "Your match alerts were turned off because I couldn't DM you. Open your DMs and \
+                 use /match_alerts to turn them back on",
+            )
+            .ephemeral(true);
+        if let Err(e) = ctx.send(note).await {
+            warn!("Could not report match alert failure: {e:#}");
+        }
+    }
+}
+
+/// Parses an ID typed as a command parameter, where Discord's own types can't be used.
+fn parse_id(kind: &'static str, id: &str) -> Result<u64, logic::CommandError> {
+    id.trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&id| id != 0)
+        .ok_or_else(|| logic::CommandError::InvalidId(kind, id.to_string()))
+}
+
+/// Shows `prompt` with Confirm/Cancel buttons and waits for the invoker to press one. Returns
+/// false if they cancel or don't answer within a minute. The buttons are removed afterwards.
+async fn confirm(ctx: Context<'_>, prompt: String) -> Result<bool, anyhow::Error> {
+    let confirm_id = format!("{}-confirm", ctx.id());
+    let cancel_id = format!("{}-cancel", ctx.id());
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .content(&prompt)
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    serenity::CreateButton::new(&confirm_id)
+                        .label("Confirm")
+                        .style(serenity::ButtonStyle::Danger),
+                    serenity::CreateButton::new(&cancel_id)
+                        .label("Cancel")
+                        .style(serenity::ButtonStyle::Secondary),
+                ])]),
+        )
+        .await?;
+
+    let pressed = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .custom_ids(vec![confirm_id.clone(), cancel_id])
+        .timeout(Duration::from_secs(60))
+        .await;
+    let confirmed = pressed
+        .as_ref()
+        .is_some_and(|mci| mci.data.custom_id == confirm_id);
+    if let Some(mci) = pressed {
+        mci.create_response(ctx, serenity::CreateInteractionResponse::Acknowledge)
+            .await?;
+    }
+    reply
+        .edit(
+            ctx,
+            poise::CreateReply::default()
+                .content(prompt)
+                .components(vec![]),
+        )
+        .await?;
+    Ok(confirmed)
+}
+
+/// Headmates of `owner` for an autocomplete. Nothing is suggested while the registry is being
+/// changed, since waiting on it could take longer than Discord waits for choices.
+fn try_headmate_choices(
+    ctx: Context<'_>,
+    who: Invoker,
+    owner: serenity::UserId,
+    partial: &str,
+) -> Vec<String> {
+    match ctx.data().data.try_read() {
+        Ok(data) => logic::headmate_choices(&data, who, owner, partial),
+        Err(_) => vec![],
+    }
+}
+
+pub async fn autocomplete_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(who) = invoker(ctx) else {
+        return vec![];
+    };
+    try_headmate_choices(ctx, who, who.user_id, partial)
+}
+
+/// Headmates of the member already entered in the option `member`, that the invoker may see.
+async fn headmates_of(ctx: Context<'_>, member: &str, partial: &str) -> Vec<String> {
+    let (Ok(who), Some(owner)) = (invoker(ctx), entered_user(ctx, member)) else {
+        return vec![];
+    };
+    try_headmate_choices(ctx, who, owner, partial)
+}
+
+pub async fn autocomplete_member_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    headmates_of(ctx, "member", partial).await
+}
+
+pub async fn autocomplete_user_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    headmates_of(ctx, "user", partial).await
+}
+
+pub async fn autocomplete_first_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    headmates_of(ctx, "first", partial).await
+}
+
+pub async fn autocomplete_second_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    headmates_of(ctx, "second", partial).await
+}
+
+/// The member already entered for the option `name` of the command being autocompleted.
+fn entered_user(ctx: Context<'_>, name: &str) -> Option<serenity::UserId> {
+    let poise::Context::Application(ctx) = ctx else {
+        return None;
+    };
+    ctx.args
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match option.value {
+            serenity::ResolvedValue::User(user, _) => Some(user.id),
+            _ => None,
+        })
+}
+
+/// The string already entered for the option `name` of the command being autocompleted.
+fn entered_string(ctx: Context<'_>, name: &str) -> Option<String> {
+    let poise::Context::Application(ctx) = ctx else {
+        return None;
+    };
+    ctx.args
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match option.value {
+            serenity::ResolvedValue::String(value) => Some(value.to_string()),
+            _ => None,
+        })
+}
+
+/// The invoker's results, labelled for picking one. The results are those of the headmate
+/// already entered in the `headmate` option, if any. Each value is the exact timestamp the result
+/// is stored under. Like [`try_headmate_choices`], nothing is suggested while the registry is
+/// being changed.
+pub async fn autocomplete_result_date(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let Ok(who) = invoker(ctx) else {
+        return vec![];
+    };
+    let Ok(data) = ctx.data().data.try_read() else {
+        return vec![];
+    };
+    logic::result_date_choices(&data, who, entered_string(ctx, "headmate"), partial)
+        .into_iter()
+        .map(|(label, value)| serenity::AutocompleteChoice::new(label, value))
+        .collect()
+}
+
+pub async fn autocomplete_archetype(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    archetypes::matching(partial)
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// `pages` split up wherever one is too long for a single message.
+fn fit_pages(pages: Vec<String>) -> Vec<String> {
+    pages
+        .iter()
+        .flat_map(|page| format::split_message(page, format::MESSAGE_LIMIT))
+        .collect()
+}
+
+/// Sends `pages` in order, replying to the command with the first one. Pages too long for one
+/// message are split. Nobody is pinged.
+async fn send_pages(ctx: Context<'_>, pages: Vec<String>) -> Result<(), anyhow::Error> {
+    for (i, page) in fit_pages(pages).into_iter().enumerate() {
+        ctx.send(
+            poise::CreateReply::default()
+                .content(page)
+                .reply(i == 0)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Runs `scan` behind a progress message with a Cancel button that only the invoker can press, then
+/// sends its pages. Pressing it cancels `cancel`, so the scan stops fetching and its pages only
+/// cover what it had so far. The progress message becomes the first page. Returns the entries
+/// that were listed.
+async fn send_scan_pages(
+    ctx: Context<'_>,
+    cancel: &CancelToken,
+    scan: impl std::future::Future<Output = Result<logic::Listing, logic::CommandError>>,
+) -> Result<Vec<format::CompatEntry>, anyhow::Error> {
+    let cancel_id = format!("{}-cancel-scan", ctx.id());
+    let progress = ctx
+        .send(
+            poise::CreateReply::default()
+                .content("Scanning compatibility...")
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    serenity::CreateButton::new(&cancel_id)
+                        .label("Cancel")
+                        .style(serenity::ButtonStyle::Danger),
+                ])]),
+        )
+        .await?;
+
+    let pressed = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .custom_ids(vec![cancel_id])
+        .timeout(Duration::from_secs(15 * 60));
+    tokio::pin!(scan);
+    let result = tokio::select! {
+        result = &mut scan => result,
+        Some(mci) = pressed => {
+            info!("Scan cancelled");
+            cancel.cancel();
+            mci.create_response(ctx, serenity::CreateInteractionResponse::Acknowledge)
+                .await?;
+            progress
+                .edit(
+                    ctx,
+                    poise::CreateReply::default()
+                        .content("Cancelling, listing what was scanned so far...")
+                        .components(vec![]),
+                )
+                .await?;
+            scan.await
+        }
+    };
+    let (mut pages, entries) = match result {
+        Ok(listing) => (fit_pages(listing.pages).into_iter(), listing.entries),
+        Err(e) => {
+            progress.delete(ctx).await?;
+            return Err(e.into());
+        }
+    };
+    progress
+        .edit(
+            ctx,
+            poise::CreateReply::default()
+                .content(pages.next().unwrap_or_default())
+                .components(vec![])
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    for page in pages {
+        ctx.send(
+            poise::CreateReply::default()
+                .content(page)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    }
+    Ok(entries)
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Adds a result from bdsmtest.org. A headmate can also be provided if they took the test on their own.
+pub async fn add_bdsm_result(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Announce your first registration in this server (defaults to your setting)"]
+    announce: Option<bool>,
+    #[description = "Remove it automatically after a while, for guests (defaults to false)"]
+    temporary: Option<bool>,
+    #[description = "The result ID from bdsmtest.org"]
+    #[rest]
+    id: String,
+) -> Result<(), anyhow::Error> {
+    info!("Adding bdsmtest.org result");
+
+    ctx.defer_ephemeral().await?;
+
+    ensure_human(ctx.author())?;
+    let who = invoker(ctx)?;
+    let now = Utc::now();
+    let id = id.trim().to_string();
+    let taken = logic::check_new_result(&ctx.data().api, &ctx.data().cache, &id, now).await?;
+    let result_id = id.clone();
+    let (announce_channel, reply, headmate, name, subject) = {
+        let mut data = ctx.data().data.write().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let name = author_display_name(ctx, &data);
+        let subject = match &headmate {
+            Some(headmate) => format!("{name} ({headmate})"),
+            None => name.clone(),
+        };
+        let expires = temporary
+            .unwrap_or(false)
+            .then(|| logic::make_temporary(&mut data, who, &headmate, &id, now));
+        let announce = logic::add_result(&mut data, who, &headmate, id, taken, announce);
+        logic::keep_fetched_results(&mut data, who, &headmate, &*ctx.data().cache.lock().await);
+        ctx.data().saves.request();
+        let reply = match expires {
+            Some(at) => format!(
+                "Result Saved, it will be removed {}. Use /keep_result to keep it",
+                format::format_timestamp(&at, logic::timezone(&data, who))
+            ),
+            None => "Result Saved".to_string(),
+        };
+        let announce_channel = data
+            .guild(who.guild_id)
+            .and_then(|g| g.config.announce_channel)
+            .filter(|_| announce);
+        (announce_channel, reply, headmate, name, subject)
+    };
+
+    // Any failure to load a result just leaves the diff or summary out.
+    let (diff, summary) = {
+        let data = ctx.data().data.read().await;
+        let diff = logic::retake_diff(
+            &data,
+            &ctx.data().api,
+            &ctx.data().cache,
+            who,
+            &headmate,
+            &result_id,
+        )
+        .await;
+        let summary = logic::result_summary(
+            &data,
+            &ctx.data().api,
+            &ctx.data().cache,
+            who,
+            &share_button::subject(&name, &headmate),
+            &headmate,
+            &result_id,
+        )
+        .await
+        .ok();
+        (diff, summary)
+    };
+    let mut reply = match diff {
+        Some(diff) => format!("{reply}\n{diff}"),
+        None => reply,
+    };
+    // The summary is only posted for others if the button is pressed.
+    let share = summary.and_then(|summary| {
+        reply = format!("{reply}\n\n{summary}");
+        share_button::button(who.user_id, headmate.clone(), result_id, now)
+    });
+
+    ctx.data().refresh.request(who.guild_id);
+    tokio::spawn(alerts::notify(
+        ctx.serenity_context().clone(),
+        ctx.data().clone(),
+        who,
+        headmate,
+        subject,
+    ));
+
+    let mut reply = poise::CreateReply::default().content(reply).reply(true);
+    if let Some(share) = share {
+        reply = reply.components(vec![share]);
+    }
+    ctx.send(reply).await.context("while sending reply")?;
+
+    if let Some(channel) = announce_channel {
+        announce_registration(ctx, channel).await;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Adds scores copied from an old result that has no result ID, like "Rigger: 95%, Switch: 40%".
+pub async fn add_manual_result(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Announce your first registration in this server (defaults to your setting)"]
+    announce: Option<bool>,
+    #[description = "A text file with one `Archetype: percent` line per archetype"] file: Option<
+        serenity::Attachment,
+    >,
+    #[description = "Scores as `Archetype: percent`, separated by commas"]
+    #[rest]
+    scores: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Adding manual result");
+
+    ctx.defer_ephemeral().await?;
+
+    let mut text = scores.unwrap_or_default();
+    if let Some(file) = file {
+        let contents = file.download().await.context("while downloading scores")?;
+        text += "\n";
+        text += &String::from_utf8_lossy(&contents);
+    }
+    let scores = logic::parse_manual_scores(&text)?;
+    let count = scores.len();
+
+    ensure_human(ctx.author())?;
+    let who = invoker(ctx)?;
+    let announce_channel = {
+        let mut data = ctx.data().data.write().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let announce =
+            logic::add_manual_result(&mut data, who, &headmate, scores, Utc::now(), announce);
+        ctx.data().saves.request();
+        data.guild(who.guild_id)
+            .and_then(|g| g.config.announce_channel)
+            .filter(|_| announce)
+    };
+
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(format!(
+        "Manual result with {count} archetypes saved. Its matches are estimated locally, since \
+         bdsmtest.org can only compare its own results"
+    ))
+    .await
+    .context("while sending reply")?;
+
+    if let Some(channel) = announce_channel {
+        announce_registration(ctx, channel).await;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Adds a result from the text bdsmtest.org gives you to share, pasted as is.
+pub async fn import_share_text(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Announce your first registration in this server (defaults to your setting)"]
+    announce: Option<bool>,
+    #[description = "The shared text, starting with \"== Results from bdsmtest.org ==\""]
+    #[rest]
+    text: String,
+) -> Result<(), anyhow::Error> {
+    info!("Importing share text");
+
+    ctx.defer_ephemeral().await?;
+
+    let shared = share::parse(&text).ok_or(logic::CommandError::UnreadableShareText)?;
+
+    ensure_human(ctx.author())?;
+    let who = invoker(ctx)?;
+    let (reply, announce_channel) = {
+        let mut data = ctx.data().data.write().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let (reply, announce) = match shared {
+            share::SharedResult::Id(id) => {
+                let reply = format!("Result {id} saved");
+                let announce =
+                    logic::add_result(&mut data, who, &headmate, id, Utc::now(), announce);
+                (reply, announce)
+            }
+            share::SharedResult::Scores(scores) => {
+                let reply = format!(
+                    "There was no result link, so the {} archetypes were saved as a manual \
+                     result. Its matches are estimated locally",
+                    scores.len()
+                );
+                let announce = logic::add_manual_result(
+                    &mut data,
+                    who,
+                    &headmate,
+                    scores,
+                    Utc::now(),
+                    announce,
+                );
+                (reply, announce)
+            }
+        };
+        ctx.data().saves.request();
+        let channel = data
+            .guild(who.guild_id)
+            .and_then(|g| g.config.announce_channel)
+            .filter(|_| announce);
+        (reply, channel)
+    };
+
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(reply).await.context("while sending reply")?;
+
+    if let Some(channel) = announce_channel {
+        announce_registration(ctx, channel).await;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Adds many results from bdsmtest.org at once, each stored under the date it was taken.
+pub async fn import_results(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Announce your first registration in this server (defaults to your setting)"]
+    announce: Option<bool>,
+    #[description = "A JSON file with a list of result IDs, or an entry's results by date"]
+    file: Option<serenity::Attachment>,
+    #[description = "Result IDs from bdsmtest.org, separated by commas"]
+    #[rest]
+    ids: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Importing results");
+
+    ctx.defer_ephemeral().await?;
+
+    let mut found = match ids {
+        Some(ids) => logic::parse_import(&ids)?,
+        None => Vec::new(),
+    };
+    if let Some(file) = file {
+        let contents = file.download().await.context("while downloading results")?;
+        found.extend(logic::parse_import(&String::from_utf8_lossy(&contents))?);
+    }
+    // Parsing again merges what the text and the file both had, and checks the total.
+    let ids = logic::parse_import(&found.join("\n"))?;
+
+    ensure_human(ctx.author())?;
+    let who = invoker(ctx)?;
+    let checked = logic::check_imports(&ctx.data().api, &ctx.data().cache, ids, Utc::now()).await;
+    let (imports, announce_channel) = {
+        let mut data = ctx.data().data.write().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let (imports, announce) =
+            logic::store_imports(&mut data, who, &headmate, checked, announce);
+        logic::keep_fetched_results(&mut data, who, &headmate, &*ctx.data().cache.lock().await);
+        ctx.data().saves.request();
+        let channel = data
+            .guild(who.guild_id)
+            .and_then(|g| g.config.announce_channel)
+            .filter(|_| announce);
+        (imports, channel)
+    };
+    info!(ids = imports.len(), "Imported results");
+
+    ctx.data().refresh.request(who.guild_id);
+
+    send_pages(ctx, format::format_import(&imports)).await?;
+
+    if let Some(channel) = announce_channel {
+        announce_registration(ctx, channel).await;
+    }
+
+    Ok(())
+}
+
+/// Lets the guild know the invoker joined the registry. This never includes any scores or result
+/// IDs, and failures are only logged since the result has already been saved.
+async fn announce_registration(ctx: Context<'_>, channel: serenity::ChannelId) {
+    let message = serenity::CreateMessage::new()
+        .content(format!(
+            "{} just joined the compatibility registry — run /list_compatibility to see your score!",
+            ctx.author().mention()
+        ))
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+    if let Err(e) = channel.send_message(ctx, message).await {
+        warn!("Could not announce registration in {channel}: {e}");
+    }
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Removes every result of the current user (or one of their headmates) and the whole entry
+pub async fn remove_bdsm_results(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Only show what would be removed (defaults to false)"] preview: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Attempting to remove data");
+
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    if preview.unwrap_or(false) {
+        let data = ctx.data().data.read().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let target = logic::describe_removal(&data, who, &headmate)?;
+        ctx.reply(format::format_removal_preview(
+            &target,
+            logic::timezone(&data, who),
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let mut data = ctx.data().data.write().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let reply = match &headmate {
+        Some(headmate) => format!("Entries for {headmate} Removed"),
+        None => "Entries Removed".to_string(),
+    };
+    let now = Utc::now();
+    let removal = logic::remove_results(&mut data, who, headmate, now)?;
+    ctx.data().saves.request();
+    ctx.data().undo.record(who, now, removal);
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(format!(
+        "{reply}. Use /undo within 10 minutes to put them back, or /recover_my_data within {} \
+         days",
+        ctx.data().config.deletion.recovery_days
+    ))
+    .await
+    .context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Removes a single result of the current user (or one of their headmates) for good, keeping the
+/// rest.
+pub async fn remove_bdsm_result(
+    ctx: Context<'_>,
+    #[description = "Date of the result"]
+    #[autocomplete = "autocomplete_result_date"]
+    result: String,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Removing a single result");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let (id, empty) = logic::remove_result(&mut data, who, &headmate, &result)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    let mut reply = format!(
+        "Removed result {id}. This can't be undone, add it again with /add_bdsm_result if you \
+         change your mind"
+    );
+    if empty {
+        reply += &match &headmate {
+            Some(headmate) => format!(
+                ". ({headmate}) has no results left, use /remove_bdsm_results to remove the entry \
+                 too"
+            ),
+            None => ". Your primary entry has no results left".to_string(),
+        };
+    }
+    ctx.reply(reply).await.context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Puts back the entries you last removed, if that was in the last 10 minutes.
+pub async fn undo(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Undoing last removal");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let removal = ctx
+        .data()
+        .undo
+        .take(who, Utc::now())
+        .ok_or(logic::CommandError::NothingToUndo)?;
+    let reply = match &removal.headmate {
+        Some(headmate) => format!("Entries for {headmate} put back"),
+        None => "Entries put back".to_string(),
+    };
+    logic::undo_removal(&mut data, who, &removal)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(reply).await.context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Lists the entries you removed that can still be recovered, or restores one of them.
+pub async fn recover_my_data(
+    ctx: Context<'_>,
+    #[description = "Number of the entry to restore, from the list"]
+    #[min = 1]
+    restore: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    info!("Recovering removed entries");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let Some(number) = restore else {
+        let data = ctx.data().data.read().await;
+        let list = format::format_tombstones(
+            logic::tombstones(&data, who),
+            ctx.data().config.deletion.recovery_window(),
+            logic::timezone(&data, who),
+        );
+        ctx.reply(list).await?;
+        return Ok(());
+    };
+
+    let mut data = ctx.data().data.write().await;
+    let restored = logic::restore_tombstone(&mut data, who, number - 1)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(match restored.headmate {
+        Some(headmate) => format!("Entries for {headmate} restored"),
+        None => "Entries restored".to_string(),
+    })
+    .await
+    .context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Keeps the temporary results of the current user (or one of their headmates) for good.
+pub async fn keep_result(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Keeping temporary results");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let kept = logic::keep_results(&mut data, who, &headmate)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(format!("Kept {kept} temporary results"))
+        .await
+        .context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Renames one of your headmates, merging it into another if that name is taken already.
+pub async fn rename_headmate(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    old_name: String,
+    #[description = "The name to move it to"] new_name: String,
+) -> Result<(), anyhow::Error> {
+    info!("Renaming headmate");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let moved = logic::rename_headmate(&mut data, who, &old_name, &new_name)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(format!(
+        "Moved {moved} results from ({old_name}) to ({})",
+        new_name.trim()
+    ))
+    .await
+    .context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Makes one of your headmates your primary entry, and the old primary entry that headmate.
+pub async fn promote_headmate(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: String,
+) -> Result<(), anyhow::Error> {
+    info!("Promoting headmate");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let (promoted, demoted) = logic::promote_headmate(&mut data, who, &headmate)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    let mut reply = format!("Moved {promoted} results from ({headmate}) to your primary entry");
+    if demoted > 0 {
+        reply += &format!(", and {demoted} results from your old primary entry to ({headmate})");
+    }
+    ctx.reply(reply).await.context("while sending reply")?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+// Every argument is a Discord option, there is nothing to bundle.
+#[allow(clippy::too_many_arguments)]
+/// Display the newest result registered to the current user. (or for the specified headmate)
+pub async fn show_result(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Respond in public to the server (defaults to true)"] public: Option<bool>,
+    #[description = "Only show the result from this date (defaults to the newest)"]
+    #[autocomplete = "autocomplete_result_date"]
+    date: Option<String>,
+    #[description = "Show every result instead of only the newest (defaults to false)"] all: Option<
+        bool,
+    >,
+    #[description = "Include the archetypes this server hides"] show_all: Option<bool>,
+    #[description = "Fetch the results from bdsmtest.org again (defaults to false)"]
+    refresh: Option<bool>,
+    #[description = "Show plain text for copying elsewhere instead of an embed (defaults to false)"]
+    plain: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    let public = public.unwrap_or(true);
+    info!("Fetching results");
+    if public {
+        ctx.defer().await?;
+    } else {
+        ctx.defer_ephemeral().await?;
+    }
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    // Only public output gets a button, for whoever reads it.
+    let compare = public
+        .then(|| logic::posted_result(&data, who, &headmate, date.as_deref()))
+        .flatten()
+        .and_then(|result_id| {
+            compare_button::button(who.user_id, headmate.clone(), result_id, Utc::now())
+        });
+    let messages = logic::show_result(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        logic::display_name(&data, who).unwrap_or(&ctx.author().name),
+        &headmate,
+        &logic::ShowOptions {
+            date,
+            all: all.unwrap_or(false),
+            show_all: show_all.unwrap_or(false),
+            refresh: refresh.unwrap_or(false),
+            plain: plain.unwrap_or(false),
+        },
+    )
+    .await?;
+    drop(data);
+    // Results fetched just now are kept, so they never have to be fetched again.
+    {
+        let mut data = ctx.data().data.write().await;
+        let cache = ctx.data().cache.lock().await;
+        if logic::keep_fetched_results(&mut data, who, &headmate, &cache) {
+            ctx.data().saves.request();
+        }
+    }
+    let messages: Vec<_> = messages
+        .into_iter()
+        .flat_map(|message| {
+            if message.embeds.is_empty() {
+                fit_pages(vec![message.content])
+                    .into_iter()
+                    .map(|page| poise::CreateReply::default().content(page))
+                    .collect()
+            } else {
+                let reply = poise::CreateReply::default().content(message.content);
+                vec![message.embeds.into_iter().fold(reply, |r, e| r.embed(e))]
+            }
+        })
+        .collect();
+    let last = messages.len().saturating_sub(1);
+    for (i, reply) in messages.into_iter().enumerate() {
+        let mut reply = reply.reply(i == 0);
+        if let Some(compare) = compare.clone().filter(|_| i == last) {
+            reply = reply.components(vec![compare]);
+        }
+        ctx.send(reply).await?;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    user_cooldown = 300
+)]
+/// Checks that bdsmtest.org still has all of your results.
+pub async fn verify_my_results(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Verifying results");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let (pages, verified) = {
+        let data = ctx.data().data.read().await;
+        logic::verify_results(&data, &ctx.data().api, &ctx.data().cache, who).await?
+    };
+    {
+        let mut data = ctx.data().data.write().await;
+        let cache = ctx.data().cache.lock().await;
+        if logic::record_verified(&mut data, who, &verified, &cache) {
+            ctx.data().saves.request();
+        }
+    }
+    send_pages(ctx, pages).await
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Shows your compatibility with one member, without listing everyone else.
+pub async fn compare(
+    ctx: Context<'_>,
+    #[description = "Member to compare with"] user: serenity::User,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Their headmate"]
+    #[autocomplete = "autocomplete_user_headmate"]
+    their_headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Comparing with one member");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let display_name = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&user.id)?.display_name.as_deref());
+    let member_names = BTreeMap::from([(
+        user.id,
+        member_name(ctx, who.guild_id, user.id, display_name).await,
+    )]);
+    let score = logic::compare(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &headmate,
+        (user.id, their_headmate),
+        &member_names,
+    )
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content(score)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Shows the compatibility between two members, if both of them allow it.
+pub async fn compatibility_between(
+    ctx: Context<'_>,
+    #[description = "First member"] first: serenity::User,
+    #[description = "Second member"] second: serenity::User,
+    #[description = "The first member's headmate"]
+    #[autocomplete = "autocomplete_first_headmate"]
+    first_headmate: Option<String>,
+    #[description = "The second member's headmate"]
+    #[autocomplete = "autocomplete_second_headmate"]
+    second_headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Comparing two members");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let mut member_names = BTreeMap::new();
+    for user_id in [first.id, second.id] {
+        let display_name = data
+            .guild(who.guild_id)
+            .and_then(|g| g.users.get(&user_id)?.display_name.as_deref());
+        let name = member_name(ctx, who.guild_id, user_id, display_name).await;
+        member_names.insert(user_id, name);
+    }
+    let score = logic::compatibility_between(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        [(first.id, first_headmate), (second.id, second_headmate)],
+        &member_names,
+    )
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content(score)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Draws the server's strong matches as a graph, from the scores looked up so far.
+pub async fn compat_graph(
+    ctx: Context<'_>,
+    #[description = "Lowest score drawn as a line (defaults to 70)"]
+    #[min = 1]
+    #[max = 100]
+    threshold: Option<u32>,
+    #[description = "Also draw entries without a strong match"] include_isolated: Option<bool>,
+    #[description = "Look up the missing scores first if fewer than half are known"] scan: Option<
+        bool,
+    >,
+) -> Result<(), anyhow::Error> {
+    info!("Drawing compatibility graph");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let threshold = threshold.unwrap_or(graph::DEFAULT_THRESHOLD);
+    let include_isolated = include_isolated.unwrap_or(false);
+    let build =
+        |data: &GlobalData, member_names: &BTreeMap<serenity::UserId, String>, cache: &Cache| {
+            let guild = data
+                .guild(who.guild_id)
+                .ok_or(logic::CommandError::NoGuildData)?;
+            Ok::<_, logic::CommandError>(graph::build(
+                guild,
+                who.user_id,
+                cache,
+                member_names,
+                threshold,
+                include_isolated,
+            ))
+        };
+    let _scan = ctx.data().scans.enter(who.guild_id).await;
+    let (member_names, mut built) = {
+        let data = ctx.data().data.read().await;
+        let member_names = member_names(ctx, &data, who.guild_id).await?;
+        let built = build(&data, &member_names, &*ctx.data().cache.lock().await)?;
+        (member_names, built)
+    };
+    // A graph that is already too big would only get bigger, so it isn't worth the lookups.
+    if scan.unwrap_or(false)
+        && built.coverage() < graph::POOR_COVERAGE
+        && built.graph.labels.len() <= graph::MAX_NODES
+    {
+        info!(missing = built.missing.len(), "Looking up missing scores");
+        // The registry isn't locked meanwhile, the graph is built again from it afterwards.
+        let (looked_up, failure) = logic::look_up_missing(
+            &ctx.data().api,
+            &ctx.data().cache,
+            built.missing,
+            &CancelToken::default(),
+        )
+        .await;
+        if let Some(e) = failure {
+            warn!("Stopped looking up scores: {e:#}");
+        }
+        info!(looked_up, "Looked up missing scores");
+        let data = ctx.data().data.read().await;
+        built = build(&data, &member_names, &*ctx.data().cache.lock().await)?;
+    }
+
+    let coverage = built.coverage();
+    let graph = built.graph;
+    if graph.labels.is_empty() {
+        ctx.reply(format!("No known matches score {threshold}% or more yet"))
+            .await?;
+        return Ok(());
+    }
+    if graph.labels.len() > graph::MAX_NODES {
+        ctx.reply(format!(
+            "The graph would have {} entries, too many to read. Try a higher threshold, or \
+             list_compatibility's CSV export",
+            graph.labels.len()
+        ))
+        .await?;
+        return Ok(());
+    }
+    let (labels, png) = tokio::task::spawn_blocking(move || {
+        let png = graph::render(&graph);
+        (graph.labels, png)
+    })
+    .await?;
+    let mut pages = format::format_graph_key(&labels, threshold, coverage).into_iter();
+    ctx.send(
+        poise::CreateReply::default()
+            .content(pages.next().unwrap_or_default())
+            .attachment(serenity::CreateAttachment::bytes(png, "compat_graph.png"))
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+    for page in pages {
+        ctx.send(
+            poise::CreateReply::default()
+                .content(page)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Shows the score between every pair of entries in the server, from the scores looked up so far.
+pub async fn compat_matrix(
+    ctx: Context<'_>,
+    #[description = "Numbers, or a color-coded image for bigger servers (defaults to text)"]
+    output: Option<logic::MatrixOutput>,
+) -> Result<(), anyhow::Error> {
+    info!("Showing compatibility matrix");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let output = output.unwrap_or_default();
+    let matrix = {
+        let data = ctx.data().data.read().await;
+        let guild = data
+            .guild(who.guild_id)
+            .ok_or(logic::CommandError::NoGuildData)?;
+        let member_names = member_names(ctx, &data, who.guild_id).await?;
+        heatmap::build(
+            guild,
+            who.user_id,
+            &*ctx.data().cache.lock().await,
+            &member_names,
+        )
+    };
+    let entries = matrix.labels.len();
+    if entries < 2 {
+        ctx.reply("There aren't enough entries to compare yet")
+            .await?;
+        return Ok(());
+    }
+    if entries > heatmap::MAX_ENTRIES {
+        ctx.reply(format!(
+            "The matrix would have {entries} entries, too many to read. Try list_compatibility's \
+             CSV export"
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    match output {
+        logic::MatrixOutput::Text if entries > heatmap::MAX_TEXT_ENTRIES => {
+            ctx.reply(format!(
+                "The matrix would have {entries} entries, too many to show as numbers. Try \
+                 output:image"
+            ))
+            .await?;
+        }
+        logic::MatrixOutput::Text => {
+            send_pages(ctx, format::format_matrix(&matrix.labels, &matrix.scores)).await?;
+        }
+        logic::MatrixOutput::Image => {
+            let png = tokio::task::spawn_blocking(move || heatmap::render(&matrix)).await?;
+            ctx.send(
+                poise::CreateReply::default()
+                    .attachment(serenity::CreateAttachment::bytes(png, "compat_matrix.png")),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Explains which archetypes drive your match with another member, if they allow it.
+pub async fn compat_explain(
+    ctx: Context<'_>,
+    #[description = "Member to compare with"] member: serenity::User,
+    #[description = "Their headmate"]
+    #[autocomplete = "autocomplete_member_headmate"]
+    member_headmate: Option<String>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Explaining a match");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let mut member_names = BTreeMap::new();
+    for user_id in [who.user_id, member.id] {
+        let display_name = data
+            .guild(who.guild_id)
+            .and_then(|g| g.users.get(&user_id)?.display_name.as_deref());
+        let name = member_name(ctx, who.guild_id, user_id, display_name).await;
+        member_names.insert(user_id, name);
+    }
+    let explanation = logic::compat_explain(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &headmate,
+        (member.id, member_headmate),
+        &member_names,
+    )
+    .await?;
+    ctx.reply(explanation).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Shows your single best match in the server.
+pub async fn match_me(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Finding best match");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let _scan = ctx.data().scans.enter(who.guild_id).await;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let subject = format!(
+        "**{}**",
+        headmate
+            .clone()
+            .unwrap_or_else(|| author_display_name(ctx, &data))
+    );
+    let best = logic::match_me(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &subject,
+        &headmate,
+        &member_names,
+    )
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content(best)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Sums up your (or a headmate's) results over time and how they changed.
+pub async fn stats_me(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Summarizing personal stats");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let subject = headmate
+        .clone()
+        .unwrap_or_else(|| author_display_name(ctx, &data));
+    let stats = logic::stats_me(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &subject,
+        &headmate,
+    )
+    .await?;
+    ctx.reply(stats).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Shows how your archetype scores changed between two results, by default the oldest and newest.
+pub async fn result_history(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "Date of the earlier result"]
+    #[autocomplete = "autocomplete_result_date"]
+    from: Option<String>,
+    #[description = "Date of the later result"]
+    #[autocomplete = "autocomplete_result_date"]
+    to: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Comparing results over time");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let subject = headmate
+        .clone()
+        .unwrap_or_else(|| author_display_name(ctx, &data));
+    let pages = logic::result_history(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &subject,
+        &headmate,
+        from.as_deref(),
+        to.as_deref(),
+    )
+    .await?;
+    send_pages(ctx, pages).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Sums up your (or a headmate's) top archetypes in one line.
+pub async fn my_top_archetypes(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "How many archetypes to show (defaults to 5)"]
+    #[min = 1]
+    #[max = 10]
+    count: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    info!("Summarizing top archetypes");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let subject = format!(
+        "**{}**",
+        headmate
+            .clone()
+            .unwrap_or_else(|| author_display_name(ctx, &data))
+    );
+    let summary = logic::my_top_archetypes(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &subject,
+        &headmate,
+        count.unwrap_or(5).clamp(1, 10),
+    )
+    .await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content(summary)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+// Every argument is a Discord option, there is nothing to bundle.
+#[allow(clippy::too_many_arguments)]
+/// List the compatibility of yourself and everyone else (including headmates).
+pub async fn list_compatibility(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+    #[description = "How to score each match (defaults to the bdsmtest.org score)"] scoring: Option<
+        logic::Scoring,
+    >,
+    #[description = "Compare against other members' headmates (defaults to the server setting)"]
+    include_headmates: Option<bool>,
+    #[description = "List each member's entries together"] group_by_user: Option<bool>,
+    #[description = "Average the scores of your last few results (at most 3)"]
+    #[min = 1]
+    #[max = 3]
+    use_average: Option<usize>,
+    #[description = "Use your result from this date instead of your latest"]
+    #[autocomplete = "autocomplete_result_date"]
+    my_result_date: Option<String>,
+    #[description = "Run in the background and ping you here when it's done, for big servers"]
+    background: Option<bool>,
+    #[description = "Show how old each result is (defaults to the server setting)"]
+    show_age: Option<bool>,
+    #[description = "Only list members or headmates whose name contains this"] filter: Option<
+        String,
+    >,
+    #[description = "Also send the results as a file only you can see"] export: Option<
+        logic::Export,
+    >,
+    #[description = "List entries under a header for each score tier (defaults to the server setting)"]
+    by_tier: Option<bool>,
+    #[description = "Only list matches scoring at least this"] min_score: Option<u32>,
+    #[description = "Only list this many of the best matches"]
+    #[min = 1]
+    top: Option<usize>,
+    #[description = "List entries whose score couldn't be fetched (defaults to only without filters)"]
+    show_errors: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Starting List");
+    let filters = logic::ListOptions {
+        min_score,
+        top,
+        show_errors,
+        ..Default::default()
+    };
+    if let Err(e) = filters.check_filters() {
+        // Nothing was deferred yet, so the error can stay private.
+        ctx.send(
+            poise::CreateReply::default()
+                .content(e.to_string())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    if background.unwrap_or(false) && export.is_some() {
+        // Background output is posted in the channel, where the export wouldn't stay private.
+        ctx.reply("Exports can't be made in the background").await?;
+        return Ok(());
+    }
+    let (subject, options) = {
+        let data = ctx.data().data.read().await;
+        let headmate = logic::resolve_headmate(&data, who, headmate);
+        let subject = headmate
+            .clone()
+            .unwrap_or_else(|| author_display_name(ctx, &data));
+        let options = logic::ListOptions {
+            headmate,
+            scoring: scoring.unwrap_or_default(),
+            include_headmates,
+            group_by_user: group_by_user.unwrap_or(false),
+            use_average,
+            my_result_date,
+            cancel: Default::default(),
+            show_age,
+            now: Utc::now(),
+            filter,
+            by_tier,
+            ..filters
+        };
+        (subject, options)
+    };
+
+    if background.unwrap_or(false) {
+        // Recorded under the lock, so the job can't finish before it is recorded.
+        let mut data = ctx.data().data.write().await;
+        let record = ctx
+            .data()
+            .jobs
+            .enqueue(who, ctx.channel_id(), subject, options, Utc::now());
+        data.pending_jobs.push(record.clone());
+        ctx.data().saves.request();
+        ctx.reply(format!(
+            "Queued as job #{}, I'll ping you here when it's ready. Use /my_jobs to check on it",
+            record.id
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    // Any scan already running for the guild leaves its matches cached for this one.
+    let _scan = ctx.data().scans.enter(who.guild_id).await;
+    let data = ctx.data().data.read().await;
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let scan = logic::list_compatibility(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &subject,
+        &member_names,
+        &options,
+    );
+    let entries = send_scan_pages(ctx, &options.cancel, scan).await?;
+    if let Some(logic::Export::Csv) = export {
+        // Made from the listed entries, so the file matches the list even when it was cancelled.
+        let csv = format::format_compat_csv(&entries);
+        ctx.send(
+            poise::CreateReply::default()
+                .ephemeral(true)
+                .attachment(serenity::CreateAttachment::bytes(csv, "compatibility.csv")),
+        )
+        .await?;
+    }
+
+    info!("List Complete");
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Shows the compatibility scans you queued in the background.
+pub async fn my_jobs(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Listing jobs");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let tz = logic::timezone(&*ctx.data().data.read().await, who);
+    let jobs: Vec<_> = ctx
+        .data()
+        .jobs
+        .list(who)
+        .into_iter()
+        .map(|(record, status)| {
+            let status = match status {
+                jobs::JobStatus::Running => "running".to_string(),
+                jobs::JobStatus::Queued(0) => "up next".to_string(),
+                jobs::JobStatus::Queued(ahead) => format!("{ahead} jobs ahead"),
+            };
+            format!(
+                "- #{} queued {}, {status}",
+                record.id,
+                format::format_timestamp(&record.queued_at, tz)
+            )
+        })
+        .collect();
+
+    ctx.reply(if jobs.is_empty() {
+        "You have no queued jobs".to_string()
+    } else {
+        format!("Your jobs:\n{}", jobs.join("\n"))
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Cancels a compatibility scan you queued in the background, even if it already started.
+pub async fn cancel_job(
+    ctx: Context<'_>,
+    #[description = "The job number from /my_jobs"] job: u64,
+) -> Result<(), anyhow::Error> {
+    info!("Cancelling job");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let reply = match ctx.data().jobs.cancel(who, job) {
+        Some(record) => {
+            let mut data = ctx.data().data.write().await;
+            data.pending_jobs.retain(|r| r.id != record.id);
+            ctx.data().saves.request();
+            format!("Job #{job} cancelled")
+        }
+        None => format!("You have no job #{job}"),
+    };
+    ctx.reply(reply).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true)]
+/// Shows how quickly each of the bot's gateway shards is responding.
+pub async fn ping(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Checking shard latency");
+
+    let mut shards: Vec<_> = ctx
+        .framework()
+        .shard_manager()
+        .runners
+        .lock()
+        .await
+        .iter()
+        .map(|(id, runner)| (id.0, runner.latency))
+        .collect();
+    shards.sort();
+    ctx.reply(format::format_shard_latencies(&shards)).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Shows the server's average score for each archetype.
+pub async fn server_stats(
+    ctx: Context<'_>,
+    #[description = "Include the archetypes this server hides"] show_all: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Computing server stats");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let stats =
+        logic::server_stats(&data, &ctx.data().cache, who, show_all.unwrap_or(false)).await?;
+    ctx.reply(stats).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Ranks everyone in the server by how close their score for one archetype is to yours.
+pub async fn similar_on(
+    ctx: Context<'_>,
+    #[description = "Archetype to compare"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: String,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Ranking by similarity");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let pages = logic::similar_on(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &archetype,
+        &headmate,
+        &member_names,
+    )
+    .await?;
+    send_pages(ctx, pages).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Shows which members have a headmate with the given name.
+pub async fn whois_headmate(
+    ctx: Context<'_>,
+    #[description = "Headmate Name"] name: String,
+) -> Result<(), anyhow::Error> {
+    info!("Looking up headmate owners");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let owners = logic::whois_headmate(&data, who, &name, &member_names);
+    send_pages(ctx, vec![owners]).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Lists your primary entry and every headmate, with how many results each has.
+pub async fn list_headmates(
+    ctx: Context<'_>,
+    #[description = "Whose entries to list, for members who can manage the server"] user: Option<
+        serenity::User,
+    >,
+) -> Result<(), anyhow::Error> {
+    info!("Listing headmates");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    if user.as_ref().is_some_and(|u| u.id != who.user_id) {
+        let admin = ctx
+            .author_member()
+            .await
+            .and_then(|m| m.permissions)
+            .is_some_and(|p| p.manage_guild());
+        if !admin {
+            return Err(logic::CommandError::NotAdmin.into());
+        }
+    }
+    let data = ctx.data().data.read().await;
+    let (user_id, subject) = match user {
+        Some(user) if user.id != who.user_id => {
+            let display_name = data
+                .guild(who.guild_id)
+                .and_then(|g| g.users.get(&user.id)?.display_name.as_deref());
+            let name = member_name(ctx, who.guild_id, user.id, display_name).await;
+            (user.id, name)
+        }
+        _ => (
+            who.user_id,
+            format!("**{}**", author_display_name(ctx, &data)),
+        ),
+    };
+    let pages = logic::list_headmates(&data, who, user_id, &subject)?;
+    send_pages(ctx, pages).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, guild_only = true)]
+/// Ranks everyone in the server by their score for a single archetype.
+pub async fn top_archetype(
+    ctx: Context<'_>,
+    #[description = "Archetype to rank by"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: String,
+    #[description = "Include the archetypes this server hides"] show_all: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Ranking archetype");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let pages = logic::top_archetype(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        &archetype,
+        show_all.unwrap_or(false),
+        &member_names,
+    )
+    .await?;
+    send_pages(ctx, pages).await?;
+
+    Ok(())
+}
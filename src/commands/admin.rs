@@ -0,0 +1,1158 @@
+use std::collections::BTreeMap;
+
+use chrono::{Utc, Weekday};
+use poise::{
+    serenity_prelude::{self as serenity, futures::StreamExt as _},
+    ChoiceParameter as _,
+};
+use tracing::{info, instrument, warn};
+
+use super::{
+    autocomplete_archetype, confirm, ensure_human, invoker, member_names, parse_id, send_pages,
+    MEMBER_LOOKUPS,
+};
+use crate::{
+    board,
+    data::{BoardConfig, DigestConfig, PowerCoupleConfig, DEFAULT_GUEST_HOURS},
+    format, logic, Context,
+};
+
+#[derive(Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Monday => Weekday::Mon,
+            Day::Tuesday => Weekday::Tue,
+            Day::Wednesday => Weekday::Wed,
+            Day::Thursday => Weekday::Thu,
+            Day::Friday => Weekday::Fri,
+            Day::Saturday => Weekday::Sat,
+            Day::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Posts a weekly summary of registry activity and the top pairings in a channel.
+pub async fn enable_digest(
+    ctx: Context<'_>,
+    #[description = "Channel to post the digest in"]
+    #[channel_types("Text")]
+    channel: serenity::ChannelId,
+    #[description = "Day of the week to post on"] day: Day,
+    #[description = "Hour of the day to post at (UTC)"]
+    #[min = 0]
+    #[max = 23]
+    hour: u32,
+) -> Result<(), anyhow::Error> {
+    info!("Enabling weekly digest");
+    ctx.defer_ephemeral().await?;
+
+    if hour > 23 {
+        return Err(logic::CommandError::OutOfRange("hour", 0, 23).into());
+    }
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id).config.digest = Some(DigestConfig {
+        channel,
+        weekday: day.into(),
+        hour,
+        last_posted: Some(Utc::now()),
+    });
+    ctx.data().saves.request();
+
+    ctx.reply(format!(
+        "The weekly digest will be posted in <#{channel}> every {} at {hour:02}:00 UTC",
+        day.name()
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Stops posting the weekly digest.
+pub async fn disable_digest(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Disabling weekly digest");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id)
+        .config
+        .digest
+        .take()
+        .ok_or(logic::CommandError::DigestDisabled)?;
+    ctx.data().saves.request();
+
+    ctx.reply("The weekly digest has been disabled").await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Announces members adding their first result in a channel. Leave the channel empty to stop.
+pub async fn set_announcement_channel(
+    ctx: Context<'_>,
+    #[description = "Channel to post announcements in"]
+    #[channel_types("Text")]
+    channel: Option<serenity::ChannelId>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting announcement channel");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id).config.announce_channel = channel;
+    ctx.data().saves.request();
+
+    ctx.reply(match channel {
+        Some(channel) => format!("New registrations will be announced in <#{channel}>"),
+        None => "New registrations will no longer be announced".to_string(),
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Posts a board of registered members that is kept up to date. Replaces any existing board.
+pub async fn create_board(
+    ctx: Context<'_>,
+    #[description = "Channel to post the board in"]
+    #[channel_types("Text")]
+    channel: serenity::ChannelId,
+    #[description = "Also show the server's top pairings (defaults to false)"]
+    show_pairings: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Creating compatibility board");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let show_pairings = show_pairings.unwrap_or(false);
+    let content = board::render(
+        ctx.serenity_context(),
+        ctx.data(),
+        who.guild_id,
+        show_pairings,
+    )
+    .await;
+    let message = channel
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .content(content)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await?;
+    let pinned = match message.pin(ctx).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Could not pin the compatibility board: {e}");
+            false
+        }
+    };
+
+    let previous = {
+        let mut data = ctx.data().data.write().await;
+        let previous = data
+            .guild_mut(who.guild_id)
+            .config
+            .board
+            .replace(BoardConfig {
+                channel,
+                message: message.id,
+                show_pairings,
+            });
+        ctx.data().saves.request();
+        previous
+    };
+    if let Some(previous) = previous {
+        let _ = previous.channel.delete_message(ctx, previous.message).await;
+    }
+
+    ctx.reply(if pinned {
+        format!("The compatibility board has been posted and pinned in <#{channel}>")
+    } else {
+        format!("The compatibility board has been posted in <#{channel}>, but could not be pinned")
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Stops updating the compatibility board and deletes it.
+pub async fn remove_board(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Removing compatibility board");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let board = {
+        let mut data = ctx.data().data.write().await;
+        let board = data
+            .guild_mut(who.guild_id)
+            .config
+            .board
+            .take()
+            .ok_or(logic::CommandError::NoBoard)?;
+        ctx.data().saves.request();
+        board
+    };
+    // The message may already be gone, which is fine.
+    let _ = board.channel.delete_message(ctx, board.message).await;
+
+    ctx.reply("The compatibility board has been removed")
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Sets where the bot reports problems admins need to fix. Leave the channel empty to stop.
+pub async fn set_audit_channel(
+    ctx: Context<'_>,
+    #[description = "Channel to report problems in"]
+    #[channel_types("Text")]
+    channel: Option<serenity::ChannelId>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting audit channel");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id).config.audit_channel = channel;
+    ctx.data().saves.request();
+
+    ctx.reply(match channel {
+        Some(channel) => format!("Problems will be reported in <#{channel}>"),
+        None => "Problems will no longer be reported".to_string(),
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Grants a role to members with a very high match. Leave the role empty to stop.
+pub async fn set_power_couple_role(
+    ctx: Context<'_>,
+    #[description = "Role to grant"] role: Option<serenity::RoleId>,
+    #[description = "Lowest qualifying score (defaults to 95)"]
+    #[min = 1]
+    #[max = 100]
+    threshold: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting power couple role");
+    ctx.defer_ephemeral().await?;
+
+    let threshold = threshold.unwrap_or(95);
+    if !(1..=100).contains(&threshold) {
+        return Err(logic::CommandError::OutOfRange("threshold", 1, 100).into());
+    }
+
+    let who = invoker(ctx)?;
+    let previous = {
+        let mut data = ctx.data().data.write().await;
+        let config = &mut data.guild_mut(who.guild_id).config.power_couple;
+        let previous = config.take();
+        *config = role.map(|role| PowerCoupleConfig {
+            role,
+            threshold,
+            // Keep track of who already has the role so it can still be taken away.
+            holders: previous
+                .as_ref()
+                .filter(|p| p.role == role)
+                .map(|p| p.holders.clone())
+                .unwrap_or_default(),
+            forbidden: false,
+        });
+        ctx.data().saves.request();
+        previous
+    };
+    if let Some(previous) = previous.filter(|p| Some(p.role) != role) {
+        for user_id in previous.holders {
+            let _ = ctx
+                .http()
+                .remove_member_role(who.guild_id, user_id, previous.role, None)
+                .await;
+        }
+    }
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(match role {
+        Some(role) => format!("Members with a match of {threshold}% or more will get <@&{role}>"),
+        None => "The power couple role has been turned off".to_string(),
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Sets how long temporary results are kept. Leave the hours empty for the default.
+pub async fn set_guest_duration(
+    ctx: Context<'_>,
+    #[description = "Hours to keep temporary results for (defaults to 48)"]
+    #[min = 1]
+    #[max = 720]
+    hours: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting guest duration");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id).config.guest_hours = hours;
+    ctx.data().saves.request();
+
+    ctx.reply(format!(
+        "Temporary results will be kept for {} hours",
+        hours.unwrap_or(DEFAULT_GUEST_HOURS)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Asks for the guild's name before /wipe_guild deletes anything.
+#[derive(Debug, poise::Modal)]
+#[name = "Wipe this server's data"]
+struct WipeConfirmation {
+    #[name = "Type the server's name to confirm"]
+    guild_name: String,
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "ADMINISTRATOR",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+/// Deletes everything the bot stores for this server, after you confirm with the server's name.
+pub async fn wipe_guild(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Starting guild wipe");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let guild_name = ctx
+        .guild()
+        .map(|g| g.name.clone())
+        .ok_or_else(|| anyhow::anyhow!("Guild not cached"))?;
+    let (users, results) = logic::guild_summary(&*ctx.data().data.read().await, who);
+    if users == 0 {
+        ctx.reply("Nothing is stored for this server").await?;
+        return Ok(());
+    }
+
+    let wipe_id = format!("{}-wipe", ctx.id());
+    let summary = format!(
+        "This deletes {results} results of {users} members, and every setting of this server. \
+         It can't be undone"
+    );
+    let reply = ctx
+        .send(
+            poise::CreateReply::default()
+                .content(&summary)
+                .components(vec![serenity::CreateActionRow::Buttons(vec![
+                    serenity::CreateButton::new(&wipe_id)
+                        .label("Wipe")
+                        .style(serenity::ButtonStyle::Danger),
+                ])]),
+        )
+        .await?;
+    let pressed = serenity::ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .custom_ids(vec![wipe_id])
+        .timeout(std::time::Duration::from_secs(60))
+        .await;
+    reply
+        .edit(
+            ctx,
+            poise::CreateReply::default()
+                .content(summary)
+                .components(vec![]),
+        )
+        .await?;
+    let Some(mci) = pressed else {
+        ctx.reply("Wipe cancelled, nothing was deleted").await?;
+        return Ok(());
+    };
+    let confirmation = poise::execute_modal_on_component_interaction::<WipeConfirmation>(
+        ctx,
+        mci,
+        None,
+        Some(std::time::Duration::from_secs(120)),
+    )
+    .await?;
+    if confirmation.is_none_or(|c| c.guild_name.trim() != guild_name) {
+        ctx.reply("The name didn't match, nothing was deleted")
+            .await?;
+        return Ok(());
+    }
+
+    let wiped = {
+        let mut data = ctx.data().data.write().await;
+        let wiped = logic::wipe_guild(&mut data, &mut *ctx.data().cache.lock().await, who);
+        ctx.data().saves.request();
+        wiped
+    };
+    info!("Wiped guild");
+    let audit = format!(
+        "<@{}> deleted all {results} results of {users} members stored for this server",
+        who.user_id
+    );
+    let channel = wiped
+        .and_then(|g| g.config.audit_channel)
+        .unwrap_or(ctx.channel_id());
+    if let Err(e) = channel
+        .send_message(
+            ctx,
+            serenity::CreateMessage::new()
+                .content(audit)
+                .allowed_mentions(serenity::CreateAllowedMentions::new()),
+        )
+        .await
+    {
+        warn!("Could not post wipe audit message: {e:#}");
+    }
+    ctx.reply("Everything stored for this server was deleted")
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Moves a member's results to their new account, merging them with any it already has.
+pub async fn transfer_user_data(
+    ctx: Context<'_>,
+    #[description = "ID of the old account, which may no longer exist"] old_user_id: String,
+    #[description = "The new account"] new_user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    info!("Transferring user data");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let old = serenity::UserId::new(parse_id("user", &old_user_id)?);
+    ensure_human(&new_user)?;
+    let plan = logic::plan_transfer(&*ctx.data().data.read().await, who, old, new_user.id)?;
+    let mut prompt = format!(
+        "This moves {} results of <@{old}> ({} headmates) to <@{}>",
+        plan.results, plan.headmates, new_user.id
+    );
+    for (from, to) in &plan.renamed {
+        prompt += &format!("\n- {from} will be renamed to {to}");
+    }
+    if !confirm(ctx, prompt).await? {
+        ctx.reply("Transfer cancelled, nothing was moved").await?;
+        return Ok(());
+    }
+
+    let (transfer, audit_channel) = {
+        let mut data = ctx.data().data.write().await;
+        let transfer = logic::transfer_user_data(&mut data, who, old, new_user.id)?;
+        ctx.data().saves.request();
+        let audit_channel = data
+            .guild(who.guild_id)
+            .and_then(|g| g.config.audit_channel);
+        (transfer, audit_channel)
+    };
+    ctx.data().refresh.request(who.guild_id);
+    info!(results = transfer.results, "Transferred user data");
+    if let Some(channel) = audit_channel {
+        let audit = format!(
+            "<@{}> moved {} results from <@{old}> to <@{}>",
+            who.user_id, transfer.results, new_user.id
+        );
+        if let Err(e) = channel
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new()
+                    .content(audit)
+                    .allowed_mentions(serenity::CreateAllowedMentions::new()),
+            )
+            .await
+        {
+            warn!("Could not post transfer audit message: {e:#}");
+        }
+    }
+    ctx.reply(format!(
+        "Moved {} results to <@{}>",
+        transfer.results, new_user.id
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Deletes everything stored for one user, who may no longer be in the server.
+pub async fn admin_remove_user(
+    ctx: Context<'_>,
+    #[description = "ID of the user to remove"] user_id: String,
+) -> Result<(), anyhow::Error> {
+    info!("Removing a user");
+    let who = invoker(ctx)?;
+    let user_id = serenity::UserId::new(parse_id("user", &user_id)?);
+    let (users, results) = {
+        let mut data = ctx.data().data.write().await;
+        let removed = logic::remove_users(
+            &mut data,
+            &mut *ctx.data().cache.lock().await,
+            who,
+            &[user_id],
+        );
+        if removed.0 > 0 {
+            ctx.data().saves.request();
+        }
+        removed
+    };
+    if users > 0 {
+        ctx.data().refresh.request(who.guild_id);
+    }
+    info!(users, results, "Removed a user");
+    ctx.reply(format!("Deleted {users} users and {results} results"))
+        .await?;
+
+    Ok(())
+}
+
+/// How many departed users the prune prompt names before summing up the rest.
+const MAX_LISTED_DEPARTED: usize = 40;
+
+/// Whether a member lookup failed because the user isn't in the guild, rather than because
+/// Discord could not be asked.
+fn is_unknown_member(e: &serenity::Error) -> bool {
+    matches!(
+        e,
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response))
+            if response.status_code == serenity::StatusCode::NOT_FOUND
+    )
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Deletes everything stored for users who have left the server, after you confirm.
+pub async fn admin_prune_departed(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Looking for departed users");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let stored: Vec<_> = ctx
+        .data()
+        .data
+        .read()
+        .await
+        .guild(who.guild_id)
+        .map(|g| g.users.keys().copied().collect())
+        .unwrap_or_default();
+    let lookups: Vec<_> = stored
+        .into_iter()
+        .map(|user_id| async move { (user_id, who.guild_id.member(ctx, user_id).await) })
+        .collect();
+    let lookups: Vec<_> = serenity::futures::stream::iter(lookups)
+        .buffered(MEMBER_LOOKUPS)
+        .collect()
+        .await;
+    let mut departed = Vec::new();
+    for (user_id, lookup) in lookups {
+        match lookup {
+            Ok(_) => {}
+            Err(e) if is_unknown_member(&e) => departed.push(user_id),
+            // Nobody is pruned on a guess, so the admin can just try again.
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if departed.is_empty() {
+        ctx.reply("Everyone stored for this server is still in it")
+            .await?;
+        return Ok(());
+    }
+
+    let mut prompt = format!(
+        "These {} users have left the server, and everything stored for them will be deleted:",
+        departed.len()
+    );
+    for user_id in departed.iter().take(MAX_LISTED_DEPARTED) {
+        prompt += &format!("\n- <@{user_id}> ({user_id})");
+    }
+    if departed.len() > MAX_LISTED_DEPARTED {
+        prompt += &format!("\n- and {} more", departed.len() - MAX_LISTED_DEPARTED);
+    }
+    if !confirm(ctx, prompt).await? {
+        ctx.reply("Prune cancelled, nothing was deleted").await?;
+        return Ok(());
+    }
+
+    let (users, results) = {
+        let mut data = ctx.data().data.write().await;
+        let removed = logic::remove_users(
+            &mut data,
+            &mut *ctx.data().cache.lock().await,
+            who,
+            &departed,
+        );
+        ctx.data().saves.request();
+        removed
+    };
+    ctx.data().refresh.request(who.guild_id);
+    info!(users, results, "Pruned departed users");
+    ctx.reply(format!("Deleted {users} users and {results} results"))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Sets the defaults list_compatibility uses when members don't choose.
+pub async fn set_list_defaults(
+    ctx: Context<'_>,
+    #[description = "Compare against other members' headmates (defaults to true)"]
+    include_headmates: Option<bool>,
+    #[description = "Show how old each result is (defaults to false)"] show_age: Option<bool>,
+    #[description = "List entries under a header for each score tier (defaults to false)"]
+    by_tier: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting list defaults");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let config = &mut data.guild_mut(who.guild_id).config;
+    config.include_headmates = include_headmates;
+    config.show_age = show_age;
+    config.by_tier = by_tier;
+    ctx.data().saves.request();
+
+    ctx.reply(format!(
+        "{}, {}{}",
+        if include_headmates.unwrap_or(true) {
+            "Lists will include headmates by default"
+        } else {
+            "Lists will only include primary entries by default"
+        },
+        if show_age.unwrap_or(false) {
+            "and show how old each result is"
+        } else {
+            "without showing how old each result is"
+        },
+        if by_tier.unwrap_or(false) {
+            ", split into score tiers"
+        } else {
+            ""
+        }
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Only lists entries that keep participating. Leave both options empty to list everyone.
+pub async fn set_list_requirements(
+    ctx: Context<'_>,
+    #[description = "The fewest results an entry needs"]
+    #[min = 1]
+    min_results: Option<usize>,
+    #[description = "Entries need a result from this date (YYYY-MM-DD, UTC) or later"]
+    results_since: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting list requirements");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_list_requirements(&mut data, who, min_results, results_since.as_deref())?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    let mut requirements = Vec::new();
+    if let Some(min_results) = min_results {
+        requirements.push(format!("at least {min_results} results"));
+    }
+    if let Some(since) = data.guild_mut(who.guild_id).config.results_since {
+        requirements.push(format!(
+            "a result from {} or later",
+            since.format("%Y-%m-%d")
+        ));
+    }
+    ctx.reply(if requirements.is_empty() {
+        "Lists will include every entry".to_string()
+    } else {
+        format!(
+            "Lists will only include entries with {}",
+            requirements.join(" and ")
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Leaves an archetype out of displayed results, stats and leaderboards. Scores are unaffected.
+pub async fn hide_archetype(
+    ctx: Context<'_>,
+    #[description = "Archetype to hide"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: String,
+) -> Result<(), anyhow::Error> {
+    info!("Hiding archetype");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let (archetype, changed) = logic::set_archetype_hidden(&mut data, who, &archetype, true)?;
+    ctx.data().saves.request();
+
+    ctx.reply(if changed {
+        format!("{archetype} will be left out unless members ask for all archetypes")
+    } else {
+        format!("{archetype} was already hidden")
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Shows a hidden archetype in results, stats and leaderboards again.
+pub async fn unhide_archetype(
+    ctx: Context<'_>,
+    #[description = "Archetype to show again"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: String,
+) -> Result<(), anyhow::Error> {
+    info!("Unhiding archetype");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let (archetype, changed) = logic::set_archetype_hidden(&mut data, who, &archetype, false)?;
+    ctx.data().saves.request();
+
+    ctx.reply(if changed {
+        format!("{archetype} will be shown again")
+    } else {
+        format!("{archetype} was not hidden")
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Shows which archetypes this server leaves out of displayed output.
+pub async fn list_hidden_archetypes(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Listing hidden archetypes");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let hidden: Vec<_> = data
+        .guild(who.guild_id)
+        .iter()
+        .flat_map(|g| &g.config.hidden_archetypes)
+        .map(|a| format!("- {a}"))
+        .collect();
+
+    ctx.reply(if hidden.is_empty() {
+        "No archetypes are hidden".to_string()
+    } else {
+        format!("Hidden archetypes:\n{}", hidden.join("\n"))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// What /archetype_alias does.
+#[derive(Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum AliasAction {
+    #[name = "set"]
+    Set,
+    #[name = "remove"]
+    Remove,
+    #[name = "list"]
+    List,
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Shows an archetype under this server's own name for it. Scores and matching are unaffected.
+pub async fn archetype_alias(
+    ctx: Context<'_>,
+    #[description = "Set or remove an alias, or list them"] action: AliasAction,
+    #[description = "Archetype to rename (not needed to list)"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: Option<String>,
+    #[description = "The name to show instead (only needed to set)"] alias: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!(action = action.name(), "Managing archetype aliases");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    if let AliasAction::List = action {
+        let data = ctx.data().data.read().await;
+        let aliases: Vec<_> = data
+            .guild(who.guild_id)
+            .iter()
+            .flat_map(|g| &g.config.archetype_aliases)
+            .map(|(archetype, alias)| format!("- {archetype} is shown as {alias}"))
+            .collect();
+        ctx.reply(if aliases.is_empty() {
+            "No archetypes have aliases".to_string()
+        } else {
+            format!("Archetype aliases:\n{}", aliases.join("\n"))
+        })
+        .await?;
+        return Ok(());
+    }
+
+    let Some(archetype) = archetype else {
+        ctx.reply("Pick the archetype to change").await?;
+        return Ok(());
+    };
+    let alias = match (action, alias) {
+        (AliasAction::Set, None) => {
+            ctx.reply("Give the name to show instead").await?;
+            return Ok(());
+        }
+        (AliasAction::Set, alias) => alias,
+        _ => None,
+    };
+    let mut data = ctx.data().data.write().await;
+    let (archetype, previous) =
+        logic::set_archetype_alias(&mut data, who, &archetype, alias.as_deref())?;
+    ctx.data().saves.request();
+    let alias = data
+        .guild(who.guild_id)
+        .and_then(|g| g.config.archetype_aliases.get(archetype));
+
+    ctx.reply(match (alias, previous) {
+        (Some(alias), _) => format!("{archetype} will be shown as {alias}"),
+        (None, Some(_)) => format!("{archetype} will be shown by its own name again"),
+        (None, None) => format!("{archetype} had no alias"),
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Lets admins list members who haven't added a result with /coverage, or stops it.
+pub async fn allow_coverage(
+    ctx: Context<'_>,
+    #[description = "Whether /coverage may list members"] allowed: bool,
+) -> Result<(), anyhow::Error> {
+    info!(allowed, "Setting coverage reports");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id).config.allow_coverage = allowed;
+    ctx.data().saves.request();
+
+    ctx.reply(if allowed {
+        "Admins can now list members who haven't added a result with /coverage"
+    } else {
+        "/coverage is turned off"
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Lets members share their results publicly in a channel that isn't NSFW, or stops it.
+pub async fn allow_sharing(
+    ctx: Context<'_>,
+    #[description = "Channel to allow sharing in"]
+    #[channel_types("Text")]
+    channel: serenity::ChannelId,
+    #[description = "Whether results may be shared there"] allowed: bool,
+) -> Result<(), anyhow::Error> {
+    info!(allowed, "Setting a share channel");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let channels = &mut data.guild_mut(who.guild_id).config.share_channels;
+    if allowed {
+        channels.insert(channel);
+    } else {
+        channels.remove(&channel);
+    }
+    ctx.data().saves.request();
+
+    ctx.reply(if allowed {
+        format!("Members can now share their results in <#{channel}>")
+    } else {
+        format!("Results can only be shared in <#{channel}> if it is NSFW")
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// How many members are fetched per request, Discord's maximum.
+const MEMBER_CHUNK: u64 = 1000;
+/// Coverage reports longer than this many pages are attached as a file instead.
+const COVERAGE_PAGES: usize = 3;
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    ephemeral = true,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Counts the members who have added a result and lists those who haven't, if allowed here.
+pub async fn coverage(
+    ctx: Context<'_>,
+    #[description = "Only look at members with this role"] role: Option<serenity::Role>,
+) -> Result<(), anyhow::Error> {
+    info!("Reporting coverage");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    // Checked before fetching anything, since member lists can be long.
+    if !ctx
+        .data()
+        .data
+        .read()
+        .await
+        .guild(who.guild_id)
+        .is_some_and(|g| g.config.allow_coverage)
+    {
+        return Err(logic::CommandError::CoverageDisabled.into());
+    }
+
+    let mut names = BTreeMap::new();
+    let mut after = None;
+    loop {
+        let chunk = who
+            .guild_id
+            .members(ctx.http(), Some(MEMBER_CHUNK), after)
+            .await?;
+        after = chunk.last().map(|m| m.user.id);
+        let done = chunk.len() < MEMBER_CHUNK as usize;
+        for member in chunk {
+            if member.user.bot || role.as_ref().is_some_and(|r| !member.roles.contains(&r.id)) {
+                continue;
+            }
+            names.insert(member.user.id, member.display_name().to_string());
+        }
+        if done {
+            break;
+        }
+    }
+
+    let coverage = logic::coverage(&*ctx.data().data.read().await, who, names.keys().copied())?;
+    let scope = match &role {
+        Some(role) => format!("members with {}", role.name),
+        None => "members".to_string(),
+    };
+    let pages = format::format_coverage(&coverage, &scope);
+    if pages.len() <= COVERAGE_PAGES {
+        return send_pages(ctx, pages).await;
+    }
+    let list: String = coverage
+        .unregistered
+        .iter()
+        .map(|id| format!("{id}\t{}\n", names[id]))
+        .collect();
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "{}The members who haven't are attached",
+                format::format_coverage_counts(&coverage, &scope)
+            ))
+            .attachment(serenity::CreateAttachment::bytes(list, "unregistered.tsv")),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(
+    slash_command,
+    guild_only = true,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Posts the most compatible pairs of members in the server.
+pub async fn compatibility_leaderboard(
+    ctx: Context<'_>,
+    #[description = "How many pairs to show (defaults to 10)"]
+    #[min = 1]
+    #[max = 50]
+    count: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    info!("Ranking pairs");
+    ctx.defer().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let member_names = member_names(ctx, &data, who.guild_id).await?;
+    let pages = logic::leaderboard(
+        &data,
+        &ctx.data().api,
+        &ctx.data().cache,
+        who,
+        count.unwrap_or(10),
+        &member_names,
+    )
+    .await?;
+    drop(data);
+    send_pages(ctx, pages).await?;
+
+    Ok(())
+}
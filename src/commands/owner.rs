@@ -0,0 +1,236 @@
+use poise::serenity_prelude as serenity;
+use tracing::{info, instrument};
+
+use super::{confirm, ensure_human, parse_id};
+use crate::{
+    backup::{self, RestoreTarget},
+    data::BACKUP_DIR,
+    format,
+    logic::{self, Invoker},
+    Context,
+};
+
+#[instrument(skip(ctx), err, fields(user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, owners_only = true)]
+/// Looks up a result on bdsmtest.org, skipping the cache, and shows exactly what came back.
+pub async fn debug_result(
+    ctx: Context<'_>,
+    #[description = "The result ID to look up"] result_id: String,
+    #[description = "Send it as a DM instead (defaults to false)"] dm: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Debugging result lookup");
+    ctx.defer_ephemeral().await?;
+
+    let result_id = result_id.trim();
+    let raw = ctx.data().api.get_result_raw(result_id).await?;
+    let (message, body) = format::format_raw_result(result_id, &raw);
+    let attachment =
+        body.map(|body| serenity::CreateAttachment::bytes(body, format!("{result_id}.json")));
+    if dm.unwrap_or(false) {
+        let mut dm = serenity::CreateMessage::new().content(message);
+        if let Some(attachment) = attachment {
+            dm = dm.add_file(attachment);
+        }
+        ctx.author().direct_message(ctx, dm).await?;
+        ctx.reply("Sent you the response in a DM").await?;
+    } else {
+        let mut reply = poise::CreateReply::default().content(message);
+        if let Some(attachment) = attachment {
+            reply = reply.attachment(attachment);
+        }
+        ctx.send(reply).await?;
+    }
+
+    Ok(())
+}
+
+/// What /rebuild_cache drops.
+#[derive(Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum CacheRebuild {
+    /// Every cached result and match score.
+    #[name = "clear"]
+    Clear,
+    /// Only what involves a result no guild stores anymore.
+    #[name = "prune"]
+    Prune,
+}
+
+#[instrument(skip(ctx), err, fields(user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, owners_only = true)]
+/// Drops cached results and match scores, for after a restore or a fix to bad data.
+pub async fn rebuild_cache(
+    ctx: Context<'_>,
+    #[description = "Drop everything, or only what no stored result uses"] mode: CacheRebuild,
+) -> Result<(), anyhow::Error> {
+    info!(?mode, "Rebuilding cache");
+    ctx.defer_ephemeral().await?;
+
+    let (matches, results) = match mode {
+        CacheRebuild::Clear => ctx.data().cache.lock().await.clear(),
+        CacheRebuild::Prune => {
+            // Collected first, so the registry isn't locked while the cache is.
+            let stored = ctx.data().data.read().await.result_ids();
+            ctx.data()
+                .cache
+                .lock()
+                .await
+                .prune(|id| stored.contains(id))
+        }
+    };
+    info!(matches, results, "Rebuilt cache");
+
+    ctx.reply(format!(
+        "Dropped {matches} match score(s) and {results} result(s) from the cache"
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, owners_only = true)]
+/// Restores one user's (or headmate's) results from the most recent backup that has them.
+pub async fn restore_user_data(
+    ctx: Context<'_>,
+    #[description = "ID of the guild the data belongs to"] guild_id: String,
+    #[description = "ID of the user whose data should be restored"] user_id: String,
+    #[description = "Headmate Name"] headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Searching backups");
+    ctx.defer_ephemeral().await?;
+
+    let target = RestoreTarget {
+        guild_id: serenity::GuildId::new(parse_id("guild", &guild_id)?),
+        user_id: serenity::UserId::new(parse_id("user", &user_id)?),
+        headmate,
+    };
+
+    let user = target.user_id.to_user(ctx).await?;
+    ensure_human(&user)?;
+
+    let found = {
+        let target = target.clone();
+        let backups = ctx.data().config.data_dir.join(BACKUP_DIR);
+        tokio::task::spawn_blocking(move || {
+            backup::snapshots(backups).map(|snapshots| backup::find(&snapshots, &target))
+        })
+        .await??
+    };
+    let Some(found) = found else {
+        ctx.reply("No backup snapshot contains results for that entry")
+            .await?;
+        return Ok(());
+    };
+
+    let (description, missing) = {
+        let data = ctx.data().data.read().await;
+        let live = data
+            .guild(target.guild_id)
+            .and_then(|g| g.users.get(&target.user_id))
+            .and_then(|u| u.headmate(&target.headmate));
+        let tz = ctx
+            .guild_id()
+            .map(|guild_id| {
+                let who = Invoker {
+                    guild_id,
+                    user_id: ctx.author().id,
+                };
+                logic::timezone(&data, who)
+            })
+            .unwrap_or_default();
+        (
+            backup::describe(&found, live, tz),
+            backup::missing(live, &found.data).len(),
+        )
+    };
+    if missing == 0 {
+        ctx.reply(description).await?;
+        return Ok(());
+    }
+
+    if !confirm(ctx, description).await? {
+        ctx.reply("Restore cancelled").await?;
+        return Ok(());
+    }
+
+    let mut data = ctx.data().data.write().await;
+    let restored = backup::merge(
+        data.guild_mut(target.guild_id)
+            .users
+            .entry(target.user_id)
+            .or_default()
+            .headmate_mut(&target.headmate),
+        &found.data,
+    );
+    ctx.data().saves.request();
+    ctx.data().refresh.request(target.guild_id);
+    info!(restored, "Restored results from backup");
+
+    ctx.reply(format!("Restored {restored} result(s)")).await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, owners_only = true)]
+/// Merges one guild's registry into another's, for servers that were combined.
+pub async fn merge_guilds(
+    ctx: Context<'_>,
+    #[description = "ID of the guild to merge, which is removed afterwards"]
+    source_guild_id: String,
+    #[description = "ID of the guild to merge into"] destination_guild_id: String,
+    #[description = "Only report what would happen (defaults to false)"] dry_run: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    info!("Planning guild merge");
+    ctx.defer_ephemeral().await?;
+
+    let source = serenity::GuildId::new(parse_id("guild", &source_guild_id)?);
+    let destination = serenity::GuildId::new(parse_id("guild", &destination_guild_id)?);
+    let plan = logic::plan_guild_merge(&*ctx.data().data.read().await, source, destination)?;
+    let mut description = format!(
+        "Merging guild {source} into {destination} moves {} results: {} members move as they \
+         are and {} are in both guilds",
+        plan.results,
+        plan.moved,
+        plan.shared.len()
+    );
+    for user_id in &plan.shared {
+        description += &format!("\n- <@{user_id}>");
+    }
+    if plan.duplicates > 0 {
+        description += &format!(
+            "\n{} results are already in {destination} and are skipped",
+            plan.duplicates
+        );
+    }
+    if plan.nudged > 0 {
+        description += &format!(
+            "\n{} results share a timestamp with one in {destination} and are moved a few \
+             seconds later",
+            plan.nudged
+        );
+    }
+    if dry_run.unwrap_or(false) {
+        ctx.reply(description).await?;
+        return Ok(());
+    }
+    if !confirm(ctx, description).await? {
+        ctx.reply("Merge cancelled").await?;
+        return Ok(());
+    }
+
+    let mut data = ctx.data().data.write().await;
+    let merge = logic::merge_guilds(&mut data, source, destination)?;
+    ctx.data().saves.request();
+    drop(data);
+    ctx.data().refresh.request(destination);
+    info!(results = merge.results, "Merged guilds");
+
+    ctx.reply(format!(
+        "Merged {} results into {destination}, guild {source} is gone",
+        merge.results
+    ))
+    .await?;
+
+    Ok(())
+}
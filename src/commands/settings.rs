@@ -0,0 +1,444 @@
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use tracing::{info, instrument};
+
+use super::{autocomplete_archetype, autocomplete_headmate, autocomplete_result_date, invoker};
+use crate::{
+    data::Visibility,
+    format::format_timestamp,
+    logic::{self, MAX_CHOICES},
+    scoring::DEFAULT_WEIGHT,
+    Context,
+};
+
+async fn autocomplete_timezone(_ctx: Context<'_>, partial: &str) -> Vec<&'static str> {
+    let partial = partial.trim().to_lowercase();
+    chrono_tz::TZ_VARIANTS
+        .iter()
+        .map(|tz| tz.name())
+        .filter(|name| name.to_lowercase().contains(&partial))
+        .take(MAX_CHOICES)
+        .collect()
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Chooses whether your first result in this server is announced in its announcement channel.
+pub async fn set_announcement_preference(
+    ctx: Context<'_>,
+    #[description = "Announce your first registration"] announce: bool,
+) -> Result<(), anyhow::Error> {
+    info!("Setting announcement preference");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .suppress_announcements = !announce;
+    ctx.data().saves.request();
+
+    ctx.reply(if announce {
+        "Your first registration will be announced"
+    } else {
+        "Your registration will not be announced"
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Hides you (or one of your headmates) from everyone else's listings. You still see yourself.
+pub async fn set_visibility(
+    ctx: Context<'_>,
+    #[description = "Show up in other members' listings"] visible: bool,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting visibility");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let visibility = if visible {
+        Visibility::Visible
+    } else {
+        Visibility::HiddenFromOthers
+    };
+    let mut data = ctx.data().data.write().await;
+    logic::set_visibility(&mut data, who, &headmate, visibility)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    let whom = headmate.as_deref().unwrap_or("You");
+    ctx.reply(if visible {
+        format!("{whom} will show up in other members' listings")
+    } else {
+        format!("{whom} will be hidden from other members' listings")
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Hides one of your results from everyone else, who get compared against an older one instead.
+pub async fn set_result_visibility(
+    ctx: Context<'_>,
+    #[description = "Let other members compare against this result"] visible: bool,
+    #[description = "Date of the result"]
+    #[autocomplete = "autocomplete_result_date"]
+    date: String,
+    #[description = "Headmate Name"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting result visibility");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let visibility = if visible {
+        Visibility::Visible
+    } else {
+        Visibility::HiddenFromOthers
+    };
+    let mut data = ctx.data().data.write().await;
+    let headmate = logic::resolve_headmate(&data, who, headmate);
+    let id = logic::set_result_visibility(&mut data, who, &headmate, &date, visibility)?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    ctx.reply(if visible {
+        format!("Other members can compare against result {id} again")
+    } else {
+        format!("Result {id} is hidden from other members")
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Sets how much an archetype counts when listing compatibility with custom scoring.
+pub async fn set_weights(
+    ctx: Context<'_>,
+    #[description = "Archetype to weigh"]
+    #[autocomplete = "autocomplete_archetype"]
+    archetype: String,
+    #[description = "How much it counts (0 ignores it, 1 is the default)"]
+    #[min = 0]
+    #[max = 10]
+    weight: f64,
+) -> Result<(), anyhow::Error> {
+    info!("Setting archetype weight");
+    ctx.defer_ephemeral().await?;
+
+    if !(0.0..=10.0).contains(&weight) {
+        return Err(logic::CommandError::OutOfRange("weight", 0, 10).into());
+    }
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let weights = logic::set_weight(&mut data, who, &archetype, weight)?;
+    ctx.data().saves.request();
+
+    let weights: Vec<_> = weights.iter().map(|(a, w)| format!("{a} ×{w}")).collect();
+    ctx.reply(if weights.is_empty() {
+        "All archetypes count equally".to_string()
+    } else {
+        format!(
+            "Your weights: {} (everything else ×{DEFAULT_WEIGHT})",
+            weights.join(", ")
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Leaves a member out of your own compatibility listings. They aren't told and see no difference.
+pub async fn ignore_user(
+    ctx: Context<'_>,
+    #[description = "Member to leave out"] user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    info!("Ignoring user");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let changed = logic::set_ignored(&mut data, who, user.id, true);
+    ctx.data().saves.request();
+
+    ctx.reply(if changed {
+        format!("<@{}> will be left out of your listings", user.id)
+    } else {
+        format!("<@{}> is already ignored", user.id)
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Shows a member in your compatibility listings again.
+pub async fn unignore_user(
+    ctx: Context<'_>,
+    #[description = "Member to show again"] user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    info!("Unignoring user");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let changed = logic::set_ignored(&mut data, who, user.id, false);
+    ctx.data().saves.request();
+
+    ctx.reply(if changed {
+        format!("<@{}> will show up in your listings again", user.id)
+    } else {
+        format!("<@{}> was not ignored", user.id)
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Shows who you are leaving out of your compatibility listings.
+pub async fn list_ignored(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    info!("Listing ignored users");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let data = ctx.data().data.read().await;
+    let ignored: Vec<_> = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .iter()
+        .flat_map(|u| &u.ignored)
+        .map(|id| format!("- <@{id}>"))
+        .collect();
+
+    ctx.reply(if ignored.is_empty() {
+        "You are not ignoring anyone".to_string()
+    } else {
+        format!("You are ignoring:\n{}", ignored.join("\n"))
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Sets the name the bot shows for you in this server. Leave it empty to use your nickname again.
+pub async fn set_display_name(
+    ctx: Context<'_>,
+    #[description = "Name to show (at most 32 characters)"] name: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting display name");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let name = logic::set_display_name(&mut data, who, name.as_deref().unwrap_or_default())?;
+    ctx.data().saves.request();
+    ctx.data().refresh.request(who.guild_id);
+
+    let Some(name) = name else {
+        ctx.reply("The bot will show your server nickname again")
+            .await?;
+        return Ok(());
+    };
+    let headmates: Vec<_> = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .iter()
+        .flat_map(|u| u.headmates.keys())
+        .map(|h| format!("**{name}** ({h})"))
+        .collect();
+    let mut reply = format!("Your entries will show up as:\n- **{name}**\n");
+    for headmate in headmates {
+        reply += &format!("- {headmate}\n");
+    }
+    ctx.send(
+        poise::CreateReply::default()
+            .content(reply)
+            .allowed_mentions(serenity::CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Sets the timezone the bot shows dates in for you.
+pub async fn set_timezone(
+    ctx: Context<'_>,
+    #[description = "Timezone name, like America/New_York"]
+    #[autocomplete = "autocomplete_timezone"]
+    timezone: String,
+) -> Result<(), anyhow::Error> {
+    info!("Setting timezone");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    let tz = logic::set_timezone(&mut data, who, &timezone)?;
+    ctx.data().saves.request();
+
+    ctx.reply(format!(
+        "Dates will be shown in {tz}, it is currently {}",
+        format_timestamp(&Utc::now(), tz)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Lets other members use /compatibility_between to compare you with someone else.
+pub async fn set_third_party_comparisons(
+    ctx: Context<'_>,
+    #[description = "Allow other members to compare you with someone else"] allow: bool,
+) -> Result<(), anyhow::Error> {
+    info!("Setting third party comparisons");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_third_party(&mut data, who, allow);
+    ctx.data().saves.request();
+
+    ctx.reply(if allow {
+        "Other members can now compare you with someone else"
+    } else {
+        "Only you can compare yourself with others"
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Lets other members use /compat_explain to see which archetypes drive their match with you.
+pub async fn set_explain_consent(
+    ctx: Context<'_>,
+    #[description = "Allow other members to see what drives their match with you"] allow: bool,
+) -> Result<(), anyhow::Error> {
+    info!("Setting explain consent");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_allow_explain(&mut data, who, allow);
+    ctx.data().saves.request();
+
+    ctx.reply(if allow {
+        "Other members can now see which archetypes drive their match with you"
+    } else {
+        "Other members can no longer see which archetypes drive their match with you"
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// DMs you when someone adds a result that matches you this well. Leave it empty to stop.
+pub async fn match_alerts(
+    ctx: Context<'_>,
+    #[description = "Lowest match percentage to be told about"]
+    #[min = 1]
+    #[max = 100]
+    threshold: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting match alerts");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_match_alert(&mut data, who, threshold);
+    ctx.data().saves.request();
+
+    ctx.reply(match threshold {
+        Some(threshold) => {
+            format!(
+                "I'll DM you when someone adds a result that matches you at {threshold}% or more"
+            )
+        }
+        None => "Match alerts are off".to_string(),
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Chooses whether /show_result includes the gender you took the test as.
+pub async fn set_gender_display(
+    ctx: Context<'_>,
+    #[description = "Show the gender you took the test as"] show: bool,
+) -> Result<(), anyhow::Error> {
+    info!("Setting gender display");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_show_gender(&mut data, who, show);
+    ctx.data().saves.request();
+
+    ctx.reply(if show {
+        "Your results will show the gender you took the test as"
+    } else {
+        "Your results will no longer show the gender you took the test as"
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
+#[poise::command(slash_command, ephemeral = true, guild_only = true)]
+/// Sets the headmate your commands act as when you don't name one. Leave it out to clear it.
+pub async fn set_default_headmate(
+    ctx: Context<'_>,
+    #[description = "Headmate Name (\"primary\" can still be given to act as yourself)"]
+    #[autocomplete = "autocomplete_headmate"]
+    headmate: Option<String>,
+) -> Result<(), anyhow::Error> {
+    info!("Setting default headmate");
+    ctx.defer_ephemeral().await?;
+
+    let who = invoker(ctx)?;
+    let mut data = ctx.data().data.write().await;
+    logic::set_default_headmate(&mut data, who, headmate)?;
+    let reply = match logic::resolve_headmate(&data, who, None) {
+        Some(headmate) => format!(
+            "Your commands will act as {headmate} unless you name another headmate, or \
+             \"{}\" for yourself",
+            logic::PRIMARY_HEADMATE
+        ),
+        None => "Your commands will act as yourself unless you name a headmate".to_string(),
+    };
+    ctx.data().saves.request();
+
+    ctx.reply(reply).await?;
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! The "Compare with me" button on public show_result output. Everything the click needs is in
+//! the button's custom ID, so it keeps working across restarts until it expires, and whether the
+//! result may still be compared with is checked again when it is clicked.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::{commands::resolve_member_names, logic, result_button::ResultTarget, GlobalState};
+
+/// Starts the custom ID of every compare button.
+const PREFIX: &str = "compare-with-me";
+/// How long a button works after it is posted.
+const EXPIRY: chrono::Duration = chrono::Duration::days(7);
+
+/// A button comparing whoever clicks it with `result_id` of `user_id` (or their headmate),
+/// expiring [`EXPIRY`] after `now`. Targets too long to encode get no button.
+pub fn button(
+    user_id: serenity::UserId,
+    headmate: Option<String>,
+    result_id: String,
+    now: DateTime<Utc>,
+) -> Option<serenity::CreateActionRow> {
+    ResultTarget {
+        user_id,
+        headmate,
+        result_id,
+        expires: now + EXPIRY,
+    }
+    .button(PREFIX, "Compare with me", serenity::ButtonStyle::Primary)
+}
+
+/// Answers a click on a compare button, privately. Clicks on any other component are left to
+/// whatever is collecting them.
+pub async fn handle(
+    ctx: &serenity::Context,
+    state: &Arc<GlobalState>,
+    mci: &serenity::ComponentInteraction,
+) {
+    let Some(target) = ResultTarget::parse(PREFIX, &mci.data.custom_id) else {
+        return;
+    };
+    let Some(guild_id) = mci.guild_id else {
+        return;
+    };
+    info!(clicker = %mci.user.id, poster = %target.user_id, "Comparing from a button");
+    // Fetching the score can take longer than Discord waits for an answer.
+    let defer = serenity::CreateInteractionResponse::Defer(
+        serenity::CreateInteractionResponseMessage::new().ephemeral(true),
+    );
+    if let Err(e) = mci.create_response(ctx, defer).await {
+        warn!("Could not answer a compare button: {e:#}");
+        return;
+    }
+    let reply = match reply(ctx, state, guild_id, mci.user.id, &target).await {
+        Ok(reply) => reply,
+        Err(e) => e.to_string(),
+    };
+    let edit = serenity::EditInteractionResponse::new()
+        .content(reply)
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+    if let Err(e) = mci.edit_response(ctx, edit).await {
+        warn!("Could not answer a compare button: {e:#}");
+    }
+}
+
+async fn reply(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+    guild_id: serenity::GuildId,
+    clicker: serenity::UserId,
+    target: &ResultTarget,
+) -> Result<String, logic::CommandError> {
+    if Utc::now() > target.expires {
+        return Err(logic::CommandError::CompareUnavailable);
+    }
+    let who = logic::Invoker {
+        guild_id,
+        user_id: clicker,
+    };
+    let display_name = state
+        .data
+        .read()
+        .await
+        .guild(guild_id)
+        .and_then(|g| g.users.get(&target.user_id))
+        .and_then(|u| u.display_name.clone());
+    let member_names =
+        resolve_member_names(ctx, guild_id, vec![(target.user_id, display_name)]).await;
+    logic::compare_with_posted(
+        &*state.data.read().await,
+        &state.api,
+        &state.cache,
+        who,
+        (target.user_id, &target.headmate, &target.result_id),
+        &member_names,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_expire_after_a_week() {
+        let now = "2024-05-01T12:00:00Z".parse().unwrap();
+        let button = button(serenity::UserId::new(42), None, "abc123".into(), now);
+        let custom_id = "compare-with-me:42:1715169600:abc123:";
+        assert_eq!(
+            button,
+            Some(serenity::CreateActionRow::Buttons(vec![
+                serenity::CreateButton::new(custom_id)
+                    .label("Compare with me")
+                    .style(serenity::ButtonStyle::Primary)
+            ]))
+        );
+        assert_eq!(
+            ResultTarget::parse(PREFIX, custom_id).map(|t| t.expires),
+            Some(now + EXPIRY)
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Bot-level settings, read at startup from an optional TOML file. Every setting has a default,
+//! so the file only needs the ones that differ. Secrets besides the API's are still read from the
+//! environment, and so can the limits on requests to bdsmtest.org, overriding the file.
+
+use std::{
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+/// The environment variable naming the config file.
+pub const CONFIG_VAR: &str = "BOT_CONFIG";
+/// The config file used when [`CONFIG_VAR`] isn't set. It doesn't have to exist.
+pub const DEFAULT_CONFIG: &str = "config.toml";
+/// Overrides `api.max_in_flight`.
+const MAX_IN_FLIGHT_VAR: &str = "BDSM_MAX_IN_FLIGHT";
+/// Overrides `api.request_interval_ms`.
+const REQUEST_INTERVAL_VAR: &str = "BDSM_REQUEST_INTERVAL_MS";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the registry and its backups are kept.
+    pub data_dir: PathBuf,
+    pub backups: BackupRetention,
+    pub api: ApiConfig,
+    pub features: Features,
+    pub sharding: Sharding,
+    pub health: Health,
+    pub slow_commands: SlowCommands,
+    pub deletion: Deletion,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("."),
+            backups: BackupRetention::default(),
+            api: ApiConfig::default(),
+            features: Features::default(),
+            sharding: Sharding::default(),
+            health: Health::default(),
+            slow_commands: SlowCommands::default(),
+            deletion: Deletion::default(),
+        }
+    }
+}
+
+/// How many backups of each tier are kept. Monthly backups are kept forever unless limited.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupRetention {
+    pub history: usize,
+    pub hourly: usize,
+    pub daily: usize,
+    pub monthly: Option<usize>,
+}
+
+impl Default for BackupRetention {
+    fn default() -> Self {
+        BackupRetention {
+            history: 20,
+            hourly: 24,
+            daily: 30,
+            monthly: None,
+        }
+    }
+}
+
+/// How bdsmtest.org is reached.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub base_url: String,
+    /// Sent along with every result lookup.
+    pub authsig: String,
+    /// The minimum time between two requests, in milliseconds.
+    pub request_interval_ms: u64,
+    /// How long connecting may take, in milliseconds.
+    pub connect_timeout_ms: u64,
+    /// How long a whole request may take, in milliseconds.
+    pub timeout_ms: u64,
+    /// The pause before the first retry, in milliseconds. It doubles with every retry after.
+    pub retry_backoff_ms: u64,
+    /// How many requests may be waiting on bdsmtest.org at once.
+    pub max_in_flight: usize,
+}
+
+impl ApiConfig {
+    /// Replaces the request limits with the ones `var` has for their environment variables.
+    fn override_from(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), anyhow::Error> {
+        if let Some(value) = var(MAX_IN_FLIGHT_VAR) {
+            self.max_in_flight = match value.trim().parse() {
+                Ok(0) | Err(_) => {
+                    anyhow::bail!(
+                        "{MAX_IN_FLIGHT_VAR} must be a number of at least 1, not {value:?}"
+                    )
+                }
+                Ok(max) => max,
+            };
+        }
+        if let Some(value) = var(REQUEST_INTERVAL_VAR) {
+            self.request_interval_ms = value.trim().parse().with_context(|| {
+                format!("{REQUEST_INTERVAL_VAR} must be milliseconds, not {value:?}")
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            base_url: "https://bdsmtest.org".to_string(),
+            authsig: "814a69afc15258000678f00526b0c107ac271b5ea997beb4f7c1e81c861c972b".to_string(),
+            request_interval_ms: 200,
+            connect_timeout_ms: 2_000,
+            timeout_ms: 5_000,
+            retry_backoff_ms: 250,
+            max_in_flight: 4,
+        }
+    }
+}
+
+/// Parts of the bot that can be turned off.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Rotates the bot's presence. `DISABLE_PRESENCE` turns it off too.
+    pub presence: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features { presence: true }
+    }
+}
+
+/// The HTTP listener for liveness and readiness probes. It is off unless an address is given.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Health {
+    pub listen: Option<SocketAddr>,
+}
+
+/// How long removed entries can be recovered before they are deleted for good.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deletion {
+    pub recovery_days: u32,
+}
+
+impl Default for Deletion {
+    fn default() -> Self {
+        Deletion { recovery_days: 14 }
+    }
+}
+
+impl Deletion {
+    pub fn recovery_window(&self) -> chrono::Duration {
+        chrono::Duration::days(self.recovery_days.into())
+    }
+}
+
+/// When a command counts as slow, and where slow runs are reported besides the log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlowCommands {
+    pub threshold_secs: u64,
+    /// The channel slow runs are posted to, if any.
+    pub report_channel: Option<u64>,
+    /// The shortest time between two posts about the same command.
+    pub report_interval_secs: u64,
+}
+
+impl Default for SlowCommands {
+    fn default() -> Self {
+        SlowCommands {
+            threshold_secs: 20,
+            report_channel: None,
+            report_interval_secs: 60 * 60,
+        }
+    }
+}
+
+/// How the gateway connection is split into shards. Without any settings Discord's recommended
+/// shard count is used, all in this process.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sharding {
+    /// The number of shards across every process.
+    pub total: Option<u32>,
+    /// The first and last shard this process runs, when the shards are split between processes.
+    pub ids: Option<[u32; 2]>,
+}
+
+/// Which shards this process starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Shards {
+    /// As many as Discord recommends.
+    Auto,
+    /// All of this many.
+    All(u32),
+    /// Only these, out of `total`.
+    Range { ids: Range<u32>, total: u32 },
+}
+
+impl Sharding {
+    pub fn shards(&self) -> Shards {
+        match (self.total, self.ids) {
+            (Some(total), Some([first, last])) => Shards::Range {
+                ids: first..last + 1,
+                total,
+            },
+            (Some(total), None) => Shards::All(total),
+            (None, _) => Shards::Auto,
+        }
+    }
+
+    fn check(&self) -> Result<(), anyhow::Error> {
+        if self.total == Some(0) {
+            anyhow::bail!("sharding.total must be at least 1");
+        }
+        if let Some([first, last]) = self.ids {
+            let Some(total) = self.total else {
+                anyhow::bail!("sharding.ids needs sharding.total to be set");
+            };
+            if first > last || last >= total {
+                anyhow::bail!("sharding.ids must be a first and last shard below sharding.total");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Parses and checks a config file's contents.
+    pub fn parse(contents: &str) -> Result<Config, anyhow::Error> {
+        let config: Config = toml::from_str(contents)?;
+        let backups = &config.backups;
+        for (key, keep) in [
+            ("history", Some(backups.history)),
+            ("hourly", Some(backups.hourly)),
+            ("daily", Some(backups.daily)),
+            ("monthly", backups.monthly),
+        ] {
+            if keep == Some(0) {
+                anyhow::bail!("backups.{key} must keep at least 1 backup");
+            }
+        }
+        reqwest::Url::parse(&config.api.base_url)
+            .with_context(|| format!("api.base_url {:?} is not a URL", config.api.base_url))?;
+        config.sharding.check()?;
+        if config.api.max_in_flight == 0 {
+            anyhow::bail!("api.max_in_flight must allow at least 1 request");
+        }
+        if config.deletion.recovery_days == 0 {
+            anyhow::bail!("deletion.recovery_days must be at least 1");
+        }
+        Ok(config)
+    }
+
+    /// Reads the config file at `path`, falling back to the defaults if it doesn't exist and
+    /// `required` isn't set.
+    pub fn load(path: &Path, required: bool) -> Result<Config, anyhow::Error> {
+        match std::fs::read_to_string(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                Ok(Config::default())
+            }
+            contents => Config::parse(&contents?)
+                .with_context(|| format!("while reading config {}", path.display())),
+        }
+    }
+
+    /// Reads the config file named by [`CONFIG_VAR`], or [`DEFAULT_CONFIG`] if there is one.
+    pub fn from_env() -> Result<Config, anyhow::Error> {
+        let mut config = match std::env::var_os(CONFIG_VAR) {
+            Some(path) => Config::load(Path::new(&path), true)?,
+            None => Config::load(Path::new(DEFAULT_CONFIG), false)?,
+        };
+        config.api.override_from(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// A one-line summary for the startup log, leaving out credentials.
+    pub fn summary(&self) -> String {
+        let backups = &self.backups;
+        format!(
+            "data in {}, keeping {} history, {} hourly, {} daily and {} monthly backups, \
+             bdsmtest.org at {} with {}ms between requests and {} at once, presence {}, {}, {}, \
+             commands slow after {}s",
+            self.data_dir.display(),
+            backups.history,
+            backups.hourly,
+            backups.daily,
+            backups
+                .monthly
+                .map_or("all".to_string(), |keep| keep.to_string()),
+            self.api.base_url,
+            self.api.request_interval_ms,
+            self.api.max_in_flight,
+            if self.features.presence { "on" } else { "off" },
+            match self.sharding.shards() {
+                Shards::Auto => "recommended shard count".to_string(),
+                Shards::All(total) => format!("{total} shards"),
+                Shards::Range { ids, total } => {
+                    format!("shards {} to {} of {total}", ids.start, ids.end - 1)
+                }
+            },
+            self.health
+                .listen
+                .map_or("no health checks".to_string(), |addr| {
+                    format!("health checks on {addr}")
+                }),
+            self.slow_commands.threshold_secs,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_fill_in_what_is_missing() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        let config = Config::parse(
+            r#"
+            data_dir = "/var/lib/bot"
+            [backups]
+            daily = 7
+            monthly = 12
+            [features]
+            presence = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/bot"));
+        assert_eq!(
+            config.backups,
+            BackupRetention {
+                daily: 7,
+                monthly: Some(12),
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.api, ApiConfig::default());
+        assert!(!config.features.presence);
+        assert_eq!(config.health.listen, None);
+        assert_eq!(config.slow_commands.threshold_secs, 20);
+        assert_eq!(config.deletion.recovery_days, 14);
+    }
+
+    #[test]
+    fn errors_name_the_offending_key() {
+        let error = |contents: &str| format!("{:#}", Config::parse(contents).unwrap_err());
+        assert!(error("[backups]\nhourlly = 3").contains("hourlly"));
+        assert!(error("[api]\nrequest_interval_ms = \"fast\"").contains("request_interval_ms"));
+        assert!(error("[backups]\nmonthly = 0").contains("backups.monthly"));
+        assert!(error("[api]\nbase_url = \"bdsmtest\"").contains("api.base_url"));
+        assert!(error("[health]\nlisten = \"8080\"").contains("listen"));
+        assert!(error("[deletion]\nrecovery_days = 0").contains("deletion.recovery_days"));
+    }
+
+    #[test]
+    fn only_a_named_config_has_to_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("config.toml");
+        assert_eq!(Config::load(&missing, false).unwrap(), Config::default());
+        assert!(Config::load(&missing, true).is_err());
+
+        std::fs::write(&missing, "[features]\npresence = false\n").unwrap();
+        assert!(!Config::load(&missing, true).unwrap().features.presence);
+    }
+
+    #[test]
+    fn shards_are_auto_unless_configured() {
+        let shards = |contents: &str| Config::parse(contents).unwrap().sharding.shards();
+        assert_eq!(shards(""), Shards::Auto);
+        assert_eq!(shards("[sharding]\ntotal = 4"), Shards::All(4));
+        assert_eq!(
+            shards("[sharding]\ntotal = 4\nids = [2, 3]"),
+            Shards::Range {
+                ids: 2..4,
+                total: 4
+            }
+        );
+
+        let error = |contents: &str| format!("{:#}", Config::parse(contents).unwrap_err());
+        assert!(error("[sharding]\nids = [0, 1]").contains("sharding.total"));
+        assert!(error("[sharding]\ntotal = 2\nids = [1, 2]").contains("sharding.ids"));
+        assert!(error("[sharding]\ntotal = 2\nids = [1, 0]").contains("sharding.ids"));
+        assert!(error("[sharding]\ntotal = 0").contains("sharding.total"));
+    }
+
+    #[test]
+    fn environment_overrides_request_limits() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let mut api = ApiConfig::default();
+        api.override_from(env(&[])).unwrap();
+        assert_eq!(api, ApiConfig::default());
+        api.override_from(env(&[
+            ("BDSM_MAX_IN_FLIGHT", "2"),
+            ("BDSM_REQUEST_INTERVAL_MS", " 500 "),
+        ]))
+        .unwrap();
+        assert_eq!((api.max_in_flight, api.request_interval_ms), (2, 500));
+
+        let error = |vars| {
+            format!(
+                "{:#}",
+                ApiConfig::default().override_from(env(vars)).unwrap_err()
+            )
+        };
+        assert!(error(&[("BDSM_MAX_IN_FLIGHT", "0")]).contains("BDSM_MAX_IN_FLIGHT"));
+        assert!(error(&[("BDSM_REQUEST_INTERVAL_MS", "fast")]).contains("BDSM_REQUEST_INTERVAL_MS"));
+        assert!(format!(
+            "{:#}",
+            Config::parse("[api]\nmax_in_flight = 0").unwrap_err()
+        )
+        .contains("api.max_in_flight"));
+    }
+
+    #[test]
+    fn summary_leaves_out_credentials() {
+        let summary = Config::default().summary();
+        assert!(summary.contains("20 history, 24 hourly, 30 daily and all monthly"));
+        assert!(!summary.contains(&ApiConfig::default().authsig));
+    }
+}
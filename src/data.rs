@@ -0,0 +1,675 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context as _;
+use chrono::{DateTime, Utc, Weekday};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{GetResultResult, GetResultScore},
+    archetypes,
+    config::BackupRetention,
+};
+
+pub const REGISTRY: &str = "registry.json";
+pub const BACKUP_DIR: &str = "bku";
+/// How many not-found checks in a row flag a result as unresolvable.
+pub const UNRESOLVABLE_AFTER: u32 = 3;
+/// How long temporary results are kept when the guild doesn't say.
+pub const DEFAULT_GUEST_HOURS: u32 = 48;
+/// The registry's current schema. Version 1 added tombstones for removed entries, version 2 kept
+/// copies of fetched results.
+pub const SCHEMA_VERSION: u32 = 2;
+/// Starts the IDs of results entered by hand, which bdsmtest.org knows nothing about.
+pub const MANUAL_PREFIX: &str = "manual-";
+
+/// Whether the result `id` was entered by hand.
+pub fn is_manual(id: &str) -> bool {
+    id.starts_with(MANUAL_PREFIX)
+}
+
+/// Whether an entry shows up for the other members of a guild.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visibility {
+    #[default]
+    Visible,
+    HiddenFromOthers,
+}
+
+impl Visibility {
+    pub fn is_visible(&self) -> bool {
+        *self == Visibility::Visible
+    }
+}
+
+/// One archetype's score in a [`StoredResult`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredScore {
+    pub name: String,
+    pub score: u32,
+}
+
+/// What is kept of a result fetched from bdsmtest.org, so it never has to be fetched again.
+/// Results don't change once published.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredResult {
+    pub date: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub gender: String,
+    /// The version of the test, unknown (0) for copies kept before it was.
+    #[serde(default, skip_serializing_if = "is_unknown_version")]
+    pub version: u32,
+    pub scores: Vec<StoredScore>,
+}
+
+fn is_unknown_version(version: &u32) -> bool {
+    *version == 0
+}
+
+impl From<&GetResultResult> for StoredResult {
+    fn from(result: &GetResultResult) -> Self {
+        StoredResult {
+            date: result.date.clone(),
+            gender: result.gender.clone(),
+            version: result.version,
+            scores: result
+                .scores
+                .iter()
+                .map(|s| StoredScore {
+                    name: s.name.clone(),
+                    score: s.score,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl StoredResult {
+    /// The result in the shape bdsmtest.org returns results in. Descriptions aren't kept.
+    pub fn to_result(&self) -> GetResultResult {
+        GetResultResult {
+            langfile: String::new(),
+            date: self.date.clone(),
+            version: self.version,
+            gender: self.gender.clone(),
+            auth: false,
+            scores: self
+                .scores
+                .iter()
+                .enumerate()
+                .map(|(i, s)| GetResultScore {
+                    id: i as u32,
+                    name: s.name.clone(),
+                    pairdesc: String::new(),
+                    description: String::new(),
+                    score: s.score,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeadmateData {
+    pub results: BTreeMap<DateTime<Utc>, String>,
+    #[serde(default, skip_serializing_if = "Visibility::is_visible")]
+    pub visibility: Visibility,
+    /// Archetype percentages of the manual results in `results`, by result ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub manual: BTreeMap<String, BTreeMap<String, u32>>,
+    /// How many background checks in a row bdsmtest.org didn't know a result, by result ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub not_found: BTreeMap<String, u32>,
+    /// When temporary (guest) results are removed, by result ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expires: BTreeMap<String, DateTime<Utc>>,
+    /// Results only their owner can use, by result ID. Everyone else is compared against the most
+    /// recent visible one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub result_visibility: BTreeMap<String, Visibility>,
+    /// Copies of the results fetched from bdsmtest.org, by result ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fetched: BTreeMap<String, StoredResult>,
+}
+
+impl HeadmateData {
+    /// Result IDs are stored as they always were, so this only drops copies of results that
+    /// aren't stored anymore.
+    pub fn migrate(&mut self) {
+        let results = &self.results;
+        self.fetched
+            .retain(|id, _| results.values().any(|result| result == id));
+    }
+
+    /// Whether the result `id` consistently failed to resolve. It is only flagged, never
+    /// removed.
+    pub fn is_unresolvable(&self, id: &str) -> bool {
+        self.not_found
+            .get(id)
+            .is_some_and(|&count| count >= UNRESOLVABLE_AFTER)
+    }
+
+    /// Whether the result `id` is temporary.
+    pub fn is_temporary(&self, id: &str) -> bool {
+        self.expires.contains_key(id)
+    }
+
+    /// Removes the temporary results that expired by `now`. Returns whether there were any.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> bool {
+        let expired: Vec<_> = self
+            .expires
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.results.retain(|_, result| result != id);
+            self.manual.remove(id);
+            self.not_found.remove(id);
+            self.expires.remove(id);
+            self.result_visibility.remove(id);
+            self.fetched.remove(id);
+        }
+        !expired.is_empty()
+    }
+
+    /// Removes the result stored at `at`, along with everything stored about it unless it is also
+    /// stored at another time. Returns its ID.
+    pub fn remove_result(&mut self, at: &DateTime<Utc>) -> Option<String> {
+        let id = self.results.remove(at)?;
+        if !self.results.values().any(|other| *other == id) {
+            self.manual.remove(&id);
+            self.not_found.remove(&id);
+            self.expires.remove(&id);
+            self.result_visibility.remove(&id);
+            self.fetched.remove(&id);
+        }
+        Some(id)
+    }
+
+    pub fn most_recent(&self) -> Option<&String> {
+        self.results.iter().max_by_key(|h| h.0).map(|h| h.1)
+    }
+
+    /// Whether other members may use the result `id`.
+    pub fn is_result_visible(&self, id: &str) -> bool {
+        self.result_visibility
+            .get(id)
+            .is_none_or(Visibility::is_visible)
+    }
+
+    /// The most recent result other members may use.
+    pub fn most_recent_visible(&self) -> Option<&String> {
+        self.results
+            .values()
+            .rev()
+            .find(|id| self.is_result_visible(id))
+    }
+}
+
+/// An entry removed with remove_bdsm_results, kept out of everything until it is recovered or the
+/// recovery window passes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub headmate: Option<String>,
+    pub deleted: DateTime<Utc>,
+    /// Whether the headmate was the user's default.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub was_default: bool,
+    pub data: HeadmateData,
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<HeadmateData>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headmates: BTreeMap<String, HeadmateData>,
+    /// Set once the user has added a result, so later adds are never announced.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub announced: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suppress_announcements: bool,
+    /// Hides all of the user's entries, including their headmates, from everyone else.
+    #[serde(default, skip_serializing_if = "Visibility::is_visible")]
+    pub visibility: Visibility,
+    /// Per-archetype weights for custom scoring.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weights: BTreeMap<String, f64>,
+    /// Users left out of this user's own listings. They may not have registered (yet).
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub ignored: BTreeSet<serenity::UserId>,
+    /// Shown instead of the user's server nickname. Already sanitized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// The zone dates are shown in. Defaults to UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<chrono_tz::Tz>,
+    /// Lets other members look up this user's compatibility with someone else.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_third_party: bool,
+    /// Lets other members see which archetypes drive their match with this user.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_explain: bool,
+    /// Shows the gender the test was taken as along with the user's result details.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub show_gender: bool,
+    /// The headmate commands act as when none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_headmate: Option<String>,
+    /// DMs the user when someone adds a result that matches them at least this well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_alert: Option<u32>,
+    /// Set when an alert could not be DMed and alerts were turned off, until the user is told.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub match_alert_failed: bool,
+    /// Set once a lookup showed the user is a bot. Their data is kept, but has no entries.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bot: bool,
+    /// Removed entries that can still be recovered, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tombstones: Vec<Tombstone>,
+}
+
+impl UserData {
+    pub fn migrate(&mut self) {
+        self.primary.iter_mut().for_each(HeadmateData::migrate);
+        self.headmates.values_mut().for_each(HeadmateData::migrate)
+    }
+
+    pub fn has_results(&self) -> bool {
+        self.primary.iter().any(|p| !p.results.is_empty())
+            || self.headmates.values().any(|h| !h.results.is_empty())
+    }
+
+    pub fn headmate(&self, name: &Option<String>) -> Option<&HeadmateData> {
+        match name {
+            Some(name) => self.headmates.get(name),
+            None => self.primary.as_ref(),
+        }
+    }
+
+    pub fn headmate_mut(&mut self, name: &Option<String>) -> &mut HeadmateData {
+        match name {
+            Some(name) => self.headmates.entry(name.clone()).or_default(),
+            None => self.primary.get_or_insert_with(HeadmateData::default),
+        }
+    }
+}
+
+/// When and where a guild's weekly digest is posted. Times are in UTC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DigestConfig {
+    pub channel: serenity::ChannelId,
+    pub weekday: Weekday,
+    pub hour: u32,
+    pub last_posted: Option<DateTime<Utc>>,
+}
+
+/// The message the bot keeps up to date with the guild's registry.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BoardConfig {
+    pub channel: serenity::ChannelId,
+    pub message: serenity::MessageId,
+    pub show_pairings: bool,
+}
+
+/// A compatibility scan queued to run in the background, kept until it finishes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: u64,
+    pub guild_id: serenity::GuildId,
+    pub user_id: serenity::UserId,
+    /// Where the output is posted.
+    pub channel_id: serenity::ChannelId,
+    pub queued_at: DateTime<Utc>,
+}
+
+/// The role granted to members with a match at or above `threshold`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PowerCoupleConfig {
+    pub role: serenity::RoleId,
+    pub threshold: u32,
+    /// Members the bot has granted the role to.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub holders: BTreeSet<serenity::UserId>,
+    /// Set when the bot was not allowed to manage the role. Nothing is retried until an admin
+    /// configures the role again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forbidden: bool,
+}
+
+/// Settings chosen by a guild's admins.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct GuildConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<DigestConfig>,
+    /// Where to announce members adding their first result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce_channel: Option<serenity::ChannelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board: Option<BoardConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_couple: Option<PowerCoupleConfig>,
+    /// Whether list_compatibility includes headmates when the invoker doesn't say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_headmates: Option<bool>,
+    /// Whether list_compatibility shows how old each result is when the invoker doesn't say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_age: Option<bool>,
+    /// Whether list_compatibility splits lists into tiers when the invoker doesn't say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by_tier: Option<bool>,
+    /// Where problems the admins need to fix are reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_channel: Option<serenity::ChannelId>,
+    /// Archetypes left out of displayed results, stats and leaderboards unless asked for. Scores
+    /// and matches are unaffected.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub hidden_archetypes: BTreeSet<String>,
+    /// What this guild calls archetypes in displayed output, by canonical name. Everything else,
+    /// stored results and scores included, keeps the canonical names.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub archetype_aliases: BTreeMap<String, String>,
+    /// How long temporary results are kept. Defaults to [`DEFAULT_GUEST_HOURS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest_hours: Option<u32>,
+    /// The fewest results an entry needs to be listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_results: Option<usize>,
+    /// Entries need a result taken at or after this to be listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results_since: Option<DateTime<Utc>>,
+    /// Lets admins list members who haven't added a result with /coverage.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_coverage: bool,
+    /// Channels besides NSFW ones where members may share their results publicly.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub share_channels: BTreeSet<serenity::ChannelId>,
+}
+
+/// What an entry is missing to meet its guild's list requirements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shortfall {
+    TooFewResults { needed: usize, have: usize },
+    NoResultSince(DateTime<Utc>),
+}
+
+impl GuildConfig {
+    /// What `data` is missing to be listed, if anything. Every requirement that is set has to be
+    /// met.
+    pub fn list_shortfall(&self, data: &HeadmateData) -> Option<Shortfall> {
+        if let Some(needed) = self.min_results {
+            if data.results.len() < needed {
+                return Some(Shortfall::TooFewResults {
+                    needed,
+                    have: data.results.len(),
+                });
+            }
+        }
+        if let Some(since) = self.results_since {
+            if data.results.keys().next_back().is_none_or(|&at| at < since) {
+                return Some(Shortfall::NoResultSince(since));
+            }
+        }
+        None
+    }
+}
+
+/// One comparable entry in a guild: either a user's primary data or one of their headmates.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    pub user_id: serenity::UserId,
+    pub headmate: Option<&'a str>,
+    pub data: &'a HeadmateData,
+    /// Whether other members may see this entry.
+    pub listed: bool,
+}
+
+impl<'a> Entry<'a> {
+    /// The result `viewer` is compared against: the most recent one for the entry's owner, the
+    /// most recent visible one for everyone else.
+    pub fn result_for(&self, viewer: serenity::UserId) -> Option<&'a String> {
+        if self.user_id == viewer {
+            self.data.most_recent()
+        } else {
+            self.data.most_recent_visible()
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct GuildData {
+    pub users: BTreeMap<serenity::UserId, UserData>,
+    #[serde(default)]
+    pub config: GuildConfig,
+}
+
+impl GuildData {
+    pub fn migrate(&mut self) {
+        self.users.values_mut().for_each(UserData::migrate)
+    }
+
+    /// Every primary and headmate entry in the guild, ordered by user. Bots have none.
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'_>> {
+        self.users
+            .iter()
+            .filter(|(_, user)| !user.bot)
+            .flat_map(|(&user_id, user)| {
+                let listed = user.visibility.is_visible();
+                let primary = user.primary.iter().map(move |data| Entry {
+                    user_id,
+                    headmate: None,
+                    data,
+                    listed: listed && data.visibility.is_visible(),
+                });
+                let headmates = user.headmates.iter().map(move |(name, data)| Entry {
+                    user_id,
+                    headmate: Some(name),
+                    data,
+                    listed: listed && data.visibility.is_visible(),
+                });
+                primary.chain(headmates)
+            })
+    }
+
+    /// Every registered user, with their display name override if they set one.
+    pub fn display_names(&self) -> Vec<(serenity::UserId, Option<String>)> {
+        self.users
+            .iter()
+            .map(|(&user_id, user)| (user_id, user.display_name.clone()))
+            .collect()
+    }
+
+    /// The primary (or `headmate`) entry of `user_id`.
+    pub fn entry(&self, user_id: serenity::UserId, headmate: &Option<String>) -> Option<Entry<'_>> {
+        self.entries()
+            .find(|e| e.user_id == user_id && e.headmate == headmate.as_deref())
+    }
+
+    /// The entries that every member of the guild may see.
+    pub fn listed_entries(&self) -> impl Iterator<Item = Entry<'_>> {
+        self.entries().filter(|e| e.listed)
+    }
+
+    /// The entries `viewer` may see: the listed ones, plus all of their own.
+    pub fn visible_entries(&self, viewer: serenity::UserId) -> impl Iterator<Item = Entry<'_>> {
+        self.entries()
+            .filter(move |e| e.listed || e.user_id == viewer)
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct GlobalData {
+    /// The [`SCHEMA_VERSION`] the registry was last saved with. Registries from before versioning
+    /// read as 0.
+    #[serde(default)]
+    pub version: u32,
+    pub guilds: BTreeMap<serenity::GuildId, GuildData>,
+    /// Archetypes seen on bdsmtest.org that aren't bundled, so they are known from startup.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub archetypes: BTreeSet<String>,
+    /// Background jobs that haven't finished. Jobs don't survive a restart, so any found on
+    /// startup were dropped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_jobs: Vec<JobRecord>,
+    /// Where [`persist`] writes the registry, and how many backups it keeps.
+    #[serde(skip)]
+    pub storage: Storage,
+}
+
+/// Where the registry and its backups are written.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    pub root: PathBuf,
+    pub retention: BackupRetention,
+    /// Whether the last [`persist`] failed, shared with the health check.
+    pub failing: Arc<AtomicBool>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            root: PathBuf::from("."),
+            retention: BackupRetention::default(),
+            failing: Arc::default(),
+        }
+    }
+}
+
+impl GlobalData {
+    pub fn migrate(&mut self) {
+        self.guilds.values_mut().for_each(GuildData::migrate);
+        // Earlier versions only lack fields that default to empty.
+        self.version = SCHEMA_VERSION;
+    }
+
+    /// Loads the saved archetypes into [`archetypes`], and saves any learned since. Returns
+    /// whether there were new ones to save.
+    pub fn sync_archetypes(&mut self) -> bool {
+        for name in &self.archetypes {
+            archetypes::learn(name);
+        }
+        let before = self.archetypes.len();
+        self.archetypes
+            .extend(archetypes::learned().into_iter().map(String::from));
+        self.archetypes.len() != before
+    }
+
+    /// Every result ID stored in any guild.
+    pub fn result_ids(&self) -> BTreeSet<String> {
+        self.guilds
+            .values()
+            .flat_map(|g| g.entries())
+            .flat_map(|e| e.data.results.values().cloned())
+            .collect()
+    }
+
+    pub fn guild(&self, id: serenity::GuildId) -> Option<&GuildData> {
+        self.guilds.get(&id)
+    }
+
+    pub fn guild_mut(&mut self, guild_id: serenity::GuildId) -> &mut GuildData {
+        self.guilds.entry(guild_id).or_default()
+    }
+}
+
+/// Reads the registry at `path` and migrates it to the current format. Nothing is written back.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<GlobalData, anyhow::Error> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("while reading {}", path.display()))?;
+    let mut data: GlobalData = serde_json::from_str(&contents)
+        .with_context(|| format!("while parsing {}", path.display()))?;
+    data.migrate();
+    Ok(data)
+}
+
+fn persist_folder<P: AsRef<Path>, P2: AsRef<Path>>(
+    registry: &Path,
+    folder: P,
+    filename: P2,
+    keep: usize,
+) -> std::io::Result<()> {
+    let folder = folder.as_ref();
+    std::fs::create_dir_all(folder)?;
+    if !registry.is_file() {
+        return Ok(());
+    }
+    std::fs::copy(registry, folder.join(filename))?;
+    let mut existing: Vec<_> = std::fs::read_dir(folder)?.collect::<Result<_, _>>()?;
+    existing.sort_by_key(|f| f.path());
+
+    let count = existing.len();
+    if count > keep {
+        for file in existing.into_iter().take(count - keep) {
+            std::fs::remove_file(file.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn persist(data: &GlobalData) -> Result<(), anyhow::Error> {
+    let result = persist_in(&data.storage, data);
+    data.storage
+        .failing
+        .store(result.is_err(), Ordering::Relaxed);
+    result
+}
+
+/// Writes the registry and its backups to `storage`.
+pub fn persist_in(storage: &Storage, data: &GlobalData) -> Result<(), anyhow::Error> {
+    let json = serde_json::to_vec_pretty(data).context("while formatting json")?;
+    write_in(storage, &json)
+}
+
+/// Writes `json`, a serialized registry, and its backups to `storage`.
+pub fn write_in(storage: &Storage, json: &[u8]) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let registry = storage.root.join(REGISTRY);
+    let backups = storage.root.join(BACKUP_DIR);
+    let keep = &storage.retention;
+    persist_folder(
+        &registry,
+        backups.join("history"),
+        format!("registry-{}.json", now.timestamp()),
+        keep.history,
+    )?;
+
+    // Written beside the registry and renamed over it, so a crash mid-write leaves the old one.
+    let temp = storage.root.join(format!("{REGISTRY}.tmp"));
+    let mut output = std::fs::File::create(&temp).context("while opening data file")?;
+    output.write_all(json).context("while writing data file")?;
+    output.sync_all().context("while writing data file")?;
+    std::fs::rename(&temp, &registry).context("while replacing data file")?;
+
+    persist_folder(
+        &registry,
+        backups.join("hourly"),
+        format!("registry-{}.json", now.timestamp() / 60 / 60),
+        keep.hourly,
+    )?;
+    persist_folder(
+        &registry,
+        backups.join("daily"),
+        format!("registry-{}.json", now.timestamp() / 60 / 60 / 24),
+        keep.daily,
+    )?;
+    persist_folder(
+        &registry,
+        backups.join("monthly"),
+        format!("registry-{}.json", now.timestamp() / 60 / 60 / 24 / 28),
+        keep.monthly.unwrap_or(usize::MAX),
+    )?;
+
+    Ok(())
+}
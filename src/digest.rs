@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::{DateTime, Datelike as _, Days, Duration, Utc, Weekday};
+use poise::serenity_prelude as serenity;
+use tracing::{error, info, warn};
+
+use crate::{
+    cache::{Cache, Matchup},
+    commands::resolve_member_names,
+    data::{DigestConfig, GuildData},
+    logic::entry_label,
+    GlobalState,
+};
+
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+const TOP_PAIRINGS: usize = 5;
+const MAX_NEW_MEMBERS: usize = 10;
+
+/// The most recent `weekday` at `hour`:00 UTC that is not after `now`.
+pub fn latest_slot(weekday: Weekday, hour: u32, now: DateTime<Utc>) -> DateTime<Utc> {
+    let days_back = (now.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+    let slot = (now.date_naive() - Days::new(days_back.into()))
+        .and_hms_opt(hour, 0, 0)
+        .expect("digest hour is validated when configured")
+        .and_utc();
+    if slot > now {
+        slot - Duration::weeks(1)
+    } else {
+        slot
+    }
+}
+
+/// Whether a digest should be posted now. A slot that passed while the bot was offline is still
+/// due, but only once.
+pub fn is_due(config: &DigestConfig, now: DateTime<Utc>) -> bool {
+    let slot = latest_slot(config.weekday, config.hour, now);
+    config.last_posted.is_none_or(|last| last < slot)
+}
+
+/// The highest cached scores between listed entries of different users, best first, as
+/// `(score, label, label)`.
+pub fn top_pairings(
+    guild: &GuildData,
+    cache: &Cache,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Vec<(u32, String, String)> {
+    let entries: Vec<_> = guild
+        .listed_entries()
+        .filter(|e| guild.config.list_shortfall(e.data).is_none())
+        .filter_map(|e| Some((e, e.data.most_recent_visible()?)))
+        .collect();
+    let mut pairings = Vec::new();
+    for (i, (a, a_id)) in entries.iter().enumerate() {
+        for (b, b_id) in &entries[i + 1..] {
+            if a.user_id == b.user_id {
+                continue;
+            }
+            if let Some(score) = cache.get(&Matchup::new((*a_id).clone(), (*b_id).clone())) {
+                pairings.push((
+                    score,
+                    entry_label(member_names, a),
+                    entry_label(member_names, b),
+                ));
+            }
+        }
+    }
+    pairings.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+    pairings
+}
+
+/// Builds the digest message for the week ending at `now`. Pairings only come from scores that are
+/// already cached, so composing a digest never calls out to bdsmtest.org. Hidden entries are only
+/// counted, never named.
+pub fn compose(
+    guild: &GuildData,
+    cache: &Cache,
+    member_names: &BTreeMap<serenity::UserId, String>,
+    now: DateTime<Utc>,
+) -> String {
+    let week_ago = now - Duration::weeks(1);
+    let new_results = guild
+        .entries()
+        .flat_map(|e| e.data.results.keys())
+        .filter(|&&at| at > week_ago && at <= now)
+        .count();
+
+    let mut new_members: Vec<_> = guild
+        .users
+        .keys()
+        .filter_map(|&user_id| {
+            let joined = guild
+                .listed_entries()
+                .filter(|e| e.user_id == user_id)
+                .flat_map(|e| e.data.results.keys())
+                .min()?;
+            (*joined > week_ago).then_some((*joined, user_id))
+        })
+        .collect();
+    new_members.sort_by_key(|&(joined, _)| std::cmp::Reverse(joined));
+
+    let pairings = top_pairings(guild, cache, member_names);
+
+    let mut digest = format!(
+        "**Weekly compatibility digest**\n{new_results} new result(s) registered this week\n"
+    );
+    if new_members.is_empty() {
+        digest += "No new members joined the registry this week\n";
+    } else {
+        let names: Vec<_> = new_members
+            .iter()
+            .take(MAX_NEW_MEMBERS)
+            .map(|(_, user_id)| {
+                member_names
+                    .get(user_id)
+                    .cloned()
+                    .unwrap_or_else(|| "**Deleted User**".to_string())
+            })
+            .collect();
+        digest += &format!("New to the registry: {}", names.join(", "));
+        if new_members.len() > MAX_NEW_MEMBERS {
+            digest += &format!(" and {} more", new_members.len() - MAX_NEW_MEMBERS);
+        }
+        digest += "\n";
+    }
+
+    digest += "\n**Top pairings**\n";
+    if pairings.is_empty() {
+        digest += "No scores have been calculated yet, run /list_compatibility to get started\n";
+    }
+    for (rank, (score, a, b)) in pairings.into_iter().take(TOP_PAIRINGS).enumerate() {
+        digest += &format!("{}. {a} & {b}: {score:02}%\n", rank + 1);
+    }
+    digest
+}
+
+async fn post_due_digests(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let due: Vec<_> = state
+        .data
+        .read()
+        .await
+        .guilds
+        .iter()
+        .filter_map(|(&guild_id, guild)| {
+            let config = guild.config.digest.as_ref()?;
+            is_due(config, now).then(|| (guild_id, config.channel, guild.display_names()))
+        })
+        .collect();
+
+    for (guild_id, channel, users) in due {
+        info!(%guild_id, "Posting weekly digest");
+        let member_names = resolve_member_names(ctx, guild_id, users).await;
+
+        let content = {
+            let data = state.data.read().await;
+            let Some(guild) = data.guild(guild_id) else {
+                continue;
+            };
+            compose(guild, &*state.cache.lock().await, &member_names, now)
+        };
+        if let Err(e) = channel
+            .send_message(
+                ctx,
+                serenity::CreateMessage::new()
+                    .content(content)
+                    .allowed_mentions(serenity::CreateAllowedMentions::new()),
+            )
+            .await
+        {
+            // Still mark the digest as posted so a missing permission doesn't retry every check.
+            warn!(%guild_id, "Could not post weekly digest: {e}");
+        }
+
+        let mut data = state.data.write().await;
+        if let Some(config) = data.guild_mut(guild_id).config.digest.as_mut() {
+            config.last_posted = Some(now);
+        }
+        state.saves.request();
+    }
+    Ok(())
+}
+
+/// Periodically posts any weekly digests that are due. The first check happens immediately so
+/// digests missed while the bot was offline go out on startup.
+pub async fn run(ctx: serenity::Context, state: Arc<GlobalState>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = post_due_digests(&ctx, &state).await {
+            error!("Failed to post weekly digests: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::UserData;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn config(last_posted: Option<&str>) -> DigestConfig {
+        DigestConfig {
+            channel: serenity::ChannelId::new(1),
+            weekday: Weekday::Mon,
+            hour: 18,
+            last_posted: last_posted.map(time),
+        }
+    }
+
+    #[test]
+    fn latest_slot_finds_previous_occurrence() {
+        // 2024-05-06 is a Monday.
+        let slot = time("2024-05-06T18:00:00Z");
+        assert_eq!(
+            latest_slot(Weekday::Mon, 18, time("2024-05-06T18:00:00Z")),
+            slot
+        );
+        assert_eq!(
+            latest_slot(Weekday::Mon, 18, time("2024-05-09T03:00:00Z")),
+            slot
+        );
+        assert_eq!(
+            latest_slot(Weekday::Mon, 18, time("2024-05-06T17:59:59Z")),
+            time("2024-04-29T18:00:00Z")
+        );
+        assert_eq!(
+            latest_slot(Weekday::Sun, 0, time("2024-05-06T12:00:00Z")),
+            time("2024-05-05T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn due_once_per_slot() {
+        let now = time("2024-05-06T18:05:00Z");
+        assert!(is_due(&config(Some("2024-05-01T00:00:00Z")), now));
+        assert!(!is_due(&config(Some("2024-05-06T18:01:00Z")), now));
+        // Offline for two weeks: the missed slot is due, but only once.
+        let now = time("2024-05-20T19:00:00Z");
+        assert!(is_due(&config(Some("2024-05-01T00:00:00Z")), now));
+        assert!(!is_due(&config(Some("2024-05-20T18:30:00Z")), now));
+    }
+
+    #[test]
+    fn compose_summarizes_week() {
+        let now = time("2024-05-06T18:00:00Z");
+        let mut guild = GuildData::default();
+        let mut old = UserData::default();
+        old.headmate_mut(&None)
+            .results
+            .insert(time("2024-01-01T00:00:00Z"), "a".into());
+        old.headmate_mut(&None)
+            .results
+            .insert(time("2024-05-05T00:00:00Z"), "b".into());
+        let mut new = UserData::default();
+        new.headmate_mut(&Some("Ash".into()))
+            .results
+            .insert(time("2024-05-04T00:00:00Z"), "c".into());
+        guild.users.insert(serenity::UserId::new(1), old);
+        guild.users.insert(serenity::UserId::new(2), new);
+
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("b".into(), "c".into()), 91);
+        cache.insert(Matchup::new("a".into(), "c".into()), 12);
+        let names = BTreeMap::from([
+            (serenity::UserId::new(1), "**Old**".to_string()),
+            (serenity::UserId::new(2), "**New**".to_string()),
+        ]);
+
+        assert_eq!(
+            compose(&guild, &cache, &names, now),
+            "**Weekly compatibility digest**\n\
+             2 new result(s) registered this week\n\
+             New to the registry: **New**\n\
+             \n\
+             **Top pairings**\n\
+             1. **Old** & **New** (Ash): 91%\n"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Replies to every command that fails, so no interaction is left thinking forever. Mistakes the
+//! user can fix, the [`CommandError`]s, are shown as they are. Anything else is logged, and the
+//! user only hears that something went wrong, since internal errors can name files or URLs.
+
+use std::sync::Arc;
+
+use poise::{serenity_prelude as serenity, CreateReply, FrameworkError};
+use tracing::{error, info, warn};
+
+use crate::{logic::CommandError, Context, GlobalState};
+
+/// What the user is told about anything that isn't their mistake.
+const SOMETHING_WENT_WRONG: &str =
+    "Something went wrong on my end, try again in a bit. It has been logged";
+
+/// The mistake the user can fix behind `e`, if that's what it is, whatever context was added.
+pub fn user_error(e: &anyhow::Error) -> Option<&CommandError> {
+    e.downcast_ref::<CommandError>()
+}
+
+/// What the user is told about `e`.
+pub fn reply_text(e: &anyhow::Error) -> String {
+    user_error(e).map_or_else(|| SOMETHING_WENT_WRONG.to_string(), ToString::to_string)
+}
+
+/// Handles everything that goes wrong in the framework. Only command errors and panics are
+/// answered here, the rest is left to poise.
+pub async fn on_error(error: FrameworkError<'_, Arc<GlobalState>, anyhow::Error>) {
+    match error {
+        FrameworkError::Command { error, ctx, .. } => {
+            if let Some(mistake) = user_error(&error) {
+                info!(command = ctx.command().qualified_name, "Refused: {mistake}");
+            } else {
+                error!(
+                    command = ctx.command().qualified_name,
+                    guild = ?ctx.guild_id(),
+                    user = %ctx.author().id,
+                    "Command failed: {error:#}"
+                );
+            }
+            reply(ctx, reply_text(&error)).await;
+        }
+        FrameworkError::CommandPanic { payload, ctx, .. } => {
+            error!(
+                command = ctx.command().qualified_name,
+                guild = ?ctx.guild_id(),
+                user = %ctx.author().id,
+                "Command panicked: {}",
+                payload.as_deref().unwrap_or("no message")
+            );
+            reply(ctx, SOMETHING_WENT_WRONG.to_string()).await;
+        }
+        error => {
+            if let Err(e) = poise::builtins::on_error(error).await {
+                warn!("Could not report an error: {e:#}");
+            }
+        }
+    }
+}
+
+/// Answers the command with `content`. The reply is private, unless the command already said
+/// publicly that it's thinking, which the reply then takes the place of.
+async fn reply(ctx: Context<'_>, content: String) {
+    let reply = CreateReply::default()
+        .content(content)
+        .ephemeral(true)
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+    if let Err(e) = ctx.send(reply).await {
+        warn!("Could not reply to a failed command: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_user_errors_are_shown_as_they_are() {
+        let mistake = anyhow::Error::from(CommandError::NoResults);
+        assert_eq!(reply_text(&mistake), CommandError::NoResults.to_string());
+        let wrapped = mistake.context("while showing a result");
+        assert_eq!(reply_text(&wrapped), CommandError::NoResults.to_string());
+
+        let internal = anyhow::anyhow!("could not write /data/registry.json");
+        assert_eq!(reply_text(&internal), SOMETHING_WENT_WRONG);
+    }
+}
@@ -0,0 +1,2095 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use poise::serenity_prelude as serenity;
+
+use crate::{
+    api::{GetResultResult, RawResponse},
+    data::Tombstone,
+    scoring::{Explanation, Pairing},
+    stats::{ArchetypeAverage, ArchetypeChange, History},
+};
+
+/// Discord rejects message content longer than this many characters.
+pub const MESSAGE_LIMIT: usize = 2000;
+/// Archetype scores below this percentage are noise and are left out of summaries.
+pub const NOISE_THRESHOLD: u32 = 10;
+
+/// Who a result belongs to, for the header line of [`format_result`].
+pub struct ResultNames<'a> {
+    pub user: &'a str,
+    pub headmate: Option<&'a str>,
+    pub result_id: &'a str,
+    /// Marks results entered by hand.
+    pub manual: bool,
+    /// Whether to show the gender the test was taken as. Only the owner of the result can turn
+    /// this on.
+    pub show_gender: bool,
+    /// Marks results hidden from other members.
+    pub hidden: bool,
+}
+
+/// A single row in the output of list_compatibility. `member` is the already resolved name of
+/// `user_id`, and a `score` of `None` means the match could not be fetched.
+#[derive(Clone, Debug)]
+pub struct CompatEntry {
+    pub user_id: serenity::UserId,
+    pub member: String,
+    pub headmate: Option<String>,
+    pub score: Option<u32>,
+    /// Set when the score was estimated locally, because bdsmtest.org can't match manual results.
+    pub estimated: bool,
+    /// Set when the entry's result is temporary, and will be removed.
+    pub temporary: bool,
+    /// When the entry's result was taken, if its age should be shown.
+    pub taken: Option<DateTime<Utc>>,
+}
+
+impl CompatEntry {
+    /// How the entry is named in a flat list.
+    pub fn label(&self) -> String {
+        match &self.headmate {
+            Some(headmate) => format!("{} ({headmate})", self.member),
+            None => self.member.clone(),
+        }
+    }
+}
+
+/// The score of `entry`. Estimates are only marked when the whole list isn't estimated anyway.
+/// Temporary entries get an hourglass.
+fn format_score(entry: &CompatEntry, options: &CompatListOptions) -> String {
+    let mut score = match entry.score {
+        Some(score) if entry.estimated && !options.custom_scoring => {
+            format!("{score:02}% (estimated)")
+        }
+        Some(score) => format!("{score:02}%"),
+        None => "Invalid Result".to_string(),
+    };
+    if let Some(taken) = entry.taken {
+        score += &format!(" ({})", format_age(taken, options.now));
+    }
+    if entry.temporary {
+        format!("{score} ⏳")
+    } else {
+        score
+    }
+}
+
+pub struct CompatListOptions {
+    pub max_len: usize,
+    /// Lists the entries under a header for each [`Tier`], with invalid results last.
+    pub by_tier: bool,
+    /// Marks the scores as estimated locally with the invoker's weights.
+    pub custom_scoring: bool,
+    /// How many headmate entries were left out, noted at the bottom of the list.
+    pub skipped_headmates: usize,
+    /// Entries left out by the guild's list requirements.
+    pub not_listable: usize,
+    /// When the list was made, which the ages of entries count back from.
+    pub now: DateTime<Utc>,
+    /// Entries whose stored result no longer resolves, noted at the bottom of the list.
+    pub unresolvable: Vec<String>,
+    /// Lists every member's entries together under a header with their best score.
+    pub group_by_user: bool,
+    /// How many of the invoker's results the scores are averaged over. Noted in the header when
+    /// more than one.
+    pub averaged_over: usize,
+    /// The label of the invoker's result that was used, noted in the header, when it wasn't their
+    /// most recent one.
+    pub older_result: Option<String>,
+    /// How many entries a cancelled scan didn't get to. The list is marked partial when any.
+    pub not_scored: usize,
+    /// The score entries were filtered to at least, noted in the header.
+    pub min_score: Option<u32>,
+    /// How many of the best matches the list was cut down to, noted in the header.
+    pub top: Option<usize>,
+}
+
+impl Default for CompatListOptions {
+    fn default() -> Self {
+        CompatListOptions {
+            max_len: MESSAGE_LIMIT,
+            by_tier: false,
+            custom_scoring: false,
+            skipped_headmates: 0,
+            not_listable: 0,
+            now: DateTime::default(),
+            unresolvable: Vec::new(),
+            group_by_user: false,
+            averaged_over: 1,
+            older_result: None,
+            not_scored: 0,
+            min_score: None,
+            top: None,
+        }
+    }
+}
+
+pub fn format_result(result: &GetResultResult, names: &ResultNames) -> String {
+    let mut response = format!(
+        "```==== {} {}({}) {}{}{} ====\n",
+        names.user,
+        if let Some(hm) = names.headmate {
+            format!("({hm}) ")
+        } else {
+            String::new()
+        },
+        result.date,
+        names.result_id,
+        if names.manual { " [manual]" } else { "" },
+        if names.hidden { " [hidden]" } else { "" }
+    );
+    if names.show_gender && !result.gender.is_empty() {
+        response += &format!("taken as: {}\n", result.gender);
+    }
+    for score in &result.scores {
+        response += &format!("{:-30} {:02}%\n", score.name, score.score);
+    }
+    response + "```"
+}
+
+/// Discord shows at most this many fields in one embed.
+const MAX_EMBED_FIELDS: usize = 25;
+/// The colors result embeds are picked from, by their top archetype.
+const EMBED_COLORS: [u32; 8] = [
+    0xE74C3C, 0xE67E22, 0xF1C40F, 0x2ECC71, 0x1ABC9C, 0x3498DB, 0x9B59B6, 0xE91E63,
+];
+
+/// The same color for every result topped by `archetype`.
+fn archetype_color(archetype: &str) -> u32 {
+    let hash = archetype.bytes().fold(0usize, |hash, b| {
+        hash.wrapping_mul(31).wrapping_add(b.into())
+    });
+    EMBED_COLORS[hash % EMBED_COLORS.len()]
+}
+
+/// `result` as an embed, with an inline field per archetype in the order given. Archetypes past
+/// Discord's field limit are listed in the description instead.
+pub fn format_result_embed(result: &GetResultResult, names: &ResultNames) -> serenity::CreateEmbed {
+    let mut title = match names.headmate {
+        Some(hm) => format!("{} ({hm}) — {}", names.user, result.date),
+        None => format!("{} — {}", names.user, result.date),
+    };
+    if names.manual {
+        title += " [manual]";
+    }
+    if names.hidden {
+        title += " [hidden]";
+    }
+    let (fields, rest) = result
+        .scores
+        .split_at(result.scores.len().min(MAX_EMBED_FIELDS));
+    let mut description = format!("Result {}", names.result_id);
+    for score in rest {
+        description += &format!("\n{}: {}%", score.name, score.score);
+    }
+    let mut footer = Vec::new();
+    if names.show_gender && !result.gender.is_empty() {
+        footer.push(format!("Taken as {}", result.gender));
+    }
+    if names.manual {
+        footer.push("Entered by hand".to_string());
+    } else if result.version > 0 {
+        footer.push(format!("Test version {}", result.version));
+    }
+
+    let mut embed = serenity::CreateEmbed::new()
+        .title(title)
+        .description(description)
+        .fields(
+            fields
+                .iter()
+                .map(|score| (score.name.clone(), format!("{}%", score.score), true)),
+        );
+    if !footer.is_empty() {
+        embed = embed.footer(serenity::CreateEmbedFooter::new(footer.join(" · ")));
+    }
+    // The first of several tied archetypes counts as the top one.
+    if let Some(top) = result.scores.iter().min_by_key(|s| Reverse(s.score)) {
+        embed = embed.color(archetype_color(&top.name));
+    }
+    embed
+}
+
+/// The key to a compatibility graph, naming its numbered nodes. `coverage` is the share of pairs
+/// whose score was known.
+pub fn format_graph_key(labels: &[String], threshold: u32, coverage: f64) -> Vec<String> {
+    let mut lines = vec![format!("Matches of {threshold}% or more:\n")];
+    for (i, label) in labels.iter().enumerate() {
+        lines.push(format!("{}. {label}\n", i + 1));
+    }
+    if coverage < 1.0 {
+        lines.push(format!(
+            "_Only {:.0}% of scores are known so far_\n",
+            coverage * 100.0
+        ));
+    }
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// A matrix of scores as a numbered table, followed by the entry each number stands for. Unknown
+/// scores are shown as `-`.
+pub fn format_matrix(labels: &[String], scores: &[Vec<Option<u32>>]) -> Vec<String> {
+    let mut table = "```\n   ".to_string();
+    for column in 1..=labels.len() {
+        table += &format!("{column:>4}");
+    }
+    for (i, row) in scores.iter().enumerate() {
+        table += &format!("\n{:>2}.", i + 1);
+        for score in row {
+            match score {
+                Some(score) => table += &format!("{score:>4}"),
+                None => table += "   -",
+            }
+        }
+    }
+    table += "\n```\n";
+    let mut lines = vec![table];
+    for (i, label) in labels.iter().enumerate() {
+        lines.push(format!("{}. {label}\n", i + 1));
+    }
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// `field` as a CSV field, quoted when it contains a separator, quote or line break. Fields that
+/// a spreadsheet would read as a formula start with a `'`, so they are shown as text instead.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// A compatibility list as CSV, one row per entry in the order given. Member names lose their
+/// bold, unknown scores are left empty and result dates are in UTC.
+pub fn format_compat_csv(entries: &[CompatEntry]) -> String {
+    let mut csv = "member,headmate,score,result_date\n".to_string();
+    for entry in entries {
+        let row = [
+            csv_field(entry.member.trim_matches('*')),
+            csv_field(entry.headmate.as_deref().unwrap_or_default()),
+            entry.score.map(|s| s.to_string()).unwrap_or_default(),
+            entry
+                .taken
+                .map(|at| at.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+        ];
+        csv += &row.join(",");
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Names every entry that shares the highest score, along with how many entries were compared.
+pub fn format_best_match(subject: &str, entries: &[CompatEntry]) -> String {
+    let Some(best) = entries.iter().filter_map(|e| e.score).max() else {
+        return format!("No matches found for {subject} yet");
+    };
+    let names: Vec<_> = entries
+        .iter()
+        .filter(|e| e.score == Some(best))
+        .map(CompatEntry::label)
+        .collect();
+    format!(
+        "Best match for {subject}: {} at {best:02}% ({} entries considered)",
+        names.join(" and "),
+        entries.len()
+    )
+}
+
+/// How every stored timestamp is shown to users, in their own timezone.
+pub fn format_timestamp(at: &DateTime<Utc>, tz: Tz) -> String {
+    at.with_timezone(&tz)
+        .format("%Y-%m-%d %H:%M %Z")
+        .to_string()
+}
+
+/// Shows the official score between `me` and `them` next to what drives it.
+pub fn format_explanation(
+    me: &str,
+    them: &str,
+    score: Option<u32>,
+    explanation: &Explanation,
+) -> String {
+    let mut text = format!(
+        "Compatibility between {me} and {them}: {}\n",
+        match score {
+            Some(score) => format!("{score:02}%"),
+            None => "Invalid Result".to_string(),
+        }
+    );
+    let line = |p: &Pairing| {
+        if p.mine == p.theirs {
+            format!(
+                "- {}: {:02}% and {:02}%\n",
+                p.mine, p.my_score, p.their_score
+            )
+        } else {
+            format!(
+                "- {} {:02}% with {} {:02}%\n",
+                p.mine, p.my_score, p.theirs, p.their_score
+            )
+        }
+    };
+    text += "**What works**\n";
+    if explanation.harmonies.is_empty() {
+        text += "Nothing stands out\n";
+    }
+    text.extend(explanation.harmonies.iter().map(line));
+    text += "**Where you differ**\n";
+    if explanation.frictions.is_empty() {
+        text += "Nothing stands out\n";
+    }
+    text.extend(explanation.frictions.iter().map(line));
+    text
+}
+
+/// A short tag derived from a result ID, which tells results apart even when they were added at
+/// the same minute. It is a truncated FNV-1a hash, so it never changes between releases.
+pub fn result_tag(id: &str) -> String {
+    let hash = id.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    });
+    format!("#{:04x}", hash & 0xffff)
+}
+
+/// How each of `results` is named wherever a member picks one, in order. The label is the date
+/// in `tz` and the result's [`result_tag`], with the time added when another result shares the
+/// date: "2024-05-01 #1a2b" or "2024-05-01 14:30 #1a2b".
+pub fn result_labels(
+    results: &BTreeMap<DateTime<Utc>, String>,
+    tz: Tz,
+) -> Vec<(DateTime<Utc>, String)> {
+    let date = |at: &DateTime<Utc>| at.with_timezone(&tz).date_naive();
+    results
+        .iter()
+        .map(|(at, id)| {
+            let shared = results
+                .keys()
+                .filter(|other| date(other) == date(at))
+                .count()
+                > 1;
+            let when = if shared {
+                at.with_timezone(&tz).format("%Y-%m-%d %H:%M")
+            } else {
+                at.with_timezone(&tz).format("%Y-%m-%d")
+            };
+            (*at, format!("{when} {}", result_tag(id)))
+        })
+        .collect()
+}
+
+/// A one-line summary of the `count` highest scoring archetypes in `result`.
+pub fn format_top_archetypes(subject: &str, result: &GetResultResult, count: usize) -> String {
+    let mut scores: Vec<_> = result
+        .scores
+        .iter()
+        .filter(|s| s.score >= NOISE_THRESHOLD)
+        .collect();
+    scores.sort_by_key(|s| Reverse(s.score));
+    if scores.is_empty() {
+        return format!("{subject} has no archetypes scoring {NOISE_THRESHOLD}% or more");
+    }
+    let top: Vec<_> = scores
+        .iter()
+        .take(count)
+        .map(|s| format!("{} {}%", s.name, s.score))
+        .collect();
+    format!("{subject}'s top archetypes: {}", top.join(", "))
+}
+
+/// A newly added result in two lines: when it was taken and its top `count` archetypes.
+pub fn format_result_summary(subject: &str, result: &GetResultResult, count: usize) -> String {
+    format!(
+        "{subject} took the test on {}\n{}",
+        result.date,
+        format_top_archetypes(subject, result, count)
+    )
+}
+
+/// The lowest scores of an exceptional, great and good match. Anything lower is a low match.
+pub const EXCEPTIONAL_SCORE: u32 = 90;
+pub const GREAT_SCORE: u32 = 75;
+pub const GOOD_SCORE: u32 = 50;
+
+/// How good a match is, best first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tier {
+    Exceptional,
+    Great,
+    Good,
+    Low,
+}
+
+impl Tier {
+    pub const ALL: [Tier; 4] = [Tier::Exceptional, Tier::Great, Tier::Good, Tier::Low];
+
+    pub fn of(score: u32) -> Tier {
+        match score {
+            s if s >= EXCEPTIONAL_SCORE => Tier::Exceptional,
+            s if s >= GREAT_SCORE => Tier::Great,
+            s if s >= GOOD_SCORE => Tier::Good,
+            _ => Tier::Low,
+        }
+    }
+
+    /// The tier's name and the scores it covers, like "Great (75–89%)".
+    pub fn heading(self) -> String {
+        match self {
+            Tier::Exceptional => format!("Exceptional ({EXCEPTIONAL_SCORE}%+)"),
+            Tier::Great => format!("Great ({GREAT_SCORE}–{}%)", EXCEPTIONAL_SCORE - 1),
+            Tier::Good => format!("Good ({GOOD_SCORE}–{}%)", GREAT_SCORE - 1),
+            Tier::Low => format!("Low (<{GOOD_SCORE}%)"),
+        }
+    }
+}
+
+/// Formats the compatibility list for `subject`, sorted by descending score with invalid results
+/// last. The output is split into pages that each fit in `options.max_len` characters.
+pub fn format_compat_list(
+    subject: &str,
+    entries: &[CompatEntry],
+    options: &CompatListOptions,
+) -> Vec<String> {
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|e| Reverse(e.score));
+
+    let mut notes = Vec::new();
+    match (options.top, options.min_score) {
+        (Some(top), Some(min)) => notes.push(format!("top {top} matches ≥ {min}%")),
+        (Some(top), None) => notes.push(format!("top {top} matches")),
+        (None, Some(min)) => notes.push(format!("matches ≥ {min}%")),
+        (None, None) => {}
+    }
+    if options.not_scored > 0 {
+        notes.push(format!(
+            "PARTIAL, cancelled with {} entries left",
+            options.not_scored
+        ));
+    }
+    if options.custom_scoring {
+        notes.push("custom weighted scores, estimated locally".to_string());
+    }
+    if let Some(label) = &options.older_result {
+        notes.push(format!("using your result from {label}"));
+    }
+    if options.averaged_over > 1 {
+        notes.push(format!("averaged over {} results", options.averaged_over));
+    }
+    let mut lines = vec![if notes.is_empty() {
+        format!("Compatibility for: {subject}\n")
+    } else {
+        format!("Compatibility for: {subject} ({})\n", notes.join(", "))
+    }];
+    if options.by_tier {
+        lines.extend(tiered_lines(entries, options));
+    } else {
+        lines.extend(entry_lines(entries, options));
+    }
+    if options.skipped_headmates > 0 {
+        lines.push(format!(
+            "_{} headmate entries were left out_\n",
+            options.skipped_headmates
+        ));
+    }
+    if options.not_listable > 0 {
+        lines.push(format!(
+            "_{} entries were left out by this server's list requirements_\n",
+            options.not_listable
+        ));
+    }
+    for name in &options.unresolvable {
+        lines.push(format!("_{name}'s stored result no longer resolves_\n"));
+    }
+
+    paginate(lines, options.max_len)
+}
+
+/// The lines listing `entries`, sorted by descending score, grouped by member if the options say.
+fn entry_lines(entries: Vec<CompatEntry>, options: &CompatListOptions) -> Vec<String> {
+    if options.group_by_user {
+        return grouped_lines(entries, options);
+    }
+    entries
+        .iter()
+        .map(|entry| format!("- {}: {}\n", entry.label(), format_score(entry, options)))
+        .collect()
+}
+
+/// The lines of a list split into tiers, given `entries` sorted by descending score. Empty tiers
+/// are left out, and invalid results get their own section at the end.
+fn tiered_lines(entries: Vec<CompatEntry>, options: &CompatListOptions) -> Vec<String> {
+    let mut sections: Vec<(String, Vec<CompatEntry>)> = Tier::ALL
+        .iter()
+        .map(|tier| (tier.heading(), Vec::new()))
+        .collect();
+    sections.push(("Invalid results".to_string(), Vec::new()));
+    for entry in entries {
+        let section = match entry.score {
+            Some(score) => Tier::ALL
+                .iter()
+                .position(|&t| t == Tier::of(score))
+                .unwrap(),
+            None => Tier::ALL.len(),
+        };
+        sections[section].1.push(entry);
+    }
+
+    let mut lines = Vec::new();
+    for (heading, entries) in sections {
+        if !entries.is_empty() {
+            lines.push(format!("**{heading}**\n"));
+            lines.extend(entry_lines(entries, options));
+        }
+    }
+    lines
+}
+
+/// The lines of a grouped list, given `entries` sorted by descending score. Members with a single
+/// entry keep a single line; everyone else gets a header with their best score and one indented
+/// line per entry. Groups are ordered by their best score.
+fn grouped_lines(entries: Vec<CompatEntry>, options: &CompatListOptions) -> Vec<String> {
+    let mut groups: BTreeMap<serenity::UserId, Vec<CompatEntry>> = BTreeMap::new();
+    for entry in entries {
+        groups.entry(entry.user_id).or_default().push(entry);
+    }
+    let mut groups: Vec<_> = groups.into_values().collect();
+    // Entries are already sorted, so the first one in each group holds its best score.
+    groups.sort_by_key(|group| Reverse(group[0].score));
+
+    let mut lines = Vec::new();
+    for group in groups {
+        if let [entry] = group.as_slice() {
+            lines.push(format!(
+                "- {}: {}\n",
+                entry.label(),
+                format_score(entry, options)
+            ));
+            continue;
+        }
+        lines.push(format!(
+            "- {}: best {}\n",
+            group[0].member,
+            format_score(&group[0], options)
+        ));
+        for entry in &group {
+            lines.push(format!(
+                "  - {}: {}\n",
+                entry.headmate.as_deref().unwrap_or("Primary"),
+                format_score(entry, options)
+            ));
+        }
+    }
+    lines
+}
+
+/// What /verify_my_results found out about a stored result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// bdsmtest.org returned the result, which was taken on this date.
+    Found(String),
+    NotFound,
+    Unavailable,
+    /// Entered by hand, so there is nothing to check.
+    Manual,
+    /// Left out to keep the number of requests down.
+    Skipped,
+}
+
+/// One result checked by /verify_my_results.
+pub struct VerifiedResult {
+    pub headmate: Option<String>,
+    pub result_id: String,
+    pub verification: Verification,
+}
+
+/// Lists the outcome for every result, grouped by entry, with advice for results that are gone.
+pub fn format_verification(results: &[VerifiedResult], max_len: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = None;
+    for result in results {
+        if current != Some(&result.headmate) {
+            current = Some(&result.headmate);
+            lines.push(format!(
+                "**{}**\n",
+                result.headmate.as_deref().unwrap_or("Primary")
+            ));
+        }
+        let status = match &result.verification {
+            Verification::Found(date) => format!("OK, taken {date}"),
+            Verification::NotFound => "not found on bdsmtest.org. Remove it with \
+                                       /remove_bdsm_result, or add it again with the right ID"
+                .to_string(),
+            Verification::Unavailable => {
+                "bdsmtest.org could not be reached, try again later".to_string()
+            }
+            Verification::Manual => "entered by hand, nothing to check".to_string(),
+            Verification::Skipped => "not checked, run the command again later".to_string(),
+        };
+        lines.push(format!("- {}: {status}\n", result.result_id));
+    }
+    if lines.is_empty() {
+        lines.push("You have no results to verify\n".to_string());
+    }
+    paginate(lines, max_len)
+}
+
+/// A row of /top_archetype. Entries marked `own` belong to the invoker and are always shown.
+#[derive(Clone, Debug)]
+pub struct RankedEntry {
+    pub name: String,
+    pub score: u32,
+    pub own: bool,
+}
+
+/// Sorts `entries` by descending score and numbers them, giving tied entries the same rank
+/// (1, 1, 3, ...).
+pub fn rank(entries: &[RankedEntry]) -> Vec<(usize, RankedEntry)> {
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|e| Reverse(e.score));
+    let mut ranked: Vec<(usize, RankedEntry)> = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.into_iter().enumerate() {
+        let rank = match ranked.last() {
+            Some((rank, last)) if last.score == entry.score => *rank,
+            _ => i + 1,
+        };
+        ranked.push((rank, entry));
+    }
+    ranked
+}
+
+/// Formats the top `limit` entries for `archetype`. Any of the invoker's entries below the cutoff
+/// are appended after the top entries.
+pub fn format_archetype_ranking(
+    archetype: &str,
+    entries: &[RankedEntry],
+    limit: usize,
+    options: &CompatListOptions,
+) -> Vec<String> {
+    let mut lines = vec![format!("Top {archetype}:\n")];
+    if entries.is_empty() {
+        lines.push(format!("No one has a {archetype} score yet\n"));
+    }
+    let line = |(rank, entry): &(usize, RankedEntry)| {
+        format!("{rank}. {}: {:02}%\n", entry.name, entry.score)
+    };
+    let ranked = rank(entries);
+    lines.extend(ranked.iter().take(limit).map(line));
+    let own: Vec<_> = ranked.iter().skip(limit).filter(|(_, e)| e.own).collect();
+    if !own.is_empty() {
+        lines.push("…\n".to_string());
+        lines.extend(own.into_iter().map(line));
+    }
+
+    paginate(lines, options.max_len)
+}
+
+/// A row of /similar_on: an entry's score for the archetype, and how far it is from the invoker's.
+#[derive(Clone, Debug)]
+pub struct SimilarEntry {
+    pub name: String,
+    pub score: u32,
+    pub distance: u32,
+}
+
+/// Formats the `limit` entries closest to the invoker's `my_score` on `archetype`, closest first.
+/// Entries at the same distance share a rank. `skipped` entries had no result to compare.
+pub fn format_similarity(
+    archetype: &str,
+    my_score: u32,
+    entries: &[SimilarEntry],
+    skipped: usize,
+    limit: usize,
+) -> Vec<String> {
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|e| e.distance);
+    let mut lines = vec![format!(
+        "Closest to your {archetype} score ({my_score:02}%):\n"
+    )];
+    if entries.is_empty() {
+        lines.push(format!("No one else has a {archetype} score yet\n"));
+    }
+    let mut rank = 0;
+    for (i, entry) in entries.iter().enumerate().take(limit) {
+        if i == 0 || entries[i - 1].distance != entry.distance {
+            rank = i + 1;
+        }
+        lines.push(format!(
+            "{rank}. {}: {:02}% ({} apart)\n",
+            entry.name, entry.score, entry.distance
+        ));
+    }
+    if skipped > 0 {
+        lines.push(format!(
+            "_{skipped} entries were skipped because their results could not be fetched_\n"
+        ));
+    }
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// How long ago `taken` was, compactly and rounded down to the largest unit that fits: "today",
+/// "3d ago", "2w ago", "5mo ago" or "2y ago". Months count as 30 days and years as 365.
+pub fn format_age(taken: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    match (now - taken).num_days() {
+        ..=0 => "today".to_string(),
+        days @ 1..7 => format!("{days}d ago"),
+        days @ 7..30 => format!("{}w ago", days / 7),
+        days @ 30..365 => format!("{}mo ago", days / 30),
+        days => format!("{}y ago", days / 365),
+    }
+}
+
+/// One line per shard this process runs, with how long its last heartbeat took to be
+/// acknowledged. Shards that haven't had one acknowledged yet say so.
+pub fn format_shard_latencies(shards: &[(u32, Option<std::time::Duration>)]) -> String {
+    let mut lines = vec!["Pong!".to_string()];
+    for (id, latency) in shards {
+        lines.push(match latency {
+            Some(latency) => format!("Shard {id}: {}ms", latency.as_millis()),
+            None => format!("Shard {id}: waiting for a heartbeat"),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Describes a raw bdsmtest.org lookup of `id` for /debug_result: its status, timing, what the
+/// body parses to, and the body itself, pretty-printed if it is JSON. Bodies that would push the
+/// message past [`MESSAGE_LIMIT`] are returned separately to attach as a file instead.
+pub fn format_raw_result(id: &str, raw: &RawResponse) -> (String, Option<String>) {
+    let mut message = format!(
+        "Result {id}: HTTP {} in {}ms\n",
+        raw.status,
+        raw.elapsed.as_millis()
+    );
+    message += &match raw.parse_result() {
+        Ok(result) => format!(
+            "Parsed: version {}, taken {}, {} scores\n",
+            result.version,
+            result.date,
+            result.scores.len()
+        ),
+        Err(e) => format!("Could not parse: {e}\n"),
+    };
+    let body = serde_json::from_str::<serde_json::Value>(&raw.body)
+        .ok()
+        .and_then(|json| serde_json::to_string_pretty(&json).ok())
+        .unwrap_or_else(|| raw.body.clone());
+    // Keeps the body from closing the code block early.
+    let block = format!("```json\n{}\n```", body.replace("```", "`\u{200b}``"));
+    if message.chars().count() + block.chars().count() <= MESSAGE_LIMIT {
+        (message + &block, None)
+    } else {
+        (message + "The body is attached", Some(body))
+    }
+}
+
+/// What remove_bdsm_results would remove for an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemovalTarget {
+    pub headmate: Option<String>,
+    pub results: usize,
+    /// When the oldest and newest of those results were taken.
+    pub taken: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// How many other guilds the member has results in, which are left alone.
+    pub other_guilds: usize,
+}
+
+/// What remove_bdsm_results would remove, for its preview.
+pub fn format_removal_preview(target: &RemovalTarget, tz: Tz) -> String {
+    let entry = match &target.headmate {
+        Some(headmate) => format!("Entries for {headmate}"),
+        None => "Your primary entry".to_string(),
+    };
+    let taken = match target.taken {
+        Some((first, last)) if first == last => {
+            format!(", taken {}", format_timestamp(&first, tz))
+        }
+        Some((first, last)) => format!(
+            ", taken {} to {}",
+            format_timestamp(&first, tz),
+            format_timestamp(&last, tz)
+        ),
+        None => String::new(),
+    };
+    let mut lines = vec![format!(
+        "{entry} would be removed: {} results{taken}",
+        target.results
+    )];
+    if target.other_guilds > 0 {
+        lines.push(format!(
+            "Your results in {} other servers would stay",
+            target.other_guilds
+        ));
+    }
+    lines.push("Nothing was removed".to_string());
+    lines.join("\n")
+}
+
+/// Numbers the removed entries /recover_my_data can restore, with when each is deleted for good
+/// (`keep` after it was removed).
+pub fn format_tombstones(tombstones: &[Tombstone], keep: chrono::Duration, tz: Tz) -> String {
+    if tombstones.is_empty() {
+        return "You have no removed entries to recover".to_string();
+    }
+    let mut lines = vec!["Removed entries you can recover:".to_string()];
+    for (i, tombstone) in tombstones.iter().enumerate() {
+        let entry = match &tombstone.headmate {
+            Some(headmate) => format!("({headmate})"),
+            None => "Primary entry".to_string(),
+        };
+        lines.push(format!(
+            "{}. {entry}, {} results, removed {}, deleted for good {}",
+            i + 1,
+            tombstone.data.results.len(),
+            format_timestamp(&tombstone.deleted, tz),
+            format_timestamp(&(tombstone.deleted + keep), tz),
+        ));
+    }
+    lines.push("Use recover_my_data with a number to restore that entry".to_string());
+    lines.join("\n")
+}
+
+/// Which members have added a result, for /coverage.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub registered: usize,
+    /// Members with data but no results, who removed theirs or only changed settings. They are
+    /// not listed.
+    pub opted_out: usize,
+    /// The members with no data at all.
+    pub unregistered: Vec<serenity::UserId>,
+}
+
+/// The counts of a [`Coverage`] report in one line. `scope` says which members were looked at.
+pub fn format_coverage_counts(coverage: &Coverage, scope: &str) -> String {
+    let total = coverage.registered + coverage.opted_out + coverage.unregistered.len();
+    format!(
+        "{} of {total} {scope} have added a result, {} haven't, and {} removed their data or \
+         only changed settings and aren't listed\n",
+        coverage.registered,
+        coverage.unregistered.len(),
+        coverage.opted_out
+    )
+}
+
+/// The counts of a [`Coverage`] report, then every unregistered member, in pages.
+pub fn format_coverage(coverage: &Coverage, scope: &str) -> Vec<String> {
+    let mut lines = vec![format_coverage_counts(coverage, scope)];
+    if !coverage.unregistered.is_empty() {
+        lines.push("Not registered yet:\n".to_string());
+    }
+    lines.extend(
+        coverage
+            .unregistered
+            .iter()
+            .map(|id| format!("- <@{id}>\n")),
+    );
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// The best pairings of a server as `(score, label, label)`, best first, in pages. `unscored`
+/// pairs couldn't be scored and are only counted.
+pub fn format_leaderboard(pairings: &[(String, String, String)], unscored: usize) -> Vec<String> {
+    let mut lines = vec!["**Compatibility leaderboard**\n".to_string()];
+    if pairings.is_empty() {
+        lines.push("No pairs to rank yet, it takes two members with results\n".to_string());
+    }
+    lines.extend(
+        pairings
+            .iter()
+            .enumerate()
+            .map(|(rank, (score, a, b))| format!("{}. {a} & {b}: {score}\n", rank + 1)),
+    );
+    if unscored > 0 {
+        lines.push(format!(
+            "{unscored} pair(s) could not be scored by bdsmtest.org\n"
+        ));
+    }
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// How many results one entry holds, for /list_headmates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryResults {
+    /// `None` for the primary entry.
+    pub headmate: Option<String>,
+    pub count: usize,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// Lists the entries of `subject` one per line, in pages. Entries without results are flagged
+/// rather than left out.
+pub fn format_headmate_list(subject: &str, entries: &[EntryResults], tz: Tz) -> Vec<String> {
+    let mut lines = vec![format!("Entries for {subject}:\n")];
+    lines.extend(entries.iter().map(|entry| {
+        let name = entry.headmate.as_deref().unwrap_or("Primary");
+        match (entry.count, entry.newest) {
+            (count, Some(newest)) if count > 0 => format!(
+                "- {name}: {count} result{}, newest from {}\n",
+                if count == 1 { "" } else { "s" },
+                format_timestamp(&newest, tz)
+            ),
+            _ => format!("- {name}: no results yet\n"),
+        }
+    }));
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// What became of one result ID given to /import_results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportStatus {
+    Imported,
+    /// The entry already had it.
+    Duplicate,
+    /// bdsmtest.org has no such result.
+    Invalid,
+    /// bdsmtest.org could not be asked about it.
+    Unavailable,
+}
+
+/// Sums up an import on the first line, then says what became of each ID, in pages.
+pub fn format_import(imports: &[(String, ImportStatus)]) -> Vec<String> {
+    let count = |status| imports.iter().filter(|(_, s)| *s == status).count();
+    let skipped: Vec<_> = [
+        (ImportStatus::Invalid, "invalid"),
+        (ImportStatus::Duplicate, "duplicate"),
+        (ImportStatus::Unavailable, "unavailable"),
+    ]
+    .into_iter()
+    .map(|(status, label)| (count(status), label))
+    .filter(|(skipped, _)| *skipped > 0)
+    .map(|(skipped, label)| format!("{skipped} {label}"))
+    .collect();
+    let mut summary = format!("Imported {}", count(ImportStatus::Imported));
+    if !skipped.is_empty() {
+        summary += &format!(", skipped {}", skipped.join(", "));
+    }
+    let mut lines = vec![format!("{summary}\n")];
+    lines.extend(imports.iter().map(|(id, status)| {
+        let what = match status {
+            ImportStatus::Imported => "imported",
+            ImportStatus::Duplicate => "already stored",
+            ImportStatus::Invalid => "not found on bdsmtest.org",
+            ImportStatus::Unavailable => "could not be checked",
+        };
+        format!("- {id}: {what}\n")
+    }));
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// Rounds `gap` to the largest unit that fits.
+fn format_gap(gap: chrono::Duration) -> String {
+    match (gap.num_days(), gap.num_hours()) {
+        (1, _) => "1 day".to_string(),
+        (days, _) if days > 1 => format!("{days} days"),
+        (_, 1) => "1 hour".to_string(),
+        (_, hours) if hours > 1 => format!("{hours} hours"),
+        _ => "less than an hour".to_string(),
+    }
+}
+
+/// The biggest score changes since the previous result, for the reply to a retake. Empty if
+/// nothing changed.
+pub fn format_retake_diff(increases: &[ArchetypeChange], decreases: &[ArchetypeChange]) -> String {
+    let list = |changes: &[ArchetypeChange]| -> String {
+        changes
+            .iter()
+            .map(|c| format!("{} {:+}", c.name, i64::from(c.to) - i64::from(c.from)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut lines = Vec::new();
+    if !increases.is_empty() {
+        lines.push(format!("Up since your last result: {}", list(increases)));
+    }
+    if !decreases.is_empty() {
+        lines.push(format!("Down since your last result: {}", list(decreases)));
+    }
+    lines.join("\n")
+}
+
+/// Every archetype's score in the result labelled `from` next to the one in `to`, in pages.
+pub fn format_result_history(
+    subject: &str,
+    from: &str,
+    to: &str,
+    changes: &[ArchetypeChange],
+) -> Vec<String> {
+    let mut lines = vec![format!("{subject} from {from} to {to}:\n")];
+    lines.extend(changes.iter().map(|c| {
+        format!(
+            "- {}: {:02}% → {:02}% ({:+})\n",
+            c.name,
+            c.from,
+            c.to,
+            i64::from(c.to) - i64::from(c.from)
+        )
+    }));
+    paginate(lines, MESSAGE_LIMIT)
+}
+
+/// Summarizes `subject`'s own results. `change` is the biggest change between the oldest and
+/// newest result, where `compared` says whether both could be loaded at all.
+pub fn format_personal_stats(
+    subject: &str,
+    history: &History,
+    change: Option<&ArchetypeChange>,
+    compared: bool,
+    tz: Tz,
+) -> String {
+    let mut stats = format!(
+        "**Stats for {subject}**\nResults stored: {}\n",
+        history.count
+    );
+    let Some(gap) = history.average_gap else {
+        stats += &format!("Tested: {}\n", format_timestamp(&history.first, tz));
+        return stats + "Retake the test to see how your results change over time\n";
+    };
+    stats += &format!(
+        "First test: {}\nMost recent test: {}\nAverage time between retakes: {}\n",
+        format_timestamp(&history.first, tz),
+        format_timestamp(&history.latest, tz),
+        format_gap(gap)
+    );
+    stats += &match change {
+        Some(c) => format!(
+            "Biggest change since the first test: {} {:02}% → {:02}% ({:+})\n",
+            c.name,
+            c.from,
+            c.to,
+            i64::from(c.to) - i64::from(c.from)
+        ),
+        None if compared => "No archetype changed since the first test\n".to_string(),
+        None => "The first and most recent results could not both be loaded to compare them\n"
+            .to_string(),
+    };
+    stats
+}
+
+/// Formats the top `limit` server-wide archetype averages as a bar chart. `uncached` entries were
+/// left out because their results haven't been fetched yet.
+pub fn format_server_stats(
+    averages: &[ArchetypeAverage],
+    included: usize,
+    uncached: usize,
+    limit: usize,
+) -> String {
+    let mut stats = format!("**Server archetype averages** across {included} entries\n");
+    if averages.is_empty() {
+        stats +=
+            "No results have been fetched yet, run /list_compatibility or /show_result first\n";
+    }
+    for (rank, average) in averages.iter().take(limit).enumerate() {
+        let filled = (average.average / 10.0).round() as usize;
+        stats += &format!(
+            "{}. {} `{}{}` {:.0}%\n",
+            rank + 1,
+            average.name,
+            "█".repeat(filled),
+            "░".repeat(10 - filled.min(10)),
+            average.average
+        );
+    }
+    if uncached > 0 {
+        stats += &format!(
+            "{uncached} entries were left out because their results haven't been fetched yet\n"
+        );
+    }
+    stats
+}
+
+/// Opens and closes a code block.
+const FENCE: &str = "```";
+
+/// The fence that opens a code block again after a break, with the language of `opening`, the
+/// line that opened the block, if it named one.
+fn reopening_fence(opening: &str) -> String {
+    let info = opening.rsplit(FENCE).next().unwrap_or_default().trim_end();
+    let language = if info
+        .chars()
+        .all(|c| c.is_alphanumeric() || "+-_#.".contains(c))
+    {
+        info
+    } else {
+        ""
+    };
+    format!("{FENCE}{language}\n")
+}
+
+/// Splits `text` into messages of at most `max_len` characters, only breaking between lines
+/// unless a single line is too long to fit in a message by itself. A code block that is broken
+/// up, even in the middle of a line, is closed at the end of one message and opened again with
+/// the same language at the start of the next.
+pub fn split_message(text: &str, max_len: usize) -> Vec<String> {
+    let len = |s: &str| s.chars().count();
+    let toggles = |s: &str| s.matches(FENCE).count() % 2 == 1;
+    let mut reopen = reopening_fence("");
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    let mut in_block = false;
+    for line in text.split_inclusive('\n') {
+        let in_block_after = in_block ^ toggles(line);
+        if !in_block && in_block_after {
+            reopen = reopening_fence(line);
+        }
+        let closing = if in_block_after { len(FENCE) } else { 0 };
+        let fresh = current.is_empty() || (in_block && current == reopen);
+        if !fresh && len(&current) + len(line) + closing > max_len {
+            if in_block {
+                current += FENCE;
+            }
+            messages.push(std::mem::take(&mut current));
+            if in_block {
+                current.clone_from(&reopen);
+            }
+        }
+        let mut line = line;
+        while !line.is_empty() && len(&current) + len(line) + closing > max_len {
+            let closing = if in_block { len(FENCE) } else { 0 };
+            let room = max_len.saturating_sub(len(&current) + closing).max(1);
+            let split = line.char_indices().nth(room).map_or(line.len(), |(i, _)| i);
+            current += &line[..split];
+            in_block ^= toggles(&line[..split]);
+            line = &line[split..];
+            if in_block {
+                current += FENCE;
+            }
+            messages.push(std::mem::take(&mut current));
+            if in_block {
+                current.clone_from(&reopen);
+            }
+        }
+        current += line;
+        in_block = in_block_after;
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+/// Packs `messages` into as few pages of at most `max_len` characters as they fit in, a blank line
+/// apart. Messages too long for a page of their own are split like [`split_message`] does.
+pub fn combine_messages(messages: &[String], max_len: usize) -> Vec<String> {
+    let pieces = messages
+        .iter()
+        .flat_map(|message| split_message(message, max_len - 2))
+        .map(|piece| piece + "\n\n");
+    paginate(pieces, max_len)
+        .into_iter()
+        .map(|page| page.trim_end().to_string())
+        .collect()
+}
+
+/// Joins `lines` into pages of at most `max_len` characters, only breaking between lines unless a
+/// single line is too long to fit on a page by itself.
+fn paginate<I: IntoIterator<Item = String>>(lines: I, max_len: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if current.chars().count() + line.chars().count() > max_len && !current.is_empty() {
+            pages.push(std::mem::take(&mut current));
+        }
+        let mut line = line.as_str();
+        while line.chars().count() > max_len {
+            let split = line
+                .char_indices()
+                .nth(max_len)
+                .map(|(i, _)| i)
+                .unwrap_or(line.len());
+            pages.push(line[..split].to_string());
+            line = &line[split..];
+        }
+        current += line;
+    }
+    if !current.is_empty() {
+        pages.push(current);
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::api::GetResultScore;
+
+    /// Compares `actual` against the golden file `testdata/golden/<name>`. Run the tests with
+    /// `UPDATE_GOLDEN=1` to rewrite the golden files after an intentional formatting change.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/golden")
+            .join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("could not read {}: {e}", path.display()));
+        assert_eq!(actual, expected, "golden mismatch for {name}");
+    }
+
+    fn join_pages(pages: &[String]) -> String {
+        pages.join("\n==== page break ====\n")
+    }
+
+    fn result() -> GetResultResult {
+        let scores = [
+            ("Rigger", 100),
+            ("Rope bunny", 95),
+            ("Switch", 71),
+            ("Brat tamer", 50),
+            ("Experimentalist", 7),
+            ("Vanilla", 0),
+        ];
+        GetResultResult {
+            langfile: "en".into(),
+            date: "2024-05-01".into(),
+            version: 3,
+            gender: String::new(),
+            auth: false,
+            scores: scores
+                .into_iter()
+                .enumerate()
+                .map(|(i, (name, score))| GetResultScore {
+                    id: i as u32,
+                    name: name.into(),
+                    pairdesc: String::new(),
+                    description: String::new(),
+                    score,
+                })
+                .collect(),
+        }
+    }
+
+    fn entry(user: u64, member: &str, headmate: Option<&str>, score: Option<u32>) -> CompatEntry {
+        CompatEntry {
+            user_id: serenity::UserId::new(user),
+            member: member.into(),
+            headmate: headmate.map(String::from),
+            score,
+            estimated: false,
+            temporary: false,
+            taken: None,
+        }
+    }
+
+    #[test]
+    fn graph_key_numbers_nodes() {
+        let labels = ["**Alex**".to_string(), "**Sam** (River)".to_string()];
+        assert_eq!(
+            format_graph_key(&labels, 70, 1.0),
+            ["Matches of 70% or more:\n1. **Alex**\n2. **Sam** (River)\n"]
+        );
+        assert!(format_graph_key(&labels, 70, 0.25)[0]
+            .ends_with("_Only 25% of scores are known so far_\n"));
+    }
+
+    #[test]
+    fn matrix_numbers_rows_and_columns() {
+        let labels = ["**Alex**".to_string(), "**Sam** (River)".to_string()];
+        let scores = [vec![None, Some(90)], vec![Some(90), None]];
+        assert_eq!(
+            format_matrix(&labels, &scores),
+            ["```\n      1   2\n 1.   -  90\n 2.  90   -\n```\n1. **Alex**\n2. **Sam** (River)\n"]
+        );
+    }
+
+    #[test]
+    fn csv_quotes_names_that_need_it() {
+        let mut plain = entry(1, "**Alex**", None, Some(42));
+        plain.taken = Some("2024-05-01T12:00:00Z".parse().unwrap());
+        let entries = [
+            plain,
+            entry(2, "**Sam, Jr.**", Some("The \"Ash\""), None),
+            entry(3, "**Jo\nKit**", Some("River"), Some(7)),
+        ];
+        assert_eq!(
+            format_compat_csv(&entries),
+            "member,headmate,score,result_date\n\
+             Alex,,42,2024-05-01\n\
+             \"Sam, Jr.\",\"The \"\"Ash\"\"\",,\n\
+             \"Jo\nKit\",River,7,\n"
+        );
+    }
+
+    #[test]
+    fn csv_keeps_formulas_as_text() {
+        let entries = [
+            entry(1, "**=HYPERLINK(\"x\")**", None, Some(42)),
+            entry(2, "**+Sam**", Some("-Ash"), None),
+            entry(3, "**@Jo**", Some("Ri=ver"), Some(7)),
+        ];
+        assert_eq!(
+            format_compat_csv(&entries),
+            "member,headmate,score,result_date\n\
+             \"'=HYPERLINK(\"\"x\"\")\",,42,\n\
+             '+Sam,'-Ash,,\n\
+             '@Jo,Ri=ver,7,\n"
+        );
+    }
+
+    fn mixed_entries() -> [CompatEntry; 5] {
+        [
+            entry(1, "**Alex**", None, Some(42)),
+            entry(1, "**Alex**", Some("Ash"), Some(87)),
+            entry(3, "**Deleted User**", None, None),
+            entry(2, "**Sam**", None, Some(7)),
+            entry(2, "**Sam**", Some("River"), Some(100)),
+        ]
+    }
+
+    #[test]
+    fn personal_stats() {
+        let at =
+            |day: u32| -> DateTime<Utc> { format!("2024-01-{day:02}T12:00:00Z").parse().unwrap() };
+        let history = History {
+            count: 3,
+            first: at(1),
+            latest: at(21),
+            average_gap: Some(chrono::Duration::days(10)),
+        };
+        let change = ArchetypeChange {
+            name: "Switch".into(),
+            from: 90,
+            to: 5,
+        };
+        assert_eq!(
+            format_personal_stats("Me", &history, Some(&change), true, Tz::UTC),
+            "**Stats for Me**\n\
+             Results stored: 3\n\
+             First test: 2024-01-01 12:00 UTC\n\
+             Most recent test: 2024-01-21 12:00 UTC\n\
+             Average time between retakes: 10 days\n\
+             Biggest change since the first test: Switch 90% → 05% (-85)\n"
+        );
+        assert!(format_personal_stats("Me", &history, None, true, Tz::UTC)
+            .ends_with("No archetype changed since the first test\n"));
+        assert!(format_personal_stats("Me", &history, None, false, Tz::UTC)
+            .ends_with("could not both be loaded to compare them\n"));
+
+        let single = History {
+            count: 1,
+            first: at(1),
+            latest: at(1),
+            average_gap: None,
+        };
+        assert_eq!(
+            format_personal_stats("Me", &single, None, false, Tz::UTC),
+            "**Stats for Me**\n\
+             Results stored: 1\n\
+             Tested: 2024-01-01 12:00 UTC\n\
+             Retake the test to see how your results change over time\n"
+        );
+    }
+
+    #[test]
+    fn gaps_round_down_to_one_unit() {
+        assert_eq!(format_gap(chrono::Duration::hours(49)), "2 days");
+        assert_eq!(format_gap(chrono::Duration::hours(25)), "1 day");
+        assert_eq!(format_gap(chrono::Duration::minutes(150)), "2 hours");
+        assert_eq!(
+            format_gap(chrono::Duration::minutes(5)),
+            "less than an hour"
+        );
+    }
+
+    #[test]
+    fn result_primary() {
+        let names = ResultNames {
+            user: "zmbush",
+            headmate: None,
+            result_id: "abc123",
+            manual: false,
+            show_gender: false,
+            hidden: false,
+        };
+        assert_golden("result_primary.txt", &format_result(&result(), &names));
+    }
+
+    #[test]
+    fn result_headmate() {
+        let names = ResultNames {
+            user: "zmbush",
+            headmate: Some("Ash"),
+            result_id: "abc123",
+            manual: false,
+            show_gender: false,
+            hidden: false,
+        };
+        assert_golden("result_headmate.txt", &format_result(&result(), &names));
+    }
+
+    #[test]
+    fn result_embed() {
+        let mut result = result();
+        result.gender = "nonbinary".into();
+        let names = ResultNames {
+            user: "zmbush",
+            headmate: Some("Ash"),
+            result_id: "abc123",
+            manual: false,
+            show_gender: true,
+            hidden: true,
+        };
+        let embed = serde_json::to_string_pretty(&format_result_embed(&result, &names)).unwrap();
+        assert_golden("result_embed.json", &embed);
+    }
+
+    #[test]
+    fn result_embed_folds_extra_scores_into_the_description() {
+        let mut result = result();
+        let score = result.scores[0].clone();
+        result.scores = (0..MAX_EMBED_FIELDS + 2)
+            .map(|i| GetResultScore {
+                name: format!("Archetype {i}"),
+                ..score.clone()
+            })
+            .collect();
+        let names = ResultNames {
+            user: "zmbush",
+            headmate: None,
+            result_id: "abc123",
+            manual: true,
+            show_gender: false,
+            hidden: false,
+        };
+        let embed = serde_json::to_value(format_result_embed(&result, &names)).unwrap();
+        assert_eq!(embed["fields"].as_array().unwrap().len(), MAX_EMBED_FIELDS);
+        assert_eq!(
+            embed["description"],
+            "Result abc123\nArchetype 25: 100%\nArchetype 26: 100%"
+        );
+        assert_eq!(embed["footer"]["text"], "Entered by hand");
+    }
+
+    #[test]
+    fn result_gender_only_when_shown() {
+        let mut result = result();
+        result.gender = "nonbinary".into();
+        let mut names = ResultNames {
+            user: "zmbush",
+            headmate: None,
+            result_id: "abc123",
+            manual: false,
+            show_gender: false,
+            hidden: false,
+        };
+        assert!(!format_result(&result, &names).contains("taken as"));
+        names.show_gender = true;
+        let lines: Vec<_> = format_result(&result, &names)
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines[1], "taken as: nonbinary");
+        result.gender.clear();
+        assert!(!format_result(&result, &names).contains("taken as"));
+    }
+
+    #[test]
+    fn compat_list_mixed() {
+        let pages = format_compat_list("Alex", &mixed_entries(), &CompatListOptions::default());
+        assert_eq!(pages.len(), 1);
+        assert_golden("compat_list_mixed.txt", &join_pages(&pages));
+    }
+
+    #[test]
+    fn compat_list_grouped() {
+        let mut entries = mixed_entries().to_vec();
+        entries.extend([
+            entry(4, "**Kit**", None, Some(90)),
+            entry(5, "**Jo**", Some("Wren"), Some(50)),
+            entry(5, "**Jo**", Some("Rue"), None),
+            entry(5, "**Jo**", None, Some(88)),
+        ]);
+        let options = CompatListOptions {
+            group_by_user: true,
+            ..Default::default()
+        };
+        let pages = format_compat_list("Alex", &entries, &options);
+        assert_eq!(pages.len(), 1);
+        assert_golden("compat_list_grouped.txt", &join_pages(&pages));
+    }
+
+    #[test]
+    fn compat_list_tiered() {
+        let mut entries = mixed_entries().to_vec();
+        entries.extend([
+            entry(4, "**Kit**", None, Some(90)),
+            entry(5, "**Jo**", None, Some(75)),
+            entry(6, "**Rue**", None, Some(74)),
+        ]);
+        let options = CompatListOptions {
+            by_tier: true,
+            ..Default::default()
+        };
+        let pages = format_compat_list("Alex", &entries, &options);
+        assert_eq!(pages.len(), 1);
+        assert_golden("compat_list_tiered.txt", &join_pages(&pages));
+    }
+
+    #[test]
+    fn tiers_leave_out_empty_sections() {
+        let options = CompatListOptions {
+            by_tier: true,
+            ..Default::default()
+        };
+        let entries = [
+            entry(1, "**Alex**", None, Some(95)),
+            entry(2, "**Sam**", None, Some(10)),
+        ];
+        assert_eq!(
+            format_compat_list("Me", &entries, &options),
+            ["Compatibility for: Me\n\
+              **Exceptional (90%+)**\n- **Alex**: 95%\n\
+              **Low (<50%)**\n- **Sam**: 10%\n"]
+        );
+        let entries = [
+            entry(1, "**Alex**", None, None),
+            entry(2, "**Sam**", None, None),
+        ];
+        assert_eq!(
+            format_compat_list("Me", &entries, &options),
+            [
+                "Compatibility for: Me\n**Invalid results**\n- **Alex**: Invalid Result\n\
+              - **Sam**: Invalid Result\n"
+            ]
+        );
+        assert_eq!(
+            format_compat_list("Me", &[], &options),
+            ["Compatibility for: Me\n"]
+        );
+    }
+
+    #[test]
+    fn tiers_start_at_their_boundaries() {
+        assert_eq!(Tier::of(100), Tier::Exceptional);
+        assert_eq!(Tier::of(EXCEPTIONAL_SCORE), Tier::Exceptional);
+        assert_eq!(Tier::of(EXCEPTIONAL_SCORE - 1), Tier::Great);
+        assert_eq!(Tier::of(GREAT_SCORE), Tier::Great);
+        assert_eq!(Tier::of(GOOD_SCORE), Tier::Good);
+        assert_eq!(Tier::of(GOOD_SCORE - 1), Tier::Low);
+        assert_eq!(Tier::of(0), Tier::Low);
+        assert_eq!(Tier::Great.heading(), "Great (75–89%)");
+    }
+
+    #[test]
+    fn ages_round_down_to_the_largest_unit() {
+        let now: DateTime<Utc> = "2024-06-01T12:00:00Z".parse().unwrap();
+        let ago = |hours: i64| format_age(now - chrono::Duration::hours(hours), now);
+        assert_eq!(ago(23), "today");
+        assert_eq!(ago(-5), "today");
+        assert_eq!(ago(24), "1d ago");
+        assert_eq!(ago(6 * 24 + 23), "6d ago");
+        assert_eq!(ago(7 * 24), "1w ago");
+        assert_eq!(ago(29 * 24), "4w ago");
+        assert_eq!(ago(30 * 24), "1mo ago");
+        assert_eq!(ago(364 * 24), "12mo ago");
+        assert_eq!(ago(365 * 24), "1y ago");
+        assert_eq!(ago(3 * 365 * 24), "3y ago");
+    }
+
+    #[test]
+    fn raw_results_show_parse_errors_and_attach_long_bodies() {
+        let raw = RawResponse {
+            status: reqwest::StatusCode::OK,
+            elapsed: std::time::Duration::from_millis(120),
+            body: r#"{"scores":[]}"#.into(),
+        };
+        let (message, attached) = format_raw_result("abc", &raw);
+        assert!(message.starts_with("Result abc: HTTP 200 OK in 120ms\nCould not parse: missing"));
+        assert!(message.ends_with("```json\n{\n  \"scores\": []\n}\n```"));
+        assert_eq!(attached, None);
+
+        let long = RawResponse {
+            body: "x".repeat(MESSAGE_LIMIT),
+            ..raw
+        };
+        let (message, attached) = format_raw_result("abc", &long);
+        assert!(message.ends_with("The body is attached"));
+        assert_eq!(attached.unwrap().len(), MESSAGE_LIMIT);
+    }
+
+    #[test]
+    fn removal_previews_name_the_entry_and_dates() {
+        let first: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let last: DateTime<Utc> = "2024-06-01T12:00:00Z".parse().unwrap();
+        let mut target = RemovalTarget {
+            headmate: Some("Ash".into()),
+            results: 2,
+            taken: Some((first, last)),
+            other_guilds: 1,
+        };
+        assert_eq!(
+            format_removal_preview(&target, Tz::UTC),
+            "Entries for Ash would be removed: 2 results, taken 2024-05-01 12:00 UTC to \
+             2024-06-01 12:00 UTC\nYour results in 1 other servers would stay\nNothing was removed"
+        );
+        target.headmate = None;
+        target.results = 0;
+        target.taken = None;
+        target.other_guilds = 0;
+        assert_eq!(
+            format_removal_preview(&target, Tz::UTC),
+            "Your primary entry would be removed: 0 results\nNothing was removed"
+        );
+    }
+
+    #[test]
+    fn retake_diffs_are_signed() {
+        let change = |name: &str, from, to| ArchetypeChange {
+            name: name.into(),
+            from,
+            to,
+        };
+        assert_eq!(
+            format_retake_diff(
+                &[change("Rigger", 10, 70), change("Brat", 40, 45)],
+                &[change("Switch", 90, 30)]
+            ),
+            "Up since your last result: Rigger +60, Brat +5\n\
+             Down since your last result: Switch -60"
+        );
+        assert_eq!(format_retake_diff(&[], &[]), "");
+    }
+
+    #[test]
+    fn tombstones_are_numbered_with_their_deadline() {
+        let deleted: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let mut data = crate::data::HeadmateData::default();
+        data.results.insert(deleted, "abc".into());
+        let tombstones = [
+            Tombstone {
+                headmate: None,
+                deleted,
+                was_default: false,
+                data: data.clone(),
+            },
+            Tombstone {
+                headmate: Some("Ash".into()),
+                deleted,
+                was_default: true,
+                data,
+            },
+        ];
+        assert_eq!(
+            format_tombstones(&tombstones, chrono::Duration::days(14), Tz::UTC),
+            "Removed entries you can recover:\n\
+             1. Primary entry, 1 results, removed 2024-05-01 12:00 UTC, deleted for good \
+             2024-05-15 12:00 UTC\n\
+             2. (Ash), 1 results, removed 2024-05-01 12:00 UTC, deleted for good 2024-05-15 12:00 \
+             UTC\n\
+             Use recover_my_data with a number to restore that entry"
+        );
+        assert_eq!(
+            format_tombstones(&[], chrono::Duration::days(14), Tz::UTC),
+            "You have no removed entries to recover"
+        );
+    }
+
+    #[test]
+    fn shard_latencies_list_every_shard() {
+        let ms = std::time::Duration::from_millis;
+        assert_eq!(
+            format_shard_latencies(&[(0, Some(ms(42))), (1, None), (2, Some(ms(130)))]),
+            "Pong!\nShard 0: 42ms\nShard 1: waiting for a heartbeat\nShard 2: 130ms"
+        );
+    }
+
+    #[test]
+    fn compat_list_shows_ages_and_still_fits() {
+        let now: DateTime<Utc> = "2024-06-01T12:00:00Z".parse().unwrap();
+        let mut entries = crate::testutil::synthetic_entries(200);
+        for entry in &mut entries {
+            entry.taken = Some(now - chrono::Duration::days(800));
+        }
+        let options = CompatListOptions {
+            now,
+            ..Default::default()
+        };
+        let pages = format_compat_list("Member 000", &entries, &options);
+        assert!(pages.iter().all(|p| p.len() <= MESSAGE_LIMIT));
+        assert!(pages[0].lines().nth(1).unwrap().ends_with("% (2y ago)"));
+    }
+
+    #[test]
+    fn compat_list_marks_temporary_entries() {
+        let mut guest = entry(2, "**Sam**", None, Some(70));
+        guest.temporary = true;
+        let pages = format_compat_list(
+            "Alex",
+            &[guest, entry(1, "**Alex**", None, Some(42))],
+            &CompatListOptions::default(),
+        );
+        assert_eq!(
+            pages,
+            ["Compatibility for: Alex\n- **Sam**: 70% ⏳\n- **Alex**: 42%\n"]
+        );
+    }
+
+    #[test]
+    fn compat_list_long_names() {
+        let entries = [
+            entry(
+                1,
+                &format!("**{}**", "Very Long Display Name ".repeat(3)),
+                None,
+                Some(64),
+            ),
+            entry(
+                2,
+                "**Someone**",
+                Some("An Exceptionally Long Headmate Name"),
+                Some(65),
+            ),
+        ];
+        let pages = format_compat_list("Someone", &entries, &CompatListOptions::default());
+        assert_golden("compat_list_long_names.txt", &join_pages(&pages));
+    }
+
+    #[test]
+    fn compat_list_straddles_message_limit() {
+        let entries: Vec<_> = (0..120)
+            .map(|i| {
+                entry(
+                    i as u64 + 1,
+                    &format!("**Member {i:03}**"),
+                    Some(&format!("Headmate {i:03}")),
+                    (i % 17 != 0).then_some(i % 101),
+                )
+            })
+            .collect();
+        let pages = format_compat_list("Member 000", &entries, &CompatListOptions::default());
+        assert!(pages.len() > 1);
+        for page in &pages {
+            assert!(page.chars().count() <= MESSAGE_LIMIT);
+            assert!(page.ends_with('\n'));
+        }
+        assert_eq!(pages.concat().lines().count(), entries.len() + 1);
+        assert_golden("compat_list_straddle.txt", &join_pages(&pages));
+    }
+
+    #[test]
+    fn ranking_shares_ties_and_shows_own_position() {
+        let ranked = |name: &str, score, own| RankedEntry {
+            name: name.into(),
+            score,
+            own,
+        };
+        let entries = [
+            ranked("**Sam**", 40, false),
+            ranked("**Alex**", 90, false),
+            ranked("**Me** (Ash)", 10, true),
+            ranked("**Kit**", 90, false),
+            ranked("**Me**", 60, true),
+            ranked("**Jo**", 40, false),
+        ];
+        let pages = format_archetype_ranking("Rigger", &entries, 3, &CompatListOptions::default());
+        assert_eq!(
+            pages,
+            [concat!(
+                "Top Rigger:\n",
+                "1. **Alex**: 90%\n",
+                "1. **Kit**: 90%\n",
+                "3. **Me**: 60%\n",
+                "…\n",
+                "6. **Me** (Ash): 10%\n",
+            )]
+        );
+        let ranks: Vec<_> = rank(&entries).into_iter().map(|(r, _)| r).collect();
+        assert_eq!(ranks, [1, 1, 3, 4, 4, 6]);
+    }
+
+    #[test]
+    fn server_stats_bar_chart() {
+        let average = |name: &str, average| ArchetypeAverage {
+            name: name.into(),
+            average,
+            count: 2,
+        };
+        let averages = [
+            average("Switch", 75.0),
+            average("Brat", 44.5),
+            average("Rigger", 3.0),
+        ];
+        assert_eq!(
+            format_server_stats(&averages, 2, 1, 2),
+            concat!(
+                "**Server archetype averages** across 2 entries\n",
+                "1. Switch `████████░░` 75%\n",
+                "2. Brat `████░░░░░░` 44%\n",
+                "1 entries were left out because their results haven't been fetched yet\n",
+            )
+        );
+    }
+
+    #[test]
+    fn top_archetypes_skip_noise() {
+        assert_eq!(
+            format_top_archetypes("**Alex**", &result(), 5),
+            "**Alex**'s top archetypes: Rigger 100%, Rope bunny 95%, Switch 71%, Brat tamer 50%"
+        );
+        assert_eq!(
+            format_top_archetypes("**Alex**", &result(), 2),
+            "**Alex**'s top archetypes: Rigger 100%, Rope bunny 95%"
+        );
+    }
+
+    #[test]
+    fn best_match_lists_ties() {
+        let entries = [
+            entry(1, "**Alex**", None, Some(87)),
+            entry(2, "**Sam**", None, None),
+            entry(2, "**Sam**", Some("River"), Some(87)),
+            entry(3, "**Kit**", None, Some(7)),
+        ];
+        assert_eq!(
+            format_best_match("**Me**", &entries),
+            "Best match for **Me**: **Alex** and **Sam** (River) at 87% (4 entries considered)"
+        );
+        assert_eq!(
+            format_best_match("**Me**", &[entry(2, "**Sam**", None, None)]),
+            "No matches found for **Me** yet"
+        );
+    }
+
+    #[test]
+    fn verification_groups_by_entry() {
+        let verified = |headmate: Option<&str>, id: &str, verification| VerifiedResult {
+            headmate: headmate.map(String::from),
+            result_id: id.into(),
+            verification,
+        };
+        let results = [
+            verified(None, "abc", Verification::Found("2024-05-01".into())),
+            verified(None, "gone", Verification::NotFound),
+            verified(Some("Ash"), "manual-1", Verification::Manual),
+            verified(Some("Ash"), "def", Verification::Unavailable),
+            verified(Some("Kit"), "ghi", Verification::Skipped),
+        ];
+        assert_eq!(
+            format_verification(&results, MESSAGE_LIMIT).concat(),
+            "**Primary**\n\
+             - abc: OK, taken 2024-05-01\n\
+             - gone: not found on bdsmtest.org. Remove it with /remove_bdsm_result, or add it \
+             again with the right ID\n\
+             **Ash**\n\
+             - manual-1: entered by hand, nothing to check\n\
+             - def: bdsmtest.org could not be reached, try again later\n\
+             **Kit**\n\
+             - ghi: not checked, run the command again later\n"
+        );
+        assert_eq!(
+            format_verification(&[], MESSAGE_LIMIT),
+            ["You have no results to verify\n"]
+        );
+    }
+
+    #[test]
+    fn result_labels_add_times_for_shared_dates() {
+        let results = BTreeMap::from([
+            (
+                "2024-05-01T03:30:00Z".parse().unwrap(),
+                "abc123".to_string(),
+            ),
+            (
+                "2024-05-01T22:00:00Z".parse().unwrap(),
+                "def456".to_string(),
+            ),
+            (
+                "2024-05-03T12:00:00Z".parse().unwrap(),
+                "ghi789".to_string(),
+            ),
+        ]);
+        let labels = |tz| -> Vec<String> {
+            result_labels(&results, tz)
+                .into_iter()
+                .map(|(_, label)| label)
+                .collect()
+        };
+        let [a, b, c] = ["abc123", "def456", "ghi789"].map(result_tag);
+        assert_eq!(
+            labels(Tz::UTC),
+            [
+                format!("2024-05-01 03:30 {a}"),
+                format!("2024-05-01 22:00 {b}"),
+                format!("2024-05-03 {c}"),
+            ]
+        );
+        // In Los Angeles the first result was taken the evening before.
+        assert_eq!(
+            labels(Tz::America__Los_Angeles),
+            [
+                format!("2024-04-30 {a}"),
+                format!("2024-05-01 {b}"),
+                format!("2024-05-03 {c}"),
+            ]
+        );
+        assert_eq!(result_tag("abc123"), a);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn similarity_ranks_by_distance() {
+        let similar = |name: &str, score, distance| SimilarEntry {
+            name: name.into(),
+            score,
+            distance,
+        };
+        let entries = [
+            similar("**Sam**", 60, 22),
+            similar("**Alex**", 90, 8),
+            similar("**Kit**", 74, 8),
+            similar("**Jo**", 82, 0),
+        ];
+        assert_eq!(
+            format_similarity("Sadist", 82, &entries, 2, 3),
+            [concat!(
+                "Closest to your Sadist score (82%):\n",
+                "1. **Jo**: 82% (0 apart)\n",
+                "2. **Alex**: 90% (8 apart)\n",
+                "2. **Kit**: 74% (8 apart)\n",
+                "_2 entries were skipped because their results could not be fetched_\n",
+            )]
+        );
+        assert_eq!(
+            format_similarity("Sadist", 5, &[], 0, 3),
+            ["Closest to your Sadist score (05%):\nNo one else has a Sadist score yet\n"]
+        );
+    }
+
+    #[test]
+    fn explanation_lists_pairings() {
+        let explanation = Explanation {
+            harmonies: vec![Pairing {
+                mine: "Dominant".into(),
+                my_score: 90,
+                theirs: "Submissive".into(),
+                their_score: 85,
+            }],
+            frictions: vec![Pairing {
+                mine: "Switch".into(),
+                my_score: 5,
+                theirs: "Switch".into(),
+                their_score: 80,
+            }],
+        };
+        assert_eq!(
+            format_explanation("**Me**", "**Sam**", Some(87), &explanation),
+            "Compatibility between **Me** and **Sam**: 87%\n\
+             **What works**\n\
+             - Dominant 90% with Submissive 85%\n\
+             **Where you differ**\n\
+             - Switch: 05% and 80%\n"
+        );
+    }
+
+    #[test]
+    fn timestamps_in_user_timezone() {
+        let at = "2024-05-01T03:30:00Z".parse().unwrap();
+        assert_eq!(format_timestamp(&at, Tz::UTC), "2024-05-01 03:30 UTC");
+        assert_eq!(
+            format_timestamp(&at, Tz::America__Los_Angeles),
+            "2024-04-30 20:30 PDT"
+        );
+    }
+
+    #[test]
+    fn coverage_lists_only_unregistered_members() {
+        let coverage = Coverage {
+            registered: 3,
+            opted_out: 1,
+            unregistered: vec![serenity::UserId::new(1), serenity::UserId::new(2)],
+        };
+        assert_eq!(
+            format_coverage(&coverage, "members"),
+            [
+                "3 of 6 members have added a result, 2 haven't, and 1 removed their data or only \
+              changed settings and aren't listed\nNot registered yet:\n- <@1>\n- <@2>\n"
+            ]
+        );
+        let many = Coverage {
+            unregistered: vec![serenity::UserId::new(123_456_789_012_345_678); 200],
+            ..coverage
+        };
+        let pages = format_coverage(&many, "members");
+        assert!(pages.len() > 1 && pages.iter().all(|p| p.len() <= MESSAGE_LIMIT));
+    }
+
+    #[test]
+    fn split_messages_reopen_code_blocks() {
+        assert_eq!(split_message("short\n", 20), ["short\n"]);
+        let text = "intro\n```==== Me ====\nRigger 90%\nSwitch 50%\nBrat   40%\n```\nafter\n";
+        let messages = split_message(text, 30);
+        assert_eq!(
+            messages,
+            [
+                "intro\n```==== Me ====\n```",
+                "```\nRigger 90%\nSwitch 50%\n```",
+                "```\nBrat   40%\n```\nafter\n",
+            ]
+        );
+        assert!(messages.iter().all(|m| m.chars().count() <= 30));
+        assert!(messages.iter().all(|m| m.matches(FENCE).count() % 2 == 0));
+        assert_eq!(split_message(&"a".repeat(25), 10).concat(), "a".repeat(25));
+    }
+
+    #[test]
+    fn split_messages_break_long_lines_in_code_blocks() {
+        let text = format!("```diff\n+ {}\n```\n", "a".repeat(40));
+        let messages = split_message(&text, 20);
+        assert_eq!(
+            messages,
+            [
+                "```diff\n+ aaaaaaa```",
+                "```diff\naaaaaaaaa```",
+                "```diff\naaaaaaaaa```",
+                "```diff\naaaaaaaaa```",
+                "```diff\naaaaaa\n```\n",
+            ]
+        );
+        assert!(messages.iter().all(|m| m.chars().count() <= 20));
+        assert!(messages.iter().all(|m| m.matches(FENCE).count() % 2 == 0));
+        assert_eq!(reopening_fence("```==== Me ====\n"), "```\n");
+    }
+
+    #[test]
+    fn combine_messages_packs_whole_messages() {
+        let messages = ["a".repeat(4), "b".repeat(4), "c".repeat(4), "d".repeat(20)];
+        assert_eq!(
+            combine_messages(&messages, 12),
+            ["aaaa\n\nbbbb", "cccc", "dddddddddd", "dddddddddd"]
+        );
+    }
+
+    #[test]
+    fn paginate_splits_oversized_line() {
+        let pages = paginate(["a".repeat(25)], 10);
+        assert_eq!(pages, ["a".repeat(10), "a".repeat(10), "a".repeat(5)]);
+    }
+
+    #[test]
+    fn import_sums_up_every_id() {
+        let imports = [
+            ("a".to_string(), ImportStatus::Imported),
+            ("b".to_string(), ImportStatus::Invalid),
+            ("c".to_string(), ImportStatus::Imported),
+            ("d".to_string(), ImportStatus::Duplicate),
+        ];
+        assert_eq!(
+            format_import(&imports),
+            vec![
+                "Imported 2, skipped 1 invalid, 1 duplicate\n- a: imported\n- b: not found \
+                 on bdsmtest.org\n- c: imported\n- d: already stored\n"
+            ]
+        );
+    }
+}
@@ -0,0 +1,493 @@
+//! Draws a guild as a graph of its strong matches: a node for every listed entry, and an edge
+//! between every pair of entries whose cached score reaches a threshold. The layout and drawing
+//! only work on a [`Graph`], so they can be tried out on made-up graphs.
+
+use std::{collections::BTreeMap, f64::consts::TAU};
+
+use poise::serenity_prelude as serenity;
+
+use crate::{
+    cache::{Cache, Matchup},
+    data::{is_manual, GuildData},
+    format::Tier,
+    logic::{charted_entries, entry_label},
+    png,
+};
+
+/// The lowest score drawn as an edge when none is given.
+pub const DEFAULT_THRESHOLD: u32 = 70;
+/// The most nodes a graph can have and still be readable.
+pub const MAX_NODES: usize = 50;
+/// Below this share of scored pairs, a graph can offer to look up the rest first.
+pub const POOR_COVERAGE: f64 = 0.5;
+/// How many rounds the layout runs for.
+const ITERATIONS: usize = 300;
+/// How hard the layout pulls every node towards the middle.
+const GRAVITY: f64 = 1.0;
+/// The width and height of the image, in pixels.
+const SIZE: usize = 800;
+const MARGIN: f64 = 40.0;
+const NODE_RADIUS: f64 = 13.0;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const NODE: [u8; 3] = [240, 150, 60];
+const TEXT: [u8; 3] = [0, 0, 0];
+
+/// The entries of a graph and the strong matches between them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub labels: Vec<String>,
+    /// `(node, node, score)`, from the scores that reach the threshold.
+    pub edges: Vec<(usize, usize, u32)>,
+}
+
+/// The graph of a guild's listed entries, along with the pairs whose score isn't cached (and
+/// could be looked up) and how many pairs there are in total.
+pub struct Built {
+    pub graph: Graph,
+    pub missing: Vec<Matchup>,
+    pub pairs: usize,
+}
+
+impl Built {
+    /// The share of pairs with a cached score.
+    pub fn coverage(&self) -> f64 {
+        match self.pairs {
+            0 => 1.0,
+            pairs => 1.0 - self.missing.len() as f64 / pairs as f64,
+        }
+    }
+}
+
+/// Builds the graph of the [`charted_entries`] of `guild` for `viewer`, using only cached scores.
+/// Entries of the same user are never joined, and entries without an edge are left out unless
+/// `include_isolated` is set. Scores can't be looked up for manual results, so they never count
+/// as missing.
+pub fn build(
+    guild: &GuildData,
+    viewer: serenity::UserId,
+    cache: &Cache,
+    member_names: &BTreeMap<serenity::UserId, String>,
+    threshold: u32,
+    include_isolated: bool,
+) -> Built {
+    let entries = charted_entries(guild, viewer);
+    let mut edges = Vec::new();
+    let mut missing = Vec::new();
+    let mut pairs = 0;
+    for (i, (a, a_id)) in entries.iter().enumerate() {
+        for (j, (b, b_id)) in entries.iter().enumerate().skip(i + 1) {
+            if a.user_id == b.user_id {
+                continue;
+            }
+            pairs += 1;
+            let matchup = Matchup::new((*a_id).clone(), (*b_id).clone());
+            match cache.get(&matchup) {
+                Some(score) if score >= threshold => edges.push((i, j, score)),
+                Some(_) => {}
+                None if is_manual(a_id) || is_manual(b_id) => pairs -= 1,
+                None => missing.push(matchup),
+            }
+        }
+    }
+
+    let kept: Vec<usize> = (0..entries.len())
+        .filter(|&i| include_isolated || edges.iter().any(|&(a, b, _)| a == i || b == i))
+        .collect();
+    let index = |node: usize| kept.iter().position(|&k| k == node).unwrap();
+    let graph = Graph {
+        labels: kept
+            .iter()
+            .map(|&i| entry_label(member_names, &entries[i].0))
+            .collect(),
+        edges: edges
+            .iter()
+            .map(|&(a, b, score)| (index(a), index(b), score))
+            .collect(),
+    };
+    Built {
+        graph,
+        missing,
+        pairs,
+    }
+}
+
+/// Places the nodes of `graph` with a force-directed layout: every node pushes the others away
+/// and every edge pulls its ends together, harder the higher its score. Nodes start on a circle,
+/// so the same graph always gets the same layout. Positions are scaled to fit `0.0..=1.0`.
+pub fn layout(graph: &Graph) -> Vec<(f64, f64)> {
+    let n = graph.labels.len();
+    let mut positions: Vec<(f64, f64)> = (0..n)
+        .map(|i| {
+            let angle = TAU * i as f64 / n as f64;
+            (angle.cos(), angle.sin())
+        })
+        .collect();
+    let ideal = 2.0 / (n.max(1) as f64).sqrt();
+    let mut temperature = 0.2;
+    for _ in 0..ITERATIONS {
+        let mut moves = vec![(0.0, 0.0); n];
+        for i in 0..n {
+            for j in 0..n {
+                if i == j {
+                    continue;
+                }
+                let (dx, dy) = (
+                    positions[i].0 - positions[j].0,
+                    positions[i].1 - positions[j].1,
+                );
+                let distance = dx.hypot(dy).max(0.01);
+                let push = ideal * ideal / distance;
+                moves[i].0 += dx / distance * push;
+                moves[i].1 += dy / distance * push;
+            }
+        }
+        // A pull towards the middle keeps unconnected parts of the graph from drifting apart.
+        for (movement, position) in moves.iter_mut().zip(&positions) {
+            movement.0 -= position.0 * GRAVITY * ideal;
+            movement.1 -= position.1 * GRAVITY * ideal;
+        }
+        for &(a, b, score) in &graph.edges {
+            let (dx, dy) = (
+                positions[a].0 - positions[b].0,
+                positions[a].1 - positions[b].1,
+            );
+            let distance = dx.hypot(dy).max(0.01);
+            let pull = distance * distance / ideal * f64::from(score) / 100.0;
+            moves[a].0 -= dx / distance * pull;
+            moves[a].1 -= dy / distance * pull;
+            moves[b].0 += dx / distance * pull;
+            moves[b].1 += dy / distance * pull;
+        }
+        for (position, (mx, my)) in positions.iter_mut().zip(moves) {
+            let length = mx.hypot(my);
+            if length > 0.0 {
+                let step = length.min(temperature);
+                position.0 += mx / length * step;
+                position.1 += my / length * step;
+            }
+        }
+        temperature *= 0.98;
+    }
+
+    let min_x = positions.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let max_x = positions
+        .iter()
+        .map(|p| p.0)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = positions.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let max_y = positions
+        .iter()
+        .map(|p| p.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let scale = |v: f64, min: f64, max: f64| {
+        if max - min < 1e-9 {
+            0.5
+        } else {
+            (v - min) / (max - min)
+        }
+    };
+    positions
+        .into_iter()
+        .map(|(x, y)| (scale(x, min_x, max_x), scale(y, min_y, max_y)))
+        .collect()
+}
+
+/// The pixels of `positions[i]` on the image.
+fn to_pixels((x, y): (f64, f64)) -> (f64, f64) {
+    let span = SIZE as f64 - 2.0 * MARGIN;
+    (MARGIN + x * span, MARGIN + y * span)
+}
+
+/// How thick the edge of a match scoring `score` is drawn, in pixels.
+pub fn edge_width(score: u32) -> f64 {
+    1.0 + 5.0 * f64::from(score.min(100)) / 100.0
+}
+
+/// The color of the edge of a match scoring `score`, darker for better tiers.
+pub fn edge_color(score: u32) -> [u8; 3] {
+    match Tier::of(score) {
+        Tier::Exceptional => [40, 60, 170],
+        Tier::Great => [90, 110, 200],
+        Tier::Good => [140, 160, 215],
+        Tier::Low => [195, 200, 225],
+    }
+}
+
+/// 3x5 pixel digits, a row per entry with the leftmost pixel in the highest bit.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+/// How many image pixels each pixel of a digit takes, per side.
+const DIGIT_SCALE: usize = 2;
+
+/// An RGB image being drawn on.
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new() -> Self {
+        Canvas {
+            pixels: BACKGROUND.repeat(SIZE * SIZE),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < SIZE && y < SIZE {
+            let at = (y * SIZE + x) * 3;
+            self.pixels[at..at + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Colors every pixel within the box `from..to` for which `inside` holds.
+    fn fill(
+        &mut self,
+        from: (f64, f64),
+        to: (f64, f64),
+        color: [u8; 3],
+        inside: impl Fn(f64, f64) -> bool,
+    ) {
+        let clamp = |v: f64| v.clamp(0.0, SIZE as f64) as usize;
+        for y in clamp(from.1.floor())..clamp(to.1.ceil()) {
+            for x in clamp(from.0.floor())..clamp(to.0.ceil()) {
+                if inside(x as f64 + 0.5, y as f64 + 0.5) {
+                    self.set(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, a: (f64, f64), b: (f64, f64), width: f64, color: [u8; 3]) {
+        let half = width / 2.0;
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx * dx + dy * dy;
+        self.fill(
+            (a.0.min(b.0) - half, a.1.min(b.1) - half),
+            (a.0.max(b.0) + half, a.1.max(b.1) + half),
+            color,
+            |x, y| {
+                let t = if length > 0.0 {
+                    (((x - a.0) * dx + (y - a.1) * dy) / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (x - a.0 - t * dx).hypot(y - a.1 - t * dy) <= half
+            },
+        );
+    }
+
+    fn circle(&mut self, center: (f64, f64), radius: f64, color: [u8; 3]) {
+        self.fill(
+            (center.0 - radius, center.1 - radius),
+            (center.0 + radius, center.1 + radius),
+            color,
+            |x, y| (x - center.0).hypot(y - center.1) <= radius,
+        );
+    }
+
+    /// Writes `number` centered on `center`.
+    fn number(&mut self, center: (f64, f64), number: usize, color: [u8; 3]) {
+        let digits: Vec<usize> = number
+            .to_string()
+            .bytes()
+            .map(|b| usize::from(b - b'0'))
+            .collect();
+        let width = (digits.len() * 4 - 1) * DIGIT_SCALE;
+        let left = (center.0 - width as f64 / 2.0).max(0.0) as usize;
+        let top = (center.1 - (5 * DIGIT_SCALE) as f64 / 2.0).max(0.0) as usize;
+        for (n, &digit) in digits.iter().enumerate() {
+            for (row, bits) in DIGITS[digit].iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..DIGIT_SCALE {
+                        for dx in 0..DIGIT_SCALE {
+                            let x = left + (n * 4 + column) * DIGIT_SCALE + dx;
+                            self.set(x, top + row * DIGIT_SCALE + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draws `graph` with its nodes at `positions` (see [`layout`]), each node numbered from 1 in the
+/// order of `graph.labels`. Returns the raw RGB pixels, row by row.
+fn draw(graph: &Graph, positions: &[(f64, f64)]) -> Vec<u8> {
+    let mut canvas = Canvas::new();
+    for &(a, b, score) in &graph.edges {
+        canvas.line(
+            to_pixels(positions[a]),
+            to_pixels(positions[b]),
+            edge_width(score),
+            edge_color(score),
+        );
+    }
+    for (i, &position) in positions.iter().enumerate() {
+        canvas.circle(to_pixels(position), NODE_RADIUS, NODE);
+        canvas.number(to_pixels(position), i + 1, TEXT);
+    }
+    canvas.pixels
+}
+
+/// Lays out and draws `graph` as a PNG.
+pub fn render(graph: &Graph) -> Vec<u8> {
+    png::encode(SIZE, SIZE, &draw(graph, &layout(graph)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{UserData, Visibility};
+
+    fn graph(nodes: usize, edges: &[(usize, usize, u32)]) -> Graph {
+        Graph {
+            labels: (1..=nodes).map(|i| format!("node {i}")).collect(),
+            edges: edges.to_vec(),
+        }
+    }
+
+    fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+        (a.0 - b.0).hypot(a.1 - b.1)
+    }
+
+    #[test]
+    fn matches_end_up_closer_than_strangers() {
+        let graph = graph(4, &[(0, 1, 95), (2, 3, 95)]);
+        let positions = layout(&graph);
+        assert_eq!(positions, layout(&graph));
+        assert!(positions
+            .iter()
+            .all(|&(x, y)| (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y)));
+        assert!(distance(positions[0], positions[1]) < distance(positions[0], positions[2]));
+        assert!(distance(positions[2], positions[3]) < distance(positions[1], positions[3]));
+    }
+
+    #[test]
+    fn lays_out_tiny_graphs() {
+        assert!(layout(&graph(0, &[])).is_empty());
+        assert_eq!(layout(&graph(1, &[])), [(0.5, 0.5)]);
+    }
+
+    #[test]
+    fn higher_scores_draw_thicker_edges() {
+        assert!(edge_width(90) > edge_width(60));
+        assert_eq!(edge_width(100), edge_width(250));
+    }
+
+    #[test]
+    fn draws_nodes_over_edges() {
+        let graph = graph(2, &[(0, 1, 80)]);
+        let positions = [(0.0, 0.5), (1.0, 0.5)];
+        let pixels = draw(&graph, &positions);
+        let at = |(x, y): (f64, f64)| {
+            let i = (y as usize * SIZE + x as usize) * 3;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+        assert_eq!(at((SIZE as f64 / 2.0, SIZE as f64 / 2.0)), edge_color(80));
+        let (x, y) = to_pixels(positions[0]);
+        assert_eq!(at((x + NODE_RADIUS - 2.0, y)), NODE);
+        assert_eq!(at((x, 5.0)), BACKGROUND);
+    }
+
+    #[test]
+    fn encodes_a_png() {
+        let png = render(&graph(3, &[(0, 1, 70), (1, 2, 90)]));
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+        assert_eq!(&png[16..24], [0, 0, 3, 32, 0, 0, 3, 32]);
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn builds_from_cached_scores_of_listed_entries() {
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        let mut guild = GuildData::default();
+        let mut add = |user: u64, headmate: Option<&str>, id: &str| {
+            let user = guild.users.entry(serenity::UserId::new(user)).or_default();
+            user.headmate_mut(&headmate.map(String::from))
+                .results
+                .insert(at, id.into());
+        };
+        add(1, None, "a");
+        add(1, Some("Ash"), "ash");
+        add(2, None, "b");
+        add(3, None, "c");
+        add(4, None, "hidden");
+        add(5, None, "private");
+        for user in [2, 3, 4] {
+            guild
+                .users
+                .get_mut(&serenity::UserId::new(user))
+                .unwrap()
+                .allow_third_party = true;
+        }
+        let hidden: &mut UserData = guild.users.get_mut(&serenity::UserId::new(4)).unwrap();
+        hidden.visibility = Visibility::HiddenFromOthers;
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("a".into(), "b".into()), 90);
+        cache.insert(Matchup::new("ash".into(), "b".into()), 40);
+        cache.insert(Matchup::new("a".into(), "hidden".into()), 100);
+        cache.insert(Matchup::new("a".into(), "private".into()), 100);
+        let names = BTreeMap::from([(serenity::UserId::new(1), "**Alex**".to_string())]);
+        let viewer = serenity::UserId::new(1);
+
+        let built = build(&guild, viewer, &cache, &names, 70, false);
+        assert_eq!(
+            built.graph,
+            Graph {
+                labels: vec!["**Alex**".into(), "**Deleted User**".into()],
+                edges: vec![(0, 1, 90)],
+            }
+        );
+        // a-c, ash-c and b-c are missing, and a-ash is never a pair.
+        assert_eq!(built.pairs, 5);
+        assert_eq!(built.missing.len(), 3);
+        assert!((built.coverage() - 0.4).abs() < 1e-9);
+
+        let built = build(&guild, viewer, &cache, &names, 70, true);
+        assert_eq!(built.graph.labels.len(), 4);
+        assert_eq!(built.graph.edges, [(0, 2, 90)]);
+    }
+
+    #[test]
+    fn only_draws_members_who_allow_third_party_comparisons() {
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        let mut guild = GuildData::default();
+        for (user, id) in [(1, "a"), (2, "b"), (3, "c")] {
+            let user = guild.users.entry(serenity::UserId::new(user)).or_default();
+            user.headmate_mut(&None).results.insert(at, id.into());
+        }
+        guild
+            .users
+            .get_mut(&serenity::UserId::new(2))
+            .unwrap()
+            .allow_third_party = true;
+        let names = BTreeMap::from([
+            (serenity::UserId::new(1), "Alex".to_string()),
+            (serenity::UserId::new(2), "Sam".to_string()),
+            (serenity::UserId::new(3), "Kim".to_string()),
+        ]);
+        let drawn = |viewer: u64| {
+            let viewer = serenity::UserId::new(viewer);
+            build(&guild, viewer, &Cache::new(), &names, 70, true)
+                .graph
+                .labels
+        };
+        // Everyone sees themselves, but only the member who allowed it is shown to others.
+        assert_eq!(drawn(1), ["Alex", "Sam"]);
+        assert_eq!(drawn(2), ["Sam"]);
+        assert_eq!(drawn(3), ["Sam", "Kim"]);
+    }
+}
@@ -0,0 +1,30 @@
+//! Removes temporary (guest) results once they expire, along with whatever they leave empty.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use tracing::info;
+
+use crate::{logic, GlobalState};
+
+/// How often expired results are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Sweeps every [`SWEEP_INTERVAL`], refreshing the guilds that changed.
+pub async fn run(state: Arc<GlobalState>) {
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let changed = {
+            let mut data = state.data.write().await;
+            let changed = logic::expire_guest_results(&mut data, Utc::now());
+            if !changed.is_empty() {
+                state.saves.request();
+            }
+            changed
+        };
+        for guild_id in changed {
+            info!(%guild_id, "Removed expired guest results");
+            state.refresh.request(guild_id);
+        }
+    }
+}
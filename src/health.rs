@@ -0,0 +1,243 @@
+//! A small HTTP listener for liveness and readiness probes, started once setup has loaded the
+//! registry. `/healthz` answers as long as the runtime can still schedule tasks, `/readyz` only
+//! once every shard is connected and the registry is being saved. Both report the same shard
+//! latencies as /ping.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use poise::serenity_prelude as serenity;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+
+use crate::GlobalState;
+
+/// How long a freshly spawned task may take to run before the runtime counts as stuck.
+const RESPONSIVE_WITHIN: Duration = Duration::from_secs(1);
+/// How long a probe may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What one shard this process runs looks like right now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShardStatus {
+    pub id: u32,
+    pub connected: bool,
+    pub latency: Option<Duration>,
+}
+
+/// Everything the probes report on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub responsive: bool,
+    pub shards: Vec<ShardStatus>,
+    pub persist_failing: bool,
+}
+
+impl Status {
+    fn shards_json(&self) -> serde_json::Value {
+        self.shards
+            .iter()
+            .map(|shard| {
+                json!({
+                    "id": shard.id,
+                    "connected": shard.connected,
+                    "latency_ms": shard.latency.map(|l| l.as_millis() as u64),
+                })
+            })
+            .collect()
+    }
+
+    /// The status code and body for `/healthz`.
+    pub fn liveness(&self) -> (u16, serde_json::Value) {
+        let body = json!({
+            "ok": self.responsive,
+            "responsive": self.responsive,
+            "shards": self.shards_json(),
+        });
+        (if self.responsive { 200 } else { 503 }, body)
+    }
+
+    /// The status code and body for `/readyz`. A process with no shards yet isn't connected.
+    pub fn readiness(&self) -> (u16, serde_json::Value) {
+        let connected = !self.shards.is_empty() && self.shards.iter().all(|s| s.connected);
+        let ok = self.responsive && connected && !self.persist_failing;
+        let body = json!({
+            "ok": ok,
+            "responsive": self.responsive,
+            "gateway_connected": connected,
+            "registry_loaded": true,
+            "persist_failing": self.persist_failing,
+            "shards": self.shards_json(),
+        });
+        (if ok { 200 } else { 503 }, body)
+    }
+}
+
+/// The path asked for in an HTTP request, if it is a GET.
+fn request_path(request: &str) -> Option<&str> {
+    let mut words = request.lines().next()?.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) => Some(path.split('?').next().unwrap_or(path)),
+        _ => None,
+    }
+}
+
+/// A complete HTTP/1.1 response carrying `body` as JSON.
+fn response(code: u16, body: &serde_json::Value) -> String {
+    let reason = match code {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let body = body.to_string();
+    format!(
+        "HTTP/1.1 {code} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Looks at the shards and the registry as they are right now.
+async fn status(shards: &serenity::ShardManager, persist_failing: &AtomicBool) -> Status {
+    let responsive = tokio::time::timeout(RESPONSIVE_WITHIN, tokio::spawn(async {}))
+        .await
+        .is_ok_and(|joined| joined.is_ok());
+    let mut shards: Vec<_> = shards
+        .runners
+        .lock()
+        .await
+        .iter()
+        .map(|(id, runner)| ShardStatus {
+            id: id.0,
+            connected: runner.stage == serenity::ConnectionStage::Connected,
+            latency: runner.latency,
+        })
+        .collect();
+    shards.sort_by_key(|s| s.id);
+    Status {
+        responsive,
+        shards,
+        persist_failing: persist_failing.load(Ordering::Relaxed),
+    }
+}
+
+/// Answers the one request a probe sends on `stream`.
+async fn answer(
+    mut stream: TcpStream,
+    shards: &serenity::ShardManager,
+    persist_failing: &AtomicBool,
+) -> Result<(), anyhow::Error> {
+    let mut buf = [0; 1024];
+    let read = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buf)).await??;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let (code, body) = match request_path(&request) {
+        Some("/healthz") => status(shards, persist_failing).await.liveness(),
+        Some("/readyz") => status(shards, persist_failing).await.readiness(),
+        Some(_) => (404, json!({ "ok": false, "error": "not found" })),
+        None => (
+            405,
+            json!({ "ok": false, "error": "only GET is supported" }),
+        ),
+    };
+    stream.write_all(response(code, &body).as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serves the probes on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, shards: Arc<serenity::ShardManager>, state: Arc<GlobalState>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(%addr, "Could not start the health check listener: {e:#}");
+            return;
+        }
+    };
+    // Read once so probes never wait on the registry lock.
+    let persist_failing = state.data.read().await.storage.failing.clone();
+    info!(%addr, "Serving health checks");
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Could not accept a health check: {e:#}");
+                continue;
+            }
+        };
+        let shards = shards.clone();
+        let persist_failing = persist_failing.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, &shards, &persist_failing).await {
+                warn!("Could not answer a health check: {e:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(connected: &[bool], persist_failing: bool) -> Status {
+        Status {
+            responsive: true,
+            shards: connected
+                .iter()
+                .enumerate()
+                .map(|(id, &connected)| ShardStatus {
+                    id: id as u32,
+                    connected,
+                    latency: connected.then(|| Duration::from_millis(40)),
+                })
+                .collect(),
+            persist_failing,
+        }
+    }
+
+    #[test]
+    fn ready_only_when_connected_and_saving() {
+        let (code, body) = status(&[true, true], false).readiness();
+        assert_eq!(code, 200);
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["shards"][1]["latency_ms"], 40);
+
+        assert_eq!(status(&[true, false], false).readiness().0, 503);
+        assert_eq!(status(&[], false).readiness().0, 503);
+        let (code, body) = status(&[true], true).readiness();
+        assert_eq!(code, 503);
+        assert_eq!(body["persist_failing"], true);
+    }
+
+    #[test]
+    fn alive_while_disconnected_but_not_when_stuck() {
+        let (code, body) = status(&[false], true).liveness();
+        assert_eq!(code, 200);
+        assert_eq!(body["shards"][0]["latency_ms"], serde_json::Value::Null);
+
+        let mut stuck = status(&[true], false);
+        stuck.responsive = false;
+        assert_eq!(stuck.liveness().0, 503);
+    }
+
+    #[test]
+    fn reads_the_path_of_get_requests() {
+        assert_eq!(
+            request_path("GET /readyz?verbose=1 HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Some("/readyz")
+        );
+        assert_eq!(request_path("POST /healthz HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(request_path(""), None);
+        assert!(response(503, &json!({})).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+}
@@ -0,0 +1,421 @@
+//! Draws the scores between every pair of a guild's entries as a color-coded heatmap, for
+//! matrices too big to read as numbers. Only cached scores are used, the others are drawn in a
+//! neutral color. Drawing only works on a [`Matrix`], so it can be tried out on made-up matrices.
+//!
+//! Labels are drawn with a small built-in font that only covers ASCII letters, digits and a few
+//! symbols, so nothing has to be installed. Accented Latin letters are drawn as the letter they
+//! are based on, and anything else, like other scripts or emoji, as a question mark. The text
+//! output of compat_matrix shows names as they are.
+
+use std::collections::BTreeMap;
+
+use poise::serenity_prelude as serenity;
+
+use crate::{
+    cache::{Cache, Matchup},
+    data::GuildData,
+    logic::{charted_entries, entry_label},
+    png,
+};
+
+/// The most entries a heatmap can have and still be readable. Anything bigger is better read as a
+/// CSV export.
+pub const MAX_ENTRIES: usize = 40;
+/// The most entries shown as numbers before the matrix gets too wide for a message.
+pub const MAX_TEXT_ENTRIES: usize = 15;
+/// The most characters of an axis label, truncation marker included.
+const MAX_LABEL_CHARS: usize = 14;
+/// How many image pixels each pixel of a label's glyph takes, per side.
+const LABEL_SCALE: usize = 2;
+/// The width and height of a cell, in pixels.
+const CELL: usize = 28;
+const MARGIN: usize = 10;
+/// The space between the labels and the cells, in pixels.
+const GAP: usize = 6;
+/// The width of the legend's color scale, in pixels.
+const LEGEND_WIDTH: usize = 200;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const TEXT: [u8; 3] = [0, 0, 0];
+/// The color of cells without a score.
+pub const MISSING: [u8; 3] = [205, 205, 205];
+/// The colors of a score of 0, 50 and 100. Everything in between is blended.
+const LOW: [u8; 3] = [235, 100, 90];
+const MIDDLE: [u8; 3] = [250, 225, 130];
+const HIGH: [u8; 3] = [90, 180, 110];
+
+/// The entries of a heatmap and the cached score between every pair of them, by row and column.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Matrix {
+    pub labels: Vec<String>,
+    pub scores: Vec<Vec<Option<u32>>>,
+}
+
+/// Builds the matrix of the [`charted_entries`] of `guild` for `viewer`, using only cached
+/// scores. An entry has no score with itself.
+pub fn build(
+    guild: &GuildData,
+    viewer: serenity::UserId,
+    cache: &Cache,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Matrix {
+    let entries = charted_entries(guild, viewer);
+    let scores = entries
+        .iter()
+        .enumerate()
+        .map(|(i, (_, a_id))| {
+            entries
+                .iter()
+                .enumerate()
+                .map(|(j, (_, b_id))| {
+                    (i != j)
+                        .then(|| cache.get(&Matchup::new((*a_id).clone(), (*b_id).clone())))
+                        .flatten()
+                })
+                .collect()
+        })
+        .collect();
+    Matrix {
+        labels: entries
+            .iter()
+            .map(|(e, _)| entry_label(member_names, e))
+            .collect(),
+        scores,
+    }
+}
+
+/// `label` as drawn on an axis: without markdown bold, and cut to `max` characters ending in
+/// `..` when it is longer.
+pub fn truncate_label(label: &str, max: usize) -> String {
+    let label: String = label.chars().filter(|&c| c != '*').collect();
+    let label = label.trim();
+    if label.chars().count() <= max {
+        return label.to_string();
+    }
+    let kept: String = label.chars().take(max.saturating_sub(2)).collect();
+    format!("{}..", kept.trim_end())
+}
+
+/// The color of a cell scoring `score`, blended from red through yellow to green. Cells without
+/// a score are [`MISSING`].
+pub fn cell_color(score: Option<u32>) -> [u8; 3] {
+    let Some(score) = score else {
+        return MISSING;
+    };
+    let score = f64::from(score.min(100));
+    let (from, to, t) = if score < 50.0 {
+        (LOW, MIDDLE, score / 50.0)
+    } else {
+        (MIDDLE, HIGH, (score - 50.0) / 50.0)
+    };
+    let blend = |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * t).round() as u8;
+    [
+        blend(from[0], to[0]),
+        blend(from[1], to[1]),
+        blend(from[2], to[2]),
+    ]
+}
+
+/// The ASCII letter `c` is based on, if it is an accented Latin letter. Everything else is
+/// returned as is.
+fn unaccent(c: char) -> char {
+    match c {
+        'À'..='Å' | 'à'..='å' | 'Ā'..='ą' => 'A',
+        'Ç' | 'ç' | 'Ć'..='č' => 'C',
+        'Ď'..='đ' | 'Ð' | 'ð' => 'D',
+        'È'..='Ë' | 'è'..='ë' | 'Ē'..='ě' => 'E',
+        'Ĝ'..='ģ' => 'G',
+        'Ĥ'..='ħ' => 'H',
+        'Ì'..='Ï' | 'ì'..='ï' | 'Ĩ'..='ı' => 'I',
+        'Ĵ' | 'ĵ' => 'J',
+        'Ķ' | 'ķ' => 'K',
+        'Ĺ'..='ł' => 'L',
+        'Ñ' | 'ñ' | 'Ń'..='ň' => 'N',
+        'Ò'..='Ö' | 'Ø' | 'ò'..='ö' | 'ø' | 'Ō'..='ő' => 'O',
+        'Ŕ'..='ř' => 'R',
+        'Ś'..='š' | 'ß' => 'S',
+        'Ţ'..='ŧ' => 'T',
+        'Ù'..='Ü' | 'ù'..='ü' | 'Ũ'..='ų' => 'U',
+        'Ŵ' | 'ŵ' => 'W',
+        'Ý' | 'ý' | 'ÿ' | 'Ŷ'..='Ÿ' => 'Y',
+        'Ź'..='ž' => 'Z',
+        c => c,
+    }
+}
+
+/// The 5x7 pixel glyph of `c`, a row per entry with the leftmost pixel in the highest bit.
+/// Letters are all drawn in uppercase, accented ones without their accent, and anything without
+/// a glyph as a question mark.
+fn glyph(c: char) -> [u8; 7] {
+    match unaccent(c).to_ascii_uppercase() {
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        ' ' => [0; 7],
+        '.' => [0, 0, 0, 0, 0, 0x0c, 0x0c],
+        '-' => [0, 0, 0, 0x1f, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 0x1f],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0, 0x04],
+    }
+}
+
+/// How many pixels `text` takes along its line at `scale`.
+fn text_length(text: &str, scale: usize) -> usize {
+    (text.chars().count() * 6).saturating_sub(1) * scale
+}
+
+/// An RGB image of any size being drawn on.
+struct Picture {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Picture {
+    fn new(width: usize, height: usize) -> Self {
+        Picture {
+            width,
+            height,
+            pixels: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let at = (y * self.width + x) * 3;
+            self.pixels[at..at + 3].copy_from_slice(&color);
+        }
+    }
+
+    fn rect(&mut self, (x, y): (usize, usize), (width, height): (usize, usize), color: [u8; 3]) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    /// Writes `text` from its top left corner at `at`. Vertical text reads from the bottom up,
+    /// and starts from its bottom left corner instead.
+    fn text(&mut self, at: (usize, usize), text: &str, scale: usize, vertical: bool) {
+        for (n, c) in text.chars().enumerate() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0x10 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let along = (n * 6 + column) * scale + dx;
+                            let across = row * scale + dy;
+                            if vertical {
+                                if let Some(y) = at.1.checked_sub(along) {
+                                    self.set(at.0 + across, y, TEXT);
+                                }
+                            } else {
+                                self.set(at.0 + along, at.1 + across, TEXT);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draws `matrix` with its rows and columns labelled, every cell colored by [`cell_color`] with
+/// its score written on it, and a legend below. Returns the width, height and raw RGB pixels,
+/// row by row.
+fn draw(matrix: &Matrix) -> (usize, usize, Vec<u8>) {
+    let n = matrix.labels.len();
+    let labels: Vec<_> = matrix
+        .labels
+        .iter()
+        .map(|label| truncate_label(label, MAX_LABEL_CHARS))
+        .collect();
+    let line = 7 * LABEL_SCALE;
+    let labels_extent = labels
+        .iter()
+        .map(|label| text_length(label, LABEL_SCALE))
+        .max()
+        .unwrap_or(0);
+    let origin = MARGIN + labels_extent + GAP;
+    let grid = n * CELL;
+    let legend_top = origin + grid + 2 * GAP;
+    let width = origin + grid.max(LEGEND_WIDTH + 2 * CELL) + MARGIN;
+    let height = legend_top + 4 * line + 2 * GAP + MARGIN;
+    let mut picture = Picture::new(width, height);
+
+    for (i, label) in labels.iter().enumerate() {
+        let middle = origin + i * CELL + (CELL - line) / 2;
+        let start = origin - GAP - text_length(label, LABEL_SCALE);
+        picture.text((start, middle), label, LABEL_SCALE, false);
+        let end = origin - GAP;
+        picture.text((middle, end - 1), label, LABEL_SCALE, true);
+    }
+    for (i, row) in matrix.scores.iter().enumerate() {
+        for (j, &score) in row.iter().enumerate() {
+            let corner = (origin + j * CELL, origin + i * CELL);
+            picture.rect(corner, (CELL - 1, CELL - 1), cell_color(score));
+            if let Some(score) = score {
+                let score = score.to_string();
+                let left = corner.0 + (CELL - 1 - text_length(&score, 1)) / 2;
+                picture.text((left, corner.1 + (CELL - 8) / 2), &score, 1, false);
+            }
+        }
+    }
+
+    for x in 0..LEGEND_WIDTH {
+        let score = (x * 100 / (LEGEND_WIDTH - 1)) as u32;
+        picture.rect((origin + x, legend_top), (1, line), cell_color(Some(score)));
+    }
+    let below = legend_top + line + GAP / 2;
+    picture.text((origin, below), "0%", LABEL_SCALE, false);
+    let high = origin + LEGEND_WIDTH - text_length("100%", LABEL_SCALE);
+    picture.text((high, below), "100%", LABEL_SCALE, false);
+    let missing = below + line + GAP;
+    picture.rect((origin, missing), (line, line), MISSING);
+    picture.text(
+        (origin + line + GAP, missing),
+        "no score",
+        LABEL_SCALE,
+        false,
+    );
+    (width, height, picture.pixels)
+}
+
+/// Draws `matrix` as a PNG.
+pub fn render(matrix: &Matrix) -> Vec<u8> {
+    let (width, height, pixels) = draw(matrix);
+    png::encode(width, height, &pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blends_scores_from_red_to_green() {
+        assert_eq!(cell_color(Some(0)), LOW);
+        assert_eq!(cell_color(Some(50)), MIDDLE);
+        assert_eq!(cell_color(Some(100)), HIGH);
+        assert_eq!(cell_color(Some(250)), HIGH);
+        assert_eq!(cell_color(Some(25)), [243, 163, 110]);
+        // Missing scores never look like any score.
+        assert!((0..=100).all(|score| cell_color(Some(score)) != MISSING));
+        assert_eq!(cell_color(None), MISSING);
+    }
+
+    #[test]
+    fn truncates_long_labels() {
+        assert_eq!(truncate_label("**Alex**", 14), "Alex");
+        assert_eq!(truncate_label("**Alex** (Ash)", 14), "Alex (Ash)");
+        assert_eq!(
+            truncate_label("**Alexandria** (Ashley)", 14),
+            "Alexandria (..",
+        );
+        assert_eq!(truncate_label("Alexandria Ashley", 14), "Alexandria A..");
+        assert_eq!(truncate_label("Alexandria Ashley", 13), "Alexandria..");
+        assert_eq!(truncate_label("Łukasz Żółkiewski", 8), "Łukasz..");
+        assert_eq!(truncate_label("Alexandria Ashley", 14).chars().count(), 14);
+    }
+
+    #[test]
+    fn draws_accented_letters_without_their_accent() {
+        assert_eq!(glyph('Ł'), glyph('L'));
+        assert_eq!(glyph('ż'), glyph('Z'));
+        assert_eq!(glyph('é'), glyph('E'));
+        assert_eq!(glyph('ß'), glyph('S'));
+        // Anything else falls back to a question mark rather than being left out.
+        assert_eq!(glyph('名'), glyph('?'));
+        assert_eq!(glyph('🦊'), glyph('?'));
+        assert_ne!(glyph('L'), glyph('?'));
+    }
+
+    #[test]
+    fn leaves_out_members_who_dont_allow_third_party_comparisons() {
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        let mut guild = GuildData::default();
+        for (user, id) in [(1, "a"), (2, "b"), (3, "c")] {
+            let user = guild.users.entry(serenity::UserId::new(user)).or_default();
+            user.headmate_mut(&None).results.insert(at, id.into());
+        }
+        guild
+            .users
+            .get_mut(&serenity::UserId::new(2))
+            .unwrap()
+            .allow_third_party = true;
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("a".into(), "b".into()), 90);
+        cache.insert(Matchup::new("b".into(), "c".into()), 80);
+        let names = BTreeMap::from([
+            (serenity::UserId::new(1), "**Alex**".to_string()),
+            (serenity::UserId::new(2), "**Sam**".to_string()),
+            (serenity::UserId::new(3), "**Kim**".to_string()),
+        ]);
+        assert_eq!(
+            build(&guild, serenity::UserId::new(1), &cache, &names),
+            Matrix {
+                labels: vec!["**Alex**".into(), "**Sam**".into()],
+                scores: vec![vec![None, Some(90)], vec![Some(90), None]],
+            }
+        );
+    }
+
+    #[test]
+    fn draws_cells_by_score() {
+        let matrix = Matrix {
+            labels: vec!["Alex".into(), "Sam".into()],
+            scores: vec![vec![None, Some(90)], vec![Some(90), None]],
+        };
+        let (width, height, pixels) = draw(&matrix);
+        assert_eq!(pixels.len(), width * height * 3);
+        let at = |x: usize, y: usize| {
+            let i = (y * width + x) * 3;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+        let origin = MARGIN + text_length("Alex", LABEL_SCALE) + GAP;
+        assert_eq!(at(origin + 1, origin + 1), MISSING);
+        assert_eq!(at(origin + CELL + 1, origin + 1), cell_color(Some(90)));
+        assert_eq!(at(origin + CELL - 1, origin + 1), BACKGROUND);
+
+        let png = render(&matrix);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
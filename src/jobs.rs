@@ -0,0 +1,346 @@
+//! Compatibility scans queued to run in the background, for guilds too big to scan comfortably
+//! within one interaction. Jobs run one at a time and post their output in the channel they were
+//! queued from, pinging the member who queued them.
+//!
+//! The queue itself only lives in memory. Each job's [`JobRecord`] is also kept in the registry
+//! until it finishes, so the members whose jobs were lost to a restart can be told.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use tokio::{sync::Notify, task::AbortHandle};
+use tracing::{info, warn};
+
+use crate::{
+    commands::resolve_member_names,
+    data::JobRecord,
+    logic::{self, Invoker, ListOptions},
+    GlobalState,
+};
+
+/// A queued list_compatibility scan.
+#[derive(Debug)]
+struct Job {
+    record: JobRecord,
+    subject: String,
+    options: ListOptions,
+}
+
+/// Where a job is in the queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    /// How many jobs will run before this one.
+    Queued(usize),
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    queued: VecDeque<Job>,
+    running: Option<(JobRecord, AbortHandle)>,
+}
+
+/// The queued jobs, and the one that is running.
+#[derive(Default)]
+pub struct JobQueue {
+    jobs: std::sync::Mutex<Jobs>,
+    queued: Notify,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        JobQueue::default()
+    }
+
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a scan for `who`, whose output goes to `channel_id`.
+    pub fn enqueue(
+        &self,
+        who: Invoker,
+        channel_id: serenity::ChannelId,
+        subject: String,
+        options: ListOptions,
+        at: DateTime<Utc>,
+    ) -> JobRecord {
+        let mut jobs = self.jobs();
+        jobs.next_id += 1;
+        let record = JobRecord {
+            id: jobs.next_id,
+            guild_id: who.guild_id,
+            user_id: who.user_id,
+            channel_id,
+            queued_at: at,
+        };
+        jobs.queued.push_back(Job {
+            record: record.clone(),
+            subject,
+            options,
+        });
+        self.queued.notify_one();
+        record
+    }
+
+    /// The jobs `who` queued in their guild, running one first.
+    pub fn list(&self, who: Invoker) -> Vec<(JobRecord, JobStatus)> {
+        let jobs = self.jobs();
+        let mine = |r: &JobRecord| r.guild_id == who.guild_id && r.user_id == who.user_id;
+        let running = jobs
+            .running
+            .iter()
+            .map(|(record, _)| (record.clone(), JobStatus::Running));
+        let queued = jobs
+            .queued
+            .iter()
+            .enumerate()
+            .map(|(ahead, job)| (job.record.clone(), JobStatus::Queued(ahead)));
+        running
+            .chain(queued)
+            .filter(|(record, _)| mine(record))
+            .collect()
+    }
+
+    /// Cancels the job `id` if `who` queued it, stopping it if it is already running. Returns the
+    /// cancelled job.
+    pub fn cancel(&self, who: Invoker, id: u64) -> Option<JobRecord> {
+        let mut jobs = self.jobs();
+        let mine =
+            |r: &JobRecord| r.id == id && r.guild_id == who.guild_id && r.user_id == who.user_id;
+        if let Some(index) = jobs.queued.iter().position(|job| mine(&job.record)) {
+            return jobs.queued.remove(index).map(|job| job.record);
+        }
+        match jobs.running.take() {
+            Some((record, task)) if mine(&record) => {
+                task.abort();
+                Some(record)
+            }
+            running => {
+                jobs.running = running;
+                None
+            }
+        }
+    }
+
+    /// Waits for a queued job and starts it with `run`, marking it as running until [`finish`]ed.
+    ///
+    /// [`finish`]: JobQueue::finish
+    async fn start<F, Fut>(&self, run: F) -> (JobRecord, tokio::task::JoinHandle<()>)
+    where
+        F: FnOnce(Job) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        loop {
+            {
+                let mut jobs = self.jobs();
+                if let Some(job) = jobs.queued.pop_front() {
+                    let record = job.record.clone();
+                    let task = tokio::spawn(run(job));
+                    jobs.running = Some((record.clone(), task.abort_handle()));
+                    return (record, task);
+                }
+            }
+            self.queued.notified().await;
+        }
+    }
+
+    /// Marks the running job `id` as done.
+    fn finish(&self, id: u64) {
+        let mut jobs = self.jobs();
+        if jobs.running.as_ref().is_some_and(|(r, _)| r.id == id) {
+            jobs.running = None;
+        }
+    }
+}
+
+/// Runs `job` and posts its output. Everyone else is named but only the requester is pinged.
+async fn run_job(ctx: serenity::Context, state: Arc<GlobalState>, job: Job) {
+    let record = &job.record;
+    let who = Invoker {
+        guild_id: record.guild_id,
+        user_id: record.user_id,
+    };
+    let _scan = state.scans.enter(who.guild_id).await;
+    let users: Vec<_> = state
+        .data
+        .read()
+        .await
+        .guild(who.guild_id)
+        .iter()
+        .flat_map(|g| g.display_names())
+        .collect();
+    let member_names = resolve_member_names(&ctx, who.guild_id, users).await;
+
+    let data = state.data.read().await;
+    let pages = logic::list_compatibility(
+        &data,
+        &state.api,
+        &state.cache,
+        who,
+        &job.subject,
+        &member_names,
+        &job.options,
+    )
+    .await
+    .map(|listing| listing.pages);
+    drop(data);
+
+    let pages = match pages {
+        Ok(mut pages) => {
+            pages.insert(
+                0,
+                format!("<@{}> your compatibility scan is ready:", who.user_id),
+            );
+            pages
+        }
+        Err(e) => vec![format!(
+            "<@{}> your compatibility scan failed: {e}",
+            who.user_id
+        )],
+    };
+    for page in pages {
+        let message = serenity::CreateMessage::new()
+            .content(page)
+            .allowed_mentions(serenity::CreateAllowedMentions::new().users([who.user_id]));
+        if let Err(e) = record.channel_id.send_message(&ctx, message).await {
+            warn!(job = record.id, "Could not post scan output: {e:#}");
+            break;
+        }
+    }
+}
+
+/// Runs queued jobs one at a time, forever.
+pub async fn run(ctx: serenity::Context, state: Arc<GlobalState>) {
+    loop {
+        let (record, task) = state
+            .jobs
+            .start(|job| run_job(ctx.clone(), state.clone(), job))
+            .await;
+        if let Err(e) = task.await {
+            if e.is_panic() {
+                warn!(job = record.id, "Scan job panicked");
+            }
+        }
+        state.jobs.finish(record.id);
+        let mut data = state.data.write().await;
+        data.pending_jobs.retain(|r| r.id != record.id);
+        state.saves.request();
+    }
+}
+
+/// Tells the members whose jobs were still pending when the bot last stopped that they were lost.
+pub async fn report_dropped(ctx: serenity::Context, dropped: Vec<JobRecord>) {
+    for record in dropped {
+        info!(
+            job = record.id,
+            "Dropped a scan job queued before the restart"
+        );
+        let message = serenity::CreateMessage::new()
+            .content(format!(
+                "<@{}> your compatibility scan queued {} was dropped when the bot restarted, \
+                 please queue it again",
+                record.user_id,
+                record.queued_at.format("%Y-%m-%d %H:%M UTC")
+            ))
+            .allowed_mentions(serenity::CreateAllowedMentions::new().users([record.user_id]));
+        if let Err(e) = record.channel_id.send_message(&ctx, message).await {
+            warn!(job = record.id, "Could not report dropped job: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const ME: Invoker = Invoker {
+        guild_id: serenity::GuildId::new(1),
+        user_id: serenity::UserId::new(100),
+    };
+    const OTHER: Invoker = Invoker {
+        guild_id: serenity::GuildId::new(1),
+        user_id: serenity::UserId::new(200),
+    };
+    const CHANNEL: serenity::ChannelId = serenity::ChannelId::new(5);
+
+    fn enqueue(queue: &JobQueue, who: Invoker) -> u64 {
+        queue
+            .enqueue(
+                who,
+                CHANNEL,
+                "Me".into(),
+                ListOptions::default(),
+                Utc::now(),
+            )
+            .id
+    }
+
+    fn statuses(queue: &JobQueue, who: Invoker) -> Vec<(u64, JobStatus)> {
+        queue
+            .list(who)
+            .into_iter()
+            .map(|(r, status)| (r.id, status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lists_own_jobs_in_order() {
+        let queue = JobQueue::new();
+        let first = enqueue(&queue, ME);
+        let theirs = enqueue(&queue, OTHER);
+        let second = enqueue(&queue, ME);
+        assert!(first < theirs && theirs < second);
+        assert_eq!(
+            statuses(&queue, ME),
+            [
+                (first, JobStatus::Queued(0)),
+                (second, JobStatus::Queued(2))
+            ]
+        );
+
+        let (record, task) = queue.start(|_| std::future::pending()).await;
+        assert_eq!(record.id, first);
+        assert_eq!(
+            statuses(&queue, ME),
+            [(first, JobStatus::Running), (second, JobStatus::Queued(1))]
+        );
+        assert_eq!(statuses(&queue, OTHER), [(theirs, JobStatus::Queued(0))]);
+        task.abort();
+        queue.finish(first);
+        assert_eq!(statuses(&queue, ME), [(second, JobStatus::Queued(1))]);
+    }
+
+    #[tokio::test]
+    async fn cancels_only_own_jobs() {
+        let queue = JobQueue::new();
+        let running = enqueue(&queue, ME);
+        let queued = enqueue(&queue, ME);
+        let (_, task) = queue.start(|_| std::future::pending()).await;
+
+        assert_eq!(queue.cancel(OTHER, queued), None);
+        assert_eq!(queue.cancel(ME, queued).map(|r| r.id), Some(queued));
+        assert_eq!(queue.cancel(ME, queued), None);
+        assert_eq!(queue.cancel(OTHER, running), None);
+        assert_eq!(queue.cancel(ME, running).map(|r| r.id), Some(running));
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert!(queue.list(ME).is_empty());
+    }
+
+    #[tokio::test]
+    async fn start_waits_for_a_job() {
+        let queue = Arc::new(JobQueue::new());
+        let worker = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.start(|_| async {}).await.0.id }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!worker.is_finished());
+        let id = enqueue(&queue, ME);
+        assert_eq!(worker.await.unwrap(), id);
+    }
+}
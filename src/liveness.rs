@@ -0,0 +1,178 @@
+//! Slowly checks that stored results still resolve on bdsmtest.org, a few per hour, and flags the
+//! ones that keep coming back not found. Nothing is ever deleted here, the flag only shows up in
+//! the output of other commands.
+
+use std::{sync::Arc, time::Duration};
+
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::{
+    api::{is_not_found, BdsmApi as _},
+    data::{is_manual, GlobalData},
+    GlobalState,
+};
+
+/// How often a batch of results is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(20 * 60);
+/// How many results each batch checks.
+const BATCH: usize = 3;
+/// The longest wait after bdsmtest.org could not be reached.
+const MAX_BACKOFF: Duration = Duration::from_secs(8 * 60 * 60);
+
+/// A stored result, by where it is stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredResult {
+    pub guild_id: serenity::GuildId,
+    pub user_id: serenity::UserId,
+    pub headmate: Option<String>,
+    pub result_id: String,
+}
+
+/// Every stored result that bdsmtest.org should know, in a stable order.
+fn stored_results(data: &GlobalData) -> Vec<StoredResult> {
+    let mut stored = Vec::new();
+    for (&guild_id, guild) in &data.guilds {
+        for entry in guild.entries() {
+            for result_id in entry.data.results.values().filter(|id| !is_manual(id)) {
+                stored.push(StoredResult {
+                    guild_id,
+                    user_id: entry.user_id,
+                    headmate: entry.headmate.map(String::from),
+                    result_id: result_id.clone(),
+                });
+            }
+        }
+    }
+    stored
+}
+
+/// Records whether `result` was found. Returns whether anything changed.
+pub fn record(data: &mut GlobalData, result: &StoredResult, found: bool) -> bool {
+    let Some(headmate) = data
+        .guild_mut(result.guild_id)
+        .users
+        .get_mut(&result.user_id)
+        .and_then(|u| match &result.headmate {
+            Some(name) => u.headmates.get_mut(name),
+            None => u.primary.as_mut(),
+        })
+    else {
+        // Removed while it was being checked.
+        return false;
+    };
+    if found {
+        headmate.not_found.remove(&result.result_id).is_some()
+    } else {
+        *headmate
+            .not_found
+            .entry(result.result_id.clone())
+            .or_default() += 1;
+        true
+    }
+}
+
+/// Checks the next [`BATCH`] results, starting at `cursor`. Returns the new cursor, or `None` if
+/// bdsmtest.org could not be reached and checking should back off.
+async fn check_batch(state: &GlobalState, cursor: usize) -> Result<Option<usize>, anyhow::Error> {
+    let stored = stored_results(&*state.data.read().await);
+    if stored.is_empty() {
+        return Ok(Some(0));
+    }
+    let mut cursor = cursor % stored.len();
+    for _ in 0..BATCH.min(stored.len()) {
+        let result = &stored[cursor];
+        cursor = (cursor + 1) % stored.len();
+        let found = match state.api.get_result(&result.result_id).await {
+            Ok(fetched) => {
+                state
+                    .cache
+                    .lock()
+                    .await
+                    .insert_result(result.result_id.clone(), fetched);
+                true
+            }
+            Err(e) if is_not_found(&e) => {
+                info!(result_id = result.result_id, "Stored result was not found");
+                false
+            }
+            Err(e) => {
+                warn!("bdsmtest.org could not be reached, backing off: {e:#}");
+                return Ok(None);
+            }
+        };
+        let mut data = state.data.write().await;
+        // Also saves archetypes learned from any result fetched since the last check.
+        if record(&mut data, result, found) | data.sync_archetypes() {
+            state.saves.request();
+        }
+    }
+    Ok(Some(cursor))
+}
+
+/// Checks a batch every [`CHECK_INTERVAL`]. While bdsmtest.org can't be reached the wait doubles,
+/// up to [`MAX_BACKOFF`].
+pub async fn run(state: Arc<GlobalState>) {
+    let mut cursor = 0;
+    let mut wait = CHECK_INTERVAL;
+    loop {
+        tokio::time::sleep(wait).await;
+        match check_batch(&state, cursor).await {
+            Ok(Some(next)) => {
+                cursor = next;
+                wait = CHECK_INTERVAL;
+            }
+            Ok(None) => wait = (wait * 2).min(MAX_BACKOFF),
+            Err(e) => warn!("Could not record result checks: {e:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+    use crate::data::{UserData, UNRESOLVABLE_AFTER};
+
+    #[test]
+    fn flags_after_repeated_not_found_and_clears_on_success() {
+        let at: DateTime<Utc> = "2024-05-01T00:00:00Z".parse().unwrap();
+        let mut user = UserData::default();
+        user.headmate_mut(&None).results.insert(at, "abc".into());
+        user.headmate_mut(&Some("Ash".into()))
+            .results
+            .insert(at, "manual-1".into());
+        let mut data = GlobalData::default();
+        data.guild_mut(serenity::GuildId::new(1))
+            .users
+            .insert(serenity::UserId::new(2), user);
+
+        let stored = stored_results(&data);
+        assert_eq!(
+            stored,
+            [StoredResult {
+                guild_id: serenity::GuildId::new(1),
+                user_id: serenity::UserId::new(2),
+                headmate: None,
+                result_id: "abc".into(),
+            }]
+        );
+        let primary = |data: &GlobalData| {
+            data.guilds[&serenity::GuildId::new(1)].users[&serenity::UserId::new(2)]
+                .primary
+                .clone()
+                .unwrap()
+        };
+        for _ in 0..UNRESOLVABLE_AFTER - 1 {
+            assert!(record(&mut data, &stored[0], false));
+        }
+        assert!(!primary(&data).is_unresolvable("abc"));
+        record(&mut data, &stored[0], false);
+        assert!(primary(&data).is_unresolvable("abc"));
+        assert!(record(&mut data, &stored[0], true));
+        assert!(!primary(&data).is_unresolvable("abc"));
+        assert!(!record(&mut data, &stored[0], true));
+        assert_eq!(primary(&data).results.len(), 1);
+    }
+}
@@ -0,0 +1,6356 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use poise::serenity_prelude::{self as serenity, futures::StreamExt as _};
+use tokio::sync::Mutex;
+
+use crate::{
+    api::{is_not_found, BdsmApi, GetResultResult, GetResultScore, MatchRequest},
+    archetypes,
+    cache::{Cache, Matchup},
+    data::{
+        is_manual, Entry, GlobalData, GuildData, HeadmateData, Shortfall, StoredResult, Tombstone,
+        UserData, Visibility, DEFAULT_GUEST_HOURS, MANUAL_PREFIX,
+    },
+    format::{
+        combine_messages, format_archetype_ranking, format_best_match, format_compat_list,
+        format_explanation, format_headmate_list, format_leaderboard, format_personal_stats,
+        format_result, format_result_embed, format_result_history, format_result_summary,
+        format_retake_diff, format_server_stats, format_similarity, format_top_archetypes,
+        format_verification, result_labels, result_tag, CompatEntry, CompatListOptions, Coverage,
+        EntryResults, ImportStatus, RankedEntry, RemovalTarget, ResultNames, SimilarEntry,
+        Verification, VerifiedResult, MESSAGE_LIMIT,
+    },
+    liveness,
+    scan::CancelToken,
+    scoring::{archetype_distance, archetype_score, explain, weighted_score, DEFAULT_WEIGHT},
+    stats::{self, archetype_averages},
+};
+
+/// Discord's own limit on nicknames.
+const MAX_DISPLAY_NAME_LEN: usize = 32;
+/// Characters that would change how a name renders in markdown.
+const MARKDOWN_CHARS: &[char] = &[
+    '*', '_', '~', '`', '|', '>', '<', '#', '[', ']', '(', ')', '\\',
+];
+/// The longest name a guild can show in place of an archetype's.
+const MAX_ALIAS_LEN: usize = 32;
+
+/// How many entries /top_archetype shows, not counting the invoker's own.
+const TOP_ARCHETYPE_LIMIT: usize = 15;
+/// How many archetypes /server_stats shows.
+const SERVER_STATS_LIMIT: usize = 10;
+/// How many scores list_compatibility looks up at once, so bdsmtest.org isn't flooded.
+const CONCURRENT_SCORES: usize = 8;
+/// The most missing scores /compat_graph looks up in one go.
+pub const MAX_GRAPH_LOOKUPS: usize = 300;
+/// The most entries /compatibility_leaderboard ranks, since it needs a score for every pair.
+pub const MAX_LEADERBOARD_ENTRIES: usize = 40;
+/// The most result IDs /import_results takes at once, since each is checked with bdsmtest.org.
+pub const MAX_IMPORTED: usize = 50;
+/// How many entries /similar_on shows.
+const SIMILAR_LIMIT: usize = 15;
+/// The most results list_compatibility averages over, since each one costs a match request per
+/// entry.
+pub const MAX_AVERAGED: usize = 3;
+/// How many pairings /compat_explain shows of each kind.
+const EXPLAIN_COUNT: usize = 3;
+/// How many results /verify_my_results fetches per run.
+const VERIFY_LIMIT: usize = 20;
+/// Discord only shows this many autocomplete choices.
+pub const MAX_CHOICES: usize = 25;
+
+/// The user that ran a command, and the guild they ran it in.
+#[derive(Clone, Copy, Debug)]
+pub struct Invoker {
+    pub guild_id: serenity::GuildId,
+    pub user_id: serenity::UserId,
+}
+
+/// How list_compatibility scores each pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Scoring {
+    /// The match score from bdsmtest.org.
+    #[default]
+    Site,
+    /// Estimated locally from both results using the invoker's archetype weights.
+    Custom,
+}
+
+/// Files list_compatibility can export its results as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Export {
+    /// One row per entry, with its score and the date of its result.
+    #[name = "csv"]
+    Csv,
+}
+
+/// How /compat_matrix shows its scores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum MatrixOutput {
+    /// The scores as numbers, for small servers.
+    #[default]
+    #[name = "text"]
+    Text,
+    /// A color-coded heatmap.
+    #[name = "image"]
+    Image,
+}
+
+/// What list_compatibility (and the commands sharing its fan-out) compares against.
+#[derive(Clone, Debug, Default)]
+pub struct ListOptions {
+    /// The invoker's headmate to compare, or their primary entry.
+    pub headmate: Option<String>,
+    pub scoring: Scoring,
+    /// Whether to compare against other members' headmates too. Defaults to the guild's setting,
+    /// which defaults to true.
+    pub include_headmates: Option<bool>,
+    /// Lists each member's entries together instead of in one flat list.
+    pub group_by_user: bool,
+    /// Averages the scores of the invoker's last this many results, up to [`MAX_AVERAGED`].
+    pub use_average: Option<usize>,
+    /// Uses the invoker's result from this date (see [`resolve_result_date`]) instead of their
+    /// most recent one.
+    pub my_result_date: Option<String>,
+    /// Stops scoring once cancelled, keeping the scores gathered so far.
+    pub cancel: CancelToken,
+    /// Whether to show how old each entry's result is. Defaults to the guild's setting, which
+    /// defaults to false.
+    pub show_age: Option<bool>,
+    /// When the list is made, which ages count back from.
+    pub now: DateTime<Utc>,
+    /// Only lists entries whose member or headmate name contains this, ignoring case.
+    pub filter: Option<String>,
+    /// Whether to list entries under a header for each score tier. Defaults to the guild's
+    /// setting, which defaults to false.
+    pub by_tier: Option<bool>,
+    /// Only lists entries scoring at least this.
+    pub min_score: Option<u32>,
+    /// Only lists this many of the best matches.
+    pub top: Option<usize>,
+    /// Whether to list entries whose score could not be fetched. Defaults to only when neither
+    /// `min_score` nor `top` narrows the list.
+    pub show_errors: Option<bool>,
+}
+
+impl ListOptions {
+    /// Checks the filters before anything is scored.
+    pub fn check_filters(&self) -> Result<(), CommandError> {
+        match self.min_score {
+            Some(min) if min > 100 => Err(CommandError::InvalidMinScore(min)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps the entries `min_score`, `top` and `show_errors` let through. Entries without a
+    /// score never count towards `top`, and come last.
+    fn narrow<'a>(&self, scored: Vec<Scored<'a>>) -> Vec<Scored<'a>> {
+        let show_errors = self
+            .show_errors
+            .unwrap_or(self.min_score.is_none() && self.top.is_none());
+        let (mut kept, failed): (Vec<_>, Vec<_>) =
+            scored.into_iter().partition(|s| s.score.is_some());
+        kept.retain(|s| self.min_score.is_none_or(|min| s.score >= Some(min)));
+        if let Some(top) = self.top {
+            kept.sort_by_key(|s| std::cmp::Reverse(s.score));
+            kept.truncate(top);
+        }
+        if show_errors {
+            kept.extend(failed);
+        }
+        kept
+    }
+}
+
+/// Which of an entry's results show_result displays, and how.
+#[derive(Clone, Debug, Default)]
+pub struct ShowOptions {
+    /// Only shows the result from this date (see [`resolve_result_date`]).
+    pub date: Option<String>,
+    /// Shows every result, packed into as few messages as fit, instead of only the newest.
+    pub all: bool,
+    /// Includes the archetypes the guild hides.
+    pub show_all: bool,
+    /// Fetches results from bdsmtest.org again instead of using the copies kept of them.
+    pub refresh: bool,
+    /// Shows results as code blocks, for copying elsewhere, instead of embeds.
+    pub plain: bool,
+}
+
+/// How many result embeds show_result puts in one message. Discord allows 10, but also limits
+/// the text of all of a message's embeds together, which a few full results already come near.
+const EMBEDS_PER_MESSAGE: usize = 4;
+
+/// One message of show_result's reply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShownMessage {
+    pub content: String,
+    pub embeds: Vec<serenity::CreateEmbed>,
+}
+
+impl ShownMessage {
+    fn text(content: String) -> Self {
+        ShownMessage {
+            content,
+            ..Default::default()
+        }
+    }
+}
+
+/// Packs `messages` into as few as fit: results as embeds first, then everything else as text.
+fn pack_shown(messages: Vec<ShownMessage>) -> Vec<ShownMessage> {
+    let (texts, embeds): (Vec<_>, Vec<_>) = messages.into_iter().partition(|m| m.embeds.is_empty());
+    let embeds: Vec<_> = embeds.into_iter().flat_map(|m| m.embeds).collect();
+    let texts: Vec<_> = texts.into_iter().map(|m| m.content).collect();
+    embeds
+        .chunks(EMBEDS_PER_MESSAGE)
+        .map(|chunk| ShownMessage {
+            embeds: chunk.to_vec(),
+            ..Default::default()
+        })
+        .chain(
+            combine_messages(&texts, MESSAGE_LIMIT)
+                .into_iter()
+                .map(ShownMessage::text),
+        )
+        .collect()
+}
+
+/// Problems with a command's input that are reported back to the user.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    NoGuildData,
+    NotRegistered,
+    UnknownHeadmate(Option<String>),
+    NoResults,
+    NoHeadmateEntries(String),
+    NoPrimaryData,
+    UnknownArchetype(String),
+    ResultUnavailable(String),
+    UnknownResult(String),
+    InvalidDisplayName,
+    UnknownTimezone(String),
+    TargetNotRegistered(serenity::UserId, Option<String>),
+    NoThirdPartyConsent(serenity::UserId),
+    InvalidManualScore(String),
+    NoManualScores,
+    UnreadableShareText,
+    UnknownResultDate(String),
+    AmbiguousResultDate(String),
+    NoExplainConsent(serenity::UserId),
+    HiddenArchetype(String),
+    NoTemporaryResults,
+    BotAccount(serenity::UserId),
+    NotListable(Shortfall),
+    SameAccount,
+    SameGuild,
+    NoFilterMatches(String),
+    UnknownGuild(serenity::GuildId),
+    InvalidCutoffDate(String),
+    NothingToUndo,
+    CompareUnavailable,
+    ShareUnavailable,
+    ShareChannelNotAllowed,
+    InvalidArchetypeAlias,
+    CoverageDisabled,
+    AliasTaken(String),
+    RestoreConflict(Option<String>),
+    UnknownTombstone(usize),
+    NotAdmin,
+    InvalidHeadmateName,
+    SameResult,
+    TooManyForLeaderboard(usize),
+    NothingToImport,
+    TooManyToImport(usize),
+    UnreadableImportFile,
+    InvalidMinScore(u32),
+    OutOfRange(&'static str, u32, u32),
+    InvalidId(&'static str, String),
+    DigestDisabled,
+    NoBoard,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NoGuildData => write!(
+                f,
+                "No data registered for this guild, use add_bdsm_result first"
+            ),
+            CommandError::NotRegistered => write!(
+                f,
+                "You have not registered any results. Use add_bdsm_result first"
+            ),
+            CommandError::UnknownHeadmate(headmate) => {
+                write!(f, "Could not find headmate {headmate:?}")
+            }
+            CommandError::NoResults => write!(
+                f,
+                "No results registered for the given headmate. Use add_bdsm_result first"
+            ),
+            CommandError::NoHeadmateEntries(headmate) => {
+                write!(f, "No entries found for ({headmate})")
+            }
+            CommandError::NoPrimaryData => write!(f, "No data for primary entry"),
+            CommandError::UnknownArchetype(archetype) => {
+                write!(f, "{archetype:?} is not a bdsmtest.org archetype")
+            }
+            CommandError::InvalidDisplayName => write!(
+                f,
+                "Display names must be at most {MAX_DISPLAY_NAME_LEN} characters and contain more \
+                 than just formatting characters"
+            ),
+            CommandError::UnknownTimezone(name) => write!(
+                f,
+                "{name:?} is not a known timezone, pick one like America/New_York from the list"
+            ),
+            CommandError::TargetNotRegistered(user_id, None) => {
+                write!(f, "<@{user_id}> has not registered any results")
+            }
+            CommandError::TargetNotRegistered(user_id, Some(headmate)) => {
+                write!(f, "<@{user_id}> has no results for ({headmate})")
+            }
+            CommandError::NoThirdPartyConsent(user_id) => write!(
+                f,
+                "<@{user_id}> has not allowed other members to compare them with someone else"
+            ),
+            CommandError::InvalidManualScore(line) => write!(
+                f,
+                "Could not read {line:?}, write each archetype once as `Archetype: percent`"
+            ),
+            CommandError::NoManualScores => write!(
+                f,
+                "No scores given, write them as `Archetype: percent` separated by commas or lines"
+            ),
+            CommandError::UnreadableShareText => write!(
+                f,
+                "Could not find a result link or any archetype scores in that text"
+            ),
+            CommandError::NoExplainConsent(user_id) => write!(
+                f,
+                "<@{user_id}> has not allowed other members to see what drives their matches"
+            ),
+            CommandError::HiddenArchetype(archetype) => write!(
+                f,
+                "{archetype} is hidden in this server, use show_all to rank by it anyway"
+            ),
+            CommandError::BotAccount(user_id) => write!(
+                f,
+                "<@{user_id}> is a bot account. Bots can't take the test, so they can't have results"
+            ),
+            CommandError::NotListable(Shortfall::TooFewResults { needed, have }) => write!(
+                f,
+                "This server only lists entries with at least {needed} results, you have {have}"
+            ),
+            CommandError::NotListable(Shortfall::NoResultSince(since)) => write!(
+                f,
+                "This server only lists entries with a result from {} or later, add a newer one \
+                 first",
+                since.format("%Y-%m-%d")
+            ),
+            CommandError::SameAccount => write!(f, "Both accounts are the same"),
+            CommandError::SameGuild => write!(f, "Both guilds are the same"),
+            CommandError::NoFilterMatches(filter) => {
+                write!(f, "No registered entries match '{filter}'")
+            }
+            CommandError::UnknownGuild(guild_id) => {
+                write!(f, "Nothing is stored for guild {guild_id}")
+            }
+            CommandError::InvalidCutoffDate(date) => {
+                write!(f, "{date:?} is not a date, write it as YYYY-MM-DD")
+            }
+            CommandError::CompareUnavailable => write!(
+                f,
+                "That result can't be compared with anymore, use compatibility_between instead"
+            ),
+            CommandError::CoverageDisabled => write!(
+                f,
+                "Coverage reports are off in this server, turn them on with allow_coverage first"
+            ),
+            CommandError::InvalidArchetypeAlias => write!(
+                f,
+                "Aliases must be at most {MAX_ALIAS_LEN} characters and contain more than just \
+                 formatting characters"
+            ),
+            CommandError::AliasTaken(archetype) => {
+                write!(f, "That name is already used for {archetype}")
+            }
+            CommandError::ShareUnavailable => write!(
+                f,
+                "That summary can't be shared anymore, use show_result to post your result instead"
+            ),
+            CommandError::ShareChannelNotAllowed => write!(
+                f,
+                "Results can only be shared in NSFW channels or ones the admins allowed with \
+                 allow_sharing"
+            ),
+            CommandError::NothingToUndo => write!(
+                f,
+                "There is nothing to undo. Removals can only be undone for {} minutes",
+                crate::undo::UNDO_WINDOW.num_minutes()
+            ),
+            CommandError::RestoreConflict(None) => write!(
+                f,
+                "Your primary entry has new results since it was removed, so it can't be put back"
+            ),
+            CommandError::RestoreConflict(Some(headmate)) => write!(
+                f,
+                "({headmate}) has new results since it was removed, so it can't be put back"
+            ),
+            CommandError::UnknownTombstone(number) => write!(
+                f,
+                "There is no removed entry {number}, use recover_my_data to list them"
+            ),
+            CommandError::InvalidHeadmateName => write!(
+                f,
+                "Headmate names can't be empty or \"{PRIMARY_HEADMATE}\", use promote_headmate to \
+                 make a headmate your primary entry"
+            ),
+            CommandError::SameResult => write!(f, "Pick two different results to compare"),
+            CommandError::TooManyForLeaderboard(entries) => write!(
+                f,
+                "This server has {entries} entries, but the leaderboard compares at most \
+                 {MAX_LEADERBOARD_ENTRIES} so bdsmtest.org isn't flooded with requests"
+            ),
+            CommandError::OutOfRange(what, min, max) => {
+                write!(f, "The {what} must be between {min} and {max}")
+            }
+            CommandError::InvalidId(kind, id) => write!(f, "{id:?} is not a valid {kind} ID"),
+            CommandError::DigestDisabled => write!(f, "The weekly digest is not enabled"),
+            CommandError::NoBoard => write!(f, "There is no compatibility board"),
+            CommandError::InvalidMinScore(min) => write!(
+                f,
+                "Scores only go up to 100%, so nothing scores at least {min}%"
+            ),
+            CommandError::NothingToImport => write!(
+                f,
+                "No result IDs given, separate them with commas or lines, or attach a file"
+            ),
+            CommandError::TooManyToImport(ids) => write!(
+                f,
+                "That's {ids} result IDs, but at most {MAX_IMPORTED} can be imported at once"
+            ),
+            CommandError::UnreadableImportFile => write!(
+                f,
+                "The file should be a list of result IDs, or an entry like the registry stores \
+                 it, with its results by date"
+            ),
+            CommandError::NotAdmin => write!(
+                f,
+                "Only members who can manage the server can look at someone else's entries"
+            ),
+            CommandError::NoTemporaryResults => {
+                write!(f, "There are no temporary results to keep")
+            }
+            CommandError::UnknownResultDate(date) => {
+                write!(f, "No result matches {date:?}, pick one from the list")
+            }
+            CommandError::AmbiguousResultDate(date) => write!(
+                f,
+                "More than one result matches {date:?}, pick one from the list"
+            ),
+            CommandError::UnknownResult(id) => write!(
+                f,
+                "bdsmtest.org has no result {id:?}, copy the ID from the end of your result's link"
+            ),
+            CommandError::ResultUnavailable(id) => {
+                write!(f, "Could not get result {id} from bdsmtest.org")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Looks up the score for `request` in the cache, fetching it from bdsmtest.org on a miss. The
+/// cache lock is not held while the request is in flight.
+pub async fn get_match(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    request: MatchRequest,
+) -> Result<u32, anyhow::Error> {
+    let cache_key = Matchup::from(request.clone());
+    if let Some(score) = cache.lock().await.get(&cache_key) {
+        return Ok(score);
+    }
+    let score = api.get_match(&request).await?;
+    cache.lock().await.insert(cache_key, score);
+    Ok(score)
+}
+
+/// Looks up the result `id` in the cache, fetching it from bdsmtest.org on a miss.
+pub async fn get_result(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    id: &str,
+) -> Result<GetResultResult, anyhow::Error> {
+    if let Some(result) = cache.lock().await.get_result(id) {
+        return Ok(result.clone());
+    }
+    let result = api.get_result(id).await?;
+    cache
+        .lock()
+        .await
+        .insert_result(id.to_string(), result.clone());
+    Ok(result)
+}
+
+/// A manual result of `headmate` in the shape bdsmtest.org returns results in.
+fn manual_result(headmate: &HeadmateData, id: &str) -> Option<GetResultResult> {
+    let scores = headmate.manual.get(id)?;
+    let date = headmate
+        .results
+        .iter()
+        .find(|(_, result_id)| *result_id == id)
+        .map(|(at, _)| at.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    Some(GetResultResult {
+        langfile: String::new(),
+        date,
+        version: 0,
+        gender: String::new(),
+        auth: false,
+        scores: scores
+            .iter()
+            .enumerate()
+            .map(|(i, (name, &score))| GetResultScore {
+                id: i as u32,
+                name: name.clone(),
+                pairdesc: String::new(),
+                description: String::new(),
+                score,
+            })
+            .collect(),
+    })
+}
+
+/// Looks up the result `id` of `headmate`, which may have been entered by hand.
+pub async fn load_result(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    headmate: &HeadmateData,
+    id: &str,
+) -> Result<GetResultResult, anyhow::Error> {
+    match stored_result(headmate, id) {
+        Some(result) => Ok(result),
+        None => get_result(api, cache, id).await,
+    }
+}
+
+/// A result of `headmate` kept in the registry: a manual one, or a copy of one fetched before.
+fn stored_result(headmate: &HeadmateData, id: &str) -> Option<GetResultResult> {
+    manual_result(headmate, id).or_else(|| headmate.fetched.get(id).map(StoredResult::to_result))
+}
+
+/// Fetches the result `id` from bdsmtest.org even if it is already cached, and caches it again.
+async fn refetch_result(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    id: &str,
+) -> Result<GetResultResult, anyhow::Error> {
+    let result = api.get_result(id).await?;
+    cache
+        .lock()
+        .await
+        .insert_result(id.to_string(), result.clone());
+    Ok(result)
+}
+
+/// Keeps a copy of every result of the invoker's (or their headmate's) entry that is in `cache`
+/// but not yet in the registry, or differs from it. Returns whether any were copied.
+pub fn keep_fetched_results(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    cache: &Cache,
+) -> bool {
+    let Some(headmate_data) = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .and_then(|u| match headmate {
+            Some(name) => u.headmates.get_mut(name),
+            None => u.primary.as_mut(),
+        })
+    else {
+        return false;
+    };
+    let mut changed = false;
+    for id in headmate_data.results.values() {
+        let Some(result) = cache.get_result(id) else {
+            continue;
+        };
+        let result = StoredResult::from(result);
+        if headmate_data.fetched.get(id) != Some(&result) {
+            headmate_data.fetched.insert(id.clone(), result);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The score between two results, and whether it was estimated locally. bdsmtest.org can only
+/// match its own results, so manual results are estimated with equal weights unless `weights`
+/// are given, which always estimates.
+async fn score_pair(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    (mine, my_id): (&HeadmateData, &str),
+    (theirs, their_id): (&HeadmateData, &str),
+    weights: Option<&BTreeMap<String, f64>>,
+) -> (Option<u32>, bool) {
+    if weights.is_none() && !is_manual(my_id) && !is_manual(their_id) {
+        let request = MatchRequest {
+            person: my_id.to_string(),
+            partner: their_id.to_string(),
+        };
+        return (get_match(api, cache, request).await.ok(), false);
+    }
+    let (Ok(mine), Ok(theirs)) = (
+        load_result(api, cache, mine, my_id).await,
+        load_result(api, cache, theirs, their_id).await,
+    ) else {
+        return (None, true);
+    };
+    let score = weighted_score(
+        &mine.scores,
+        &theirs.scores,
+        weights.unwrap_or(&BTreeMap::new()),
+    );
+    (score, true)
+}
+
+/// Parses "Archetype: percent" pairs separated by commas, semicolons or lines. Archetype names
+/// are matched case-insensitively against the bdsmtest.org list.
+pub fn parse_manual_scores(text: &str) -> Result<BTreeMap<String, u32>, CommandError> {
+    let mut scores = BTreeMap::new();
+    for line in text
+        .split(['\n', ',', ';'])
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+    {
+        let invalid = || CommandError::InvalidManualScore(line.to_string());
+        let (name, percent) = line.rsplit_once(':').ok_or_else(invalid)?;
+        let name = archetypes::resolve(name)
+            .ok_or_else(|| CommandError::UnknownArchetype(name.trim().to_string()))?;
+        let percent: u32 = percent
+            .trim()
+            .trim_end_matches('%')
+            .trim_end()
+            .parse()
+            .map_err(|_| invalid())?;
+        if percent > 100 || scores.insert(name.to_string(), percent).is_some() {
+            return Err(invalid());
+        }
+    }
+    if scores.is_empty() {
+        return Err(CommandError::NoManualScores);
+    }
+    Ok(scores)
+}
+
+/// Stores hand-entered `scores` as a manual result, with the same announcement rules as
+/// [`add_result`].
+pub fn add_manual_result(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    scores: BTreeMap<String, u32>,
+    at: DateTime<Utc>,
+    announce: Option<bool>,
+) -> bool {
+    // The ID comes from the time the result is stored under, which add_result would move along
+    // if it's taken, so two results entered in the same second get their own IDs.
+    let stored = find_headmate(data, who, headmate).ok();
+    let mut at = at;
+    while stored.is_some_and(|h| h.results.contains_key(&at)) {
+        at += chrono::Duration::seconds(1);
+    }
+    let id = format!("{MANUAL_PREFIX}{}", at.timestamp());
+    let announce = add_result(data, who, headmate, id.clone(), at, announce);
+    data.guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .expect("add_result registers the user")
+        .headmate_mut(headmate)
+        .manual
+        .insert(id, scores);
+    announce
+}
+
+/// The headmate argument that always means the primary entry, even if a default headmate is set.
+pub const PRIMARY_HEADMATE: &str = "primary";
+
+/// The headmate a command acts as: the one given, the invoker's default headmate if none was, or
+/// the primary entry if given [`PRIMARY_HEADMATE`].
+pub fn resolve_headmate(
+    data: &GlobalData,
+    who: Invoker,
+    headmate: Option<String>,
+) -> Option<String> {
+    match headmate {
+        Some(h) if h.eq_ignore_ascii_case(PRIMARY_HEADMATE) => None,
+        Some(h) => Some(h),
+        None => data
+            .guild(who.guild_id)?
+            .users
+            .get(&who.user_id)?
+            .default_headmate
+            .clone(),
+    }
+}
+
+/// The names matching `partial`, ignoring case: the ones starting with it first, then the ones
+/// containing it, each alphabetically. At most [`MAX_CHOICES`] are kept.
+fn rank_choices<'a>(names: impl IntoIterator<Item = &'a str>, partial: &str) -> Vec<String> {
+    let partial = partial.trim().to_lowercase();
+    let mut ranked: Vec<_> = names
+        .into_iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            let rank = match lower.find(&partial)? {
+                0 => 0,
+                _ => 1,
+            };
+            Some((rank, lower, name))
+        })
+        .collect();
+    ranked.sort_unstable();
+    ranked
+        .into_iter()
+        .take(MAX_CHOICES)
+        .map(|(.., name)| name.to_string())
+        .collect()
+}
+
+/// Autocomplete choices for a headmate of `owner` matching `partial` (see [`rank_choices`]). The
+/// invoker sees all of their own headmates, along with [`PRIMARY_HEADMATE`] ahead of them once
+/// they have a default headmate to override, but only the headmates of other members they are
+/// allowed to see.
+pub fn headmate_choices(
+    data: &GlobalData,
+    who: Invoker,
+    owner: serenity::UserId,
+    partial: &str,
+) -> Vec<String> {
+    let Some(guild) = data.guild(who.guild_id) else {
+        return vec![];
+    };
+    let primary = guild
+        .users
+        .get(&owner)
+        .and_then(|u| u.default_headmate.as_ref())
+        .filter(|_| owner == who.user_id)
+        .map(|_| PRIMARY_HEADMATE);
+    let headmates = guild
+        .visible_entries(who.user_id)
+        .filter(|e| e.user_id == owner)
+        .filter_map(|e| e.headmate);
+    let mut choices = rank_choices(primary, partial);
+    choices.extend(rank_choices(headmates, partial));
+    choices.truncate(MAX_CHOICES);
+    choices
+}
+
+fn find_headmate<'a>(
+    data: &'a GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+) -> Result<&'a HeadmateData, CommandError> {
+    data.guild(who.guild_id)
+        .ok_or(CommandError::NoGuildData)?
+        .users
+        .get(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?
+        .headmate(headmate)
+        .ok_or_else(|| CommandError::UnknownHeadmate(headmate.clone()))
+}
+
+/// The archetypes the invoker's guild leaves out of displayed output, or none if `show_all`.
+fn hidden_archetypes(data: &GlobalData, who: Invoker, show_all: bool) -> BTreeSet<String> {
+    match data.guild(who.guild_id) {
+        Some(guild) if !show_all => guild.config.hidden_archetypes.clone(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// Whether the archetype bdsmtest.org calls `name` is in `hidden`.
+fn is_hidden(hidden: &BTreeSet<String>, name: &str) -> bool {
+    archetypes::resolve(name).is_some_and(|name| hidden.contains(name))
+}
+
+/// The guild's archetype aliases, by canonical name.
+fn archetype_aliases(data: &GlobalData, who: Invoker) -> BTreeMap<String, String> {
+    data.guild(who.guild_id)
+        .map(|g| g.config.archetype_aliases.clone())
+        .unwrap_or_default()
+}
+
+/// Renames `name` to what `aliases` calls it. Only done once everything has been computed, right
+/// before output is formatted.
+fn alias(aliases: &BTreeMap<String, String>, name: &mut String) {
+    *name = archetypes::display(aliases, name).to_string();
+}
+
+/// Shows `archetype` as `alias` in the guild's output, or by its own name again with `None`.
+/// Returns its canonical name and the alias it had before.
+pub fn set_archetype_alias(
+    data: &mut GlobalData,
+    who: Invoker,
+    archetype: &str,
+    alias: Option<&str>,
+) -> Result<(&'static str, Option<String>), CommandError> {
+    let archetype = archetypes::resolve_loosely(archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    let aliases = &mut data.guild_mut(who.guild_id).config.archetype_aliases;
+    let Some(alias) = alias else {
+        return Ok((archetype, aliases.remove(archetype)));
+    };
+    let alias = plain_text(alias);
+    if alias.is_empty() || alias.chars().count() > MAX_ALIAS_LEN {
+        return Err(CommandError::InvalidArchetypeAlias);
+    }
+    // Two archetypes shown under one name couldn't be told apart.
+    if let Some(other) = archetypes::resolve(&alias).filter(|&other| other != archetype) {
+        return Err(CommandError::AliasTaken(other.to_string()));
+    }
+    if let Some((other, _)) = aliases
+        .iter()
+        .find(|(other, a)| *other != archetype && a.eq_ignore_ascii_case(&alias))
+    {
+        return Err(CommandError::AliasTaken(other.clone()));
+    }
+    Ok((archetype, aliases.insert(archetype.to_string(), alias)))
+}
+
+/// Hides `archetype` from the guild's displayed output (or shows it again). Returns its canonical
+/// name, and whether anything changed.
+pub fn set_archetype_hidden(
+    data: &mut GlobalData,
+    who: Invoker,
+    archetype: &str,
+    hidden: bool,
+) -> Result<(&'static str, bool), CommandError> {
+    let archetype = archetypes::resolve_loosely(archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    let list = &mut data.guild_mut(who.guild_id).config.hidden_archetypes;
+    let changed = if hidden {
+        list.insert(archetype.to_string())
+    } else {
+        list.remove(archetype)
+    };
+    Ok((archetype, changed))
+}
+
+/// What moving one account's data to another does.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Transfer {
+    pub results: usize,
+    pub headmates: usize,
+    /// Headmates renamed because the new account already had one by that name, as `(old, new)`.
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Merges `from` into `into`. Primary results are combined like merged guilds combine them,
+/// headmates whose names are taken get a number, and settings `into` left at their default are
+/// taken from `from`.
+fn merge_user(into: &mut UserData, from: UserData) -> Transfer {
+    let mut transfer = Transfer {
+        results: 0,
+        headmates: from.headmates.len(),
+        renamed: Vec::new(),
+    };
+    match (&mut into.primary, from.primary) {
+        (Some(primary), Some(from)) => {
+            let mut merge = GuildMerge::default();
+            merge_headmate(primary, from, &mut merge);
+            transfer.results += merge.results;
+        }
+        (primary @ None, Some(from)) => {
+            transfer.results += from.results.len();
+            *primary = Some(from);
+        }
+        (_, None) => {}
+    }
+    for (name, headmate) in from.headmates {
+        let mut new_name = name.clone();
+        for n in 2.. {
+            if !into.headmates.contains_key(&new_name) {
+                break;
+            }
+            new_name = format!("{name} ({n})");
+        }
+        if new_name != name {
+            transfer.renamed.push((name, new_name.clone()));
+        }
+        transfer.results += headmate.results.len();
+        into.headmates.insert(new_name, headmate);
+    }
+    let renamed = |name: String| {
+        transfer
+            .renamed
+            .iter()
+            .find(|(old, _)| *old == name)
+            .map_or(name.clone(), |(_, new)| new.clone())
+    };
+    into.default_headmate = into
+        .default_headmate
+        .take()
+        .or(from.default_headmate.map(renamed));
+    into.announced |= from.announced;
+    into.suppress_announcements |= from.suppress_announcements;
+    if !from.visibility.is_visible() {
+        into.visibility = from.visibility;
+    }
+    for (archetype, weight) in from.weights {
+        into.weights.entry(archetype).or_insert(weight);
+    }
+    into.ignored.extend(from.ignored);
+    into.display_name = into.display_name.take().or(from.display_name);
+    into.timezone = into.timezone.or(from.timezone);
+    into.allow_third_party |= from.allow_third_party;
+    into.allow_explain |= from.allow_explain;
+    into.show_gender |= from.show_gender;
+    if into.match_alert.is_none() {
+        into.match_alert = from.match_alert;
+        into.match_alert_failed = from.match_alert_failed;
+    }
+    into.tombstones.extend(from.tombstones);
+    transfer
+}
+
+/// Checks that `old` has data to move to `new` in the invoker's guild.
+fn transfer_accounts(
+    data: &GlobalData,
+    who: Invoker,
+    old: serenity::UserId,
+    new: serenity::UserId,
+) -> Result<(&UserData, UserData), CommandError> {
+    if old == new {
+        return Err(CommandError::SameAccount);
+    }
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let from = guild
+        .users
+        .get(&old)
+        .filter(|u| u.has_results())
+        .ok_or(CommandError::TargetNotRegistered(old, None))?;
+    Ok((from, guild.users.get(&new).cloned().unwrap_or_default()))
+}
+
+/// What [`transfer_user_data`] would do, without changing anything.
+pub fn plan_transfer(
+    data: &GlobalData,
+    who: Invoker,
+    old: serenity::UserId,
+    new: serenity::UserId,
+) -> Result<Transfer, CommandError> {
+    let (from, mut into) = transfer_accounts(data, who, old, new)?;
+    Ok(merge_user(&mut into, from.clone()))
+}
+
+/// Moves everything `old` stored in the invoker's guild to `new`, merging it with whatever `new`
+/// already has.
+pub fn transfer_user_data(
+    data: &mut GlobalData,
+    who: Invoker,
+    old: serenity::UserId,
+    new: serenity::UserId,
+) -> Result<Transfer, CommandError> {
+    transfer_accounts(data, who, old, new)?;
+    let users = &mut data.guild_mut(who.guild_id).users;
+    let from = users.remove(&old).unwrap_or_default();
+    Ok(merge_user(users.entry(new).or_default(), from))
+}
+
+/// What merging one guild's registry into another does.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GuildMerge {
+    /// Members only the source guild had, moved as they are.
+    pub moved: usize,
+    /// Members both guilds had, whose entries were merged.
+    pub shared: Vec<serenity::UserId>,
+    pub results: usize,
+    /// Results the destination already had, which were skipped.
+    pub duplicates: usize,
+    /// Results moved to a later timestamp because the destination entry had one at the same time.
+    pub nudged: usize,
+}
+
+/// Adds the results of `from` that `into` doesn't have yet, along with everything stored about
+/// them, nudging each one a second later until its timestamp is free.
+fn merge_headmate(into: &mut HeadmateData, from: HeadmateData, merge: &mut GuildMerge) {
+    for (mut at, id) in from.results {
+        if into.results.values().any(|v| *v == id) {
+            merge.duplicates += 1;
+            continue;
+        }
+        let taken = into.results.contains_key(&at);
+        while into.results.contains_key(&at) {
+            at += chrono::Duration::seconds(1);
+        }
+        merge.nudged += usize::from(taken);
+        merge.results += 1;
+        if let Some(manual) = from.manual.get(&id) {
+            into.manual.insert(id.clone(), manual.clone());
+        }
+        if let Some(&count) = from.not_found.get(&id) {
+            into.not_found.insert(id.clone(), count);
+        }
+        if let Some(&expires) = from.expires.get(&id) {
+            into.expires.insert(id.clone(), expires);
+        }
+        if let Some(&visibility) = from.result_visibility.get(&id) {
+            into.result_visibility.insert(id.clone(), visibility);
+        }
+        if let Some(fetched) = from.fetched.get(&id) {
+            into.fetched.insert(id.clone(), fetched.clone());
+        }
+        into.results.insert(at, id);
+    }
+}
+
+/// The members of `destination` once those of `source` are merged in. Members both guilds have
+/// keep their settings from `destination`, and their entries are merged by headmate name.
+fn merged_users(
+    data: &GlobalData,
+    source: serenity::GuildId,
+    destination: serenity::GuildId,
+) -> Result<(BTreeMap<serenity::UserId, UserData>, GuildMerge), CommandError> {
+    if source == destination {
+        return Err(CommandError::SameGuild);
+    }
+    let from = data
+        .guild(source)
+        .ok_or(CommandError::UnknownGuild(source))?;
+    let mut users = data
+        .guild(destination)
+        .map(|g| g.users.clone())
+        .unwrap_or_default();
+    let mut merge = GuildMerge::default();
+    for (&user_id, user) in &from.users {
+        let Some(into) = users.get_mut(&user_id) else {
+            merge.moved += 1;
+            merge.results += user.primary.iter().map(|p| p.results.len()).sum::<usize>();
+            merge.results += user
+                .headmates
+                .values()
+                .map(|h| h.results.len())
+                .sum::<usize>();
+            users.insert(user_id, user.clone());
+            continue;
+        };
+        merge.shared.push(user_id);
+        let user = user.clone();
+        if let Some(primary) = user.primary {
+            merge_headmate(
+                into.primary.get_or_insert_with(Default::default),
+                primary,
+                &mut merge,
+            );
+        }
+        for (name, headmate) in user.headmates {
+            merge_headmate(
+                into.headmates.entry(name).or_default(),
+                headmate,
+                &mut merge,
+            );
+        }
+        into.announced |= user.announced;
+        into.bot |= user.bot;
+    }
+    Ok((users, merge))
+}
+
+/// What [`merge_guilds`] would do, without changing anything.
+pub fn plan_guild_merge(
+    data: &GlobalData,
+    source: serenity::GuildId,
+    destination: serenity::GuildId,
+) -> Result<GuildMerge, CommandError> {
+    merged_users(data, source, destination).map(|(_, merge)| merge)
+}
+
+/// Merges every member of the `source` guild into `destination`, then removes `source`.
+pub fn merge_guilds(
+    data: &mut GlobalData,
+    source: serenity::GuildId,
+    destination: serenity::GuildId,
+) -> Result<GuildMerge, CommandError> {
+    let (users, merge) = merged_users(data, source, destination)?;
+    data.guild_mut(destination).users = users;
+    data.guilds.remove(&source);
+    data.pending_jobs.retain(|job| job.guild_id != source);
+    Ok(merge)
+}
+
+/// How many members and results the invoker's guild has stored.
+pub fn guild_summary(data: &GlobalData, who: Invoker) -> (usize, usize) {
+    data.guild(who.guild_id).map_or((0, 0), |guild| {
+        let results = guild.entries().map(|e| e.data.results.len()).sum();
+        (guild.users.len(), results)
+    })
+}
+
+/// Deletes everything stored for the invoker's guild, along with the cached results (and their
+/// scores) no other guild stores. Returns the deleted data.
+pub fn wipe_guild(data: &mut GlobalData, cache: &mut Cache, who: Invoker) -> Option<GuildData> {
+    let wiped = data.guilds.remove(&who.guild_id)?;
+    let stored = data.result_ids();
+    cache.evict(|id| stored.contains(id));
+    data.pending_jobs.retain(|job| job.guild_id != who.guild_id);
+    Some(wiped)
+}
+
+/// Deletes everything stored for `users` in the invoker's guild, along with the cached results
+/// (and their scores) nothing else stores. Returns how many users and results were deleted.
+pub fn remove_users(
+    data: &mut GlobalData,
+    cache: &mut Cache,
+    who: Invoker,
+    users: &[serenity::UserId],
+) -> (usize, usize) {
+    let Some(guild) = data.guilds.get_mut(&who.guild_id) else {
+        return (0, 0);
+    };
+    let removed: Vec<UserData> = users
+        .iter()
+        .filter_map(|user_id| guild.users.remove(user_id))
+        .collect();
+    let results = removed
+        .iter()
+        .flat_map(|user| user.primary.iter().chain(user.headmates.values()))
+        .map(|data| data.results.len())
+        .sum();
+    data.pending_jobs
+        .retain(|job| job.guild_id != who.guild_id || !users.contains(&job.user_id));
+    let stored = data.result_ids();
+    cache.evict(|id| stored.contains(id));
+    (removed.len(), results)
+}
+
+/// Sets (or clears) the guild's list requirements. `since` is a date like 2024-05-01, from the
+/// start of which (in UTC) entries need a result.
+pub fn set_list_requirements(
+    data: &mut GlobalData,
+    who: Invoker,
+    min_results: Option<usize>,
+    since: Option<&str>,
+) -> Result<(), CommandError> {
+    let since = since
+        .map(|date| {
+            chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map(|d| d.and_time(chrono::NaiveTime::MIN).and_utc())
+                .map_err(|_| CommandError::InvalidCutoffDate(date.to_string()))
+        })
+        .transpose()?;
+    let config = &mut data.guild_mut(who.guild_id).config;
+    config.min_results = min_results;
+    config.results_since = since;
+    Ok(())
+}
+
+/// Stores a result and returns whether the user's registration should be announced. That is only
+/// the case for their first result in the guild, and only if they haven't opted out (`announce`
+/// overrides their saved preference).
+pub fn add_result(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    id: String,
+    at: DateTime<Utc>,
+    announce: Option<bool>,
+) -> bool {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default();
+    let first = !person_data.announced && !person_data.has_results();
+    let announce = first && announce.unwrap_or(!person_data.suppress_announcements);
+    person_data.announced = true;
+    let results = &mut person_data.headmate_mut(headmate).results;
+    // Results taken on the same day are all stored at midnight, so later ones move along.
+    let mut at = at;
+    while results.get(&at).is_some_and(|other| *other != id) {
+        at += chrono::Duration::seconds(1);
+    }
+    results.insert(at, id);
+    announce
+}
+
+/// Checks with bdsmtest.org that the result `id` exists before it is stored, and returns when it
+/// was taken to store it under. Results whose date can't be read are stored under `now`.
+pub async fn check_new_result(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    id: &str,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, CommandError> {
+    match get_result(api, cache, id).await {
+        Ok(result) => Ok(result.taken().unwrap_or(now)),
+        Err(e) if is_not_found(&e) => Err(CommandError::UnknownResult(id.to_string())),
+        Err(_) => Err(CommandError::ResultUnavailable(id.to_string())),
+    }
+}
+
+/// A JSON file of results to import: a list of IDs, an entry as the registry stores it, or just
+/// that entry's results by date.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ImportFile {
+    Ids(Vec<String>),
+    Entry { results: BTreeMap<String, String> },
+    Results(BTreeMap<String, String>),
+}
+
+/// Reads the result IDs to import from `text`, which is either separated by commas or lines, or
+/// an [`ImportFile`]. Repeated IDs are only kept once. At most [`MAX_IMPORTED`] are taken.
+pub fn parse_import(text: &str) -> Result<Vec<String>, CommandError> {
+    let text = text.trim();
+    let ids: Vec<String> = if text.starts_with(['[', '{']) {
+        match serde_json::from_str(text).map_err(|_| CommandError::UnreadableImportFile)? {
+            ImportFile::Ids(ids) => ids,
+            ImportFile::Entry { results } | ImportFile::Results(results) => {
+                results.into_values().collect()
+            }
+        }
+    } else {
+        text.split(['\n', ',']).map(str::to_string).collect()
+    };
+    let mut seen = BTreeSet::new();
+    let ids: Vec<String> = ids
+        .into_iter()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty() && seen.insert(id.clone()))
+        .collect();
+    match ids.len() {
+        0 => Err(CommandError::NothingToImport),
+        count if count > MAX_IMPORTED => Err(CommandError::TooManyToImport(count)),
+        _ => Ok(ids),
+    }
+}
+
+/// Checks every ID to import with bdsmtest.org, a few at a time, like [`check_new_result`].
+pub async fn check_imports(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    ids: Vec<String>,
+    now: DateTime<Utc>,
+) -> Vec<(String, Result<DateTime<Utc>, CommandError>)> {
+    let checks: Vec<_> = ids
+        .into_iter()
+        .map(|id| async move {
+            let taken = check_new_result(api, cache, &id, now).await;
+            (id, taken)
+        })
+        .collect();
+    serenity::futures::stream::iter(checks)
+        .buffered(CONCURRENT_SCORES)
+        .collect()
+        .await
+}
+
+/// Stores the checked imports in the invoker's (or their headmate's) entry, each under when it
+/// was taken, skipping the ones the entry already has. Returns what became of each ID, and
+/// whether the user's registration should be announced (see [`add_result`]).
+pub fn store_imports(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    checked: Vec<(String, Result<DateTime<Utc>, CommandError>)>,
+    announce: Option<bool>,
+) -> (Vec<(String, ImportStatus)>, bool) {
+    let mut announced = false;
+    let imports = checked
+        .into_iter()
+        .map(|(id, taken)| {
+            let stored = data
+                .guild(who.guild_id)
+                .and_then(|g| g.users.get(&who.user_id)?.headmate(headmate))
+                .is_some_and(|entry| entry.results.values().any(|other| *other == id));
+            let status = match taken {
+                _ if stored => ImportStatus::Duplicate,
+                Ok(taken) => {
+                    announced |= add_result(data, who, headmate, id.clone(), taken, announce);
+                    ImportStatus::Imported
+                }
+                Err(CommandError::UnknownResult(_)) => ImportStatus::Invalid,
+                Err(_) => ImportStatus::Unavailable,
+            };
+            (id, status)
+        })
+        .collect();
+    (imports, announced)
+}
+
+/// Makes the result `id` temporary, removed once the guild's guest duration has passed since
+/// `now`. Returns when it expires.
+pub fn make_temporary(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    id: &str,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let guild = data.guild_mut(who.guild_id);
+    let hours = guild.config.guest_hours.unwrap_or(DEFAULT_GUEST_HOURS);
+    let expires = now + chrono::Duration::hours(hours.into());
+    guild
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .headmate_mut(headmate)
+        .expires
+        .insert(id.to_string(), expires);
+    expires
+}
+
+/// Makes every temporary result of the invoker's entry permanent. Returns how many there were.
+pub fn keep_results(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+) -> Result<usize, CommandError> {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let kept = match headmate {
+        Some(name) => person_data.headmates.get_mut(name),
+        None => person_data.primary.as_mut(),
+    }
+    .map(|h| std::mem::take(&mut h.expires).len())
+    .unwrap_or_default();
+    if kept == 0 {
+        return Err(CommandError::NoTemporaryResults);
+    }
+    Ok(kept)
+}
+
+/// Moves the invoker's headmate `old` to the name `new`. If `new` already has an entry the two are
+/// merged like the entries of merged guilds are, skipping results it already has. Returns how
+/// many results were moved.
+pub fn rename_headmate(
+    data: &mut GlobalData,
+    who: Invoker,
+    old: &str,
+    new: &str,
+) -> Result<usize, CommandError> {
+    let new = new.trim();
+    if new.is_empty() || new.eq_ignore_ascii_case(PRIMARY_HEADMATE) {
+        return Err(CommandError::InvalidHeadmateName);
+    }
+    let registered = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .ok_or(CommandError::NotRegistered)?;
+    if !registered.headmates.contains_key(old) {
+        return Err(CommandError::UnknownHeadmate(Some(old.to_string())));
+    }
+    if old == new {
+        return Ok(0);
+    }
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let from = person_data
+        .headmates
+        .remove(old)
+        .ok_or_else(|| CommandError::UnknownHeadmate(Some(old.to_string())))?;
+    let moved = match person_data.headmates.get_mut(new) {
+        Some(into) => {
+            let mut merge = GuildMerge::default();
+            merge_headmate(into, from, &mut merge);
+            merge.results
+        }
+        None => {
+            let moved = from.results.len();
+            person_data.headmates.insert(new.to_string(), from);
+            moved
+        }
+    };
+    if person_data.default_headmate.as_deref() == Some(old) {
+        person_data.default_headmate = Some(new.to_string());
+    }
+    Ok(moved)
+}
+
+/// Swaps the invoker's headmate `name` with their primary entry, so the old primary entry is
+/// called `name` from now on. An empty old primary entry is dropped instead. Returns how many
+/// results each side has now, primary first.
+pub fn promote_headmate(
+    data: &mut GlobalData,
+    who: Invoker,
+    name: &str,
+) -> Result<(usize, usize), CommandError> {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let promoted = person_data
+        .headmates
+        .remove(name)
+        .ok_or_else(|| CommandError::UnknownHeadmate(Some(name.to_string())))?;
+    let promoted_results = promoted.results.len();
+    let demoted_results = match person_data.primary.replace(promoted) {
+        Some(demoted) if !demoted.results.is_empty() => {
+            let results = demoted.results.len();
+            person_data.headmates.insert(name.to_string(), demoted);
+            results
+        }
+        _ => 0,
+    };
+    // Commands that acted as the headmate should keep acting as the same results.
+    if person_data.default_headmate.as_deref() == Some(name) {
+        person_data.default_headmate = None;
+    }
+    Ok((promoted_results, demoted_results))
+}
+
+/// Removes every temporary result that expired by `now`, along with entries left without results
+/// and members left without any. Returns the guilds that changed.
+pub fn expire_guest_results(
+    data: &mut GlobalData,
+    now: DateTime<Utc>,
+) -> BTreeSet<serenity::GuildId> {
+    let mut changed = BTreeSet::new();
+    for (&guild_id, guild) in &mut data.guilds {
+        guild.users.retain(|_, user| {
+            let mut expired = false;
+            if let Some(primary) = &mut user.primary {
+                expired |= primary.expire(now);
+                if primary.results.is_empty() {
+                    user.primary = None;
+                }
+            }
+            user.headmates.retain(|_, headmate| {
+                let headmate_expired = headmate.expire(now);
+                expired |= headmate_expired;
+                !(headmate_expired && headmate.results.is_empty())
+            });
+            if !expired {
+                return true;
+            }
+            changed.insert(guild_id);
+            if let Some(name) = &user.default_headmate {
+                if !user.headmates.contains_key(name) {
+                    user.default_headmate = None;
+                }
+            }
+            user.has_results()
+        });
+    }
+    changed
+}
+
+/// Describes what [`remove_results`] would remove, failing the same way it would. Nothing is
+/// changed, not even an empty guild added.
+pub fn describe_removal(
+    data: &GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+) -> Result<RemovalTarget, CommandError> {
+    let person_data = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .ok_or(CommandError::NotRegistered)?;
+    let removed = person_data
+        .headmate(headmate)
+        .ok_or_else(|| match headmate {
+            Some(name) => CommandError::NoHeadmateEntries(name.clone()),
+            None => CommandError::NoPrimaryData,
+        })?;
+    let other_guilds = data
+        .guilds
+        .iter()
+        .filter(|(&guild_id, guild)| {
+            guild_id != who.guild_id
+                && guild
+                    .users
+                    .get(&who.user_id)
+                    .is_some_and(UserData::has_results)
+        })
+        .count();
+    Ok(RemovalTarget {
+        headmate: headmate.clone(),
+        results: removed.results.len(),
+        taken: removed
+            .results
+            .keys()
+            .next()
+            .zip(removed.results.keys().next_back())
+            .map(|(&first, &last)| (first, last)),
+        other_guilds,
+    })
+}
+
+/// Which of a member's tombstones remove_bdsm_results made, so /undo can find it again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Removal {
+    pub headmate: Option<String>,
+    pub deleted: DateTime<Utc>,
+}
+
+/// Moves the invoker's entry (or headmate's) into a tombstone made at `now`, where nothing reads
+/// it until it is recovered or swept.
+pub fn remove_results(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: Option<String>,
+    now: DateTime<Utc>,
+) -> Result<Removal, CommandError> {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let (removed, was_default) = match &headmate {
+        Some(headmate) => {
+            let removed = person_data
+                .headmates
+                .remove(headmate)
+                .ok_or_else(|| CommandError::NoHeadmateEntries(headmate.clone()))?;
+            let was_default = person_data.default_headmate.as_ref() == Some(headmate);
+            if was_default {
+                person_data.default_headmate = None;
+            }
+            (removed, was_default)
+        }
+        None => (
+            person_data
+                .primary
+                .take()
+                .ok_or(CommandError::NoPrimaryData)?,
+            false,
+        ),
+    };
+    person_data.tombstones.push(Tombstone {
+        headmate: headmate.clone(),
+        deleted: now,
+        was_default,
+        data: removed,
+    });
+    Ok(Removal {
+        headmate,
+        deleted: now,
+    })
+}
+
+/// Puts back the tombstone [`remove_results`] made for `removal`.
+pub fn undo_removal(
+    data: &mut GlobalData,
+    who: Invoker,
+    removal: &Removal,
+) -> Result<(), CommandError> {
+    let index = data
+        .guilds
+        .get(&who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .and_then(|u| {
+            u.tombstones
+                .iter()
+                .position(|t| t.headmate == removal.headmate && t.deleted == removal.deleted)
+        })
+        .ok_or(CommandError::NothingToUndo)?;
+    restore_tombstone(data, who, index).map(|_| ())
+}
+
+/// The invoker's tombstones, oldest first.
+pub fn tombstones(data: &GlobalData, who: Invoker) -> &[Tombstone] {
+    data.guilds
+        .get(&who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id))
+        .map_or(&[], |u| &u.tombstones)
+}
+
+/// Puts the invoker's `index`th tombstone back where it was removed from, and returns it. Refuses
+/// if the entry has been added again since, rather than mixing the two.
+pub fn restore_tombstone(
+    data: &mut GlobalData,
+    who: Invoker,
+    index: usize,
+) -> Result<Tombstone, CommandError> {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .filter(|u| index < u.tombstones.len())
+        .ok_or(CommandError::UnknownTombstone(index + 1))?;
+    let tombstone = &person_data.tombstones[index];
+    let taken = match &tombstone.headmate {
+        Some(name) => person_data.headmates.contains_key(name),
+        None => person_data.primary.is_some(),
+    };
+    if taken {
+        return Err(CommandError::RestoreConflict(tombstone.headmate.clone()));
+    }
+    let tombstone = person_data.tombstones.remove(index);
+    match &tombstone.headmate {
+        Some(name) => {
+            if tombstone.was_default && person_data.default_headmate.is_none() {
+                person_data.default_headmate = Some(name.clone());
+            }
+            person_data
+                .headmates
+                .insert(name.clone(), tombstone.data.clone());
+        }
+        None => person_data.primary = Some(tombstone.data.clone()),
+    }
+    Ok(tombstone)
+}
+
+/// Deletes every tombstone made more than `keep` before `now` for good, along with members left
+/// with nothing: no entries, no tombstones and no settings of their own. Returns how many were
+/// deleted.
+pub fn purge_tombstones(
+    data: &mut GlobalData,
+    now: DateTime<Utc>,
+    keep: chrono::Duration,
+) -> usize {
+    let mut purged = 0;
+    for guild in data.guilds.values_mut() {
+        guild.users.retain(|_, user| {
+            let before = user.tombstones.len();
+            user.tombstones.retain(|t| now - t.deleted <= keep);
+            purged += before - user.tombstones.len();
+            let emptied = UserData {
+                announced: user.announced,
+                ..Default::default()
+            };
+            before == user.tombstones.len() || *user != emptied
+        });
+    }
+    purged
+}
+
+/// Sets the invoker's weight for `archetype`, and returns all of their weights. Setting the
+/// default weight removes it.
+pub fn set_weight(
+    data: &mut GlobalData,
+    who: Invoker,
+    archetype: &str,
+    weight: f64,
+) -> Result<BTreeMap<String, f64>, CommandError> {
+    let archetype = archetypes::resolve_loosely(archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    let weights = &mut data
+        .guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .weights;
+    if weight == DEFAULT_WEIGHT {
+        weights.remove(archetype);
+    } else {
+        weights.insert(archetype.to_string(), weight);
+    }
+    Ok(weights.clone())
+}
+
+/// Adds `user` to (or removes them from) the invoker's ignore list. Returns false if nothing
+/// changed.
+pub fn set_ignored(
+    data: &mut GlobalData,
+    who: Invoker,
+    user: serenity::UserId,
+    ignored: bool,
+) -> bool {
+    let list = &mut data
+        .guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .ignored;
+    if ignored {
+        list.insert(user)
+    } else {
+        list.remove(&user)
+    }
+}
+
+/// The invoker's display name override, if they set one.
+pub fn display_name(data: &GlobalData, who: Invoker) -> Option<&str> {
+    data.guild(who.guild_id)?
+        .users
+        .get(&who.user_id)?
+        .display_name
+        .as_deref()
+}
+
+/// `text` without markdown characters, line breaks or runs of spaces, and with mentions defused.
+fn plain_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| !MARKDOWN_CHARS.contains(c) && !c.is_control())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        // A zero-width space after @ stops @everyone and friends from pinging.
+        .replace('@', "@\u{200B}")
+}
+
+/// Cleans up a requested display name so it renders as plain text: markdown characters and line
+/// breaks are removed and mentions are defused. An empty name means no override.
+pub fn sanitize_display_name(name: &str) -> Result<Option<String>, CommandError> {
+    if name.trim().is_empty() {
+        return Ok(None);
+    }
+    let sanitized = plain_text(name);
+    if sanitized.is_empty() || sanitized.chars().count() > MAX_DISPLAY_NAME_LEN {
+        return Err(CommandError::InvalidDisplayName);
+    }
+    Ok(Some(sanitized))
+}
+
+/// Sets or clears (with an empty `name`) the invoker's display name override. Returns the name
+/// that was stored.
+pub fn set_display_name(
+    data: &mut GlobalData,
+    who: Invoker,
+    name: &str,
+) -> Result<Option<String>, CommandError> {
+    let name = sanitize_display_name(name)?;
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .display_name = name.clone();
+    Ok(name)
+}
+
+/// The zone the invoker wants dates shown in.
+pub fn timezone(data: &GlobalData, who: Invoker) -> Tz {
+    data.guild(who.guild_id)
+        .and_then(|g| g.users.get(&who.user_id)?.timezone)
+        .unwrap_or_default()
+}
+
+/// Finds the result `input` refers to. It can be a label from [`result_labels`], the exact
+/// timestamp autocomplete uses as its value, or any part of a label that is unambiguous on its
+/// own: the date, the date and time, or the tag.
+pub fn resolve_result_date(
+    results: &BTreeMap<DateTime<Utc>, String>,
+    tz: Tz,
+    input: &str,
+) -> Result<DateTime<Utc>, CommandError> {
+    let input = input.trim();
+    let matching: Vec<_> = result_labels(results, tz)
+        .into_iter()
+        .filter(|(at, label)| {
+            let local = at.with_timezone(&tz);
+            [
+                label.clone(),
+                at.to_rfc3339(),
+                result_tag(&results[at]),
+                local.format("%Y-%m-%d").to_string(),
+                local.format("%Y-%m-%d %H:%M").to_string(),
+            ]
+            .iter()
+            .any(|form| form.eq_ignore_ascii_case(input))
+        })
+        .map(|(at, _)| at)
+        .collect();
+    match matching.as_slice() {
+        [at] => Ok(*at),
+        [] => Err(CommandError::UnknownResultDate(input.to_string())),
+        _ => Err(CommandError::AmbiguousResultDate(input.to_string())),
+    }
+}
+
+/// Choices for picking one of the invoker's results, newest first, as `(label, value)`. Labels
+/// come from [`result_labels`] in the invoker's timezone, and each value is the exact timestamp
+/// the result is stored under, which [`resolve_result_date`] maps back to it. `headmate` is
+/// resolved like any headmate argument, and an unknown one has no choices.
+pub fn result_date_choices(
+    data: &GlobalData,
+    who: Invoker,
+    headmate: Option<String>,
+    partial: &str,
+) -> Vec<(String, String)> {
+    let headmate = resolve_headmate(data, who, headmate);
+    let Ok(headmate_data) = find_headmate(data, who, &headmate) else {
+        return vec![];
+    };
+    let partial = partial.trim().to_lowercase();
+    result_labels(&headmate_data.results, timezone(data, who))
+        .into_iter()
+        .rev()
+        .filter(|(_, label)| label.to_lowercase().contains(&partial))
+        .take(MAX_CHOICES)
+        .map(|(at, label)| (label, at.to_rfc3339()))
+        .collect()
+}
+
+/// Sets the invoker's timezone from an IANA name like "Europe/Berlin", ignoring case.
+pub fn set_timezone(data: &mut GlobalData, who: Invoker, name: &str) -> Result<Tz, CommandError> {
+    let tz = chrono_tz::TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().eq_ignore_ascii_case(name.trim()))
+        .copied()
+        .ok_or_else(|| CommandError::UnknownTimezone(name.to_string()))?;
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .timezone = Some(tz);
+    Ok(tz)
+}
+
+/// Hides the invoker from (or shows them to) everyone else, or only one of their headmates.
+pub fn set_visibility(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    visibility: Visibility,
+) -> Result<(), CommandError> {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default();
+    match headmate {
+        Some(name) => {
+            person_data
+                .headmates
+                .get_mut(name)
+                .ok_or_else(|| CommandError::UnknownHeadmate(headmate.clone()))?
+                .visibility = visibility
+        }
+        None => person_data.visibility = visibility,
+    }
+    Ok(())
+}
+
+/// Sets whether other members may use the invoker's result from `date` (see
+/// [`resolve_result_date`]). Returns the result's ID.
+pub fn set_result_visibility(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    date: &str,
+    visibility: Visibility,
+) -> Result<String, CommandError> {
+    let tz = timezone(data, who);
+    let at = resolve_result_date(&find_headmate(data, who, headmate)?.results, tz, date)?;
+    // find_headmate already checked that the entry exists.
+    let headmate_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .unwrap()
+        .headmate_mut(headmate);
+    let id = headmate_data.results[&at].clone();
+    if visibility.is_visible() {
+        headmate_data.result_visibility.remove(&id);
+    } else {
+        headmate_data
+            .result_visibility
+            .insert(id.clone(), visibility);
+    }
+    Ok(id)
+}
+
+/// Removes the invoker's result from `date`, leaving the rest of the entry alone. Returns its ID
+/// and whether the entry has no results left.
+pub fn remove_result(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    date: &str,
+) -> Result<(String, bool), CommandError> {
+    let tz = timezone(data, who);
+    let at = resolve_result_date(&find_headmate(data, who, headmate)?.results, tz, date)?;
+    // find_headmate already checked that the entry exists.
+    let headmate_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .unwrap()
+        .headmate_mut(headmate);
+    let id = headmate_data.remove_result(&at).unwrap_or_default();
+    Ok((id, headmate_data.results.is_empty()))
+}
+
+/// Returns one message per stored result, in chronological order.
+pub async fn show_result(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    user_name: &str,
+    headmate: &Option<String>,
+    options: &ShowOptions,
+) -> Result<Vec<ShownMessage>, CommandError> {
+    let headmate_data = find_headmate(data, who, headmate)?;
+    let only = match options.date.as_deref() {
+        Some(date) => Some(resolve_result_date(
+            &headmate_data.results,
+            timezone(data, who),
+            date,
+        )?),
+        None if options.all => None,
+        None => headmate_data.results.keys().next_back().copied(),
+    };
+    // find_headmate already checked that the user is registered.
+    let show_gender = data
+        .guild(who.guild_id)
+        .is_some_and(|g| g.users[&who.user_id].show_gender);
+    let hidden = hidden_archetypes(data, who, options.show_all);
+    let aliases = archetype_aliases(data, who);
+    let mut messages = Vec::new();
+    for (at, result_id) in &headmate_data.results {
+        if only.is_some_and(|only| only != *at) {
+            continue;
+        }
+        if headmate_data.is_unresolvable(result_id) {
+            messages.push(ShownMessage::text(format!(
+                "Result {result_id} no longer resolves on bdsmtest.org. Remove it with \
+                 /remove_bdsm_result, or add it again with the right ID"
+            )));
+            continue;
+        }
+        let loaded = if options.refresh && !is_manual(result_id) {
+            refetch_result(api, cache, result_id).await
+        } else {
+            load_result(api, cache, headmate_data, result_id).await
+        };
+        match loaded {
+            Ok(mut result) => {
+                result.scores.retain(|s| !is_hidden(&hidden, &s.name));
+                for score in &mut result.scores {
+                    alias(&aliases, &mut score.name);
+                }
+                let names = ResultNames {
+                    user: user_name,
+                    headmate: headmate.as_deref(),
+                    result_id,
+                    manual: is_manual(result_id),
+                    show_gender,
+                    hidden: !headmate_data.is_result_visible(result_id),
+                };
+                messages.push(if options.plain {
+                    ShownMessage::text(format_result(&result, &names))
+                } else {
+                    ShownMessage {
+                        embeds: vec![format_result_embed(&result, &names)],
+                        ..Default::default()
+                    }
+                });
+            }
+            Err(e) => messages.push(ShownMessage::text(format!(
+                "Could not get result for {result_id}: {e}"
+            ))),
+        }
+    }
+    Ok(if only.is_none() {
+        pack_shown(messages)
+    } else {
+        messages
+    })
+}
+
+/// How every archetype's score changed between two of the invoker's results, in pages. The
+/// results are the oldest and the newest unless `from` or `to` name others, in any order.
+#[allow(clippy::too_many_arguments)]
+pub async fn result_history(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    headmate: &Option<String>,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Vec<String>, CommandError> {
+    let headmate_data = find_headmate(data, who, headmate)?;
+    let results = &headmate_data.results;
+    let (Some(&first), Some(&last)) = (results.keys().next(), results.keys().next_back()) else {
+        return Err(CommandError::NoResults);
+    };
+    if results.len() == 1 {
+        return Ok(vec![format!(
+            "{subject} only has one result so far. Take the test again to see how it changes"
+        )]);
+    }
+    let tz = timezone(data, who);
+    let pick = |date: Option<&str>, default| {
+        date.map_or(Ok(default), |date| resolve_result_date(results, tz, date))
+    };
+    let (from, to) = (pick(from, first)?, pick(to, last)?);
+    if from == to {
+        return Err(CommandError::SameResult);
+    }
+    let (from, to) = (from.min(to), from.max(to));
+    let load = |at: DateTime<Utc>| async move {
+        let id = &results[&at];
+        load_result(api, cache, headmate_data, id)
+            .await
+            .map_err(|_| CommandError::ResultUnavailable(id.clone()))
+    };
+    let (older, newer) = (load(from).await?, load(to).await?);
+    let hidden = hidden_archetypes(data, who, false);
+    let aliases = archetype_aliases(data, who);
+    let mut changes = stats::score_changes(&older.scores, &newer.scores);
+    changes.retain(|c| !is_hidden(&hidden, &c.name));
+    for change in &mut changes {
+        alias(&aliases, &mut change.name);
+    }
+    let labels: BTreeMap<_, _> = result_labels(results, tz).into_iter().collect();
+    Ok(format_result_history(
+        subject,
+        &labels[&from],
+        &labels[&to],
+        &changes,
+    ))
+}
+
+/// Summarizes the history of the invoker's results: how many there are, when they were taken, and
+/// which archetype moved the most between the oldest and the newest.
+pub async fn stats_me(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    headmate: &Option<String>,
+) -> Result<String, CommandError> {
+    let headmate_data = find_headmate(data, who, headmate)?;
+    let history =
+        stats::history(headmate_data.results.keys().copied()).ok_or(CommandError::NoResults)?;
+    let (mut change, mut compared) = (None, false);
+    if history.count > 1 {
+        let id = |at| &headmate_data.results[at];
+        let oldest = load_result(api, cache, headmate_data, id(&history.first)).await;
+        let newest = load_result(api, cache, headmate_data, id(&history.latest)).await;
+        if let (Ok(oldest), Ok(newest)) = (oldest, newest) {
+            change = stats::biggest_change(&oldest.scores, &newest.scores);
+            compared = true;
+        }
+        if let Some(change) = &mut change {
+            alias(&archetype_aliases(data, who), &mut change.name);
+        }
+    }
+    Ok(format_personal_stats(
+        subject,
+        &history,
+        change.as_ref(),
+        compared,
+        timezone(data, who),
+    ))
+}
+
+/// How many increases and decreases the reply to a retake shows.
+const RETAKE_CHANGES: usize = 3;
+
+/// The biggest score changes between the invoker's (or their headmate's) two most recent
+/// results, for the reply to adding the newer one, `added`. `None` if there is no earlier result,
+/// `added` was taken before another one, either couldn't be loaded, or nothing changed.
+pub async fn retake_diff(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    headmate: &Option<String>,
+    added: &str,
+) -> Option<String> {
+    let headmate_data = find_headmate(data, who, headmate).ok()?;
+    let mut recent = headmate_data.results.values().rev();
+    let (newest, previous) = (recent.next().filter(|id| *id == added)?, recent.next()?);
+    let previous = load_result(api, cache, headmate_data, previous)
+        .await
+        .ok()?;
+    let newest = load_result(api, cache, headmate_data, newest).await.ok()?;
+    let (mut increases, mut decreases) =
+        stats::top_changes(&previous.scores, &newest.scores, RETAKE_CHANGES);
+    let aliases = archetype_aliases(data, who);
+    for change in increases.iter_mut().chain(&mut decreases) {
+        alias(&aliases, &mut change.name);
+    }
+    Some(format_retake_diff(&increases, &decreases)).filter(|diff| !diff.is_empty())
+}
+
+/// Summarizes the invoker's top `count` archetypes from their most recent result.
+pub async fn my_top_archetypes(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    headmate: &Option<String>,
+    count: usize,
+) -> Result<String, CommandError> {
+    let headmate_data = find_headmate(data, who, headmate)?;
+    let most_recent = headmate_data.most_recent().ok_or(CommandError::NoResults)?;
+    Ok(
+        match load_result(api, cache, headmate_data, most_recent).await {
+            Ok(mut result) => {
+                let aliases = archetype_aliases(data, who);
+                for score in &mut result.scores {
+                    alias(&aliases, &mut score.name);
+                }
+                format_top_archetypes(subject, &result, count)
+            }
+            Err(e) => format!("Could not get result for {most_recent}: {e}"),
+        },
+    )
+}
+
+/// The resolved name of `user_id`, falling back to "Deleted User" for members that could not be
+/// resolved.
+fn member_label(
+    member_names: &BTreeMap<serenity::UserId, String>,
+    user_id: serenity::UserId,
+) -> &str {
+    member_names
+        .get(&user_id)
+        .map(String::as_str)
+        .unwrap_or("**Deleted User**")
+}
+
+/// How `entry` is shown in listings, using the resolved `member_names`.
+pub fn entry_label(member_names: &BTreeMap<serenity::UserId, String>, entry: &Entry) -> String {
+    let member_name = member_label(member_names, entry.user_id);
+    match entry.headmate {
+        Some(headmate) => format!("{member_name} ({headmate})"),
+        None => member_name.to_string(),
+    }
+}
+
+/// A score as shown on its own, saying whether it was estimated.
+fn score_label(score: Option<u32>, estimated: bool) -> String {
+    match score {
+        Some(score) if estimated => format!("{score:02}% (estimated)"),
+        Some(score) => format!("{score:02}%"),
+        None => "Invalid Result".to_string(),
+    }
+}
+
+/// The score between the invoker (or their `headmate`) and one other entry, from both most recent
+/// results. Other members' entries have to be visible to the invoker.
+pub async fn compare(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    headmate: &Option<String>,
+    (target, target_headmate): (serenity::UserId, Option<String>),
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<String, CommandError> {
+    let my_data = find_headmate(data, who, headmate)?;
+    let my_id = my_data.most_recent().ok_or(CommandError::NoResults)?;
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let (theirs, their_id) = guild
+        .entry(target, &target_headmate)
+        .filter(|e| e.user_id == who.user_id || e.listed)
+        .and_then(|e| Some((e, e.result_for(who.user_id)?)))
+        .ok_or_else(|| CommandError::TargetNotRegistered(target, target_headmate.clone()))?;
+
+    let (score, estimated) =
+        score_pair(api, cache, (my_data, my_id), (theirs.data, their_id), None).await;
+    Ok(format!(
+        "Your compatibility with {}: {}",
+        entry_label(member_names, &theirs),
+        score_label(score, estimated)
+    ))
+}
+
+/// The entries of `guild` that charts of the whole server show `viewer`, along with the result
+/// each is shown with: those that meet the guild's list requirements and have a visible result.
+/// Like with [`compatibility_between`], everyone but the viewer has to be listed and have allowed
+/// third-party comparisons.
+pub fn charted_entries(guild: &GuildData, viewer: serenity::UserId) -> Vec<(Entry<'_>, &String)> {
+    guild
+        .visible_entries(viewer)
+        .filter(|e| e.user_id == viewer || guild.users[&e.user_id].allow_third_party)
+        .filter(|e| guild.config.list_shortfall(e.data).is_none())
+        .filter_map(|e| Some((e, e.data.most_recent_visible()?)))
+        .collect()
+}
+
+/// The score between two entries, neither of which has to belong to the invoker. Everyone else
+/// has to have allowed third-party comparisons and be visible to the invoker.
+pub async fn compatibility_between(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    targets: [(serenity::UserId, Option<String>); 2],
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<String, CommandError> {
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let mut found = Vec::new();
+    for (user_id, headmate) in &targets {
+        let entry = guild
+            .entry(*user_id, headmate)
+            .filter(|e| e.result_for(who.user_id).is_some())
+            .ok_or_else(|| CommandError::TargetNotRegistered(*user_id, headmate.clone()))?;
+        let consented = guild.users[user_id].allow_third_party && entry.listed;
+        if *user_id != who.user_id && !consented {
+            return Err(CommandError::NoThirdPartyConsent(*user_id));
+        }
+        found.push(entry);
+    }
+    let [a, b] = [found[0], found[1]];
+
+    let (score, estimated) = score_pair(
+        api,
+        cache,
+        (a.data, a.result_for(who.user_id).map_or("", String::as_str)),
+        (b.data, b.result_for(who.user_id).map_or("", String::as_str)),
+        None,
+    )
+    .await;
+    let score = score_label(score, estimated);
+    Ok(format!(
+        "Compatibility between {} and {}: {score}",
+        entry_label(member_names, &a),
+        entry_label(member_names, &b)
+    ))
+}
+
+/// The result show_result posted for the invoker (or their `headmate`) that a "Compare with me"
+/// button should compare with: the one from `date`, or the one other members are compared
+/// against. `None` if other members may not use it.
+pub fn posted_result(
+    data: &GlobalData,
+    who: Invoker,
+    headmate: &Option<String>,
+    date: Option<&str>,
+) -> Option<String> {
+    let entry = data.guild(who.guild_id)?.entry(who.user_id, headmate)?;
+    let result_id = match date {
+        Some(date) => {
+            let at = resolve_result_date(&entry.data.results, timezone(data, who), date).ok()?;
+            &entry.data.results[&at]
+        }
+        None => entry.data.most_recent_visible()?,
+    };
+    (entry.listed && entry.data.is_result_visible(result_id)).then(|| result_id.clone())
+}
+
+/// Whether results may be shared publicly in `channel_id`: NSFW channels always, any other
+/// channel only if the guild's admins allowed it.
+pub fn check_share_channel(
+    data: &GlobalData,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    nsfw: bool,
+) -> Result<(), CommandError> {
+    let allowed = data
+        .guild(guild_id)
+        .is_some_and(|g| g.config.share_channels.contains(&channel_id));
+    if nsfw || allowed {
+        Ok(())
+    } else {
+        Err(CommandError::ShareChannelNotAllowed)
+    }
+}
+
+/// How many archetypes the summary of a newly added result shows.
+const SUMMARY_ARCHETYPES: usize = 5;
+
+/// A short summary of `result_id` of the invoker (or their headmate) for right after it was
+/// added: when it was taken and its top archetypes, leaving out those the guild hides. The result
+/// has to still be stored.
+pub async fn result_summary(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    headmate: &Option<String>,
+    result_id: &str,
+) -> Result<String, CommandError> {
+    let headmate_data = find_headmate(data, who, headmate)
+        .ok()
+        .filter(|h| h.results.values().any(|id| id == result_id))
+        .ok_or(CommandError::ShareUnavailable)?;
+    let mut result = load_result(api, cache, headmate_data, result_id)
+        .await
+        .map_err(|_| CommandError::ResultUnavailable(result_id.to_string()))?;
+    let hidden = hidden_archetypes(data, who, false);
+    result.scores.retain(|s| !is_hidden(&hidden, &s.name));
+    let aliases = archetype_aliases(data, who);
+    for score in &mut result.scores {
+        alias(&aliases, &mut score.name);
+    }
+    Ok(format_result_summary(subject, &result, SUMMARY_ARCHETYPES))
+}
+
+/// The score between the invoker's newest primary result and `result_id` of `poster` (or their
+/// headmate), for a "Compare with me" button. The posted result has to still be stored and
+/// visible to other members.
+pub async fn compare_with_posted(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    (poster, headmate, result_id): (serenity::UserId, &Option<String>, &str),
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<String, CommandError> {
+    let guild = data
+        .guild(who.guild_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let mine = guild
+        .entry(who.user_id, &None)
+        .and_then(|e| Some((e, e.data.most_recent()?)))
+        .ok_or(CommandError::NotRegistered)?;
+    if poster == who.user_id {
+        return Err(CommandError::SameAccount);
+    }
+    let theirs = guild
+        .entry(poster, headmate)
+        .filter(|e| {
+            e.listed
+                && e.data.results.values().any(|id| id == result_id)
+                && e.data.is_result_visible(result_id)
+        })
+        .ok_or(CommandError::CompareUnavailable)?;
+
+    let (score, estimated) = score_pair(
+        api,
+        cache,
+        (mine.0.data, mine.1),
+        (theirs.data, result_id),
+        None,
+    )
+    .await;
+    let score = score_label(score, estimated);
+    Ok(format!(
+        "Your compatibility with {}: {score}",
+        entry_label(member_names, &theirs)
+    ))
+}
+
+/// Explains the match between the invoker (or their `headmate`) and `target`. This shows more of
+/// the target's result than a score, so they have to be visible to the invoker and have allowed
+/// it.
+pub async fn compat_explain(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    headmate: &Option<String>,
+    (target, target_headmate): (serenity::UserId, Option<String>),
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<String, CommandError> {
+    let my_data = find_headmate(data, who, headmate)?;
+    let my_id = my_data.most_recent().ok_or(CommandError::NoResults)?;
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let entry = guild
+        .entry(target, &target_headmate)
+        .filter(|e| e.result_for(who.user_id).is_some())
+        .ok_or_else(|| CommandError::TargetNotRegistered(target, target_headmate.clone()))?;
+    if target != who.user_id && !(entry.listed && guild.users[&target].allow_explain) {
+        return Err(CommandError::NoExplainConsent(target));
+    }
+    let their_id = entry
+        .result_for(who.user_id)
+        .ok_or(CommandError::NoResults)?;
+
+    let mine = load_result(api, cache, my_data, my_id)
+        .await
+        .map_err(|_| CommandError::ResultUnavailable(my_id.clone()))?;
+    let theirs = load_result(api, cache, entry.data, their_id)
+        .await
+        .map_err(|_| CommandError::ResultUnavailable(their_id.clone()))?;
+    let (score, _) = score_pair(api, cache, (my_data, my_id), (entry.data, their_id), None).await;
+
+    let me = guild
+        .entry(who.user_id, headmate)
+        .map(|e| entry_label(member_names, &e))
+        .unwrap_or_default();
+    let mut explanation = explain(&mine.scores, &theirs.scores, EXPLAIN_COUNT);
+    let aliases = archetype_aliases(data, who);
+    for pairing in explanation
+        .harmonies
+        .iter_mut()
+        .chain(&mut explanation.frictions)
+    {
+        alias(&aliases, &mut pairing.mine);
+        alias(&aliases, &mut pairing.theirs);
+    }
+    Ok(format_explanation(
+        &me,
+        &entry_label(member_names, &entry),
+        score,
+        &explanation,
+    ))
+}
+
+/// Lets other members see what drives their matches with the invoker, or stops them.
+pub fn set_allow_explain(data: &mut GlobalData, who: Invoker, allow: bool) {
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .allow_explain = allow;
+}
+
+/// Lets other members compare the invoker with someone else, or stops them.
+pub fn set_third_party(data: &mut GlobalData, who: Invoker, allow: bool) {
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .allow_third_party = allow;
+}
+
+/// Turns the invoker's match alerts on at `threshold`, or off.
+pub fn set_match_alert(data: &mut GlobalData, who: Invoker, threshold: Option<u32>) {
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default();
+    person_data.match_alert = threshold;
+    person_data.match_alert_failed = false;
+}
+
+/// Turns the match alerts of `who` off after one could not be DMed to them.
+pub fn fail_match_alert(data: &mut GlobalData, who: Invoker) {
+    if let Some(person_data) = data.guild_mut(who.guild_id).users.get_mut(&who.user_id) {
+        person_data.match_alert = None;
+        person_data.match_alert_failed = true;
+    }
+}
+
+/// Whether the invoker still has to be told their match alerts were turned off. Only returns
+/// true once.
+pub fn take_match_alert_failure(data: &mut GlobalData, who: Invoker) -> bool {
+    data.guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .is_some_and(|u| std::mem::take(&mut u.match_alert_failed))
+}
+
+/// Shows or hides the gender the invoker's tests were taken as in their result details.
+pub fn set_show_gender(data: &mut GlobalData, who: Invoker, show: bool) {
+    data.guild_mut(who.guild_id)
+        .users
+        .entry(who.user_id)
+        .or_default()
+        .show_gender = show;
+}
+
+/// Sets the headmate the invoker's commands act as when none is given, or clears it (also by
+/// passing [`PRIMARY_HEADMATE`]). The headmate must already have an entry.
+pub fn set_default_headmate(
+    data: &mut GlobalData,
+    who: Invoker,
+    headmate: Option<String>,
+) -> Result<(), CommandError> {
+    let headmate = headmate.filter(|h| !h.eq_ignore_ascii_case(PRIMARY_HEADMATE));
+    let person_data = data
+        .guild_mut(who.guild_id)
+        .users
+        .get_mut(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    if let Some(h) = &headmate {
+        if !person_data.headmates.contains_key(h) {
+            return Err(CommandError::UnknownHeadmate(headmate));
+        }
+    }
+    person_data.default_headmate = headmate;
+    Ok(())
+}
+
+/// An entry and its match score with the invoker. `score` is `None` if the match could not be
+/// fetched.
+pub struct Scored<'a> {
+    pub entry: Entry<'a>,
+    /// The entry's result that was scored.
+    pub result: &'a str,
+    pub score: Option<u32>,
+    /// Set when the score was estimated locally instead of coming from bdsmtest.org.
+    pub estimated: bool,
+}
+
+impl Scored<'_> {
+    /// `show_age` also notes when the scored result was taken.
+    fn to_compat_entry(
+        &self,
+        member_names: &BTreeMap<serenity::UserId, String>,
+        show_age: bool,
+    ) -> CompatEntry {
+        CompatEntry {
+            user_id: self.entry.user_id,
+            member: member_label(member_names, self.entry.user_id).to_string(),
+            headmate: self.entry.headmate.map(String::from),
+            score: self.score,
+            estimated: self.estimated,
+            temporary: self.entry.data.is_temporary(self.result),
+            taken: show_age
+                .then(|| {
+                    let mut results = self.entry.data.results.iter();
+                    results.find_map(|(&at, id)| (id == self.result).then_some(at))
+                })
+                .flatten(),
+        }
+    }
+}
+
+/// The output of [`gather_scores`].
+pub struct Gathered<'a> {
+    pub scored: Vec<Scored<'a>>,
+    /// Headmate entries that were left out because of `include_headmates`.
+    pub skipped_headmates: usize,
+    /// Entries whose most recent result was flagged as no longer resolving.
+    pub unresolvable: Vec<Entry<'a>>,
+    /// How many of the invoker's results each score is an average of.
+    pub averaged_over: usize,
+    /// The label of the invoker's result that was used, when it wasn't their most recent.
+    pub older_result: Option<String>,
+    /// Entries left unscored because the scan was cancelled.
+    pub not_scored: usize,
+    /// Entries left out because they don't meet the guild's list requirements.
+    pub not_listable: usize,
+}
+
+/// Whether `entry`'s member name (as resolved in `member_names`) or headmate name contains
+/// `filter`, which is already lowercase.
+fn matches_filter(
+    member_names: &BTreeMap<serenity::UserId, String>,
+    entry: &Entry,
+    filter: &str,
+) -> bool {
+    let member_name = member_label(member_names, entry.user_id).trim_matches('*');
+    std::iter::once(member_name)
+        .chain(entry.headmate)
+        .any(|name| name.to_lowercase().contains(filter))
+}
+
+/// Scores the invoker's most recent result (or their headmate's) against every entry they can
+/// see, including their own. This is the shared fan-out behind the listing commands.
+pub async fn gather_scores<'a>(
+    data: &'a GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    member_names: &BTreeMap<serenity::UserId, String>,
+    options: &ListOptions,
+) -> Result<Gathered<'a>, CommandError> {
+    let my_data = find_headmate(data, who, &options.headmate)?;
+    let latest = *my_data
+        .results
+        .keys()
+        .next_back()
+        .ok_or(CommandError::NoResults)?;
+    let tz = timezone(data, who);
+    let chosen = match &options.my_result_date {
+        Some(date) => resolve_result_date(&my_data.results, tz, date)?,
+        None => latest,
+    };
+    let most_recent = &my_data.results[&chosen];
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    if let Some(shortfall) = guild.config.list_shortfall(my_data) {
+        return Err(CommandError::NotListable(shortfall));
+    }
+    let person_data = &guild.users[&who.user_id];
+    let weights = match options.scoring {
+        Scoring::Site => None,
+        Scoring::Custom => {
+            // Every score needs the invoker's own result, so fail early without it.
+            load_result(api, cache, my_data, most_recent)
+                .await
+                .map_err(|_| CommandError::ResultUnavailable(most_recent.clone()))?;
+            Some(&person_data.weights)
+        }
+    };
+    let include_headmates = options
+        .include_headmates
+        .or(guild.config.include_headmates)
+        .unwrap_or(true);
+    let mine: Vec<_> = my_data
+        .results
+        .range(..=chosen)
+        .rev()
+        .map(|(_, id)| id)
+        .take(options.use_average.unwrap_or(1).clamp(1, MAX_AVERAGED))
+        .collect();
+    let older_result = (chosen != latest).then(|| {
+        result_labels(&my_data.results, tz)
+            .into_iter()
+            .find_map(|(at, label)| (at == chosen).then_some(label))
+            .unwrap_or_default()
+    });
+
+    let mut candidates = Vec::new();
+    let mut skipped_headmates = 0;
+    let mut not_listable = 0;
+    let filter = options
+        .filter
+        .as_deref()
+        .map(str::trim)
+        .filter(|f| !f.is_empty());
+    let filter_lowercase = filter.map(str::to_lowercase);
+    let mut filter_matched = false;
+    for entry in guild.visible_entries(who.user_id) {
+        if person_data.ignored.contains(&entry.user_id) {
+            continue;
+        }
+        if let Some(filter) = &filter_lowercase {
+            if !matches_filter(member_names, &entry, filter) {
+                continue;
+            }
+            filter_matched = true;
+        }
+        if !include_headmates && entry.headmate.is_some() {
+            skipped_headmates += 1;
+            continue;
+        }
+        let Some(partner) = entry.result_for(who.user_id) else {
+            continue;
+        };
+        if guild.config.list_shortfall(entry.data).is_some() {
+            not_listable += 1;
+            continue;
+        }
+        candidates.push((entry, partner));
+    }
+    if let Some(filter) = filter.filter(|_| !filter_matched) {
+        return Err(CommandError::NoFilterMatches(filter.to_string()));
+    }
+
+    // Uncached scores are fetched a few at a time. The cache is only locked to check and fill it.
+    let mine = &mine;
+    let lookups: Vec<_> = candidates
+        .into_iter()
+        .map(|(entry, partner)| async move {
+            if options.cancel.is_cancelled() {
+                return None;
+            }
+            let mut scores = Vec::new();
+            let mut estimated = false;
+            for my_id in mine {
+                let (score, local) =
+                    score_pair(api, cache, (my_data, my_id), (entry.data, partner), weights).await;
+                scores.extend(score);
+                estimated |= local;
+            }
+            let score = (!scores.is_empty()).then(|| {
+                (f64::from(scores.iter().sum::<u32>()) / scores.len() as f64).round() as u32
+            });
+            Some(Scored {
+                entry,
+                result: partner,
+                score,
+                estimated,
+            })
+        })
+        .collect();
+    let scores: Vec<_> = serenity::futures::stream::iter(lookups)
+        .buffered(CONCURRENT_SCORES)
+        .collect()
+        .await;
+    let mut scored = Vec::new();
+    let mut unresolvable = Vec::new();
+    let mut not_scored = 0;
+    for score in scores {
+        let Some(score) = score else {
+            not_scored += 1;
+            continue;
+        };
+        if score.entry.data.is_unresolvable(score.result) {
+            unresolvable.push(score.entry);
+        }
+        scored.push(score);
+    }
+    Ok(Gathered {
+        scored,
+        skipped_headmates,
+        unresolvable,
+        averaged_over: mine.len(),
+        older_result,
+        not_scored,
+        not_listable,
+    })
+}
+
+/// Looks up and caches the scores of `missing`, a few at a time like [`gather_scores`]. Only the
+/// first [`MAX_GRAPH_LOOKUPS`] are looked up, and the first failure cancels `cancel`, since
+/// bdsmtest.org is then unlikely to answer the rest. Returns how many scores were looked up, and
+/// the failure if there was one.
+pub async fn look_up_missing(
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    missing: Vec<Matchup>,
+    cancel: &CancelToken,
+) -> (usize, Option<anyhow::Error>) {
+    let lookups: Vec<_> = missing
+        .into_iter()
+        .take(MAX_GRAPH_LOOKUPS)
+        .map(|matchup| async move {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            let looked_up = get_match(api, cache, matchup.request()).await;
+            if looked_up.is_err() {
+                cancel.cancel();
+            }
+            Some(looked_up)
+        })
+        .collect();
+    let looked_up: Vec<_> = serenity::futures::stream::iter(lookups)
+        .buffered(CONCURRENT_SCORES)
+        .collect()
+        .await;
+    let mut count = 0;
+    let mut failure = None;
+    for result in looked_up.into_iter().flatten() {
+        match result {
+            Ok(_) => count += 1,
+            Err(e) => {
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    (count, failure)
+}
+
+/// The output of [`list_compatibility`].
+pub struct Listing {
+    pub pages: Vec<String>,
+    /// The listed entries, in the order listed and with the date of their result, for
+    /// [`format_compat_csv`](crate::format::format_compat_csv).
+    pub entries: Vec<CompatEntry>,
+}
+
+/// Scores the invoker's most recent result against every other entry in the guild. Entries are
+/// labelled with [`entry_label`].
+pub async fn list_compatibility(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    member_names: &BTreeMap<serenity::UserId, String>,
+    options: &ListOptions,
+) -> Result<Listing, CommandError> {
+    let gathered = gather_scores(data, api, cache, who, member_names, options).await?;
+    let config = data.guild(who.guild_id).map(|g| &g.config);
+    let show_age = options
+        .show_age
+        .or(config.and_then(|c| c.show_age))
+        .unwrap_or(false);
+    let by_tier = options
+        .by_tier
+        .or(config.and_then(|c| c.by_tier))
+        .unwrap_or(false);
+    let entries: Vec<_> = options
+        .narrow(gathered.scored)
+        .into_iter()
+        .map(|s| s.to_compat_entry(member_names, true))
+        .collect();
+    let mut results = entries.clone();
+    if !show_age {
+        results.iter_mut().for_each(|r| r.taken = None);
+    }
+
+    let pages = format_compat_list(
+        subject,
+        &results,
+        &CompatListOptions {
+            custom_scoring: options.scoring == Scoring::Custom,
+            by_tier,
+            skipped_headmates: gathered.skipped_headmates,
+            unresolvable: gathered
+                .unresolvable
+                .iter()
+                .map(|e| entry_label(member_names, e))
+                .collect(),
+            group_by_user: options.group_by_user,
+            averaged_over: gathered.averaged_over,
+            older_result: gathered.older_result,
+            not_scored: gathered.not_scored,
+            not_listable: gathered.not_listable,
+            now: options.now,
+            min_score: options.min_score,
+            top: options.top,
+            ..Default::default()
+        },
+    );
+    Ok(Listing { pages, entries })
+}
+
+/// A DM telling a member about a new result that matches them well.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MatchAlert {
+    pub user_id: serenity::UserId,
+    pub message: String,
+}
+
+/// Scores the invoker's newest result (or their headmate's) against the entries of every member
+/// with match alerts on, and returns an alert for each member whose best entry meets their
+/// threshold. Hidden entries and results never alert anyone, and neither do members that either
+/// side ignores. Scores are fetched one at a time.
+pub async fn match_alerts(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    headmate: &Option<String>,
+    subject: &str,
+) -> Result<Vec<MatchAlert>, CommandError> {
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let mine = guild
+        .entry(who.user_id, headmate)
+        .ok_or_else(|| CommandError::UnknownHeadmate(headmate.clone()))?;
+    let my_id = mine.data.most_recent().ok_or(CommandError::NoResults)?;
+    if !mine.listed || !mine.data.is_result_visible(my_id) {
+        return Ok(vec![]);
+    }
+    let my_ignored = &guild.users[&who.user_id].ignored;
+
+    let mut alerts = Vec::new();
+    for (&user_id, user) in &guild.users {
+        let Some(threshold) = user.match_alert else {
+            continue;
+        };
+        if user_id == who.user_id
+            || my_ignored.contains(&user_id)
+            || user.ignored.contains(&who.user_id)
+        {
+            continue;
+        }
+        let mut best: Option<(u32, Option<&str>)> = None;
+        for entry in guild.entries().filter(|e| e.user_id == user_id) {
+            let Some(their_id) = entry.data.most_recent() else {
+                continue;
+            };
+            let (score, _) =
+                score_pair(api, cache, (mine.data, my_id), (entry.data, their_id), None).await;
+            if let Some(score) = score.filter(|&s| best.is_none_or(|(b, _)| s > b)) {
+                best = Some((score, entry.headmate));
+            }
+        }
+        if let Some((score, headmate)) = best.filter(|&(score, _)| score >= threshold) {
+            let headmate = headmate.map(|h| format!(" ({h})")).unwrap_or_default();
+            alerts.push(MatchAlert {
+                user_id,
+                message: format!(
+                    "{subject} just added a result that matches you{headmate} at {score:02}%"
+                ),
+            });
+        }
+    }
+    Ok(alerts)
+}
+
+/// Every entry of `user_id` with how many results it holds, primary first, for /list_headmates.
+/// Dates are shown in the invoker's zone.
+pub fn list_headmates(
+    data: &GlobalData,
+    who: Invoker,
+    user_id: serenity::UserId,
+    subject: &str,
+) -> Result<Vec<String>, CommandError> {
+    let user = data
+        .guild(who.guild_id)
+        .and_then(|g| g.users.get(&user_id))
+        .filter(|u| u.primary.is_some() || !u.headmates.is_empty())
+        .ok_or(if user_id == who.user_id {
+            CommandError::NotRegistered
+        } else {
+            CommandError::TargetNotRegistered(user_id, None)
+        })?;
+    let results = |headmate: Option<&String>, entry: Option<&HeadmateData>| EntryResults {
+        headmate: headmate.cloned(),
+        count: entry.map_or(0, |e| e.results.len()),
+        newest: entry.and_then(|e| e.results.keys().next_back().copied()),
+    };
+    let entries: Vec<_> = std::iter::once(results(None, user.primary.as_ref()))
+        .chain(
+            user.headmates
+                .iter()
+                .map(|(name, entry)| results(Some(name), Some(entry))),
+        )
+        .collect();
+    Ok(format_headmate_list(subject, &entries, timezone(data, who)))
+}
+
+/// The `count` best scores between the listed entries of different members, in pages. Scores that
+/// aren't cached are fetched a few at a time, and pairs that can't be scored are only counted.
+/// Hidden entries are left out even when they are the invoker's, since the leaderboard is posted
+/// for everyone to see.
+pub async fn leaderboard(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    count: usize,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<Vec<String>, CommandError> {
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let entries: Vec<_> = guild
+        .listed_entries()
+        .filter(|e| guild.config.list_shortfall(e.data).is_none())
+        .filter_map(|e| Some((e, e.data.most_recent_visible()?)))
+        .collect();
+    if entries.len() > MAX_LEADERBOARD_ENTRIES {
+        return Err(CommandError::TooManyForLeaderboard(entries.len()));
+    }
+    let mut pairs = Vec::new();
+    for (i, (a, a_id)) in entries.iter().enumerate() {
+        for (b, b_id) in &entries[i + 1..] {
+            if a.user_id != b.user_id {
+                pairs.push(((a, a_id.as_str()), (b, b_id.as_str())));
+            }
+        }
+    }
+    let lookups: Vec<_> = pairs
+        .iter()
+        .map(|&((a, a_id), (b, b_id))| async move {
+            score_pair(api, cache, (a.data, a_id), (b.data, b_id), None).await
+        })
+        .collect();
+    let scores: Vec<_> = serenity::futures::stream::iter(lookups)
+        .buffered(CONCURRENT_SCORES)
+        .collect()
+        .await;
+    let mut ranked = Vec::new();
+    let mut unscored = 0;
+    for (((a, _), (b, _)), (score, estimated)) in pairs.into_iter().zip(scores) {
+        match score {
+            Some(score) => ranked.push((
+                score,
+                entry_label(member_names, a),
+                entry_label(member_names, b),
+                estimated,
+            )),
+            None => unscored += 1,
+        }
+    }
+    // Stable, so pairs with the same score stay in registry order.
+    ranked.sort_by_key(|(score, ..)| std::cmp::Reverse(*score));
+    ranked.truncate(count);
+    let ranked: Vec<_> = ranked
+        .into_iter()
+        .map(|(score, a, b, estimated)| (score_label(Some(score), estimated), a, b))
+        .collect();
+    Ok(format_leaderboard(&ranked, unscored))
+}
+
+/// Which members have a headmate called `name` (ignoring case) that the invoker may see.
+pub fn whois_headmate(
+    data: &GlobalData,
+    who: Invoker,
+    name: &str,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> String {
+    let name = name.trim();
+    let owners: Vec<_> = data
+        .guild(who.guild_id)
+        .into_iter()
+        .flat_map(|g| g.visible_entries(who.user_id))
+        .filter(|e| {
+            e.headmate
+                .is_some_and(|h| h.to_lowercase() == name.to_lowercase())
+        })
+        .map(|e| format!("- {}", entry_label(member_names, &e)))
+        .collect();
+    if owners.is_empty() {
+        format!("Nobody in this server has a headmate called {name}")
+    } else {
+        format!("Headmates called {name}:\n{}", owners.join("\n"))
+    }
+}
+
+/// The invoker's best match among everyone else's entries.
+pub async fn match_me(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    subject: &str,
+    headmate: &Option<String>,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<String, CommandError> {
+    let options = ListOptions {
+        headmate: headmate.clone(),
+        ..Default::default()
+    };
+    let results: Vec<_> = gather_scores(data, api, cache, who, member_names, &options)
+        .await?
+        .scored
+        .into_iter()
+        .filter(|s| s.entry.user_id != who.user_id)
+        .map(|s| s.to_compat_entry(member_names, false))
+        .collect();
+
+    Ok(format_best_match(subject, &results))
+}
+
+/// Ranks the entries the invoker can see by their most recent score for `archetype`. Entries
+/// whose result can't be fetched are left out, and archetypes the guild hides can only be ranked
+/// with `show_all`.
+pub async fn top_archetype(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    archetype: &str,
+    show_all: bool,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<Vec<String>, CommandError> {
+    let archetype = archetypes::resolve_loosely(archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    if is_hidden(&hidden_archetypes(data, who, show_all), archetype) {
+        return Err(CommandError::HiddenArchetype(archetype.to_string()));
+    }
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+
+    let mut entries = Vec::new();
+    for entry in guild.visible_entries(who.user_id) {
+        let Some(id) = entry.result_for(who.user_id) else {
+            continue;
+        };
+        let Ok(result) = load_result(api, cache, entry.data, id).await else {
+            continue;
+        };
+        if let Some(score) = result
+            .scores
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(archetype))
+        {
+            entries.push(RankedEntry {
+                name: entry_label(member_names, &entry),
+                score: score.score,
+                own: entry.user_id == who.user_id,
+            });
+        }
+    }
+
+    Ok(format_archetype_ranking(
+        archetypes::display(&archetype_aliases(data, who), archetype),
+        &entries,
+        TOP_ARCHETYPE_LIMIT,
+        &CompatListOptions::default(),
+    ))
+}
+
+/// Ranks the entries the invoker can see by how close their most recent score for `archetype` is
+/// to the invoker's. Scores are compared locally, fetching any results that aren't cached yet;
+/// entries whose result can't be fetched are skipped and counted.
+pub async fn similar_on(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    archetype: &str,
+    headmate: &Option<String>,
+    member_names: &BTreeMap<serenity::UserId, String>,
+) -> Result<Vec<String>, CommandError> {
+    let archetype = archetypes::resolve_loosely(archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    let my_data = find_headmate(data, who, headmate)?;
+    let most_recent = my_data.most_recent().ok_or(CommandError::NoResults)?;
+    let mine = load_result(api, cache, my_data, most_recent)
+        .await
+        .map_err(|_| CommandError::ResultUnavailable(most_recent.clone()))?;
+    let my_score = archetype_score(&mine.scores, archetype)
+        .ok_or_else(|| CommandError::UnknownArchetype(archetype.to_string()))?;
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let ignored = &guild.users[&who.user_id].ignored;
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for entry in guild.visible_entries(who.user_id) {
+        let is_me = entry.user_id == who.user_id && entry.headmate == headmate.as_deref();
+        if is_me || ignored.contains(&entry.user_id) {
+            continue;
+        }
+        let Some(id) = entry.result_for(who.user_id) else {
+            continue;
+        };
+        let Ok(theirs) = load_result(api, cache, entry.data, id).await else {
+            skipped += 1;
+            continue;
+        };
+        let (Some(score), Some(distance)) = (
+            archetype_score(&theirs.scores, archetype),
+            archetype_distance(&mine.scores, &theirs.scores, archetype),
+        ) else {
+            continue;
+        };
+        entries.push(SimilarEntry {
+            name: entry_label(member_names, &entry),
+            score,
+            distance,
+        });
+    }
+
+    Ok(format_similarity(
+        archetypes::display(&archetype_aliases(data, who), archetype),
+        my_score,
+        &entries,
+        skipped,
+        SIMILAR_LIMIT,
+    ))
+}
+
+/// Which of `members` have added a result in the invoker's guild. Bots should already be left
+/// out. Only works where the guild allows it.
+pub fn coverage(
+    data: &GlobalData,
+    who: Invoker,
+    members: impl IntoIterator<Item = serenity::UserId>,
+) -> Result<Coverage, CommandError> {
+    let guild = data
+        .guild(who.guild_id)
+        .filter(|g| g.config.allow_coverage)
+        .ok_or(CommandError::CoverageDisabled)?;
+    let mut coverage = Coverage::default();
+    for user_id in members {
+        match guild.users.get(&user_id) {
+            Some(user) if user.has_results() => coverage.registered += 1,
+            Some(_) => coverage.opted_out += 1,
+            None => coverage.unregistered.push(user_id),
+        }
+    }
+    Ok(coverage)
+}
+
+/// Fetches every one of the invoker's results from bdsmtest.org again, up to [`VERIFY_LIMIT`],
+/// to check that they still exist. The cache is refreshed along the way. Returns the report, and
+/// what was found for [`record_verified`].
+pub async fn verify_results(
+    data: &GlobalData,
+    api: &dyn BdsmApi,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+) -> Result<(Vec<String>, Vec<VerifiedResult>), CommandError> {
+    let person_data = data
+        .guild(who.guild_id)
+        .ok_or(CommandError::NoGuildData)?
+        .users
+        .get(&who.user_id)
+        .ok_or(CommandError::NotRegistered)?;
+    let entries = person_data
+        .primary
+        .iter()
+        .map(|h| (None, h))
+        .chain(person_data.headmates.iter().map(|(n, h)| (Some(n), h)));
+
+    let mut fetched = 0;
+    let mut results = Vec::new();
+    for (headmate, headmate_data) in entries {
+        for result_id in headmate_data.results.values() {
+            let verification = if is_manual(result_id) {
+                Verification::Manual
+            } else if fetched == VERIFY_LIMIT {
+                Verification::Skipped
+            } else {
+                fetched += 1;
+                match api.get_result(result_id).await {
+                    Ok(result) => {
+                        let date = result.date.clone();
+                        cache.lock().await.insert_result(result_id.clone(), result);
+                        Verification::Found(date)
+                    }
+                    Err(e) if is_not_found(&e) => Verification::NotFound,
+                    Err(_) => Verification::Unavailable,
+                }
+            };
+            results.push(VerifiedResult {
+                headmate: headmate.cloned(),
+                result_id: result_id.clone(),
+                verification,
+            });
+        }
+    }
+    Ok((format_verification(&results, MESSAGE_LIMIT), results))
+}
+
+/// Stores what [`verify_results`] found the way the background checks do: results that were found
+/// are no longer flagged and get their copy kept, and results that weren't count towards being
+/// flagged. Returns whether anything changed.
+pub fn record_verified(
+    data: &mut GlobalData,
+    who: Invoker,
+    verified: &[VerifiedResult],
+    cache: &Cache,
+) -> bool {
+    let mut changed = false;
+    for result in verified {
+        let found = match result.verification {
+            Verification::Found(_) => true,
+            Verification::NotFound => false,
+            _ => continue,
+        };
+        let stored = liveness::StoredResult {
+            guild_id: who.guild_id,
+            user_id: who.user_id,
+            headmate: result.headmate.clone(),
+            result_id: result.result_id.clone(),
+        };
+        changed |= liveness::record(data, &stored, found);
+    }
+    let headmates: BTreeSet<_> = verified.iter().map(|r| &r.headmate).collect();
+    for headmate in headmates {
+        changed |= keep_fetched_results(data, who, headmate, cache);
+    }
+    changed
+}
+
+/// Averages every listed entry's most recent result, leaving out the archetypes the guild hides
+/// unless `show_all`. Only results that are already cached are used, so this never calls out to
+/// bdsmtest.org.
+pub async fn server_stats(
+    data: &GlobalData,
+    cache: &Mutex<Cache>,
+    who: Invoker,
+    show_all: bool,
+) -> Result<String, CommandError> {
+    let guild = data.guild(who.guild_id).ok_or(CommandError::NoGuildData)?;
+    let cache = cache.lock().await;
+    let mut uncached = 0;
+    let results: Vec<_> = guild
+        .listed_entries()
+        .filter_map(|e| Some((e.data, e.data.most_recent_visible()?)))
+        .filter_map(|(headmate, id)| {
+            let result = stored_result(headmate, id).or_else(|| cache.get_result(id).cloned());
+            uncached += usize::from(result.is_none());
+            result
+        })
+        .collect();
+    let mut averages = archetype_averages(results.iter().map(|r| r.scores.as_slice()));
+    let hidden = hidden_archetypes(data, who, show_all);
+    averages.retain(|a| !is_hidden(&hidden, &a.name));
+    let aliases = archetype_aliases(data, who);
+    for average in &mut averages {
+        alias(&aliases, &mut average.name);
+    }
+    Ok(format_server_stats(
+        &averages,
+        results.len(),
+        uncached,
+        SERVER_STATS_LIMIT,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{api::GetResultScore, data::JobRecord, format::format_compat_csv, testutil::at};
+
+    /// Serves results and matches from memory. Anything not registered is an error.
+    #[derive(Default)]
+    struct FakeApi {
+        results: HashMap<String, Vec<(&'static str, u32)>>,
+        matches: HashMap<Matchup, u32>,
+    }
+
+    #[async_trait]
+    impl BdsmApi for FakeApi {
+        async fn get_result(&self, id: &str) -> Result<GetResultResult, anyhow::Error> {
+            let scores = self
+                .results
+                .get(id)
+                .ok_or_else(|| anyhow::anyhow!("not found"))?;
+            Ok(GetResultResult {
+                langfile: "en".into(),
+                date: "2024-05-01".into(),
+                version: 3,
+                gender: String::new(),
+                auth: false,
+                scores: scores
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(name, score))| GetResultScore {
+                        id: i as u32,
+                        name: name.into(),
+                        pairdesc: String::new(),
+                        description: String::new(),
+                        score,
+                    })
+                    .collect(),
+            })
+        }
+
+        async fn get_match(&self, request: &MatchRequest) -> Result<u32, anyhow::Error> {
+            self.matches
+                .get(&Matchup::from(request.clone()))
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("not found"))
+        }
+    }
+
+    const GUILD: serenity::GuildId = serenity::GuildId::new(10);
+    const ME: Invoker = Invoker {
+        guild_id: GUILD,
+        user_id: serenity::UserId::new(100),
+    };
+    const OTHER: Invoker = Invoker {
+        guild_id: GUILD,
+        user_id: serenity::UserId::new(200),
+    };
+
+    fn names() -> BTreeMap<serenity::UserId, String> {
+        BTreeMap::from([(ME.user_id, "**Me**".to_string())])
+    }
+
+    async fn list(
+        data: &GlobalData,
+        api: &FakeApi,
+        headmate: Option<&str>,
+    ) -> Result<Vec<String>, CommandError> {
+        list_compatibility(
+            data,
+            api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "Me",
+            &names(),
+            &ListOptions {
+                headmate: headmate.map(String::from),
+                ..Default::default()
+            },
+        )
+        .await
+        .map(|l| l.pages)
+    }
+
+    #[tokio::test]
+    async fn commands_without_guild_data() {
+        let mut data = GlobalData::default();
+        let api = FakeApi::default();
+        assert_eq!(
+            show_result(
+                &data,
+                &api,
+                &Mutex::new(Cache::new()),
+                ME,
+                "me",
+                &None,
+                &ShowOptions::default()
+            )
+            .await,
+            Err(CommandError::NoGuildData)
+        );
+        assert_eq!(
+            list(&data, &api, None).await,
+            Err(CommandError::NoGuildData)
+        );
+        assert_eq!(
+            remove_results(&mut data, ME, None, at(5)),
+            Err(CommandError::NotRegistered)
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_for_unregistered_user() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let api = FakeApi::default();
+        assert_eq!(
+            show_result(
+                &data,
+                &api,
+                &Mutex::new(Cache::new()),
+                ME,
+                "me",
+                &None,
+                &ShowOptions::default()
+            )
+            .await,
+            Err(CommandError::NotRegistered)
+        );
+        assert_eq!(
+            list(&data, &api, None).await,
+            Err(CommandError::NotRegistered)
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_with_unknown_headmate() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        let api = FakeApi::default();
+        let ash = Some("Ash".to_string());
+        assert_eq!(
+            show_result(
+                &data,
+                &api,
+                &Mutex::new(Cache::new()),
+                ME,
+                "me",
+                &ash,
+                &ShowOptions::default()
+            )
+            .await,
+            Err(CommandError::UnknownHeadmate(ash.clone()))
+        );
+        assert_eq!(
+            list(&data, &api, Some("Ash")).await,
+            Err(CommandError::UnknownHeadmate(ash))
+        );
+        assert_eq!(
+            remove_results(&mut data, ME, Some("Ash".into()), at(5)),
+            Err(CommandError::NoHeadmateEntries("Ash".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_with_empty_results() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&ME.user_id)
+            .unwrap()
+            .headmate_mut(&Some("Ash".into()));
+        assert_eq!(
+            list(&data, &FakeApi::default(), Some("Ash")).await,
+            Err(CommandError::NoResults)
+        );
+    }
+
+    #[test]
+    fn announce_only_first_result() {
+        let mut data = GlobalData::default();
+        assert!(add_result(&mut data, ME, &None, "a".into(), at(1), None));
+        assert!(!add_result(
+            &mut data,
+            ME,
+            &None,
+            "b".into(),
+            at(2),
+            Some(true)
+        ));
+        remove_results(&mut data, ME, None, at(5)).unwrap();
+        assert!(!add_result(&mut data, ME, &None, "c".into(), at(3), None));
+
+        assert!(!add_result(
+            &mut data,
+            OTHER,
+            &None,
+            "d".into(),
+            at(1),
+            Some(false)
+        ));
+        assert!(!add_result(
+            &mut data,
+            OTHER,
+            &None,
+            "e".into(),
+            at(2),
+            None
+        ));
+    }
+
+    #[test]
+    fn announce_respects_saved_preference() {
+        let mut data = GlobalData::default();
+        data.guild_mut(GUILD)
+            .users
+            .entry(ME.user_id)
+            .or_default()
+            .suppress_announcements = true;
+        assert!(!add_result(&mut data, ME, &None, "a".into(), at(1), None));
+
+        let mut data = GlobalData::default();
+        data.guild_mut(GUILD)
+            .users
+            .entry(ME.user_id)
+            .or_default()
+            .suppress_announcements = true;
+        assert!(add_result(
+            &mut data,
+            ME,
+            &None,
+            "a".into(),
+            at(1),
+            Some(true)
+        ));
+    }
+
+    #[tokio::test]
+    async fn remove_primary_twice() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        assert_eq!(
+            remove_results(&mut data, ME, None, at(5)).map(|r| r.headmate),
+            Ok(None)
+        );
+        assert_eq!(
+            remove_results(&mut data, ME, None, at(5)),
+            Err(CommandError::NoPrimaryData)
+        );
+    }
+
+    #[test]
+    fn describing_a_removal_changes_nothing() {
+        let mut data = GlobalData::default();
+        assert_eq!(
+            describe_removal(&data, ME, &None),
+            Err(CommandError::NotRegistered)
+        );
+        assert!(data.guilds.is_empty());
+
+        add_result(&mut data, ME, &None, "a".into(), at(1), None);
+        add_result(&mut data, ME, &None, "b".into(), at(4), None);
+        let elsewhere = Invoker {
+            guild_id: serenity::GuildId::new(11),
+            ..ME
+        };
+        add_result(&mut data, elsewhere, &None, "c".into(), at(2), None);
+        assert_eq!(
+            describe_removal(&data, ME, &None),
+            Ok(RemovalTarget {
+                headmate: None,
+                results: 2,
+                taken: Some((at(1), at(4))),
+                other_guilds: 1,
+            })
+        );
+
+        assert_eq!(
+            describe_removal(&data, ME, &Some("Ash".into())),
+            Err(CommandError::NoHeadmateEntries("Ash".into()))
+        );
+        add_result(&mut data, ME, &Some("Ash".into()), "d".into(), at(3), None);
+        let target = describe_removal(&data, ME, &Some("Ash".into())).unwrap();
+        assert_eq!(target.results, 1);
+        assert_eq!(target.taken, Some((at(3), at(3))));
+        assert_eq!(data.guilds[&GUILD].users[&ME.user_id].tombstones.len(), 0);
+    }
+
+    #[test]
+    fn undo_puts_back_removals_unless_readded() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &Some("Ash".into()), "a".into(), at(1), None);
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        let removal = remove_results(&mut data, ME, Some("Ash".into()), at(5)).unwrap();
+        assert!(tombstones(&data, ME)[0].was_default);
+        undo_removal(&mut data, ME, &removal).unwrap();
+        assert!(tombstones(&data, ME).is_empty());
+        assert_eq!(
+            undo_removal(&mut data, ME, &removal),
+            Err(CommandError::NothingToUndo)
+        );
+        let user = &data.guilds[&GUILD].users[&ME.user_id];
+        assert_eq!(user.headmates["Ash"].results[&at(1)], "a");
+        assert_eq!(user.default_headmate.as_deref(), Some("Ash"));
+
+        add_result(&mut data, ME, &None, "b".into(), at(2), None);
+        let removal = remove_results(&mut data, ME, None, at(5)).unwrap();
+        add_result(&mut data, ME, &None, "c".into(), at(3), None);
+        assert_eq!(
+            undo_removal(&mut data, ME, &removal),
+            Err(CommandError::RestoreConflict(None))
+        );
+        let primary = data.guilds[&GUILD].users[&ME.user_id].primary.as_ref();
+        assert_eq!(primary.unwrap().results.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn compare_scores_one_visible_entry() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(2), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(2),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([(Matchup::new("mine".into(), "theirs".into()), 64)]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let names = BTreeMap::from([(OTHER.user_id, "**Them**".to_string())]);
+        assert_eq!(
+            compare(
+                &data,
+                &api,
+                &cache,
+                ME,
+                &None,
+                (OTHER.user_id, None),
+                &names
+            )
+            .await,
+            Ok("Your compatibility with **Them**: 64%".to_string())
+        );
+        // Served from the cache the second time.
+        let offline = FakeApi::default();
+        assert_eq!(
+            compare(
+                &data,
+                &offline,
+                &cache,
+                ME,
+                &None,
+                (OTHER.user_id, None),
+                &names
+            )
+            .await,
+            Ok("Your compatibility with **Them**: 64%".to_string())
+        );
+        let target = (OTHER.user_id, Some("Kit".to_string()));
+        assert_eq!(
+            compare(&data, &api, &cache, ME, &None, target, &names).await,
+            Err(CommandError::TargetNotRegistered(
+                OTHER.user_id,
+                Some("Kit".into())
+            ))
+        );
+        let stranger = serenity::UserId::new(300);
+        assert_eq!(
+            compare(&data, &api, &cache, ME, &None, (stranger, None), &names).await,
+            Err(CommandError::TargetNotRegistered(stranger, None))
+        );
+
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&OTHER.user_id)
+            .unwrap()
+            .visibility = Visibility::HiddenFromOthers;
+        assert_eq!(
+            compare(
+                &data,
+                &api,
+                &cache,
+                ME,
+                &None,
+                (OTHER.user_id, None),
+                &names
+            )
+            .await,
+            Err(CommandError::TargetNotRegistered(OTHER.user_id, None))
+        );
+    }
+
+    #[tokio::test]
+    async fn compare_button_respects_visibility() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(2), None);
+        let api = FakeApi {
+            matches: HashMap::from([(Matchup::new("mine".into(), "theirs".into()), 64)]),
+            ..Default::default()
+        };
+        async fn compare(
+            data: &GlobalData,
+            api: &FakeApi,
+            who: Invoker,
+            id: &str,
+        ) -> Result<String, CommandError> {
+            let names = BTreeMap::from([(OTHER.user_id, "**Them**".to_string())]);
+            let cache = Mutex::new(Cache::new());
+            compare_with_posted(data, api, &cache, who, (OTHER.user_id, &None, id), &names).await
+        }
+
+        assert_eq!(
+            posted_result(&data, OTHER, &None, None).as_deref(),
+            Some("theirs")
+        );
+        assert_eq!(
+            compare(&data, &api, ME, "theirs").await.unwrap(),
+            "Your compatibility with **Them**: 64%"
+        );
+        let newcomer = Invoker {
+            user_id: serenity::UserId::new(300),
+            ..ME
+        };
+        assert_eq!(
+            compare(&data, &api, newcomer, "theirs").await,
+            Err(CommandError::NotRegistered)
+        );
+        assert_eq!(
+            compare(&data, &api, OTHER, "theirs").await,
+            Err(CommandError::SameAccount)
+        );
+        assert_eq!(
+            compare(&data, &api, ME, "gone").await,
+            Err(CommandError::CompareUnavailable)
+        );
+
+        set_result_visibility(
+            &mut data,
+            OTHER,
+            &None,
+            "2024-01-02",
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        assert_eq!(
+            posted_result(&data, OTHER, &None, None).as_deref(),
+            Some("old")
+        );
+        assert_eq!(posted_result(&data, OTHER, &None, Some("2024-01-02")), None);
+        assert_eq!(
+            compare(&data, &api, ME, "theirs").await,
+            Err(CommandError::CompareUnavailable)
+        );
+        set_visibility(&mut data, OTHER, &None, Visibility::HiddenFromOthers).unwrap();
+        assert_eq!(posted_result(&data, OTHER, &None, None), None);
+    }
+
+    #[test]
+    fn imports_read_lists_and_registry_entries() {
+        assert_eq!(
+            parse_import(" a, b\nb,\n\nc "),
+            Ok(vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            parse_import(
+                r#"{"results": {"2024-01-01T00:00:00Z": "a", "2024-02-01T00:00:00Z": "b"}}"#
+            ),
+            Ok(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(parse_import(r#"["a", "a"]"#), Ok(vec!["a".into()]));
+        assert_eq!(
+            parse_import(r#"{"results": ["a"]}"#),
+            Err(CommandError::UnreadableImportFile)
+        );
+        assert_eq!(parse_import(" , "), Err(CommandError::NothingToImport));
+        let many = (0..=MAX_IMPORTED)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parse_import(&many.join(",")),
+            Err(CommandError::TooManyToImport(MAX_IMPORTED + 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn imports_skip_what_the_entry_has() {
+        let api = FakeApi {
+            results: HashMap::from([
+                ("new".to_string(), vec![("Switch", 50)]),
+                ("old".to_string(), vec![("Switch", 40)]),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+
+        let ids = vec!["new".into(), "old".into(), "typo".into()];
+        let checked = check_imports(&api, &cache, ids, at(20)).await;
+        let (imports, announce) = store_imports(&mut data, ME, &None, checked, None);
+        assert_eq!(
+            imports,
+            vec![
+                ("new".into(), ImportStatus::Imported),
+                ("old".into(), ImportStatus::Duplicate),
+                ("typo".into(), ImportStatus::Unavailable),
+            ]
+        );
+        assert!(!announce);
+        let results = &find_headmate(&data, ME, &None).unwrap().results;
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results.get(&"2024-05-01T00:00:00Z".parse().unwrap()),
+            Some(&"new".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn new_results_are_stored_under_their_test_date() {
+        let api = FakeApi {
+            results: HashMap::from([("new".to_string(), vec![("Switch", 50)])]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let now = at(20);
+        // FakeApi dates every result 2024-05-01.
+        let taken = check_new_result(&api, &cache, "new", now).await.unwrap();
+        assert_eq!(
+            taken,
+            "2024-05-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            check_new_result(&api, &cache, "typo", now).await,
+            Err(CommandError::ResultUnavailable("typo".into()))
+        );
+
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "a".into(), taken, None);
+        add_result(&mut data, ME, &None, "b".into(), taken, None);
+        add_result(&mut data, ME, &None, "a".into(), taken, None);
+        let results = &find_headmate(&data, ME, &None).unwrap().results;
+        assert_eq!(
+            results.values().collect::<Vec<_>>(),
+            ["a", "b"],
+            "same-day results are all kept, and re-adding one doesn't duplicate it"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetched_results_are_kept_in_the_registry() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        let api = FakeApi {
+            results: HashMap::from([("mine".to_string(), vec![("Switch", 50), ("Rigger", 20)])]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let shown = show_result(&data, &api, &cache, ME, "me", &None, &Default::default())
+            .await
+            .unwrap();
+        assert!(keep_fetched_results(
+            &mut data,
+            ME,
+            &None,
+            &*cache.lock().await
+        ));
+        assert!(!keep_fetched_results(
+            &mut data,
+            ME,
+            &None,
+            &*cache.lock().await
+        ));
+
+        // Saved and loaded again, the copy is used without bdsmtest.org or the cache.
+        let json = serde_json::to_string(&data).unwrap();
+        let mut data: GlobalData = serde_json::from_str(&json).unwrap();
+        data.migrate();
+        let (offline, empty) = (FakeApi::default(), Mutex::new(Cache::new()));
+        let options = ShowOptions::default();
+        assert_eq!(
+            show_result(&data, &offline, &empty, ME, "me", &None, &options)
+                .await
+                .unwrap(),
+            shown
+        );
+        let refresh = ShowOptions {
+            refresh: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            show_result(&data, &offline, &empty, ME, "me", &None, &refresh)
+                .await
+                .unwrap(),
+            [ShownMessage::text(
+                "Could not get result for mine: not found".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn registries_from_before_kept_results_still_load() {
+        let json = r#"{"guilds":{"10":{"users":{"100":{"primary":{"results":{
+            "2024-01-01T00:00:00Z":"mine"}}}}}},"version":1}"#;
+        let mut data: GlobalData = serde_json::from_str(json).unwrap();
+        data.migrate();
+        assert_eq!(data.version, crate::data::SCHEMA_VERSION);
+        let headmate_data = find_headmate(&data, ME, &None).unwrap();
+        assert_eq!(
+            headmate_data.results,
+            BTreeMap::from([(at(1), "mine".into())])
+        );
+        assert!(headmate_data.fetched.is_empty());
+
+        let stale = crate::data::StoredResult {
+            date: "2024-01-01".into(),
+            gender: String::new(),
+            version: 3,
+            scores: vec![],
+        };
+        let user = data.guild_mut(GUILD).users.get_mut(&ME.user_id).unwrap();
+        let primary = user.primary.as_mut().unwrap();
+        primary.fetched.insert("mine".into(), stale.clone());
+        primary.fetched.insert("removed".into(), stale);
+        data.migrate();
+        let headmate_data = find_headmate(&data, ME, &None).unwrap();
+        assert_eq!(headmate_data.fetched.keys().collect::<Vec<_>>(), ["mine"]);
+    }
+
+    #[tokio::test]
+    async fn retakes_show_the_diff_from_the_previous_result() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        let api = FakeApi {
+            results: HashMap::from([
+                ("old".to_string(), vec![("Switch", 50), ("Rigger", 20)]),
+                ("new".to_string(), vec![("Switch", 40), ("Rigger", 35)]),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        assert_eq!(
+            retake_diff(&data, &api, &cache, ME, &None, "old").await,
+            None
+        );
+
+        add_result(&mut data, ME, &None, "new".into(), at(2), None);
+        assert_eq!(
+            retake_diff(&data, &api, &cache, ME, &None, "old").await,
+            None
+        );
+        assert_eq!(
+            retake_diff(&data, &api, &cache, ME, &None, "new")
+                .await
+                .unwrap(),
+            "Up since your last result: Rigger +15\nDown since your last result: Switch -10"
+        );
+        add_result(&mut data, ME, &None, "gone".into(), at(3), None);
+        assert_eq!(
+            retake_diff(&data, &api, &cache, ME, &None, "gone").await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn summaries_leave_out_hidden_archetypes() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "new".into(), at(1), None);
+        set_archetype_hidden(&mut data, ME, "Rigger", true).unwrap();
+        let api = FakeApi {
+            results: HashMap::from([(
+                "new".to_string(),
+                vec![("Switch", 80), ("Rigger", 90), ("Brat", 60)],
+            )]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        assert_eq!(
+            result_summary(&data, &api, &cache, ME, "**Me**", &None, "new").await,
+            Ok("**Me** took the test on 2024-05-01\n\
+                **Me**'s top archetypes: Switch 80%, Brat 60%"
+                .to_string())
+        );
+        assert_eq!(
+            result_summary(&data, &api, &cache, ME, "**Me**", &None, "other").await,
+            Err(CommandError::ShareUnavailable)
+        );
+        remove_results(&mut data, ME, None, at(2)).unwrap();
+        assert_eq!(
+            result_summary(&data, &api, &cache, ME, "**Me**", &None, "new").await,
+            Err(CommandError::ShareUnavailable)
+        );
+    }
+
+    #[test]
+    fn results_are_only_shared_in_allowed_channels() {
+        let mut data = GlobalData::default();
+        let channel = serenity::ChannelId::new(7);
+        assert_eq!(
+            check_share_channel(&data, GUILD, channel, false),
+            Err(CommandError::ShareChannelNotAllowed)
+        );
+        assert_eq!(check_share_channel(&data, GUILD, channel, true), Ok(()));
+        data.guild_mut(GUILD).config.share_channels.insert(channel);
+        assert_eq!(check_share_channel(&data, GUILD, channel, false), Ok(()));
+        assert_eq!(
+            check_share_channel(&data, GUILD, serenity::ChannelId::new(8), false),
+            Err(CommandError::ShareChannelNotAllowed)
+        );
+    }
+
+    #[tokio::test]
+    async fn tombstoned_entries_are_left_out() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        remove_results(&mut data, OTHER, Some("Ash".into()), at(5)).unwrap();
+        let api = FakeApi {
+            results: HashMap::from([("mine".to_string(), vec![("Switch", 50)])]),
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 64),
+            ]),
+        };
+        let pages = list(&data, &api, None).await.unwrap();
+        assert!(!pages.concat().contains("Ash"));
+
+        remove_results(&mut data, ME, None, at(5)).unwrap();
+        let show = show_result(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "me",
+            &None,
+            &ShowOptions::default(),
+        )
+        .await;
+        assert_eq!(show, Err(CommandError::UnknownHeadmate(None)));
+        assert!(list(&data, &api, None).await.is_err());
+
+        assert_eq!(restore_tombstone(&mut data, ME, 0).unwrap().headmate, None);
+        assert_eq!(
+            restore_tombstone(&mut data, ME, 0),
+            Err(CommandError::UnknownTombstone(1))
+        );
+        assert!(list(&data, &api, None).await.is_ok());
+    }
+
+    #[test]
+    fn sweep_deletes_old_tombstones_and_empty_members() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        remove_results(&mut data, ME, None, at(1)).unwrap();
+        remove_results(&mut data, OTHER, Some("Ash".into()), at(1)).unwrap();
+        remove_results(&mut data, OTHER, None, at(9)).unwrap();
+        let third = Invoker {
+            user_id: serenity::UserId::new(3),
+            ..ME
+        };
+        add_result(&mut data, third, &None, "third".into(), at(1), None);
+        set_third_party(&mut data, third, true);
+        remove_results(&mut data, third, None, at(1)).unwrap();
+
+        let week = chrono::Duration::days(7);
+        assert_eq!(purge_tombstones(&mut data, at(8), week), 0);
+        assert_eq!(purge_tombstones(&mut data, at(9), week), 3);
+        let users = &data.guilds[&GUILD].users;
+        assert!(!users.contains_key(&ME.user_id));
+        assert_eq!(users[&OTHER.user_id].tombstones.len(), 1);
+        // Settings outlive the entries they were set with.
+        assert!(users[&third.user_id].allow_third_party);
+    }
+
+    #[test]
+    fn headmate_resolution_order() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        add_result(
+            &mut data,
+            ME,
+            &Some("Kit".into()),
+            "kit".into(),
+            at(1),
+            None,
+        );
+        assert_eq!(resolve_headmate(&data, ME, None), None);
+        assert_eq!(
+            resolve_headmate(&data, OTHER, Some("Kit".into())),
+            Some("Kit".into())
+        );
+
+        assert_eq!(
+            set_default_headmate(&mut data, ME, Some("Nobody".into())),
+            Err(CommandError::UnknownHeadmate(Some("Nobody".into())))
+        );
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        assert_eq!(resolve_headmate(&data, ME, None), Some("Ash".into()));
+        assert_eq!(
+            resolve_headmate(&data, ME, Some("Kit".into())),
+            Some("Kit".into())
+        );
+        assert_eq!(resolve_headmate(&data, ME, Some("Primary".into())), None);
+        assert_eq!(resolve_headmate(&data, OTHER, None), None);
+
+        set_default_headmate(&mut data, ME, Some("primary".into())).unwrap();
+        assert_eq!(resolve_headmate(&data, ME, None), None);
+        set_default_headmate(&mut data, ME, Some("Kit".into())).unwrap();
+        set_default_headmate(&mut data, ME, None).unwrap();
+        assert_eq!(resolve_headmate(&data, ME, None), None);
+    }
+
+    #[test]
+    fn headmate_choices_respect_visibility() {
+        let mut data = GlobalData::default();
+        for (who, name) in [(ME, "Ash"), (ME, "Alex"), (OTHER, "Kit"), (OTHER, "Kai")] {
+            add_result(&mut data, who, &Some(name.into()), name.into(), at(1), None);
+        }
+        set_visibility(
+            &mut data,
+            OTHER,
+            &Some("Kai".into()),
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        set_visibility(
+            &mut data,
+            ME,
+            &Some("Alex".into()),
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+
+        assert_eq!(headmate_choices(&data, ME, ME.user_id, ""), ["Alex", "Ash"]);
+        assert_eq!(headmate_choices(&data, ME, ME.user_id, "As"), ["Ash"]);
+        assert_eq!(headmate_choices(&data, ME, ME.user_id, "sh"), ["Ash"]);
+        assert_eq!(headmate_choices(&data, ME, OTHER.user_id, ""), ["Kit"]);
+        assert_eq!(
+            headmate_choices(&data, OTHER, OTHER.user_id, ""),
+            ["Kai", "Kit"]
+        );
+        assert_eq!(headmate_choices(&data, OTHER, ME.user_id, ""), ["Ash"]);
+
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        assert_eq!(
+            headmate_choices(&data, ME, ME.user_id, ""),
+            ["primary", "Alex", "Ash"]
+        );
+        assert_eq!(headmate_choices(&data, OTHER, ME.user_id, ""), ["Ash"]);
+
+        set_visibility(&mut data, OTHER, &None, Visibility::HiddenFromOthers).unwrap();
+        assert!(headmate_choices(&data, ME, OTHER.user_id, "").is_empty());
+    }
+
+    #[test]
+    fn choices_rank_prefixes_before_substrings() {
+        let names = ["marlowe", "Alex", "Billie", "alexis", "Mal", "Lexi"];
+        assert_eq!(rank_choices(names, "LEX"), ["Lexi", "Alex", "alexis"]);
+        assert_eq!(rank_choices(names, " al "), ["Alex", "alexis", "Mal"]);
+        assert_eq!(rank_choices(names, "").len(), names.len());
+        assert!(rank_choices(names, "zed").is_empty());
+
+        let many: Vec<_> = (0..40).map(|i| format!("Headmate {i:02}")).collect();
+        let choices = rank_choices(many.iter().map(String::as_str), "head");
+        assert_eq!(choices.len(), MAX_CHOICES);
+        assert_eq!(choices[0], "Headmate 00");
+    }
+
+    #[test]
+    fn removing_the_default_headmate_clears_it() {
+        let mut data = GlobalData::default();
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        assert_eq!(
+            set_default_headmate(&mut data, OTHER, None),
+            Err(CommandError::NotRegistered)
+        );
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        remove_results(&mut data, ME, Some("Ash".into()), at(5)).unwrap();
+        assert_eq!(resolve_headmate(&data, ME, None), None);
+    }
+
+    #[tokio::test]
+    async fn show_result_reports_failed_fetches() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "gone".into(), at(2), None);
+        let api = FakeApi {
+            results: HashMap::from([("old".to_string(), vec![("Switch", 50)])]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let newest = ShowOptions::default();
+        assert_eq!(
+            show_result(&data, &api, &cache, ME, "me", &None, &newest)
+                .await
+                .unwrap(),
+            [ShownMessage::text(
+                "Could not get result for gone: not found".into()
+            )]
+        );
+
+        // Every result goes in one message, and every failure after it.
+        let all = ShowOptions {
+            all: true,
+            ..Default::default()
+        };
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &all)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].embeds.len(), 1);
+        assert_eq!(
+            messages[1].content,
+            "Could not get result for gone: not found"
+        );
+        // As text, they all fit in one.
+        let plain = ShowOptions {
+            all: true,
+            plain: true,
+            ..Default::default()
+        };
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &plain)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].content.contains("Switch"));
+        assert!(messages[0]
+            .content
+            .ends_with("\n\nCould not get result for gone: not found"));
+    }
+
+    #[tokio::test]
+    async fn list_uses_most_recent_results() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "new".into(), at(2), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("new".into(), "new".into()), 100),
+                (Matchup::new("new".into(), "theirs".into()), 64),
+            ]),
+            ..Default::default()
+        };
+        let pages = list(&data, &api, None).await.unwrap();
+        assert_eq!(
+            pages,
+            [concat!(
+                "Compatibility for: Me\n",
+                "- **Me**: 100%\n",
+                "- **Deleted User**: 64%\n",
+                "- **Deleted User** (Ash): Invalid Result\n",
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_skips_hidden_entries() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        set_visibility(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        set_visibility(&mut data, ME, &None, Visibility::HiddenFromOthers).unwrap();
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 64),
+            ]),
+            ..Default::default()
+        };
+        // Hidden users still see themselves.
+        let pages = list(&data, &api, None).await.unwrap();
+        assert_eq!(
+            pages,
+            [concat!(
+                "Compatibility for: Me\n",
+                "- **Me**: 100%\n",
+                "- **Deleted User**: 64%\n",
+            )]
+        );
+        assert_eq!(
+            set_visibility(&mut data, ME, &Some("Kit".into()), Visibility::Visible),
+            Err(CommandError::UnknownHeadmate(Some("Kit".into())))
+        );
+    }
+
+    #[tokio::test]
+    async fn top_archetype_uses_cached_results() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "gone".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            results: HashMap::from([
+                ("mine".to_string(), vec![("Rigger", 30), ("Switch", 90)]),
+                ("theirs".to_string(), vec![("Rigger", 80)]),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let pages = top_archetype(&data, &api, &cache, ME, "rigger", false, &names())
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            ["Top Rigger:\n1. **Deleted User**: 80%\n2. **Me**: 30%\n"]
+        );
+        assert!(cache.lock().await.get_result("theirs").is_some());
+        assert_eq!(
+            top_archetype(&data, &api, &cache, ME, "Woof", false, &names()).await,
+            Err(CommandError::UnknownArchetype("Woof".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn match_me_skips_own_entries() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Kit".into()),
+            "kit".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "kit".into()), 99),
+                (Matchup::new("mine".into(), "theirs".into()), 64),
+                (Matchup::new("mine".into(), "ash".into()), 64),
+            ]),
+            ..Default::default()
+        };
+        let best = match_me(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "**Me**",
+            &None,
+            &names(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            best,
+            "Best match for **Me**: **Deleted User** and **Deleted User** (Ash) at 64% \
+             (2 entries considered)"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_with_custom_weights() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "gone".into(),
+            at(1),
+            None,
+        );
+        let weights = set_weight(&mut data, ME, "switch", 0.0).unwrap();
+        assert_eq!(weights, BTreeMap::from([("Switch".to_string(), 0.0)]));
+        let api = FakeApi {
+            results: HashMap::from([
+                ("mine".to_string(), vec![("Rigger", 90), ("Switch", 0)]),
+                (
+                    "theirs".to_string(),
+                    vec![("Rope bunny", 70), ("Switch", 100)],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let pages = list_compatibility(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "Me",
+            &names(),
+            &ListOptions {
+                scoring: Scoring::Custom,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .pages;
+        assert_eq!(
+            pages,
+            [concat!(
+                "Compatibility for: Me (custom weighted scores, estimated locally)\n",
+                "- **Deleted User**: 80%\n",
+                "- **Me**: Invalid Result\n",
+                "- **Deleted User** (Ash): Invalid Result\n",
+            )]
+        );
+        assert!(set_weight(&mut data, ME, "Switch", 1.0).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_skips_ignored_users() {
+        let mut data = GlobalData::default();
+        let third = serenity::UserId::new(300);
+        assert!(set_ignored(&mut data, ME, third, true));
+        assert!(!set_ignored(&mut data, ME, third, true));
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            Invoker {
+                guild_id: GUILD,
+                user_id: third,
+            },
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 64),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            ["Compatibility for: Me\n- **Me**: 100%\n- **Deleted User**: 64%\n"]
+        );
+        assert!(set_ignored(&mut data, ME, third, false));
+        assert_eq!(list(&data, &api, None).await.unwrap()[0].lines().count(), 4);
+    }
+
+    #[test]
+    fn display_names_are_sanitized() {
+        assert_eq!(
+            sanitize_display_name("  **Sam**\n the  ~~great~~ "),
+            Ok(Some("Sam the great".into()))
+        );
+        assert_eq!(
+            sanitize_display_name("@everyone"),
+            Ok(Some("@\u{200B}everyone".into()))
+        );
+        assert_eq!(sanitize_display_name("   "), Ok(None));
+        assert_eq!(
+            sanitize_display_name("***"),
+            Err(CommandError::InvalidDisplayName)
+        );
+        assert_eq!(
+            sanitize_display_name(&"a".repeat(33)),
+            Err(CommandError::InvalidDisplayName)
+        );
+
+        let mut data = GlobalData::default();
+        set_display_name(&mut data, ME, "Sam").unwrap();
+        assert_eq!(display_name(&data, ME), Some("Sam"));
+        set_display_name(&mut data, ME, "").unwrap();
+        assert_eq!(display_name(&data, ME), None);
+    }
+
+    #[test]
+    fn timezone_defaults_to_utc() {
+        let mut data = GlobalData::default();
+        assert_eq!(timezone(&data, ME), Tz::UTC);
+        assert_eq!(
+            set_timezone(&mut data, ME, "europe/berlin"),
+            Ok(Tz::Europe__Berlin)
+        );
+        assert_eq!(timezone(&data, ME), Tz::Europe__Berlin);
+        assert_eq!(
+            set_timezone(&mut data, ME, "Mars/Olympus"),
+            Err(CommandError::UnknownTimezone("Mars/Olympus".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn compatibility_between_requires_consent() {
+        let mut data = GlobalData::default();
+        let third = Invoker {
+            guild_id: GUILD,
+            user_id: serenity::UserId::new(300),
+        };
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(&mut data, third, &None, "third".into(), at(1), None);
+        let api = FakeApi {
+            matches: HashMap::from([(Matchup::new("theirs".into(), "third".into()), 42)]),
+            ..Default::default()
+        };
+        async fn between(
+            data: &GlobalData,
+            api: &FakeApi,
+            b: serenity::UserId,
+            headmate: Option<&str>,
+        ) -> Result<String, CommandError> {
+            let targets = [(OTHER.user_id, None), (b, headmate.map(String::from))];
+            let cache = Mutex::new(Cache::new());
+            compatibility_between(data, api, &cache, ME, targets, &names()).await
+        }
+
+        assert_eq!(
+            between(&data, &api, third.user_id, None).await,
+            Err(CommandError::NoThirdPartyConsent(OTHER.user_id))
+        );
+        set_third_party(&mut data, OTHER, true);
+        assert_eq!(
+            between(&data, &api, third.user_id, None).await,
+            Err(CommandError::NoThirdPartyConsent(third.user_id))
+        );
+        set_third_party(&mut data, third, true);
+        assert_eq!(
+            between(&data, &api, third.user_id, None).await.unwrap(),
+            "Compatibility between **Deleted User** and **Deleted User**: 42%"
+        );
+        assert_eq!(
+            between(&data, &api, third.user_id, Some("Ash")).await,
+            Err(CommandError::TargetNotRegistered(
+                third.user_id,
+                Some("Ash".into())
+            ))
+        );
+        assert_eq!(
+            between(&data, &api, ME.user_id, None).await,
+            Err(CommandError::TargetNotRegistered(ME.user_id, None))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_without_headmates() {
+        let mut data = GlobalData::default();
+        add_result(
+            &mut data,
+            ME,
+            &Some("Kit".into()),
+            "kit".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([(Matchup::new("kit".into(), "theirs".into()), 64)]),
+            ..Default::default()
+        };
+        async fn list(
+            data: &GlobalData,
+            api: &FakeApi,
+            include_headmates: Option<bool>,
+        ) -> Vec<String> {
+            let options = ListOptions {
+                headmate: Some("Kit".into()),
+                include_headmates,
+                ..Default::default()
+            };
+            list_compatibility(
+                data,
+                api,
+                &Mutex::new(Cache::new()),
+                ME,
+                "Kit",
+                &names(),
+                &options,
+            )
+            .await
+            .unwrap()
+            .pages
+        }
+        assert_eq!(
+            list(&data, &api, Some(false)).await,
+            [concat!(
+                "Compatibility for: Kit\n",
+                "- **Deleted User**: 64%\n",
+                "_2 headmate entries were left out_\n",
+            )]
+        );
+        data.guild_mut(GUILD).config.include_headmates = Some(false);
+        assert_eq!(
+            list(&data, &api, None).await,
+            list(&data, &api, Some(false)).await
+        );
+        assert_eq!(list(&data, &api, Some(true)).await[0].lines().count(), 4);
+    }
+
+    #[test]
+    fn parses_manual_scores() {
+        assert_eq!(
+            parse_manual_scores("rigger: 95%\nSwitch:40 , brat tamer : 7 %;").unwrap(),
+            BTreeMap::from([
+                ("Brat tamer".to_string(), 7),
+                ("Rigger".to_string(), 95),
+                ("Switch".to_string(), 40),
+            ])
+        );
+        assert_eq!(
+            parse_manual_scores("Rigger: 95, Wizard: 10"),
+            Err(CommandError::UnknownArchetype("Wizard".into()))
+        );
+        for invalid in [
+            "Rigger 95",
+            "Rigger: 101",
+            "Rigger: lots",
+            "Rigger: 1, rigger: 2",
+        ] {
+            assert!(matches!(
+                parse_manual_scores(invalid),
+                Err(CommandError::InvalidManualScore(_))
+            ));
+        }
+        assert_eq!(
+            parse_manual_scores(" ,\n"),
+            Err(CommandError::NoManualScores)
+        );
+    }
+
+    #[tokio::test]
+    async fn manual_results_are_estimated_locally() {
+        let mut data = GlobalData::default();
+        let scores = BTreeMap::from([("Rigger".to_string(), 80), ("Switch".to_string(), 50)]);
+        assert!(add_manual_result(&mut data, ME, &None, scores, at(1), None));
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let api = FakeApi {
+            results: HashMap::from([(
+                "theirs".to_string(),
+                vec![("Rope bunny", 80), ("Switch", 50)],
+            )]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            [concat!(
+                "Compatibility for: Me\n",
+                "- **Me**: 100% (estimated)\n",
+                "- **Deleted User**: 100% (estimated)\n",
+            )]
+        );
+        let plain = ShowOptions {
+            plain: true,
+            ..Default::default()
+        };
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &plain)
+            .await
+            .unwrap();
+        assert!(messages[0]
+            .content
+            .starts_with("```==== me (2024-01-01) manual-1704067200 [manual] ===="));
+        assert!(messages[0].content.contains("Rigger"));
+    }
+
+    #[test]
+    fn manual_results_entered_together_keep_their_own_scores() {
+        let mut data = GlobalData::default();
+        for percent in [80, 60] {
+            let scores = BTreeMap::from([("Rigger".to_string(), percent)]);
+            add_manual_result(&mut data, ME, &None, scores, at(1), None);
+        }
+        let primary = find_headmate(&data, ME, &None).unwrap();
+        assert_eq!(primary.results.len(), 2);
+        let percents: Vec<_> = primary
+            .results
+            .values()
+            .map(|id| primary.manual[id]["Rigger"])
+            .collect();
+        assert_eq!(percents, [80, 60]);
+    }
+
+    #[tokio::test]
+    async fn stats_me_compares_oldest_and_newest() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "mid".into(), at(3), None);
+        add_result(&mut data, ME, &None, "new".into(), at(5), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(2),
+            None,
+        );
+        let api = FakeApi {
+            results: HashMap::from([
+                ("old".to_string(), vec![("Rigger", 20), ("Switch", 50)]),
+                ("new".to_string(), vec![("Rigger", 30), ("Switch", 10)]),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+
+        let stats = stats_me(&data, &api, &cache, ME, "Me", &None)
+            .await
+            .unwrap();
+        assert!(stats.contains("Results stored: 3\n"));
+        assert!(stats.contains("Average time between retakes: 2 days\n"));
+        assert!(stats.contains("Switch 50% → 10% (-40)"));
+
+        // A single result is summarized without comparing anything.
+        let stats = stats_me(&data, &api, &cache, ME, "Ash", &Some("Ash".into()))
+            .await
+            .unwrap();
+        assert!(stats.contains("Results stored: 1\nTested: 2024-01-02"));
+        assert_eq!(
+            stats_me(&data, &api, &cache, OTHER, "Them", &None).await,
+            Err(CommandError::NotRegistered)
+        );
+    }
+
+    #[tokio::test]
+    async fn result_history_compares_any_two_results() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "mid".into(), at(3), None);
+        add_result(&mut data, ME, &None, "new".into(), at(5), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(2),
+            None,
+        );
+        set_archetype_hidden(&mut data, ME, "Brat", true).unwrap();
+        let api = FakeApi {
+            results: HashMap::from([
+                (
+                    "old".to_string(),
+                    vec![("Rigger", 20), ("Switch", 50), ("Owner", 40), ("Brat", 1)],
+                ),
+                ("mid".to_string(), vec![("Rigger", 25), ("Switch", 50)]),
+                (
+                    "new".to_string(),
+                    vec![("Rigger", 30), ("Switch", 10), ("Owner", 40), ("Brat", 99)],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let history = |from, to| {
+            let (data, api, cache) = (&data, &api, &cache);
+            async move { result_history(data, api, cache, ME, "Me", &None, from, to).await }
+        };
+
+        let pages = history(None, None).await.unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].starts_with("Me from 2024-01-01 #"));
+        assert!(pages[0].ends_with(
+            ":\n- Switch: 50% → 10% (-40)\n- Rigger: 20% → 30% (+10)\n- Owner: 40% → 40% (+0)\n"
+        ));
+        // Picked in either order, the older one comes first.
+        let pages = history(Some("2024-01-03"), Some("2024-01-01"))
+            .await
+            .unwrap();
+        assert!(pages[0].contains("- Rigger: 20% → 25% (+5)\n- Switch: 50% → 50% (+0)\n"));
+        assert_eq!(
+            history(Some("2024-01-05"), None).await,
+            Err(CommandError::SameResult)
+        );
+
+        let single = result_history(
+            &data,
+            &api,
+            &cache,
+            ME,
+            "Ash",
+            &Some("Ash".into()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            single,
+            ["Ash only has one result so far. Take the test again to see how it changes"]
+        );
+    }
+
+    #[tokio::test]
+    async fn match_alerts_meet_thresholds_and_ignores() {
+        let third = Invoker {
+            guild_id: GUILD,
+            user_id: serenity::UserId::new(300),
+        };
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, third, &None, "third".into(), at(1), None);
+        set_match_alert(&mut data, OTHER, Some(80));
+        set_match_alert(&mut data, third, Some(95));
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "theirs".into()), 60),
+                (Matchup::new("mine".into(), "ash".into()), 85),
+                (Matchup::new("mine".into(), "third".into()), 90),
+            ]),
+            ..Default::default()
+        };
+        async fn alerts(data: &GlobalData, api: &FakeApi) -> Vec<MatchAlert> {
+            match_alerts(data, api, &Mutex::new(Cache::new()), ME, &None, "Me")
+                .await
+                .unwrap()
+        }
+        assert_eq!(
+            alerts(&data, &api).await,
+            [MatchAlert {
+                user_id: OTHER.user_id,
+                message: "Me just added a result that matches you (Ash) at 85%".into(),
+            }]
+        );
+
+        set_ignored(&mut data, OTHER, ME.user_id, true);
+        assert!(alerts(&data, &api).await.is_empty());
+        set_ignored(&mut data, OTHER, ME.user_id, false);
+        set_visibility(&mut data, ME, &None, Visibility::HiddenFromOthers).unwrap();
+        assert!(alerts(&data, &api).await.is_empty());
+    }
+
+    #[test]
+    fn rename_headmate_moves_or_merges() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &Some("Alxe".into()), "a".into(), at(1), None);
+        add_result(&mut data, ME, &Some("Alxe".into()), "b".into(), at(2), None);
+        set_default_headmate(&mut data, ME, Some("Alxe".into())).unwrap();
+        assert_eq!(rename_headmate(&mut data, ME, "Alxe", " Alex "), Ok(2));
+        let user = &data.guilds[&GUILD].users[&ME.user_id];
+        assert!(!user.headmates.contains_key("Alxe"));
+        assert_eq!(user.headmates["Alex"].results.len(), 2);
+        assert_eq!(user.default_headmate.as_deref(), Some("Alex"));
+
+        // The same result twice is only kept once, and a clashing timestamp is nudged.
+        add_result(&mut data, ME, &Some("Alx".into()), "b".into(), at(5), None);
+        add_result(&mut data, ME, &Some("Alx".into()), "c".into(), at(1), None);
+        assert_eq!(rename_headmate(&mut data, ME, "Alx", "Alex"), Ok(1));
+        let results = &data.guilds[&GUILD].users[&ME.user_id].headmates["Alex"].results;
+        assert_eq!(
+            results.values().collect::<Vec<_>>(),
+            ["a", "c", "b"].map(String::from).iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            rename_headmate(&mut data, ME, "Nobody", "Alex"),
+            Err(CommandError::UnknownHeadmate(Some("Nobody".into())))
+        );
+        assert_eq!(
+            rename_headmate(&mut data, ME, "Alex", "Primary"),
+            Err(CommandError::InvalidHeadmateName)
+        );
+        assert_eq!(
+            rename_headmate(&mut data, ME, "Nobody", "Nobody"),
+            Err(CommandError::UnknownHeadmate(Some("Nobody".into())))
+        );
+        assert_eq!(rename_headmate(&mut data, ME, "Alex", "Alex"), Ok(0));
+        assert_eq!(
+            rename_headmate(&mut data, OTHER, "Alex", "Ash"),
+            Err(CommandError::NotRegistered)
+        );
+        let elsewhere = Invoker {
+            guild_id: serenity::GuildId::new(2),
+            ..ME
+        };
+        assert_eq!(
+            rename_headmate(&mut data, elsewhere, "Alex", "Ash"),
+            Err(CommandError::NotRegistered)
+        );
+        assert!(data.guild(elsewhere.guild_id).is_none());
+    }
+
+    #[test]
+    fn promote_headmate_swaps_with_primary() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &Some("Ash".into()), "a".into(), at(1), None);
+        add_result(&mut data, ME, &Some("Ash".into()), "b".into(), at(2), None);
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        assert_eq!(promote_headmate(&mut data, ME, "Ash"), Ok((2, 0)));
+        let user = &data.guilds[&GUILD].users[&ME.user_id];
+        assert!(user.headmates.is_empty());
+        assert_eq!(user.primary.as_ref().unwrap().results.len(), 2);
+        assert_eq!(user.default_headmate, None);
+
+        add_result(&mut data, ME, &Some("Kit".into()), "c".into(), at(3), None);
+        assert_eq!(promote_headmate(&mut data, ME, "Kit"), Ok((1, 2)));
+        let user = &data.guilds[&GUILD].users[&ME.user_id];
+        assert_eq!(user.primary.as_ref().unwrap().results[&at(3)], "c");
+        assert_eq!(user.headmates["Kit"].results.len(), 2);
+        assert_eq!(
+            promote_headmate(&mut data, ME, "Ash"),
+            Err(CommandError::UnknownHeadmate(Some("Ash".into())))
+        );
+    }
+
+    #[test]
+    fn remove_result_keeps_the_rest_of_the_entry() {
+        let mut data = GlobalData::default();
+        let ash = Some("Ash".to_string());
+        add_result(&mut data, ME, &ash, "a".into(), at(1), None);
+        add_result(&mut data, ME, &ash, "b".into(), at(2), None);
+        set_result_visibility(
+            &mut data,
+            ME,
+            &ash,
+            "2024-01-02",
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        assert_eq!(
+            remove_result(&mut data, ME, &ash, "2024-01-02"),
+            Ok(("b".into(), false))
+        );
+        let entry = &data.guilds[&GUILD].users[&ME.user_id].headmates["Ash"];
+        assert_eq!(entry.results, BTreeMap::from([(at(1), "a".to_string())]));
+        assert!(entry.result_visibility.is_empty());
+
+        assert_eq!(
+            remove_result(&mut data, ME, &ash, "2024-01-02"),
+            Err(CommandError::UnknownResultDate("2024-01-02".into()))
+        );
+        assert_eq!(
+            remove_result(&mut data, ME, &ash, "2024-01-01"),
+            Ok(("a".into(), true))
+        );
+        assert!(data.guilds[&GUILD].users[&ME.user_id]
+            .headmates
+            .contains_key("Ash"));
+    }
+
+    #[test]
+    fn list_headmates_flags_empty_entries() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "a".into(), at(1), None);
+        add_result(&mut data, ME, &None, "b".into(), at(3), None);
+        add_result(&mut data, ME, &Some("Ash".into()), "c".into(), at(2), None);
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&ME.user_id)
+            .unwrap()
+            .headmate_mut(&Some("Bo".into()));
+        assert_eq!(
+            list_headmates(&data, ME, ME.user_id, "**Me**").unwrap(),
+            ["Entries for **Me**:\n\
+              - Primary: 2 results, newest from 2024-01-03 00:00 UTC\n\
+              - Ash: 1 result, newest from 2024-01-02 00:00 UTC\n\
+              - Bo: no results yet\n"]
+        );
+        assert_eq!(
+            list_headmates(&data, ME, OTHER.user_id, "**Sam**"),
+            Err(CommandError::TargetNotRegistered(OTHER.user_id, None))
+        );
+        assert_eq!(
+            list_headmates(&data, OTHER, OTHER.user_id, "**Sam**"),
+            Err(CommandError::NotRegistered)
+        );
+    }
+
+    #[tokio::test]
+    async fn leaderboard_ranks_pairs_of_different_members() {
+        let third = Invoker {
+            guild_id: GUILD,
+            user_id: serenity::UserId::new(300),
+        };
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(&mut data, third, &None, "third".into(), at(1), None);
+        let matchup = |a: &str, b: &str| Matchup::new(a.into(), b.into());
+        let api = FakeApi {
+            matches: HashMap::from([
+                (matchup("mine", "theirs"), 70),
+                (matchup("mine", "third"), 40),
+                (matchup("ash", "theirs"), 90),
+                (matchup("theirs", "third"), 55),
+                // Never asked for, since both are the same member's.
+                (matchup("mine", "ash"), 99),
+            ]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+        let names = BTreeMap::from([
+            (ME.user_id, "**Me**".to_string()),
+            (OTHER.user_id, "**Sam**".to_string()),
+            (third.user_id, "**Jo**".to_string()),
+        ]);
+        assert_eq!(
+            leaderboard(&data, &api, &cache, ME, 3, &names).await,
+            Ok(vec!["**Compatibility leaderboard**\n\
+                     1. **Me** (Ash) & **Sam**: 90%\n\
+                     2. **Me** & **Sam**: 70%\n\
+                     3. **Sam** & **Jo**: 55%\n\
+                     1 pair(s) could not be scored by bdsmtest.org\n"
+                .to_string()])
+        );
+
+        // Hiding takes effect right away, for the invoker too.
+        set_visibility(&mut data, ME, &None, Visibility::HiddenFromOthers).unwrap();
+        let pages = leaderboard(&data, &api, &cache, ME, 3, &names)
+            .await
+            .unwrap();
+        assert!(!pages[0].contains("**Me**"));
+        assert!(pages[0].contains("\n1. **Sam** & **Jo**: 55%\n"));
+        set_visibility(&mut data, ME, &None, Visibility::Visible).unwrap();
+
+        for user in 1000..1000 + MAX_LEADERBOARD_ENTRIES as u64 {
+            let who = Invoker {
+                guild_id: GUILD,
+                user_id: serenity::UserId::new(user),
+            };
+            add_result(&mut data, who, &None, format!("r{user}"), at(1), None);
+        }
+        assert_eq!(
+            leaderboard(&data, &api, &cache, ME, 3, &names).await,
+            Err(CommandError::TooManyForLeaderboard(
+                MAX_LEADERBOARD_ENTRIES + 4
+            ))
+        );
+    }
+
+    #[test]
+    fn whois_headmate_respects_privacy() {
+        let third = Invoker {
+            guild_id: GUILD,
+            user_id: serenity::UserId::new(300),
+        };
+        let mut data = GlobalData::default();
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "a".into(),
+            at(1),
+            None,
+        );
+        add_result(
+            &mut data,
+            third,
+            &Some("ash".into()),
+            "b".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, ME, &Some("Kit".into()), "c".into(), at(1), None);
+        let names = BTreeMap::from([
+            (OTHER.user_id, "**Sam**".to_string()),
+            (third.user_id, "**Jo**".to_string()),
+        ]);
+        assert_eq!(
+            whois_headmate(&data, ME, " ASH ", &names),
+            "Headmates called ASH:\n- **Sam** (Ash)\n- **Jo** (ash)"
+        );
+
+        set_visibility(
+            &mut data,
+            third,
+            &Some("ash".into()),
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        assert_eq!(
+            whois_headmate(&data, ME, "Ash", &names),
+            "Headmates called Ash:\n- **Sam** (Ash)"
+        );
+        assert!(whois_headmate(&data, third, "Ash", &names).contains("**Jo** (ash)"));
+        assert_eq!(
+            whois_headmate(&data, ME, "River", &names),
+            "Nobody in this server has a headmate called River"
+        );
+    }
+
+    #[test]
+    fn wiping_a_guild_keeps_what_others_store() {
+        let elsewhere = Invoker {
+            guild_id: serenity::GuildId::new(2),
+            user_id: ME.user_id,
+        };
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "shared".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "only".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, elsewhere, &None, "shared".into(), at(1), None);
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("shared".into(), "only".into()), 50);
+        cache.insert(Matchup::new("shared".into(), "shared".into()), 100);
+        assert_eq!(guild_summary(&data, ME), (2, 2));
+
+        assert!(wipe_guild(&mut data, &mut cache, ME).is_some());
+        assert_eq!(guild_summary(&data, ME), (0, 0));
+        assert_eq!(data.result_ids(), BTreeSet::from(["shared".to_string()]));
+        assert_eq!(
+            cache.get(&Matchup::new("shared".into(), "only".into())),
+            None
+        );
+        assert_eq!(
+            cache.get(&Matchup::new("shared".into(), "shared".into())),
+            Some(100)
+        );
+        assert!(wipe_guild(&mut data, &mut cache, ME).is_none());
+    }
+
+    #[test]
+    fn removing_users_counts_what_was_deleted() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(2),
+            None,
+        );
+        let mut cache = Cache::new();
+        cache.insert(Matchup::new("mine".into(), "theirs".into()), 50);
+        let gone = serenity::UserId::new(300);
+
+        assert_eq!(
+            remove_users(&mut data, &mut cache, ME, &[OTHER.user_id, gone]),
+            (1, 2)
+        );
+        assert_eq!(guild_summary(&data, ME), (1, 1));
+        assert_eq!(
+            cache.get(&Matchup::new("mine".into(), "theirs".into())),
+            None
+        );
+        assert_eq!(
+            remove_users(&mut data, &mut cache, ME, &[OTHER.user_id]),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn transfers_merge_into_the_new_account() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "a".into(),
+            at(1),
+            None,
+        );
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Kit".into()),
+            "k".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, ME, &None, "new".into(), at(2), None);
+        add_result(&mut data, ME, &Some("Ash".into()), "b".into(), at(2), None);
+        set_timezone(&mut data, ME, "Europe/Berlin").unwrap();
+
+        let expected = Transfer {
+            results: 3,
+            headmates: 2,
+            renamed: vec![("Ash".into(), "Ash (2)".into())],
+        };
+        assert_eq!(
+            plan_transfer(&data, ME, OTHER.user_id, ME.user_id),
+            Ok(expected)
+        );
+        assert!(data
+            .guild(GUILD)
+            .unwrap()
+            .users
+            .contains_key(&OTHER.user_id));
+        let transfer = transfer_user_data(&mut data, ME, OTHER.user_id, ME.user_id).unwrap();
+        assert_eq!(transfer.renamed.len(), 1);
+
+        let users = &data.guild(GUILD).unwrap().users;
+        assert!(!users.contains_key(&OTHER.user_id));
+        let me = &users[&ME.user_id];
+        assert_eq!(me.primary.as_ref().unwrap().results.len(), 2);
+        assert_eq!(
+            me.headmates.keys().collect::<Vec<_>>(),
+            ["Ash", "Ash (2)", "Kit"]
+        );
+        assert_eq!(me.timezone, Some(chrono_tz::Europe::Berlin));
+    }
+
+    #[test]
+    fn transfers_keep_what_the_new_account_has() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "both".into(), at(2), None);
+        set_timezone(&mut data, OTHER, "Europe/Berlin").unwrap();
+        add_result(&mut data, ME, &None, "new".into(), at(1), None);
+        add_result(&mut data, ME, &None, "both".into(), at(3), None);
+
+        let transfer = transfer_user_data(&mut data, ME, OTHER.user_id, ME.user_id).unwrap();
+        assert_eq!(transfer.results, 1);
+        let me = &data.guild(GUILD).unwrap().users[&ME.user_id];
+        let results = &me.primary.as_ref().unwrap().results;
+        assert_eq!(results.values().collect::<Vec<_>>(), ["new", "old", "both"]);
+        assert_eq!(results[&at(1)], "new");
+        assert_eq!(results[&(at(1) + chrono::Duration::seconds(1))], "old");
+        assert_eq!(me.timezone, Some(chrono_tz::Europe::Berlin));
+
+        // Settings are kept even when the new account has no results yet.
+        let mut data = GlobalData::default();
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        set_timezone(&mut data, OTHER, "Europe/Berlin").unwrap();
+        set_timezone(&mut data, ME, "Asia/Tokyo").unwrap();
+        let transfer = transfer_user_data(&mut data, ME, OTHER.user_id, ME.user_id).unwrap();
+        assert_eq!(transfer.results, 1);
+        let me = &data.guild(GUILD).unwrap().users[&ME.user_id];
+        assert_eq!(me.timezone, Some(chrono_tz::Asia::Tokyo));
+        assert_eq!(me.primary.as_ref().unwrap().results[&at(1)], "old");
+    }
+
+    #[test]
+    fn transfers_need_data_and_two_accounts() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        assert_eq!(
+            plan_transfer(&data, ME, ME.user_id, ME.user_id),
+            Err(CommandError::SameAccount)
+        );
+        assert_eq!(
+            plan_transfer(&data, ME, OTHER.user_id, ME.user_id),
+            Err(CommandError::TargetNotRegistered(OTHER.user_id, None))
+        );
+        transfer_user_data(&mut data, ME, ME.user_id, OTHER.user_id).unwrap();
+        let users = &data.guild(GUILD).unwrap().users;
+        assert_eq!(users.keys().collect::<Vec<_>>(), [&OTHER.user_id]);
+    }
+
+    #[test]
+    fn merging_guilds_combines_shared_members() {
+        let source = serenity::GuildId::new(2);
+        let in_source = |who: Invoker| Invoker {
+            guild_id: source,
+            ..who
+        };
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, in_source(ME), &None, "mine".into(), at(2), None);
+        add_result(&mut data, in_source(ME), &None, "clash".into(), at(1), None);
+        add_result(
+            &mut data,
+            in_source(ME),
+            &Some("Ash".into()),
+            "ash".into(),
+            at(3),
+            None,
+        );
+        add_result(
+            &mut data,
+            in_source(OTHER),
+            &None,
+            "theirs".into(),
+            at(1),
+            None,
+        );
+        data.pending_jobs.push(JobRecord {
+            id: 1,
+            guild_id: source,
+            user_id: OTHER.user_id,
+            channel_id: serenity::ChannelId::new(5),
+            queued_at: at(1),
+        });
+
+        let expected = GuildMerge {
+            moved: 1,
+            shared: vec![ME.user_id],
+            results: 3,
+            duplicates: 1,
+            nudged: 1,
+        };
+        assert_eq!(plan_guild_merge(&data, source, GUILD), Ok(expected));
+        assert!(data.guild(source).is_some());
+        merge_guilds(&mut data, source, GUILD).unwrap();
+
+        assert!(data.guild(source).is_none());
+        assert!(data.pending_jobs.is_empty());
+        let users = &data.guild(GUILD).unwrap().users;
+        let me = &users[&ME.user_id];
+        assert_eq!(
+            me.primary.as_ref().unwrap().results,
+            BTreeMap::from([
+                (at(1), "mine".to_string()),
+                (at(1) + chrono::Duration::seconds(1), "clash".to_string()),
+            ])
+        );
+        assert_eq!(me.headmates["Ash"].results.len(), 1);
+        assert!(users[&OTHER.user_id].has_results());
+    }
+
+    #[test]
+    fn merging_needs_two_known_guilds() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        let elsewhere = serenity::GuildId::new(2);
+        assert_eq!(
+            plan_guild_merge(&data, GUILD, GUILD),
+            Err(CommandError::SameGuild)
+        );
+        assert_eq!(
+            merge_guilds(&mut data, elsewhere, GUILD),
+            Err(CommandError::UnknownGuild(elsewhere))
+        );
+        merge_guilds(&mut data, GUILD, elsewhere).unwrap();
+        assert!(data.guild(elsewhere).unwrap().users[&ME.user_id].has_results());
+    }
+
+    #[tokio::test]
+    async fn list_filter_matches_member_and_headmate_names() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "ash".into()), 60),
+            ]),
+            ..Default::default()
+        };
+        async fn list(
+            data: &GlobalData,
+            api: &FakeApi,
+            filter: &str,
+        ) -> Result<Vec<String>, CommandError> {
+            let options = ListOptions {
+                filter: Some(filter.into()),
+                ..Default::default()
+            };
+            let cache = Mutex::new(Cache::new());
+            list_compatibility(data, api, &cache, ME, "Me", &names(), &options)
+                .await
+                .map(|l| l.pages)
+        }
+
+        let page = &list(&data, &api, " aSH ").await.unwrap()[0];
+        assert!(page.contains("- **Deleted User** (Ash): 60%\n"));
+        assert!(!page.contains("**Me**"));
+        assert!(!page.contains("- **Deleted User**: "));
+        let page = &list(&data, &api, "me").await.unwrap()[0];
+        assert!(page.contains("- **Me**: 100%\n"));
+        assert!(!page.contains("Ash"));
+        assert_eq!(
+            list(&data, &api, "xyz").await,
+            Err(CommandError::NoFilterMatches("xyz".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_filters_by_score_and_rank() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "ash".into()), 60),
+            ]),
+            ..Default::default()
+        };
+        let list = |options: ListOptions| {
+            let (data, api) = (&data, &api);
+            async move {
+                let cache = Mutex::new(Cache::new());
+                list_compatibility(data, api, &cache, ME, "Me", &names(), &options)
+                    .await
+                    .unwrap()
+                    .pages
+                    .concat()
+            }
+        };
+
+        // OTHER's own score can't be fetched, which is only listed without filters.
+        let all = list(ListOptions::default()).await;
+        assert!(all.contains("- **Deleted User**: "));
+        let page = list(ListOptions {
+            min_score: Some(70),
+            ..Default::default()
+        })
+        .await;
+        assert!(page.starts_with("Compatibility for: Me (matches ≥ 70%)\n"));
+        assert!(page.contains("- **Me**: 100%\n"));
+        assert!(!page.contains("Deleted User"));
+        let page = list(ListOptions {
+            top: Some(2),
+            show_errors: Some(true),
+            ..Default::default()
+        })
+        .await;
+        assert!(page.starts_with("Compatibility for: Me (top 2 matches)\n"));
+        assert!(page.contains("- **Deleted User** (Ash): 60%\n"));
+        assert!(page.contains("- **Deleted User**: "));
+
+        let invalid = ListOptions {
+            min_score: Some(101),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid.check_filters(),
+            Err(CommandError::InvalidMinScore(101))
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_only_what_the_list_shows() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(2), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(3),
+            None,
+        );
+        set_visibility(
+            &mut data,
+            OTHER,
+            &Some("Ash".into()),
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 70),
+            ]),
+            ..Default::default()
+        };
+        let listing = list_compatibility(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "Me",
+            &names(),
+            &ListOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            format_compat_csv(&listing.entries),
+            "member,headmate,score,result_date\n\
+             Me,,100,2024-01-01\n\
+             Deleted User,,70,2024-01-02\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_requirements_leave_out_entries() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "mine".into(), at(5), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(5), None);
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 70),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            set_list_requirements(&mut data, ME, None, Some("May 1st")),
+            Err(CommandError::InvalidCutoffDate("May 1st".into()))
+        );
+
+        set_list_requirements(&mut data, ME, Some(2), None).unwrap();
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            ["Compatibility for: Me\n- **Me**: 100%\n\
+              _1 entries were left out by this server's list requirements_\n"]
+        );
+
+        set_list_requirements(&mut data, ME, None, Some("2024-01-06")).unwrap();
+        assert_eq!(
+            list(&data, &api, None).await,
+            Err(CommandError::NotListable(Shortfall::NoResultSince(at(6))))
+        );
+    }
+
+    #[tokio::test]
+    async fn bots_are_left_out_of_lists() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&OTHER.user_id)
+            .unwrap()
+            .bot = true;
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "theirs".into()), 70),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            ["Compatibility for: Me\n- **Me**: 100%\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn hidden_results_are_skipped_for_others() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "old".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "revealing".into(), at(2), None);
+        assert_eq!(
+            set_result_visibility(
+                &mut data,
+                OTHER,
+                &None,
+                "2024-01-02",
+                Visibility::HiddenFromOthers
+            ),
+            Ok("revealing".to_string())
+        );
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("mine".into(), "mine".into()), 100),
+                (Matchup::new("mine".into(), "old".into()), 40),
+                (Matchup::new("mine".into(), "revealing".into()), 90),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            ["Compatibility for: Me\n- **Me**: 100%\n- **Deleted User**: 40%\n"]
+        );
+
+        set_result_visibility(
+            &mut data,
+            OTHER,
+            &None,
+            "2024-01-01",
+            Visibility::HiddenFromOthers,
+        )
+        .unwrap();
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            ["Compatibility for: Me\n- **Me**: 100%\n"]
+        );
+        let entry = data
+            .guild(GUILD)
+            .unwrap()
+            .entry(OTHER.user_id, &None)
+            .unwrap();
+        assert_eq!(entry.result_for(OTHER.user_id).unwrap(), "revealing");
+    }
+
+    #[test]
+    fn guest_results_expire() {
+        let mut data = GlobalData::default();
+        data.guild_mut(GUILD).config.guest_hours = Some(2);
+        let guest = Some("Guest".to_string());
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        make_temporary(&mut data, ME, &guest, "visit", at(1));
+        add_result(&mut data, ME, &guest, "visit".into(), at(1), None);
+        set_default_headmate(&mut data, ME, Some("Guest".into())).unwrap();
+        let expires = make_temporary(&mut data, OTHER, &None, "theirs", at(1));
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        assert_eq!(expires, at(1) + chrono::Duration::hours(2));
+
+        assert!(expire_guest_results(&mut data, at(1) + chrono::Duration::hours(1)).is_empty());
+        let changed = expire_guest_results(&mut data, expires);
+        assert_eq!(changed, BTreeSet::from([GUILD]));
+        let guild = data.guild(GUILD).unwrap();
+        assert!(!guild.users.contains_key(&OTHER.user_id));
+        let mine = &guild.users[&ME.user_id];
+        assert!(mine.headmates.is_empty());
+        assert_eq!(mine.default_headmate, None);
+        assert!(mine.primary.is_some());
+    }
+
+    #[test]
+    fn kept_guest_results_stay() {
+        let mut data = GlobalData::default();
+        let expires = make_temporary(&mut data, ME, &None, "mine", at(1));
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        assert_eq!(keep_results(&mut data, ME, &None), Ok(1));
+        assert_eq!(
+            keep_results(&mut data, ME, &None),
+            Err(CommandError::NoTemporaryResults)
+        );
+        assert!(expire_guest_results(&mut data, expires).is_empty());
+        assert_eq!(
+            data.guild(GUILD).unwrap().users[&ME.user_id]
+                .primary
+                .as_ref()
+                .unwrap()
+                .results
+                .len(),
+            1
+        );
+    }
+
+    /// Scores every match 50, slowly, recording how many were requested at once.
+    #[derive(Default)]
+    struct SlowApi {
+        in_flight: std::sync::atomic::AtomicUsize,
+        most: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BdsmApi for SlowApi {
+        async fn get_result(&self, _id: &str) -> Result<GetResultResult, anyhow::Error> {
+            anyhow::bail!("not found")
+        }
+
+        async fn get_match(&self, _request: &MatchRequest) -> Result<u32, anyhow::Error> {
+            use std::sync::atomic::Ordering;
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(50)
+        }
+    }
+
+    #[tokio::test]
+    async fn scores_are_fetched_a_few_at_a_time() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        for user in 1..=20 {
+            let them = Invoker {
+                guild_id: GUILD,
+                user_id: serenity::UserId::new(1000 + user),
+            };
+            add_result(&mut data, them, &None, format!("r{user}"), at(1), None);
+        }
+        let api = SlowApi::default();
+        let cache = Mutex::new(Cache::new());
+        let options = ListOptions::default();
+        let pages = list_compatibility(&data, &api, &cache, ME, "Me", &names(), &options)
+            .await
+            .unwrap()
+            .pages;
+        assert_eq!(pages.concat().matches(": 50%").count(), 21);
+        assert_eq!(
+            api.most.load(std::sync::atomic::Ordering::SeqCst),
+            CONCURRENT_SCORES
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_scores_are_looked_up_up_to_a_limit() {
+        let missing: Vec<_> = (0..MAX_GRAPH_LOOKUPS + 10)
+            .map(|i| Matchup::new("mine".into(), format!("r{i}")))
+            .collect();
+        let api = SlowApi::default();
+        let cache = Mutex::new(Cache::new());
+        let (looked_up, failure) =
+            look_up_missing(&api, &cache, missing.clone(), &CancelToken::default()).await;
+        assert_eq!(looked_up, MAX_GRAPH_LOOKUPS);
+        assert!(failure.is_none());
+        assert_eq!(
+            api.most.load(std::sync::atomic::Ordering::SeqCst),
+            CONCURRENT_SCORES
+        );
+        assert_eq!(cache.lock().await.get(&missing[0]), Some(50));
+        assert_eq!(cache.lock().await.get(&missing[MAX_GRAPH_LOOKUPS]), None);
+
+        // bdsmtest.org not answering stops the rest.
+        let cancel = CancelToken::default();
+        let (looked_up, failure) = look_up_missing(
+            &FakeApi::default(),
+            &cache,
+            missing[MAX_GRAPH_LOOKUPS..].to_vec(),
+            &cancel,
+        )
+        .await;
+        assert_eq!(looked_up, 0);
+        assert!(failure.is_some());
+        assert!(cancel.is_cancelled());
+    }
+
+    /// Cancels `cancel` as soon as the first match is requested.
+    struct CancellingApi {
+        inner: FakeApi,
+        cancel: CancelToken,
+    }
+
+    #[async_trait]
+    impl BdsmApi for CancellingApi {
+        async fn get_result(&self, id: &str) -> Result<GetResultResult, anyhow::Error> {
+            self.inner.get_result(id).await
+        }
+
+        async fn get_match(&self, request: &MatchRequest) -> Result<u32, anyhow::Error> {
+            self.cancel.cancel();
+            self.inner.get_match(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_scan_lists_what_it_has() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let third = Invoker {
+            guild_id: GUILD,
+            user_id: serenity::UserId::new(300),
+        };
+        add_result(&mut data, third, &None, "third".into(), at(1), None);
+        let options = ListOptions::default();
+        let api = CancellingApi {
+            inner: FakeApi {
+                matches: HashMap::from([
+                    (Matchup::new("mine".into(), "mine".into()), 100),
+                    (Matchup::new("mine".into(), "theirs".into()), 70),
+                ]),
+                ..Default::default()
+            },
+            cancel: options.cancel.clone(),
+        };
+        let cache = Mutex::new(Cache::new());
+
+        let listing = list_compatibility(&data, &api, &cache, ME, "Me", &names(), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            listing.pages,
+            ["Compatibility for: Me (PARTIAL, cancelled with 2 entries left)\n- **Me**: 100%\n"]
+        );
+        // An export covers the same entries.
+        assert_eq!(
+            format_compat_csv(&listing.entries),
+            "member,headmate,score,result_date\nMe,,100,2024-01-01\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn hidden_archetypes_only_affect_display() {
+        let mut data = GlobalData::default();
+        let scores = BTreeMap::from([("Rigger".to_string(), 90), ("Voyeur".to_string(), 40)]);
+        add_manual_result(&mut data, ME, &None, scores, at(1), None);
+        let scores = BTreeMap::from([("Rigger".to_string(), 70), ("Voyeur".to_string(), 60)]);
+        add_manual_result(&mut data, OTHER, &None, scores, at(1), None);
+        let api = FakeApi::default();
+        let cache = Mutex::new(Cache::new());
+        let unhidden = list(&data, &api, None).await.unwrap();
+        assert_eq!(
+            set_archetype_hidden(&mut data, ME, "woof", true),
+            Err(CommandError::UnknownArchetype("woof".into()))
+        );
+        assert_eq!(
+            set_archetype_hidden(&mut data, ME, "voyeur", true),
+            Ok(("Voyeur", true))
+        );
+        assert_eq!(
+            set_archetype_hidden(&mut data, ME, "Voyeur", true),
+            Ok(("Voyeur", false))
+        );
+
+        let shown = ShowOptions {
+            plain: true,
+            ..Default::default()
+        };
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &shown)
+            .await
+            .unwrap();
+        let shown_text = &messages[0].content;
+        assert!(shown_text.contains("Rigger") && !shown_text.contains("Voyeur"));
+        let all = ShowOptions {
+            show_all: true,
+            plain: true,
+            ..Default::default()
+        };
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &all)
+            .await
+            .unwrap();
+        assert!(messages[0].content.contains("Voyeur"));
+
+        let stats = server_stats(&data, &cache, ME, false).await.unwrap();
+        assert!(stats.contains("Rigger") && !stats.contains("Voyeur"));
+        let stats = server_stats(&data, &cache, ME, true).await.unwrap();
+        assert!(stats.contains("Voyeur"));
+
+        assert_eq!(
+            top_archetype(&data, &api, &cache, ME, "Voyeur", false, &names()).await,
+            Err(CommandError::HiddenArchetype("Voyeur".into()))
+        );
+        let pages = top_archetype(&data, &api, &cache, ME, "Voyeur", true, &names())
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            ["Top Voyeur:\n1. **Deleted User**: 60%\n2. **Me**: 40%\n"]
+        );
+        // Matches are scored from the full results.
+        assert_eq!(list(&data, &api, None).await.unwrap(), unhidden);
+
+        assert_eq!(
+            set_archetype_hidden(&mut data, ME, "Voyeur", false),
+            Ok(("Voyeur", true))
+        );
+        let stats = server_stats(&data, &cache, ME, false).await.unwrap();
+        assert!(stats.contains("Voyeur"));
+    }
+
+    #[tokio::test]
+    async fn aliases_only_rename_displayed_archetypes() {
+        let mut data = GlobalData::default();
+        let scores = BTreeMap::from([("Rigger".to_string(), 90), ("Rope bunny".to_string(), 20)]);
+        add_manual_result(&mut data, ME, &None, scores, at(1), None);
+        let scores = BTreeMap::from([("Rigger".to_string(), 10), ("Rope bunny".to_string(), 95)]);
+        add_manual_result(&mut data, OTHER, &None, scores, at(1), None);
+        let api = FakeApi::default();
+        let cache = Mutex::new(Cache::new());
+        let unaliased = list(&data, &api, None).await.unwrap();
+
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "woof", Some("Woof")),
+            Err(CommandError::UnknownArchetype("woof".into()))
+        );
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "Rigger", Some("**")),
+            Err(CommandError::InvalidArchetypeAlias)
+        );
+        assert_eq!(
+            set_archetype_alias(
+                &mut data,
+                ME,
+                "Rigger",
+                Some(&"a".repeat(MAX_ALIAS_LEN + 1))
+            ),
+            Err(CommandError::InvalidArchetypeAlias)
+        );
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "Rigger", Some("switch")),
+            Err(CommandError::AliasTaken("Switch".into()))
+        );
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "rope bunny", Some(" Knot  *enjoyer* ")),
+            Ok(("Rope bunny", None))
+        );
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "Rigger", Some("knot enjoyer")),
+            Err(CommandError::AliasTaken("Rope bunny".into()))
+        );
+
+        // Saved under the canonical name and read back as it was.
+        let json = serde_json::to_string(&data).unwrap();
+        assert!(json.contains(r#""archetype_aliases":{"Rope bunny":"Knot enjoyer"}"#));
+        let mut data: GlobalData = serde_json::from_str(&json).unwrap();
+        let messages = show_result(&data, &api, &cache, ME, "me", &None, &Default::default())
+            .await
+            .unwrap();
+        let embed = serde_json::to_string(&messages[0].embeds).unwrap();
+        assert!(embed.contains("Knot enjoyer") && !embed.contains("Rope bunny"));
+        let stats = server_stats(&data, &cache, ME, false).await.unwrap();
+        assert!(stats.contains("Knot enjoyer") && !stats.contains("Rope bunny"));
+        let pages = top_archetype(&data, &api, &cache, ME, "rope bunny", false, &names())
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            ["Top Knot enjoyer:\n1. **Deleted User**: 95%\n2. **Me**: 20%\n"]
+        );
+        assert_eq!(list(&data, &api, None).await.unwrap(), unaliased);
+
+        assert_eq!(
+            set_archetype_alias(&mut data, ME, "Rope bunny", None),
+            Ok(("Rope bunny", Some("Knot enjoyer".into())))
+        );
+        let stats = server_stats(&data, &cache, ME, false).await.unwrap();
+        assert!(stats.contains("Rope bunny"));
+    }
+
+    #[test]
+    fn coverage_skips_members_who_removed_their_data() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        remove_results(&mut data, OTHER, None, at(2)).unwrap();
+        let members = [ME.user_id, OTHER.user_id, serenity::UserId::new(300)];
+        assert_eq!(
+            coverage(&data, ME, members),
+            Err(CommandError::CoverageDisabled)
+        );
+        data.guild_mut(GUILD).config.allow_coverage = true;
+        assert_eq!(
+            coverage(&data, ME, members),
+            Ok(Coverage {
+                registered: 1,
+                opted_out: 1,
+                unregistered: vec![serenity::UserId::new(300)],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_checks_every_result_up_to_the_limit() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "gone".into(), at(2), None);
+        let scores = BTreeMap::from([("Switch".to_string(), 50)]);
+        add_manual_result(&mut data, ME, &Some("Ash".into()), scores, at(3), None);
+        for day in 1..=VERIFY_LIMIT as u32 {
+            add_result(
+                &mut data,
+                ME,
+                &Some("Kit".into()),
+                format!("kit{day}"),
+                at(day),
+                None,
+            );
+        }
+        let api = FakeApi {
+            results: HashMap::from([("old".to_string(), vec![("Switch", 50)])]),
+            ..Default::default()
+        };
+        let cache = Mutex::new(Cache::new());
+
+        let (pages, verified) = verify_results(&data, &api, &cache, ME).await.unwrap();
+        let report = pages.concat();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines[..3],
+            ["**Primary**", "- old: OK, taken 2024-05-01", lines[2]]
+        );
+        assert!(lines[2].starts_with("- gone: bdsmtest.org could not be reached"));
+        assert_eq!(
+            lines[4],
+            "- manual-1704240000: entered by hand, nothing to check"
+        );
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("- kit{VERIFY_LIMIT}: not checked, run the command again later")
+        );
+        assert!(cache.lock().await.get_result("old").is_some());
+
+        // What was found is stored, like the background checks store it.
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&ME.user_id)
+            .unwrap()
+            .primary
+            .as_mut()
+            .unwrap()
+            .not_found
+            .insert("old".into(), 2);
+        assert!(record_verified(
+            &mut data,
+            ME,
+            &verified,
+            &*cache.lock().await
+        ));
+        let primary = data.guild(GUILD).unwrap().users[&ME.user_id]
+            .primary
+            .clone()
+            .unwrap();
+        assert!(primary.not_found.is_empty());
+        assert_eq!(primary.fetched["old"].date, "2024-05-01");
+        assert!(!record_verified(
+            &mut data,
+            ME,
+            &verified,
+            &*cache.lock().await
+        ));
+    }
+
+    #[tokio::test]
+    async fn unresolvable_results_are_flagged() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "gone".into(), at(1), None);
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&OTHER.user_id)
+            .unwrap()
+            .primary
+            .as_mut()
+            .unwrap()
+            .not_found
+            .insert("gone".into(), crate::data::UNRESOLVABLE_AFTER);
+        let api = FakeApi {
+            matches: HashMap::from([(Matchup::new("mine".into(), "mine".into()), 100)]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            list(&data, &api, None).await.unwrap(),
+            [concat!(
+                "Compatibility for: Me\n",
+                "- **Me**: 100%\n",
+                "- **Deleted User**: Invalid Result\n",
+                "_**Deleted User**'s stored result no longer resolves_\n",
+            )]
+        );
+        let messages = show_result(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            OTHER,
+            "them",
+            &None,
+            &ShowOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(messages[0]
+            .content
+            .starts_with("Result gone no longer resolves on bdsmtest.org"));
+    }
+
+    #[test]
+    fn resolves_result_dates() {
+        let first: DateTime<Utc> = "2024-05-01T03:30:00Z".parse().unwrap();
+        let second: DateTime<Utc> = "2024-05-01T22:00:00Z".parse().unwrap();
+        let third: DateTime<Utc> = "2024-05-03T12:00:00Z".parse().unwrap();
+        let results = BTreeMap::from([
+            (first, "abc123".to_string()),
+            (second, "def456".to_string()),
+            (third, "ghi789".to_string()),
+        ]);
+        let resolve = |input: &str| resolve_result_date(&results, Tz::UTC, input);
+
+        assert_eq!(resolve(&first.to_rfc3339()), Ok(first));
+        assert_eq!(resolve("2024-05-01 22:00"), Ok(second));
+        assert_eq!(resolve(" 2024-05-03 "), Ok(third));
+        assert_eq!(resolve(&result_tag("abc123")), Ok(first));
+        let labels = result_labels(&results, Tz::UTC);
+        for (at, label) in &labels {
+            assert_eq!(resolve(label), Ok(*at));
+        }
+        assert_eq!(
+            resolve("2024-05-01"),
+            Err(CommandError::AmbiguousResultDate("2024-05-01".into()))
+        );
+        assert_eq!(
+            resolve("2024-06-01"),
+            Err(CommandError::UnknownResultDate("2024-06-01".into()))
+        );
+    }
+
+    #[test]
+    fn result_date_choices_follow_the_headmate() {
+        let mut data = GlobalData::default();
+        assert!(result_date_choices(&data, ME, None, "").is_empty());
+
+        let late: DateTime<Utc> = "2024-05-01T23:30:00.250Z".parse().unwrap();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, ME, &None, "late".into(), late, None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(2),
+            None,
+        );
+        set_timezone(&mut data, ME, "Europe/Berlin").unwrap();
+
+        let choices = result_date_choices(&data, ME, None, "");
+        assert_eq!(
+            choices,
+            [
+                (
+                    format!("2024-05-02 {}", result_tag("late")),
+                    late.to_rfc3339()
+                ),
+                (
+                    format!("2024-01-01 {}", result_tag("mine")),
+                    at(1).to_rfc3339()
+                ),
+            ]
+        );
+        let results = &data.guild(GUILD).unwrap().users[&ME.user_id]
+            .primary
+            .as_ref()
+            .unwrap()
+            .results;
+        for (_, value) in &choices {
+            let at = resolve_result_date(results, Tz::UTC, value).unwrap();
+            assert!(results.contains_key(&at));
+        }
+        assert_eq!(result_date_choices(&data, ME, None, "05-02").len(), 1);
+
+        let ash = result_date_choices(&data, ME, Some("Ash".into()), "");
+        assert_eq!(
+            ash,
+            [(
+                format!("2024-01-02 {}", result_tag("ash")),
+                at(2).to_rfc3339()
+            )]
+        );
+        set_default_headmate(&mut data, ME, Some("Ash".into())).unwrap();
+        assert_eq!(result_date_choices(&data, ME, None, ""), ash);
+        assert_eq!(
+            result_date_choices(&data, ME, Some("primary".into()), "").len(),
+            2
+        );
+
+        assert!(result_date_choices(&data, ME, Some("Nobody".into()), "").is_empty());
+        assert!(result_date_choices(&data, OTHER, None, "").is_empty());
+    }
+
+    #[tokio::test]
+    async fn similar_on_compares_one_archetype() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(
+            &mut data,
+            ME,
+            &Some("Ash".into()),
+            "ash".into(),
+            at(1),
+            None,
+        );
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        add_result(
+            &mut data,
+            OTHER,
+            &Some("Kit".into()),
+            "gone".into(),
+            at(1),
+            None,
+        );
+        let api = FakeApi {
+            results: HashMap::from([
+                ("mine".to_string(), vec![("Sadist", 82)]),
+                ("ash".to_string(), vec![("Sadist", 10)]),
+                ("theirs".to_string(), vec![("Sadist", 90)]),
+            ]),
+            ..Default::default()
+        };
+        let pages = similar_on(
+            &data,
+            &api,
+            &Mutex::new(Cache::new()),
+            ME,
+            "sadist",
+            &None,
+            &names(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            pages,
+            [concat!(
+                "Closest to your Sadist score (82%):\n",
+                "1. **Deleted User**: 90% (8 apart)\n",
+                "2. **Me** (Ash): 10% (72 apart)\n",
+                "_1 entries were skipped because their results could not be fetched_\n",
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn compat_explain_needs_consent() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "mine".into(), at(1), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let api = FakeApi {
+            results: HashMap::from([
+                ("mine".to_string(), vec![("Dominant", 90)]),
+                ("theirs".to_string(), vec![("Submissive", 80)]),
+            ]),
+            matches: HashMap::from([(Matchup::new("mine".into(), "theirs".into()), 87)]),
+        };
+        async fn explain(data: &GlobalData, api: &FakeApi) -> Result<String, CommandError> {
+            let cache = Mutex::new(Cache::new());
+            let target = (OTHER.user_id, None);
+            compat_explain(data, api, &cache, ME, &None, target, &names()).await
+        }
+
+        assert_eq!(
+            explain(&data, &api).await,
+            Err(CommandError::NoExplainConsent(OTHER.user_id))
+        );
+        set_allow_explain(&mut data, OTHER, true);
+        assert_eq!(
+            explain(&data, &api).await.unwrap(),
+            "Compatibility between **Me** and **Deleted User**: 87%\n\
+             **What works**\n\
+             - Dominant 90% with Submissive 80%\n\
+             **Where you differ**\n\
+             Nothing stands out\n"
+        );
+        data.guild_mut(GUILD)
+            .users
+            .get_mut(&OTHER.user_id)
+            .unwrap()
+            .visibility = Visibility::HiddenFromOthers;
+        assert_eq!(
+            explain(&data, &api).await,
+            Err(CommandError::NoExplainConsent(OTHER.user_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_averages_recent_results() {
+        let mut data = GlobalData::default();
+        for (day, id) in [(1, "oldest"), (2, "old"), (3, "new"), (4, "newest")] {
+            add_result(&mut data, ME, &None, id.into(), at(day), None);
+        }
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("newest".into(), "theirs".into()), 90),
+                (Matchup::new("new".into(), "theirs".into()), 81),
+                (Matchup::new("old".into(), "theirs".into()), 70),
+                (Matchup::new("oldest".into(), "theirs".into()), 10),
+            ]),
+            ..Default::default()
+        };
+        for (use_average, expected) in [
+            (
+                Some(2),
+                "Compatibility for: Me (averaged over 2 results)\n- **Deleted User**: 86%\n",
+            ),
+            (
+                Some(10),
+                "Compatibility for: Me (averaged over 3 results)\n- **Deleted User**: 80%\n",
+            ),
+            (None, "Compatibility for: Me\n- **Deleted User**: 90%\n"),
+        ] {
+            let options = ListOptions {
+                use_average,
+                ..Default::default()
+            };
+            let mut pages = list_compatibility(
+                &data,
+                &api,
+                &Mutex::new(Cache::new()),
+                ME,
+                "Me",
+                &names(),
+                &options,
+            )
+            .await
+            .unwrap()
+            .pages;
+            // Comparing against our own entry has no registered match.
+            assert_eq!(pages.len(), 1);
+            let page = pages.remove(0);
+            let lines: Vec<_> = page.lines().filter(|l| !l.contains("**Me**")).collect();
+            assert_eq!(lines.join("\n") + "\n", expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn list_with_an_older_result() {
+        let mut data = GlobalData::default();
+        add_result(&mut data, ME, &None, "old".into(), at(1), None);
+        add_result(&mut data, ME, &None, "new".into(), at(2), None);
+        add_result(&mut data, OTHER, &None, "theirs".into(), at(1), None);
+        let api = FakeApi {
+            matches: HashMap::from([
+                (Matchup::new("old".into(), "theirs".into()), 40),
+                (Matchup::new("new".into(), "theirs".into()), 90),
+            ]),
+            ..Default::default()
+        };
+        async fn list(
+            data: &GlobalData,
+            api: &FakeApi,
+            date: &str,
+        ) -> Result<Vec<String>, CommandError> {
+            let options = ListOptions {
+                my_result_date: Some(date.into()),
+                ..Default::default()
+            };
+            let cache = Mutex::new(Cache::new());
+            list_compatibility(data, api, &cache, ME, "Me", &names(), &options)
+                .await
+                .map(|l| l.pages)
+        }
+
+        let page = &list(&data, &api, "2024-01-01").await.unwrap()[0];
+        assert!(page.starts_with(&format!(
+            "Compatibility for: Me (using your result from 2024-01-01 {})\n",
+            result_tag("old")
+        )));
+        assert!(page.contains("- **Deleted User**: 40%\n"));
+        let page = &list(&data, &api, "2024-01-02").await.unwrap()[0];
+        assert!(page.starts_with("Compatibility for: Me\n"));
+        assert!(page.contains("- **Deleted User**: 90%\n"));
+        assert_eq!(
+            list(&data, &api, "2023-12-31").await,
+            Err(CommandError::UnknownResultDate("2023-12-31".into()))
+        );
+    }
+}
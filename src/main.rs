@@ -1,572 +1,92 @@
 #![deny(unused)]
 
-use std::{
-    collections::{BTreeMap, HashMap},
-    path::Path,
-};
+use std::sync::{atomic::AtomicUsize, Arc};
 
-use anyhow::Context as _;
-use chrono::{DateTime, Utc};
+use clap::Parser as _;
 use poise::serenity_prelude as serenity;
-use serde::{Deserialize, Serialize};
 use tokio::sync::{Mutex, RwLock};
-use tracing::{info, instrument};
 use tracing_subscriber::{layer::SubscriberExt as _, Layer as _, Registry};
 
-const RESULT_URL: &str = "https://bdsmtest.org/ajax/getresult";
-const MATCH_URL: &str = "https://bdsmtest.org/ajax/match";
-const REGISTRY: &str = "registry.json";
-
-#[derive(Debug, Deserialize)]
-struct MatchResult {
-    score: u32,
-    #[allow(unused)]
-    partner: String,
-}
-
-#[derive(Debug, Deserialize)]
-#[allow(unused)]
-struct GetResultScore {
-    id: u32,
-    name: String,
-    pairdesc: String,
-    description: String,
-    score: u32,
-}
-
-#[derive(Debug, Deserialize)]
-#[allow(unused)]
-struct GetResultResult {
-    langfile: String,
-    date: String,
-    version: u32,
-    gender: String,
-    auth: bool,
-    scores: Vec<GetResultScore>,
-}
-
-#[derive(Clone, Debug, Serialize)]
-struct MatchRequest {
-    #[serde(rename = "rauth[rid]")]
-    person: String,
-    partner: String,
-}
-
-#[derive(Clone, Debug, Serialize)]
-struct GetResultRequest {
-    #[serde(rename = "rauth[rid]")]
-    person: String,
-    #[serde(rename = "uauth[uid]")]
-    uid: &'static str,
-    #[serde(rename = "uauth[salt]")]
-    salt: &'static str,
-    #[serde(rename = "uauth[authsig]")]
-    authsig: &'static str,
-}
-
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
-struct HeadmateData {
-    results: BTreeMap<DateTime<Utc>, String>,
-}
-
-impl HeadmateData {
-    fn migrate(&mut self) {}
-}
-
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
-struct UserData {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    primary: Option<HeadmateData>,
-    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
-    headmates: BTreeMap<String, HeadmateData>,
-}
-
-impl UserData {
-    fn migrate(&mut self) {
-        self.primary.iter_mut().for_each(HeadmateData::migrate);
-        self.headmates.values_mut().for_each(HeadmateData::migrate)
-    }
-
-    pub fn headmate(&self, name: &Option<String>) -> Option<&HeadmateData> {
-        match name {
-            Some(name) => self.headmates.get(name),
-            None => self.primary.as_ref(),
-        }
-    }
-
-    pub fn headmate_mut(&mut self, name: &Option<String>) -> &mut HeadmateData {
-        match name {
-            Some(name) => self.headmates.entry(name.clone()).or_default(),
-            None => self.primary.get_or_insert_with(HeadmateData::default),
-        }
-    }
-}
-
-#[derive(Default, Debug, Serialize, Deserialize)]
-struct GuildData {
-    users: BTreeMap<serenity::UserId, UserData>,
-}
-
-impl GuildData {
-    fn migrate(&mut self) {
-        self.users.values_mut().for_each(UserData::migrate)
-    }
-}
-
-#[derive(Default, Debug, Serialize, Deserialize)]
-struct GlobalData {
-    guilds: BTreeMap<serenity::GuildId, GuildData>,
-}
-
-impl GlobalData {
-    fn migrate(&mut self) {
-        self.guilds.values_mut().for_each(GuildData::migrate);
-    }
-
-    pub fn guild(&self, id: serenity::GuildId) -> Option<&GuildData> {
-        self.guilds.get(&id)
-    }
-
-    pub fn guild_mut(&mut self, guild_id: serenity::GuildId) -> &mut GuildData {
-        self.guilds.entry(guild_id).or_default()
-    }
-}
-
-fn persist_folder<P: AsRef<Path>, P2: AsRef<Path>>(
-    folder: P,
-    filename: P2,
-    keep: usize,
-) -> std::io::Result<()> {
-    let folder = folder.as_ref();
-    std::fs::create_dir_all(folder)?;
-    if !Path::is_file(REGISTRY.as_ref()) {
-        return Ok(());
-    }
-    std::fs::copy(REGISTRY, folder.join(filename))?;
-    let mut existing: Vec<_> = std::fs::read_dir(folder)?.collect::<Result<_, _>>()?;
-    existing.sort_by_key(|f| f.path());
-
-    let count = existing.len();
-    if count > keep {
-        for file in existing.into_iter().take(count - keep) {
-            std::fs::remove_file(file.path())?;
-        }
-    }
-
-    Ok(())
-}
-
-fn persist(data: &GlobalData) -> Result<(), anyhow::Error> {
-    let now = Utc::now();
-    persist_folder(
-        "bku/history",
-        format!("registry-{}.json", now.timestamp()),
-        20,
-    )?;
-
-    let mut output = std::fs::File::create(REGISTRY).context("while opening data file")?;
-    serde_json::to_writer_pretty(&mut output, data).context("while formatting json")?;
-
-    persist_folder(
-        "bku/hourly",
-        format!("registry-{}.json", now.timestamp() / 60 / 60),
-        24,
-    )?;
-    persist_folder(
-        "bku/daily",
-        format!("registry-{}.json", now.timestamp() / 60 / 60 / 24),
-        30,
-    )?;
-    persist_folder(
-        "bku/monthly",
-        format!("registry-{}.json", now.timestamp() / 60 / 60 / 24 / 28),
-        usize::MAX,
-    )?;
-
-    Ok(())
-}
-
-async fn get_result<S: Into<String>>(user: S) -> Result<GetResultResult, anyhow::Error> {
-    let client = reqwest::Client::new();
-    let req = GetResultRequest {
-        person: user.into(),
-        uid: "0",
-        salt: "",
-        authsig: "814a69afc15258000678f00526b0c107ac271b5ea997beb4f7c1e81c861c972b",
-    };
-
-    Ok(client
-        .post(RESULT_URL)
-        .form(&req)
-        .send()
-        .await?
-        .json()
-        .await?)
-}
-
-async fn get_match(cache: &mut Cache, request: MatchRequest) -> Result<u32, anyhow::Error> {
-    let cache_key = Matchup::from(request.clone());
-    if let Some(score) = cache.0.get(&cache_key) {
-        Ok(*score)
-    } else {
-        let client = reqwest::Client::new();
-
-        let score = client
-            .post(MATCH_URL)
-            .form(&request)
-            .send()
-            .await?
-            .json::<MatchResult>()
-            .await?
-            .score;
-        cache.0.insert(cache_key, score);
-        Ok(score)
-    }
-}
-
-#[derive(Clone, Eq, Hash, PartialEq)]
-struct Matchup(String, String);
-
-impl Matchup {
-    fn new(a: String, b: String) -> Matchup {
-        if a < b {
-            Matchup(a, b)
-        } else {
-            Matchup(b, a)
-        }
-    }
-}
-impl From<MatchRequest> for Matchup {
-    fn from(value: MatchRequest) -> Self {
-        Matchup::new(value.person, value.partner)
-    }
-}
-
-#[derive(Default)]
-struct Cache(HashMap<Matchup, u32>);
+use crate::{
+    api::BdsmClient,
+    cache::Cache,
+    config::Shards,
+    data::{persist, GlobalData, Storage},
+};
 
-impl Cache {
-    fn new() -> Self {
-        Cache::default()
-    }
-}
+mod alerts;
+mod api;
+mod archetypes;
+mod backup;
+#[cfg(test)]
+mod bench;
+mod board;
+mod bots;
+mod cache;
+mod cli;
+mod commands;
+mod compare_button;
+mod config;
+mod data;
+mod digest;
+mod errors;
+mod format;
+mod graph;
+mod guests;
+mod health;
+mod heatmap;
+mod jobs;
+mod liveness;
+mod logic;
+mod png;
+mod presence;
+mod refresh;
+mod rescore;
+mod result_button;
+mod roles;
+mod save;
+mod scan;
+mod scoring;
+mod share;
+mod share_button;
+mod slow;
+mod stats;
+#[cfg(test)]
+mod testutil;
+mod tombstones;
+mod undo;
 
 struct GlobalState {
+    api: BdsmClient,
     data: RwLock<GlobalData>,
     cache: Mutex<Cache>,
+    jobs: jobs::JobQueue,
+    refresh: refresh::RefreshQueue,
+    saves: save::SaveQueue,
+    scans: scan::ScanGate,
+    slow: slow::SlowReports,
+    undo: undo::UndoLog,
+    /// Off when DISABLE_PRESENCE is set or the config turns it off.
+    show_presence: bool,
+    /// The presence message every shard is showing, so reconnected shards show it too.
+    presence_index: AtomicUsize,
+    config: config::Config,
 }
 
-type Context<'a> = poise::Context<'a, GlobalState, anyhow::Error>;
-
-async fn autocomplete_headmate(ctx: Context<'_>, partial: &str) -> Vec<String> {
-    let guild_id = match ctx.guild_id() {
-        Some(g) => g,
-        None => return vec![],
-    };
-    let data = ctx.data().data.read().await;
-    let guild_data = match data.guild(guild_id) {
-        Some(g) => g,
-        None => return vec![],
-    };
-    let person_data = match guild_data.users.get(&ctx.author().id) {
-        Some(p) => p,
-        None => return vec![],
-    };
-
-    person_data
-        .headmates
-        .keys()
-        .filter(|k| k.starts_with(partial))
-        .cloned()
-        .collect()
-}
-
-#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
-#[poise::command(slash_command, ephemeral = true, guild_only = true)]
-/// Adds a result from bdsmtest.org. A headmate can also be provided if they took the test on their own.
-async fn add_bdsm_result(
-    ctx: Context<'_>,
-    #[description = "Headmate Name"]
-    #[autocomplete = "autocomplete_headmate"]
-    headmate: Option<String>,
-    #[description = "The result ID from bdsmtest.org"]
-    #[rest]
-    id: String,
-) -> Result<(), anyhow::Error> {
-    info!("Adding bdsmtest.org result");
-
-    ctx.defer_ephemeral().await?;
-
-    let guild_id = ctx
-        .guild_id()
-        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
-    let mut data = ctx.data().data.write().await;
-
-    {
-        let guild = data.guild_mut(guild_id);
-        let person_data = guild
-            .users
-            .entry(ctx.author().id)
-            .or_insert_with(UserData::default);
-        let headmate_data = person_data.headmate_mut(&headmate);
-        headmate_data.results.insert(Utc::now(), id);
-    }
-
-    persist(&data)?;
-
-    ctx.reply("Result Saved")
-        .await
-        .context("while sending reply")?;
-
-    Ok(())
-}
-
-#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
-#[poise::command(slash_command, ephemeral = true, guild_only = true)]
-/// Removes the entries for the current user (or one of their headmates)
-async fn remove_bdsm_results(
-    ctx: Context<'_>,
-    #[description = "Headmate Name"]
-    #[autocomplete = "autocomplete_headmate"]
-    headmate: Option<String>,
-) -> Result<(), anyhow::Error> {
-    info!("Attempting to remove data");
-
-    ctx.defer_ephemeral().await?;
-
-    let guild_id = ctx
-        .guild_id()
-        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
-    let mut data = ctx.data().data.write().await;
-
-    {
-        let guild = data.guild_mut(guild_id);
-        let person_data = guild
-            .users
-            .entry(ctx.author().id)
-            .or_insert_with(UserData::default);
-        match headmate {
-            Some(headmate) => {
-                person_data
-                    .headmates
-                    .remove(&headmate)
-                    .ok_or_else(|| anyhow::anyhow!("No entries found for ({headmate})"))?;
-            }
-            None => {
-                person_data
-                    .primary
-                    .take()
-                    .ok_or_else(|| anyhow::anyhow!("No data for primary entry"))?;
-            }
-        }
-    }
-
-    persist(&data)?;
-
-    ctx.reply("Entries Removed")
-        .await
-        .context("while sending reply")?;
-
-    Ok(())
-}
-
-#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
-#[poise::command(slash_command, guild_only = true)]
-/// Display all of the results registered to the current user. (or for the specified headmate)
-async fn show_result(
-    ctx: Context<'_>,
-    #[description = "Headmate Name"]
-    #[autocomplete = "autocomplete_headmate"]
-    headmate: Option<String>,
-    #[description = "Respond in public to the server (defaults to true)"] public: Option<bool>,
-) -> Result<(), anyhow::Error> {
-    let public = public.unwrap_or(true);
-    info!("Fetching results");
-    if public {
-        ctx.defer().await?;
-    } else {
-        ctx.defer_ephemeral().await?;
-    }
+type Context<'a> = poise::Context<'a, Arc<GlobalState>, anyhow::Error>;
 
-    let guild_id = ctx
-        .guild_id()
-        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
-    let data = ctx.data().data.read().await;
-    let guild = data.guild(guild_id).ok_or_else(|| {
-        anyhow::anyhow!("No data registered for this guild, use add_bdsm_result first")
-    })?;
-
-    let person = guild.users.get(&ctx.author().id).ok_or_else(|| {
-        anyhow::anyhow!("You have not registered any results. Use add_bdsm_result first")
-    })?;
-    let headmate_data = person
-        .headmate(&headmate)
-        .ok_or_else(|| anyhow::anyhow!("Could not find headmate {headmate:?}"))?;
-    for result_id in headmate_data.results.values() {
-        let result = match get_result(result_id).await {
-            Ok(result) => result,
-            Err(e) => {
-                ctx.reply(format!("Could not get result for {result_id}: {e}"))
-                    .await?;
-                continue;
-            }
-        };
-        let mut response = format!(
-            "```==== {} {}({}) {} ====\n",
-            ctx.author().name,
-            if let Some(ref hm) = headmate {
-                format!("({hm}) ")
-            } else {
-                String::new()
-            },
-            result.date,
-            result_id
-        );
-        for score in result.scores {
-            response += &format!("{:-30} {:02}%\n", score.name, score.score);
-        }
-        ctx.reply(response + "```").await?;
-    }
-
-    Ok(())
-}
-
-#[instrument(skip(ctx), err, fields(guild = ctx.guild().unwrap().name, user = ctx.author().name))]
-#[poise::command(slash_command, guild_only = true)]
-/// List the compatibility of yourself and everyone else (including headmates).
-async fn list_compatibility(
-    ctx: Context<'_>,
-    #[description = "Headmate Name"]
-    #[autocomplete = "autocomplete_headmate"]
-    headmate: Option<String>,
-) -> Result<(), anyhow::Error> {
-    info!("Starting List");
-    ctx.defer().await?;
-
-    let guild_id = ctx
-        .guild_id()
-        .ok_or_else(|| anyhow::anyhow!("No guild id. Must be in a guild"))?;
-    let data = ctx.data().data.read().await;
-    let guild = data.guild(guild_id).ok_or_else(|| {
-        anyhow::anyhow!("No data registered for this guild, use add_bdsm_result first")
-    })?;
-
-    let person = guild.users.get(&ctx.author().id).ok_or_else(|| {
-        anyhow::anyhow!("You have not registered any results. Use add_bdsm_result first")
-    })?;
-    let headmate_data = person
-        .headmate(&headmate)
-        .ok_or_else(|| anyhow::anyhow!("Could not find headmate {headmate:?}"))?;
-    let most_recent = headmate_data
-        .results
-        .iter()
-        .max_by_key(|h| h.0)
-        .ok_or_else(|| {
-            anyhow::anyhow!(
-                "No results registered for the given headmate. Use add_bdsm_result first"
-            )
-        })?
-        .1;
-    let mut response = format!(
-        "Compatibility for: {}\n",
-        headmate
-            .clone()
-            .unwrap_or_else(|| match &ctx.author().member {
-                Some(m) => serenity::Member::from(serenity::PartialMember::clone(m.as_ref()))
-                    .display_name()
-                    .to_string(),
-                None => ctx
-                    .author()
-                    .global_name
-                    .clone()
-                    .unwrap_or(ctx.author().name.clone()),
-            })
-    );
-    let mut results = Vec::new();
-    for (&user_id, person) in &guild.users {
-        ctx.defer().await?;
-        // if user_id == ctx.author().id {
-        //     continue;
-        // }
-        let member_name = match guild_id.member(ctx, user_id).await {
-            Ok(user) =>
-            // user.mention().to_string(),
-            {
-                format!("**{}**", user.display_name())
-            }
-            Err(_) if user_id.get() == 1 => "".to_string(),
-            Err(_) => "**Deleted User**".to_string(),
-        };
-
-        if let Some(primary) = &person.primary {
-            let score = get_match(
-                &mut *ctx.data().cache.lock().await,
-                MatchRequest {
-                    person: most_recent.clone(),
-                    partner: primary
-                        .results
-                        .iter()
-                        .max_by_key(|h| h.0)
-                        .expect("no partner result")
-                        .1
-                        .clone(),
-                },
-            )
-            .await
-            .map(|score| score as i32)
-            .unwrap_or_else(|_| -1);
-            results.push((score, member_name.to_string()));
-        }
-
-        for (headmate_name, headmate) in &person.headmates {
-            let name = format!("{member_name} ({headmate_name})",);
-            let score = get_match(
-                &mut *ctx.data().cache.lock().await,
-                MatchRequest {
-                    person: most_recent.clone(),
-                    partner: headmate
-                        .results
-                        .iter()
-                        .max_by_key(|h| h.0)
-                        .expect("no partner result")
-                        .1
-                        .clone(),
-                },
-            )
-            .await
-            .map(|score| score as i32)
-            .unwrap_or_else(|_| -1);
-            results.push((score, name.to_string()));
-        }
-    }
-
-    results.sort_by_key(|(s, _)| -s);
-    for (score, name) in results {
-        response += &format!(
-            "- {name}: {}\n",
-            if score >= 0 {
-                format!("{score:02}%")
-            } else {
-                "Invalid Result".to_string()
-            }
-        );
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    // Headless subcommands run before any logging or environment setup so they never need a
+    // DISCORD_TOKEN and never write to disk unless asked to.
+    match cli::Cli::parse().command.unwrap_or(cli::Command::Run) {
+        cli::Command::Run => run_bot().await,
+        cli::Command::Inspect { path } => cli::inspect_registry(&path),
+        cli::Command::Validate { path, write } => cli::validate_registry(&path, write),
     }
-
-    ctx.send(
-        poise::CreateReply::default()
-            .content(response)
-            .reply(true)
-            .allowed_mentions(serenity::CreateAllowedMentions::new()),
-    )
-    .await?;
-
-    info!("List Complete");
-
-    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn run_bot() -> Result<(), anyhow::Error> {
     let appender = tracing_appender::rolling::RollingFileAppender::builder()
         .max_log_files(10)
         .filename_prefix("rolling")
@@ -600,38 +120,189 @@ async fn main() -> Result<(), anyhow::Error> {
 
     dotenv::dotenv()?;
 
+    let config = config::Config::from_env()?;
+    tracing::info!("Loaded config: {}", config.summary());
     let token = std::env::var("DISCORD_TOKEN")?;
+    let show_presence = config.features.presence
+        && presence::enabled(std::env::var("DISABLE_PRESENCE").ok().as_deref());
     let intents = serenity::GatewayIntents::non_privileged();
+    let shards = config.sharding.shards();
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: vec![
-                add_bdsm_result(),
-                list_compatibility(),
-                remove_bdsm_results(),
-                show_result(),
+                commands::add_bdsm_result(),
+                commands::add_manual_result(),
+                commands::compat_explain(),
+                commands::compare(),
+                commands::compat_graph(),
+                commands::compat_matrix(),
+                commands::compatibility_between(),
+                commands::import_share_text(),
+                commands::import_results(),
+                commands::cancel_job(),
+                commands::keep_result(),
+                commands::list_compatibility(),
+                commands::match_me(),
+                commands::my_jobs(),
+                commands::my_top_archetypes(),
+                commands::ping(),
+                commands::recover_my_data(),
+                commands::remove_bdsm_results(),
+                commands::remove_bdsm_result(),
+                commands::show_result(),
+                commands::server_stats(),
+                commands::similar_on(),
+                commands::stats_me(),
+                commands::result_history(),
+                commands::top_archetype(),
+                commands::undo(),
+                commands::verify_my_results(),
+                commands::whois_headmate(),
+                commands::list_headmates(),
+                commands::rename_headmate(),
+                commands::promote_headmate(),
+                commands::admin::allow_coverage(),
+                commands::admin::allow_sharing(),
+                commands::admin::archetype_alias(),
+                commands::admin::compatibility_leaderboard(),
+                commands::admin::admin_remove_user(),
+                commands::admin::admin_prune_departed(),
+                commands::admin::coverage(),
+                commands::admin::enable_digest(),
+                commands::admin::create_board(),
+                commands::admin::disable_digest(),
+                commands::admin::hide_archetype(),
+                commands::admin::list_hidden_archetypes(),
+                commands::admin::remove_board(),
+                commands::admin::set_audit_channel(),
+                commands::admin::set_guest_duration(),
+                commands::admin::set_list_defaults(),
+                commands::admin::set_list_requirements(),
+                commands::admin::set_power_couple_role(),
+                commands::admin::set_announcement_channel(),
+                commands::admin::transfer_user_data(),
+                commands::admin::unhide_archetype(),
+                commands::admin::wipe_guild(),
+                commands::owner::debug_result(),
+                commands::owner::merge_guilds(),
+                commands::owner::rebuild_cache(),
+                commands::owner::restore_user_data(),
+                commands::settings::ignore_user(),
+                commands::settings::list_ignored(),
+                commands::settings::match_alerts(),
+                commands::settings::set_announcement_preference(),
+                commands::settings::set_default_headmate(),
+                commands::settings::set_display_name(),
+                commands::settings::set_explain_consent(),
+                commands::settings::set_gender_display(),
+                commands::settings::set_result_visibility(),
+                commands::settings::set_third_party_comparisons(),
+                commands::settings::set_timezone(),
+                commands::settings::set_visibility(),
+                commands::settings::set_weights(),
+                commands::settings::unignore_user(),
             ],
+            on_error: |error| Box::pin(errors::on_error(error)),
+            pre_command: |ctx| Box::pin(slow::start(ctx)),
+            post_command: |ctx| {
+                Box::pin(async move {
+                    // Timed first, so the note below isn't counted.
+                    slow::finish(ctx).await;
+                    commands::report_match_alert_failure(ctx).await;
+                })
+            },
+            // Replies mention members by name, but should never ping them.
+            allowed_mentions: Some(serenity::CreateAllowedMentions::new()),
+            event_handler: |ctx, event, _framework, state| {
+                Box::pin(async move {
+                    // A shard's presence is lost when it reconnects, which always ends with a
+                    // fresh Ready for that shard.
+                    match event {
+                        serenity::FullEvent::Ready { .. } if state.show_presence => {
+                            presence::show(ctx, state).await;
+                        }
+                        serenity::FullEvent::InteractionCreate {
+                            interaction: serenity::Interaction::Component(mci),
+                        } => {
+                            compare_button::handle(ctx, state, mci).await;
+                            share_button::handle(ctx, state, mci).await;
+                        }
+                        _ => {}
+                    }
+                    Ok(())
+                })
+            },
             ..Default::default()
         })
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                let mut results: GlobalData =
-                    serde_json::from_str(&std::fs::read_to_string(REGISTRY).unwrap_or_default())?;
-                results.migrate();
+                let mut results = backup::load_registry(&config.data_dir)?;
+                results.storage = Storage {
+                    root: config.data_dir.clone(),
+                    retention: config.backups.clone(),
+                    ..Default::default()
+                };
+                results.sync_archetypes();
+                let dropped_jobs = std::mem::take(&mut results.pending_jobs);
                 let _ = persist(&results);
-                Ok(GlobalState {
+                let (refresh, refresh_requests) = refresh::RefreshQueue::new();
+                let (saves, save_requests) = save::SaveQueue::new();
+                let state = Arc::new(GlobalState {
+                    api: BdsmClient::new(BdsmClient::http_client(&config.api)?, &config.api),
                     data: RwLock::new(results),
                     cache: Mutex::new(Cache::new()),
-                })
+                    jobs: jobs::JobQueue::new(),
+                    refresh,
+                    saves,
+                    scans: scan::ScanGate::new(),
+                    slow: slow::SlowReports::default(),
+                    undo: undo::UndoLog::new(),
+                    show_presence,
+                    presence_index: AtomicUsize::new(0),
+                    config,
+                });
+                tokio::spawn(digest::run(ctx.clone(), state.clone()));
+                tokio::spawn(refresh::run(ctx.clone(), state.clone(), refresh_requests));
+                tokio::spawn(save::run(state.clone(), save_requests));
+                tokio::spawn(save::on_shutdown(
+                    framework.shard_manager().clone(),
+                    state.clone(),
+                ));
+                tokio::spawn(liveness::run(state.clone()));
+                tokio::spawn(guests::run(state.clone()));
+                tokio::spawn(tombstones::run(state.clone()));
+                tokio::spawn(rescore::run(state.clone()));
+                tokio::spawn(bots::sweep(ctx.clone(), state.clone()));
+                tokio::spawn(jobs::run(ctx.clone(), state.clone()));
+                tokio::spawn(jobs::report_dropped(ctx.clone(), dropped_jobs));
+                if let Some(addr) = state.config.health.listen {
+                    tokio::spawn(health::serve(
+                        addr,
+                        framework.shard_manager().clone(),
+                        state.clone(),
+                    ));
+                }
+                if show_presence {
+                    tokio::spawn(presence::run(
+                        framework.shard_manager().clone(),
+                        state.clone(),
+                    ));
+                }
+                Ok(state)
             })
         })
         .build();
 
-    let client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .await;
-    client.unwrap().start().await.unwrap();
+        .await?;
+    match shards {
+        Shards::Auto => client.start_autosharded().await?,
+        Shards::All(total) => client.start_shards(total).await?,
+        Shards::Range { ids, total } => client.start_shard_range(ids, total).await?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,126 @@
+//! Rotates the bot's presence through a few lines about the registry.
+
+use std::sync::{atomic::Ordering, Arc};
+
+use poise::serenity_prelude as serenity;
+
+use crate::{data::GlobalData, GlobalState};
+
+const ROTATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Whether presence should be shown, given the `DISABLE_PRESENCE` environment variable. Any value
+/// other than an empty one, `0` or `false` turns it off.
+pub fn enabled(disable: Option<&str>) -> bool {
+    disable.is_none_or(|v| v.is_empty() || v == "0" || v.eq_ignore_ascii_case("false"))
+}
+
+/// What the presence reports on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub results: usize,
+    pub members: usize,
+    pub servers: usize,
+}
+
+impl Counts {
+    /// Counts every stored result, and the members and servers that have at least one.
+    pub fn of(data: &GlobalData) -> Self {
+        let mut counts = Counts::default();
+        for guild in data.guilds.values() {
+            let mut members = std::collections::BTreeSet::new();
+            for entry in guild.entries() {
+                counts.results += entry.data.results.len();
+                if !entry.data.results.is_empty() {
+                    members.insert(entry.user_id);
+                }
+            }
+            counts.members += members.len();
+            counts.servers += usize::from(!members.is_empty());
+        }
+        counts
+    }
+}
+
+/// Every line the presence rotates through, in order.
+pub fn messages(counts: Counts) -> Vec<String> {
+    vec![
+        format!(
+            "tracking {} results across {} servers",
+            counts.results, counts.servers
+        ),
+        format!("comparing {} members", counts.members),
+        "use /add_bdsm_result to get started".to_string(),
+    ]
+}
+
+/// The `index`th message (wrapping around) with the registry's current counts.
+async fn activity(state: &GlobalState, index: usize) -> serenity::ActivityData {
+    let counts = Counts::of(&*state.data.read().await);
+    let messages = messages(counts);
+    serenity::ActivityData::custom(messages[index % messages.len()].clone())
+}
+
+/// Shows the message the other shards are showing on the shard `ctx` belongs to.
+pub async fn show(ctx: &serenity::Context, state: &GlobalState) {
+    let index = state.presence_index.load(Ordering::Relaxed);
+    ctx.set_activity(Some(activity(state, index).await));
+}
+
+/// Moves every shard on to the next message every few minutes. Reconnects reset the presence, so
+/// it is also shown again on each shard's Ready event.
+pub async fn run(shards: Arc<serenity::ShardManager>, state: Arc<GlobalState>) {
+    for index in 0.. {
+        state.presence_index.store(index, Ordering::Relaxed);
+        let activity = activity(&state, index).await;
+        for runner in shards.runners.lock().await.values() {
+            runner.runner_tx.set_activity(Some(activity.clone()));
+        }
+        tokio::time::sleep(ROTATE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::UserData;
+
+    #[test]
+    fn disabled_by_any_truthy_value() {
+        for value in [None, Some(""), Some("0"), Some("FALSE")] {
+            assert!(enabled(value), "{value:?}");
+        }
+        for value in [Some("1"), Some("true"), Some("yes")] {
+            assert!(!enabled(value), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn counts_results_members_and_servers() {
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        let later = "2024-06-01T00:00:00Z".parse().unwrap();
+        let mut data = GlobalData::default();
+        let guild = data.guild_mut(serenity::GuildId::new(1));
+        let mut user = UserData::default();
+        user.headmate_mut(&None).results.insert(at, "a".into());
+        user.headmate_mut(&None).results.insert(later, "b".into());
+        user.headmate_mut(&Some("Ash".into()))
+            .results
+            .insert(at, "c".into());
+        guild.users.insert(serenity::UserId::new(10), user);
+        guild
+            .users
+            .insert(serenity::UserId::new(20), UserData::default());
+        data.guild_mut(serenity::GuildId::new(2));
+
+        let counts = Counts::of(&data);
+        assert_eq!(
+            counts,
+            Counts {
+                results: 3,
+                members: 1,
+                servers: 1,
+            }
+        );
+        assert_eq!(messages(counts)[0], "tracking 3 results across 1 servers");
+    }
+}
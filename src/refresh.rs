@@ -0,0 +1,72 @@
+//! Keeps the parts of a guild derived from its registry (the board and the power couple role)
+//! in sync, without doing the work on every single change.
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use poise::serenity_prelude as serenity;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::{board, roles, GlobalState};
+
+/// How long to wait for more changes before refreshing a guild.
+const DEBOUNCE: Duration = Duration::from_secs(10);
+/// How often every guild is refreshed even without changes, since new match scores only land in
+/// the cache.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Queues guild refreshes. Requests for the same guild that arrive close together are merged.
+pub struct RefreshQueue(mpsc::UnboundedSender<serenity::GuildId>);
+
+impl RefreshQueue {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<serenity::GuildId>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (RefreshQueue(tx), rx)
+    }
+
+    /// Schedules a refresh of `guild_id` after its results or settings changed.
+    pub fn request(&self, guild_id: serenity::GuildId) {
+        // The receiver only goes away when the bot is shutting down.
+        let _ = self.0.send(guild_id);
+    }
+}
+
+async fn refresh(ctx: &serenity::Context, state: &GlobalState, guild_id: serenity::GuildId) {
+    if let Err(e) = board::update(ctx, state, guild_id).await {
+        warn!(%guild_id, "Could not update compatibility board: {e:#}");
+    }
+    if let Err(e) = roles::reconcile(ctx, state, guild_id).await {
+        warn!(%guild_id, "Could not update power couple roles: {e:#}");
+    }
+}
+
+/// Applies queued refreshes, waiting [`DEBOUNCE`] after the first request so bursts of changes
+/// only cause one refresh per guild. Every guild is also swept every [`SWEEP_INTERVAL`].
+pub async fn run(
+    ctx: serenity::Context,
+    state: Arc<GlobalState>,
+    mut requests: mpsc::UnboundedReceiver<serenity::GuildId>,
+) {
+    let mut sweep = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        let mut pending = BTreeSet::new();
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(first) = request else {
+                    return;
+                };
+                tokio::time::sleep(DEBOUNCE).await;
+                pending.insert(first);
+            }
+            _ = sweep.tick() => {
+                pending.extend(state.data.read().await.guilds.keys().copied());
+            }
+        }
+        while let Ok(guild_id) = requests.try_recv() {
+            pending.insert(guild_id);
+        }
+        for guild_id in pending {
+            refresh(&ctx, &state, guild_id).await;
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Re-fetches the cached match scores that were fetched longest ago, a batch every night, so the
+//! cache follows any change in how bdsmtest.org scores. Scores of results that are no longer
+//! stored anywhere are evicted instead.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use tracing::{info, warn};
+
+use crate::{api::BdsmApi as _, GlobalState};
+
+/// When the batch runs, in UTC.
+const RESCORE_HOUR: u32 = 4;
+/// How many scores each night re-fetches.
+const BATCH: usize = 50;
+
+/// The next time the batch should run after `now`.
+fn next_run(now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now
+        .date_naive()
+        .and_time(NaiveTime::from_hms_opt(RESCORE_HOUR, 0, 0).unwrap())
+        .and_utc();
+    if today > now {
+        today
+    } else {
+        today + Duration::days(1)
+    }
+}
+
+/// Evicts the scores of results no longer stored, then re-fetches the oldest [`BATCH`]. Stops
+/// early if bdsmtest.org can't be reached, the scores left over are the oldest again tomorrow.
+async fn rescore(state: &GlobalState) {
+    let stored = state.data.read().await.result_ids();
+    let oldest = {
+        let mut cache = state.cache.lock().await;
+        let evicted = cache.evict_matches(|id| stored.contains(id));
+        if evicted > 0 {
+            info!(evicted, "Evicted match scores of removed results");
+        }
+        cache.oldest_matches(BATCH)
+    };
+    let mut refreshed = 0;
+    for matchup in oldest {
+        match state.api.get_match(&matchup.request()).await {
+            Ok(score) => {
+                state.cache.lock().await.insert(matchup, score);
+                refreshed += 1;
+            }
+            Err(e) => {
+                warn!("bdsmtest.org could not be reached, stopping for tonight: {e:#}");
+                break;
+            }
+        }
+    }
+    info!(refreshed, "Refreshed cached match scores");
+}
+
+/// Runs the batch every night at [`RESCORE_HOUR`].
+pub async fn run(state: Arc<GlobalState>) {
+    loop {
+        let now = Utc::now();
+        let wait = (next_run(now) - now).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        rescore(&state).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn runs_at_the_next_rescore_hour() {
+        assert_eq!(
+            next_run(time("2024-05-01T01:30:00Z")),
+            time("2024-05-01T04:00:00Z")
+        );
+        assert_eq!(
+            next_run(time("2024-05-01T04:00:00Z")),
+            time("2024-05-02T04:00:00Z")
+        );
+        assert_eq!(
+            next_run(time("2024-05-01T23:00:00Z")),
+            time("2024-05-02T04:00:00Z")
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! What the buttons on messages about a single result encode in their custom ID. Everything a
+//! click needs is in there, so the buttons keep working across restarts until they expire.
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+
+/// Discord's limit on custom IDs.
+const MAX_CUSTOM_ID: usize = 100;
+
+/// The result a button is about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResultTarget {
+    pub user_id: serenity::UserId,
+    pub headmate: Option<String>,
+    pub result_id: String,
+    pub expires: DateTime<Utc>,
+}
+
+impl ResultTarget {
+    /// Encodes the target as a custom ID starting with `prefix`. The headmate goes last since it
+    /// may contain anything.
+    pub fn custom_id(&self, prefix: &str) -> String {
+        format!(
+            "{prefix}:{}:{}:{}:{}",
+            self.user_id,
+            self.expires.timestamp(),
+            self.result_id,
+            self.headmate.as_deref().unwrap_or("")
+        )
+    }
+
+    /// Reads a custom ID made by [`ResultTarget::custom_id`] with the same `prefix`. Any other
+    /// custom ID is `None`.
+    pub fn parse(prefix: &str, custom_id: &str) -> Option<ResultTarget> {
+        let mut parts = custom_id.splitn(5, ':');
+        if parts.next()? != prefix {
+            return None;
+        }
+        let user_id = parts.next()?.parse().ok()?;
+        let expires = DateTime::from_timestamp(parts.next()?.parse().ok()?, 0)?;
+        let result_id = parts.next()?.to_string();
+        let headmate = Some(parts.next()?.to_string()).filter(|h| !h.is_empty());
+        Some(ResultTarget {
+            user_id,
+            headmate,
+            result_id,
+            expires,
+        })
+    }
+
+    /// A row with a single button for the target. Targets too long to encode get no button.
+    pub fn button(
+        &self,
+        prefix: &str,
+        label: &str,
+        style: serenity::ButtonStyle,
+    ) -> Option<serenity::CreateActionRow> {
+        let custom_id = self.custom_id(prefix);
+        (custom_id.len() <= MAX_CUSTOM_ID).then(|| {
+            serenity::CreateActionRow::Buttons(vec![serenity::CreateButton::new(custom_id)
+                .label(label)
+                .style(style)])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_ids_round_trip() {
+        let expires = "2024-05-08T12:00:00Z".parse().unwrap();
+        let target = ResultTarget {
+            user_id: serenity::UserId::new(42),
+            headmate: Some("Ash: the second".into()),
+            result_id: "abc123".into(),
+            expires,
+        };
+        assert_eq!(
+            target.custom_id("button"),
+            "button:42:1715169600:abc123:Ash: the second"
+        );
+        assert_eq!(
+            ResultTarget::parse("button", &target.custom_id("button")),
+            Some(target.clone())
+        );
+        assert_eq!(
+            ResultTarget::parse("other", &target.custom_id("button")),
+            None
+        );
+
+        let primary = ResultTarget {
+            headmate: None,
+            ..target
+        };
+        assert_eq!(
+            ResultTarget::parse("button", &primary.custom_id("button")),
+            Some(primary)
+        );
+        assert_eq!(ResultTarget::parse("button", "1-confirm"), None);
+        assert_eq!(ResultTarget::parse("button", "button:x:1:abc:"), None);
+    }
+
+    #[test]
+    fn long_targets_get_no_button() {
+        let mut target = ResultTarget {
+            user_id: serenity::UserId::new(42),
+            headmate: Some("Ash".into()),
+            result_id: "abc".into(),
+            expires: Utc::now(),
+        };
+        let style = serenity::ButtonStyle::Primary;
+        assert!(target.button("button", "Press", style).is_some());
+        target.headmate = Some("A".repeat(80));
+        assert!(target.button("button", "Press", style).is_none());
+    }
+}
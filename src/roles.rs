@@ -0,0 +1,174 @@
+//! The optional "power couple" role for members with a very high match.
+
+use std::collections::BTreeSet;
+
+use poise::serenity_prelude as serenity;
+use tracing::{info, warn};
+
+use crate::{
+    board::is_http_status,
+    cache::{Cache, Matchup},
+    data::GuildData,
+    GlobalState,
+};
+
+const AUDIT_REASON: &str = "Power couple role";
+
+/// The users with at least one cached match at or above `threshold` with another user. Only
+/// listed entries count, so hidden users never qualify.
+pub fn qualifying(guild: &GuildData, cache: &Cache, threshold: u32) -> BTreeSet<serenity::UserId> {
+    let entries: Vec<_> = guild
+        .listed_entries()
+        .filter_map(|e| Some((e.user_id, e.data.most_recent_visible()?)))
+        .collect();
+    let mut qualifying = BTreeSet::new();
+    for (i, (a, a_id)) in entries.iter().enumerate() {
+        for (b, b_id) in &entries[i + 1..] {
+            if a == b {
+                continue;
+            }
+            let matchup = Matchup::new((*a_id).clone(), (*b_id).clone());
+            if cache.get(&matchup).is_some_and(|score| score >= threshold) {
+                qualifying.insert(*a);
+                qualifying.insert(*b);
+            }
+        }
+    }
+    qualifying
+}
+
+/// Grants the power couple role to newly qualifying members and takes it from those that no
+/// longer qualify. If Discord refuses to let the bot manage the role, that is reported to the
+/// audit channel once and nothing more is attempted until the role is configured again.
+pub async fn reconcile(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+    guild_id: serenity::GuildId,
+) -> Result<(), anyhow::Error> {
+    let (config, audit_channel, qualifying) = {
+        let data = state.data.read().await;
+        let Some(guild) = data.guild(guild_id) else {
+            return Ok(());
+        };
+        let Some(config) = guild.config.power_couple.clone() else {
+            return Ok(());
+        };
+        if config.forbidden {
+            return Ok(());
+        }
+        let qualifying = qualifying(guild, &*state.cache.lock().await, config.threshold);
+        (config, guild.config.audit_channel, qualifying)
+    };
+
+    let mut holders = config.holders.clone();
+    let mut forbidden = false;
+    let changes = qualifying
+        .difference(&config.holders)
+        .map(|&user_id| (user_id, true))
+        .chain(
+            config
+                .holders
+                .difference(&qualifying)
+                .map(|&user_id| (user_id, false)),
+        )
+        .collect::<Vec<_>>();
+    for (user_id, grant) in changes {
+        let result = if grant {
+            ctx.http
+                .add_member_role(guild_id, user_id, config.role, Some(AUDIT_REASON))
+                .await
+        } else {
+            ctx.http
+                .remove_member_role(guild_id, user_id, config.role, Some(AUDIT_REASON))
+                .await
+        };
+        match result {
+            Ok(()) => {
+                info!(%guild_id, %user_id, grant, "Updated power couple role");
+                if grant {
+                    holders.insert(user_id);
+                } else {
+                    holders.remove(&user_id);
+                }
+            }
+            Err(e) if is_http_status(&e, serenity::StatusCode::FORBIDDEN) => {
+                forbidden = true;
+                break;
+            }
+            Err(e) if is_http_status(&e, serenity::StatusCode::NOT_FOUND) => {
+                // The member left, so they don't have the role anymore either.
+                holders.remove(&user_id);
+            }
+            Err(e) => warn!(%guild_id, %user_id, "Could not update power couple role: {e}"),
+        }
+    }
+
+    if forbidden {
+        warn!(%guild_id, "Not allowed to manage the power couple role");
+        if let Some(channel) = audit_channel {
+            let message = format!(
+                "I'm not allowed to manage <@&{}>. Make sure my role is above it and has the \
+                 Manage Roles permission, then run /set_power_couple_role again.",
+                config.role
+            );
+            let message = serenity::CreateMessage::new()
+                .content(message)
+                .allowed_mentions(serenity::CreateAllowedMentions::new());
+            if let Err(e) = channel.send_message(ctx, message).await {
+                warn!(%guild_id, "Could not report to the audit channel: {e}");
+            }
+        }
+    }
+
+    if holders == config.holders && !forbidden {
+        return Ok(());
+    }
+    let mut data = state.data.write().await;
+    if let Some(current) = data.guild_mut(guild_id).config.power_couple.as_mut() {
+        // An admin may have reconfigured the role while this was running.
+        if current.role == config.role {
+            current.holders = holders;
+            current.forbidden = forbidden;
+        }
+    }
+    state.saves.request();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{UserData, Visibility};
+
+    #[test]
+    fn qualifying_pairs_of_different_users() {
+        let mut guild = GuildData::default();
+        let at = "2024-05-01T00:00:00Z".parse().unwrap();
+        for (id, primary, headmate) in [(1, "a", Some("a2")), (2, "b", None), (3, "c", None)] {
+            let mut user = UserData::default();
+            user.headmate_mut(&None).results.insert(at, primary.into());
+            if let Some(headmate) = headmate {
+                user.headmate_mut(&Some("Ash".into()))
+                    .results
+                    .insert(at, headmate.into());
+            }
+            guild.users.insert(serenity::UserId::new(id), user);
+        }
+        let mut cache = Cache::new();
+        // Matching your own headmate doesn't count.
+        cache.insert(Matchup::new("a".into(), "a2".into()), 100);
+        cache.insert(Matchup::new("a2".into(), "b".into()), 96);
+        cache.insert(Matchup::new("a".into(), "c".into()), 94);
+
+        let ids = |ids: &[u64]| ids.iter().map(|&id| serenity::UserId::new(id)).collect();
+        assert_eq!(qualifying(&guild, &cache, 95), ids(&[1, 2]));
+        assert_eq!(qualifying(&guild, &cache, 90), ids(&[1, 2, 3]));
+
+        guild
+            .users
+            .get_mut(&serenity::UserId::new(2))
+            .unwrap()
+            .visibility = Visibility::HiddenFromOthers;
+        assert_eq!(qualifying(&guild, &cache, 95), ids(&[]));
+    }
+}
@@ -0,0 +1,152 @@
+//! Writes the registry to disk in the background. Changes only ask for a save, and saves are put
+//! off until changes stop for a moment, so a burst of commands is written once and no command
+//! waits on the disk while holding the registry lock.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{mpsc, Mutex},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::{data, GlobalState};
+
+/// How long changes have to stop before they are saved.
+const QUIET: Duration = Duration::from_secs(2);
+/// The longest a change waits to be saved while changes keep coming.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Collects requests to save the registry.
+pub struct SaveQueue {
+    requests: mpsc::UnboundedSender<()>,
+    /// Held while writing, so a flush never races the background save.
+    writing: Mutex<()>,
+}
+
+impl SaveQueue {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<()>) {
+        let (requests, rx) = mpsc::unbounded_channel();
+        let queue = SaveQueue {
+            requests,
+            writing: Mutex::new(()),
+        };
+        (queue, rx)
+    }
+
+    /// Schedules a save after the registry changed.
+    pub fn request(&self) {
+        // The receiver only goes away when the bot is shutting down, after the final flush.
+        let _ = self.requests.send(());
+    }
+}
+
+/// Waits until requests have stopped for `quiet`, or `max_delay` after the first one. `false`
+/// means the queue was closed, with nothing more to wait for.
+async fn settle(
+    requests: &mut mpsc::UnboundedReceiver<()>,
+    quiet: Duration,
+    max_delay: Duration,
+) -> bool {
+    let deadline = Instant::now() + max_delay;
+    loop {
+        let until = deadline.min(Instant::now() + quiet);
+        match tokio::time::timeout_at(until, requests.recv()).await {
+            Ok(Some(())) => continue,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Writes the registry as it is now. The disk work happens off the async workers, and the
+/// registry is only locked while it is serialized.
+pub async fn flush(state: &GlobalState) -> Result<(), anyhow::Error> {
+    let _writing = state.saves.writing.lock().await;
+    let (storage, json) = {
+        let data = state.data.read().await;
+        (data.storage.clone(), serde_json::to_vec_pretty(&*data)?)
+    };
+    let failing = storage.failing.clone();
+    let result = tokio::task::spawn_blocking(move || data::write_in(&storage, &json))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    failing.store(result.is_err(), std::sync::atomic::Ordering::Relaxed);
+    result
+}
+
+/// Saves the registry whenever it was asked to, once the changes have settled.
+pub async fn run(state: Arc<GlobalState>, mut requests: mpsc::UnboundedReceiver<()>) {
+    while requests.recv().await.is_some() {
+        let open = settle(&mut requests, QUIET, MAX_DELAY).await;
+        if let Err(e) = flush(&state).await {
+            warn!("Could not save the registry: {e:#}");
+        }
+        if !open {
+            return;
+        }
+    }
+}
+
+/// Waits for Ctrl-C or SIGTERM, then saves the registry one last time and stops every shard.
+pub async fn on_shutdown(
+    shards: Arc<poise::serenity_prelude::ShardManager>,
+    state: Arc<GlobalState>,
+) {
+    if let Err(e) = shutdown_signal().await {
+        warn!("Could not listen for shutdown signals: {e:#}");
+        return;
+    }
+    info!("Shutting down");
+    if let Err(e) = flush(&state).await {
+        warn!("Could not save the registry before shutting down: {e:#}");
+    }
+    shards.shutdown_all().await;
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUIET: Duration = Duration::from_millis(40);
+
+    #[tokio::test]
+    async fn settles_once_requests_stop() {
+        let (queue, mut requests) = SaveQueue::new();
+        let start = Instant::now();
+        assert!(settle(&mut requests, QUIET, Duration::from_secs(10)).await);
+        assert!(start.elapsed() >= QUIET);
+
+        // Requests that keep coming only hold the save off until the deadline.
+        let feeder = tokio::spawn(async move {
+            for _ in 0..40 {
+                queue.request();
+                tokio::time::sleep(QUIET / 4).await;
+            }
+            queue
+        });
+        let start = Instant::now();
+        assert!(settle(&mut requests, QUIET, QUIET * 3).await);
+        let waited = start.elapsed();
+        assert!(waited >= QUIET * 3 && waited < QUIET * 5, "{waited:?}");
+
+        drop(feeder.await.unwrap());
+        assert!(!settle(&mut requests, QUIET, Duration::from_secs(10)).await);
+    }
+}
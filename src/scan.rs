@@ -0,0 +1,127 @@
+//! Lets only one compatibility scan run per guild at a time. A scan fans out to bdsmtest.org for
+//! every entry in the guild, so a second one started meanwhile would fetch the same matches again.
+//! Instead it waits, and then finds everything in the cache.
+//!
+//! Scans can also be stopped early with a [`CancelToken`].
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use poise::serenity_prelude as serenity;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// The running scan of each guild.
+#[derive(Default)]
+pub struct ScanGate {
+    guilds: std::sync::Mutex<HashMap<serenity::GuildId, Arc<Mutex<()>>>>,
+}
+
+/// Marks a guild's scan as running until dropped, which also happens when the scan fails or
+/// panics.
+pub struct ScanGuard {
+    _running: OwnedMutexGuard<()>,
+}
+
+impl ScanGate {
+    pub fn new() -> Self {
+        ScanGate::default()
+    }
+
+    /// Waits for any running scan of `guild_id` to finish, then marks a new one as running.
+    pub async fn enter(&self, guild_id: serenity::GuildId) -> ScanGuard {
+        let running = self
+            .guilds
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(guild_id)
+            .or_default()
+            .clone();
+        ScanGuard {
+            _running: running.lock_owned().await,
+        }
+    }
+}
+
+/// Asks a scan to stop before its next request to bdsmtest.org. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const GUILD: serenity::GuildId = serenity::GuildId::new(1);
+
+    /// Whether entering `guild_id` would have to wait.
+    async fn is_busy(gate: &ScanGate, guild_id: serenity::GuildId) -> bool {
+        tokio::time::timeout(Duration::from_millis(20), gate.enter(guild_id))
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn one_scan_per_guild() {
+        let gate = ScanGate::new();
+        let guard = gate.enter(GUILD).await;
+        assert!(is_busy(&gate, GUILD).await);
+        assert!(!is_busy(&gate, serenity::GuildId::new(2)).await);
+        drop(guard);
+        assert!(!is_busy(&gate, GUILD).await);
+    }
+
+    #[tokio::test]
+    async fn waiting_scan_runs_after_the_first() {
+        let gate = Arc::new(ScanGate::new());
+        let guard = gate.enter(GUILD).await;
+        let waiting = tokio::spawn({
+            let gate = gate.clone();
+            async move {
+                let _guard = gate.enter(GUILD).await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(guard);
+        waiting.await.unwrap();
+    }
+
+    #[test]
+    fn cancel_tokens_share_their_state() {
+        let token = CancelToken::default();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn released_when_the_scan_panics() {
+        let gate = Arc::new(ScanGate::new());
+        let scan = tokio::spawn({
+            let gate = gate.clone();
+            async move {
+                let _guard = gate.enter(GUILD).await;
+                panic!("scan failed");
+            }
+        });
+        assert!(scan.await.is_err());
+        assert!(!is_busy(&gate, GUILD).await);
+    }
+}
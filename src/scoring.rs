@@ -0,0 +1,209 @@
+//! Compatibility estimated locally from score vectors, instead of asking bdsmtest.org.
+
+use std::collections::BTreeMap;
+
+use crate::{api::GetResultScore, archetypes};
+
+/// The weight of any archetype the user hasn't set a weight for.
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// Compares each of `mine` against the partner's score for its [`archetypes::complement`]: equal
+/// scores are 100% compatible, opposite extremes 0%. The result is the average of those, weighted
+/// by `weights`. Archetypes the partner has no score for are skipped, and `None` means there was
+/// nothing to compare.
+pub fn weighted_score(
+    mine: &[GetResultScore],
+    theirs: &[GetResultScore],
+    weights: &BTreeMap<String, f64>,
+) -> Option<u32> {
+    let mut total = 0.0;
+    let mut weight_sum = 0.0;
+    for score in mine {
+        let weight = weights.get(&score.name).copied().unwrap_or(DEFAULT_WEIGHT);
+        let complement = archetypes::complement(&score.name);
+        let Some(partner) = theirs
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(complement))
+        else {
+            continue;
+        };
+        if weight <= 0.0 {
+            continue;
+        }
+        total += weight * (100.0 - f64::from(score.score.abs_diff(partner.score)));
+        weight_sum += weight;
+    }
+    (weight_sum > 0.0).then(|| (total / weight_sum).round() as u32)
+}
+
+/// One of the invoker's archetypes next to the partner's score for its complement.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pairing {
+    pub mine: String,
+    pub my_score: u32,
+    pub theirs: String,
+    pub their_score: u32,
+}
+
+/// What drives a match: the pairings where both score highly, strongest first, and the pairings
+/// that are furthest apart, largest gap first. At most `count` of each.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Explanation {
+    pub harmonies: Vec<Pairing>,
+    pub frictions: Vec<Pairing>,
+}
+
+/// Explains [`weighted_score`] without weights: every archetype is paired with the partner's
+/// [`archetypes::complement`], the same way the score itself is estimated.
+pub fn explain(mine: &[GetResultScore], theirs: &[GetResultScore], count: usize) -> Explanation {
+    let pairings: Vec<_> = mine
+        .iter()
+        .filter_map(|score| {
+            let complement = archetypes::complement(&score.name);
+            Some(Pairing {
+                mine: score.name.clone(),
+                my_score: score.score,
+                theirs: complement.to_string(),
+                their_score: archetype_score(theirs, complement)?,
+            })
+        })
+        .collect();
+
+    let mut harmonies = pairings.clone();
+    // Both scoring highly matters more than scoring the same, so rank by the lower score.
+    harmonies.sort_by_key(|p| std::cmp::Reverse(p.my_score.min(p.their_score)));
+    harmonies.retain(|p| p.my_score.min(p.their_score) > 0);
+    harmonies.truncate(count);
+
+    let mut frictions = pairings;
+    frictions.sort_by_key(|p| std::cmp::Reverse(p.my_score.abs_diff(p.their_score)));
+    frictions.retain(|p| p.my_score != p.their_score && !harmonies.contains(p));
+    frictions.truncate(count);
+
+    Explanation {
+        harmonies,
+        frictions,
+    }
+}
+
+/// The score for `archetype` in `scores`, ignoring case.
+pub fn archetype_score(scores: &[GetResultScore], archetype: &str) -> Option<u32> {
+    scores
+        .iter()
+        .find(|s| s.name.eq_ignore_ascii_case(archetype))
+        .map(|s| s.score)
+}
+
+/// How far apart two results are on `archetype`, in percentage points. Unlike
+/// [`weighted_score`] this compares like with like, to find people who are similar rather than
+/// complementary. `None` if either result has no score for it.
+pub fn archetype_distance(
+    mine: &[GetResultScore],
+    theirs: &[GetResultScore],
+    archetype: &str,
+) -> Option<u32> {
+    Some(archetype_score(mine, archetype)?.abs_diff(archetype_score(theirs, archetype)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(scores: &[(&str, u32)]) -> Vec<GetResultScore> {
+        scores
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, score))| GetResultScore {
+                id: i as u32,
+                name: name.into(),
+                pairdesc: String::new(),
+                description: String::new(),
+                score,
+            })
+            .collect()
+    }
+
+    fn weights(weights: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        weights.iter().map(|&(a, w)| (a.to_string(), w)).collect()
+    }
+
+    #[test]
+    fn compares_against_complements() {
+        let mine = scores(&[("Dominant", 80), ("Rigger", 100), ("Switch", 40)]);
+        let theirs = scores(&[("Submissive", 60), ("Rope bunny", 90), ("Switch", 10)]);
+        // (80 + 90 + 70) / 3
+        assert_eq!(weighted_score(&mine, &theirs, &weights(&[])), Some(80));
+        // (80 + 3 * 90 + 0 * 70) / 4 = 87.5
+        assert_eq!(
+            weighted_score(
+                &mine,
+                &theirs,
+                &weights(&[("Rigger", 3.0), ("Switch", 0.0)])
+            ),
+            Some(88)
+        );
+    }
+
+    #[test]
+    fn skips_archetypes_missing_from_partner() {
+        let mine = scores(&[("Dominant", 80), ("Rigger", 100)]);
+        let theirs = scores(&[("Submissive", 60)]);
+        assert_eq!(weighted_score(&mine, &theirs, &weights(&[])), Some(80));
+        assert_eq!(
+            weighted_score(&mine, &theirs, &weights(&[("Dominant", 0.0)])),
+            None
+        );
+        assert_eq!(weighted_score(&mine, &[], &weights(&[])), None);
+    }
+
+    #[test]
+    fn distance_on_one_archetype() {
+        let mine = scores(&[("Sadist", 82), ("Masochist", 10)]);
+        let theirs = scores(&[("Sadist", 90), ("Masochist", 82)]);
+        assert_eq!(archetype_distance(&mine, &theirs, "sadist"), Some(8));
+        assert_eq!(archetype_distance(&theirs, &mine, "Sadist"), Some(8));
+        assert_eq!(archetype_distance(&mine, &mine, "Sadist"), Some(0));
+        assert_eq!(archetype_distance(&mine, &theirs, "Rigger"), None);
+    }
+
+    #[test]
+    fn explains_harmonies_and_frictions() {
+        let mine = scores(&[
+            ("Dominant", 90),
+            ("Rigger", 70),
+            ("Sadist", 10),
+            ("Switch", 40),
+            ("Brat", 0),
+        ]);
+        let theirs = scores(&[
+            ("Submissive", 85),
+            ("Rope bunny", 75),
+            ("Masochist", 95),
+            ("Switch", 40),
+            ("Brat tamer", 0),
+        ]);
+        let pairing = |mine: &str, my_score, theirs: &str, their_score| Pairing {
+            mine: mine.into(),
+            my_score,
+            theirs: theirs.into(),
+            their_score,
+        };
+        assert_eq!(
+            explain(&mine, &theirs, 2),
+            Explanation {
+                harmonies: vec![
+                    pairing("Dominant", 90, "Submissive", 85),
+                    pairing("Rigger", 70, "Rope bunny", 75),
+                ],
+                frictions: vec![pairing("Sadist", 10, "Masochist", 95)],
+            }
+        );
+        assert_eq!(
+            explain(&mine, &[], 3),
+            Explanation {
+                harmonies: vec![],
+                frictions: vec![],
+            }
+        );
+    }
+}
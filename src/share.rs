@@ -0,0 +1,261 @@
+//! Reads the text block bdsmtest.org offers for sharing results, which members paste constantly:
+//!
+//! ```text
+//! == Results from bdsmtest.org ==
+//! 100% Switch
+//! 95% Rigger
+//! ...
+//! https://bdsmtest.org/r/abc123
+//! ```
+//!
+//! Chat clients mangle it in every way imaginable, so the lines can come in any order, flattened
+//! onto one line or separated by commas, with the percentage before or after the archetype and with
+//! or without a percent sign. The header is recognized in any language, but only archetypes with
+//! their English names are understood.
+
+use std::collections::BTreeMap;
+
+use crate::archetypes;
+
+const HOST: &str = "bdsmtest.org";
+
+/// What could be read from a pasted share text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SharedResult {
+    /// The result ID from the embedded link, which is all a result needs.
+    Id(String),
+    /// The scores, for a paste without the link.
+    Scores(BTreeMap<String, u32>),
+}
+
+/// Reads a pasted share text, preferring the result link over the scores. Returns `None` if
+/// neither a link nor a single known archetype score could be found.
+pub fn parse(text: &str) -> Option<SharedResult> {
+    let text = strip_headers(text);
+    let mut rest = Vec::new();
+    for word in text.split_whitespace() {
+        match result_id(word) {
+            Some(id) => return Some(SharedResult::Id(id)),
+            // Any other link is pasted noise, and never part of an archetype's name.
+            None if word.contains(HOST) || word.contains("://") => {}
+            None => rest.push(word),
+        }
+    }
+    let scores = scores(&rest);
+    (!scores.is_empty()).then_some(SharedResult::Scores(scores))
+}
+
+/// Removes every "== ... ==" header, which is where the translations put their own wording.
+fn strip_headers(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("==") {
+        let after = rest[start..].trim_start_matches('=');
+        let Some(end) = after.find("==") else {
+            break;
+        };
+        stripped += &rest[..start];
+        stripped.push('\n');
+        rest = after[end..].trim_start_matches('=');
+    }
+    stripped + rest
+}
+
+/// The result ID in a link like `https://bdsmtest.org/r/abc123` or `bdsmtest.org/?rid=abc123`.
+fn result_id(word: &str) -> Option<String> {
+    let (_, path) = word.split_once(HOST)?;
+    let id = path
+        .strip_prefix("/r/")
+        .or_else(|| path.split_once("rid=").map(|(_, id)| id))?;
+    let id: String = id.chars().take_while(char::is_ascii_alphanumeric).collect();
+    (!id.is_empty()).then_some(id)
+}
+
+/// A percentage like `95`, `95%` or `95,` on its own.
+fn percent(word: &str) -> Option<u32> {
+    let digits = word.trim_end_matches([',', ';', ':', '.', '%']);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok().filter(|&p| p <= 100)
+}
+
+/// Pairs up percentages and archetype names, trying the percentages both before and after the
+/// names and keeping whichever finds more archetypes.
+fn scores(words: &[&str]) -> BTreeMap<String, u32> {
+    let words: Vec<&str> = words
+        .iter()
+        .map(|w| w.trim_matches(['-', '*', '•', ',', ';', ':']))
+        .filter(|w| !w.is_empty() && *w != "%")
+        .collect();
+    let before = paired(&words, true);
+    let after = paired(&words, false);
+    if after.len() > before.len() {
+        after
+    } else {
+        before
+    }
+}
+
+/// Pairs every percentage with the words after it if `percent_first`, or before it otherwise.
+fn paired(words: &[&str], percent_first: bool) -> BTreeMap<String, u32> {
+    let mut scores = BTreeMap::new();
+    let mut score = None;
+    let mut name = Vec::new();
+    for word in words {
+        match percent(word) {
+            Some(p) if percent_first => {
+                if let Some(s) = score {
+                    insert(&mut scores, &name, s, true);
+                }
+                name.clear();
+                score = Some(p);
+            }
+            Some(p) => {
+                insert(&mut scores, &name, p, false);
+                name.clear();
+            }
+            None => name.push(*word),
+        }
+    }
+    if let (Some(s), true) = (score, percent_first) {
+        insert(&mut scores, &name, s, true);
+    }
+    scores
+}
+
+/// Records `score` for the archetype named by `words`. Anything else pasted alongside ends up in
+/// `words` too, so the longest run of words that names an archetype wins, taken from the start
+/// when the name followed its percentage and from the end when it preceded it. The first score
+/// for an archetype is kept.
+fn insert(scores: &mut BTreeMap<String, u32>, words: &[&str], score: u32, from_start: bool) {
+    let found = (1..=words.len()).rev().find_map(|len| {
+        let words = if from_start {
+            &words[..len]
+        } else {
+            &words[words.len() - len..]
+        };
+        archetypes::resolve(&words.join(" "))
+    });
+    if let Some(name) = found {
+        scores.entry(name.to_string()).or_insert(score);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(pairs: &[(&str, u32)]) -> Option<SharedResult> {
+        Some(SharedResult::Scores(
+            pairs.iter().map(|&(n, s)| (n.to_string(), s)).collect(),
+        ))
+    }
+
+    #[test]
+    fn full_paste_uses_the_link() {
+        let text = "== Results from bdsmtest.org ==\n\
+                    100% Switch\n\
+                    95% Rigger\n\
+                    71% Brat tamer\n\
+                    https://bdsmtest.org/r/Ab3dE9xZ\n";
+        assert_eq!(parse(text), Some(SharedResult::Id("Ab3dE9xZ".into())));
+    }
+
+    #[test]
+    fn link_variants() {
+        for (text, id) in [
+            ("see bdsmtest.org/r/abc123 !", "abc123"),
+            ("<https://bdsmtest.org/r/abc123>", "abc123"),
+            ("https://www.bdsmtest.org/?rid=XyZ789&lang=en", "XyZ789"),
+        ] {
+            assert_eq!(parse(text), Some(SharedResult::Id(id.into())), "{text}");
+        }
+    }
+
+    #[test]
+    fn paste_without_link_gives_scores() {
+        let text = "== Results from bdsmtest.org ==\n\
+                    100% Switch\n\
+                    95% Rigger\n\
+                    71% Brat tamer\n\
+                    8% Primal (Prey)\n";
+        assert_eq!(
+            parse(text),
+            scores(&[
+                ("Brat tamer", 71),
+                ("Primal (Prey)", 8),
+                ("Rigger", 95),
+                ("Switch", 100),
+            ])
+        );
+    }
+
+    #[test]
+    fn flattened_onto_one_line() {
+        let text = "== Results from bdsmtest.org == 100% Switch, 95% Rigger, 60% Rope bunny, …";
+        assert_eq!(
+            parse(text),
+            scores(&[("Rigger", 95), ("Rope bunny", 60), ("Switch", 100)])
+        );
+    }
+
+    #[test]
+    fn percentages_after_names_and_without_signs() {
+        assert_eq!(
+            parse("Switch 100\nRigger: 95\nBrat tamer 71 %\nBoy/Girl 3"),
+            scores(&[
+                ("Boy/Girl", 3),
+                ("Brat tamer", 71),
+                ("Rigger", 95),
+                ("Switch", 100)
+            ])
+        );
+        assert_eq!(
+            parse("100 Switch 95 Rigger 71 Brat tamer"),
+            scores(&[("Brat tamer", 71), ("Rigger", 95), ("Switch", 100)])
+        );
+    }
+
+    #[test]
+    fn reordered_lines_and_chatter() {
+        let text = "omg look at mine\n\
+                    - 45% Vanilla\n\
+                    - 100% Switch\n\
+                    == Results from bdsmtest.org ==\n\
+                    - 95% Rigger lol\n";
+        assert_eq!(
+            parse(text),
+            scores(&[("Rigger", 95), ("Switch", 100), ("Vanilla", 45)])
+        );
+    }
+
+    #[test]
+    fn localized_header_and_unknown_names() {
+        let text = "== Ergebnisse von bdsmtest.org ==\n\
+                    100% Switch\n\
+                    90% Sklave\n\
+                    80% Rigger\n\
+                    Mehr: https://bdsmtest.org/";
+        assert_eq!(parse(text), scores(&[("Rigger", 80), ("Switch", 100)]));
+        let text = "== Résultats de bdsmtest.org ==\n100 % Switch\n92 % Voyeur";
+        assert_eq!(parse(text), scores(&[("Switch", 100), ("Voyeur", 92)]));
+    }
+
+    #[test]
+    fn duplicates_keep_the_first_score() {
+        assert_eq!(parse("100% Switch 50% switch"), scores(&[("Switch", 100)]));
+    }
+
+    #[test]
+    fn nothing_recognizable() {
+        for text in [
+            "",
+            "== Results from bdsmtest.org ==",
+            "hello there",
+            "200% Switch",
+        ] {
+            assert_eq!(parse(text), None, "{text}");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! The "Share publicly" button on the private reply to add_bdsm_result. Nothing is posted until
+//! the member who added the result presses it, and then only a summary of that result, in the
+//! channel the reply is in if that channel is NSFW or the admins allowed sharing there. Like the compare button, everything the click needs is in the custom
+//! ID, so it keeps working across restarts until it expires.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use tracing::{error, info, warn};
+
+use crate::{errors, logic, result_button::ResultTarget, GlobalState};
+
+/// Starts the custom ID of every share button.
+const PREFIX: &str = "share-result";
+/// How long a button works after it is shown.
+const EXPIRY: chrono::Duration = chrono::Duration::minutes(5);
+
+/// Who a shared summary is attributed to.
+pub fn subject(name: &str, headmate: &Option<String>) -> String {
+    match headmate {
+        Some(headmate) => format!("**{name} ({headmate})**"),
+        None => format!("**{name}**"),
+    }
+}
+
+/// A button sharing `result_id` of `user_id` (or their headmate), expiring [`EXPIRY`] after
+/// `now`. Targets too long to encode get no button.
+pub fn button(
+    user_id: serenity::UserId,
+    headmate: Option<String>,
+    result_id: String,
+    now: DateTime<Utc>,
+) -> Option<serenity::CreateActionRow> {
+    ResultTarget {
+        user_id,
+        headmate,
+        result_id,
+        expires: now + EXPIRY,
+    }
+    .button(PREFIX, "Share publicly", serenity::ButtonStyle::Secondary)
+}
+
+/// Posts the summary a share button is for, and takes the button off the private reply. Clicks
+/// on any other component are left to whatever is handling them.
+pub async fn handle(
+    ctx: &serenity::Context,
+    state: &Arc<GlobalState>,
+    mci: &serenity::ComponentInteraction,
+) {
+    let Some(target) = ResultTarget::parse(PREFIX, &mci.data.custom_id) else {
+        return;
+    };
+    let Some(guild_id) = mci.guild_id else {
+        return;
+    };
+    info!(user = %mci.user.id, "Sharing a new result");
+    // Fetching the result can take longer than Discord waits for an answer.
+    let defer = serenity::CreateInteractionResponse::Acknowledge;
+    if let Err(e) = mci.create_response(ctx, defer).await {
+        warn!("Could not answer a share button: {e:#}");
+        return;
+    }
+    let note = match share(ctx, state, guild_id, mci, &target).await {
+        Ok(()) => "Shared publicly".to_string(),
+        Err(e) => {
+            if errors::user_error(&e).is_none() {
+                error!(user = %mci.user.id, "Could not share a result: {e:#}");
+            }
+            errors::reply_text(&e)
+        }
+    };
+    let edit = serenity::EditInteractionResponse::new()
+        .content(format!("{}\n\n{note}", mci.message.content))
+        .components(vec![]);
+    if let Err(e) = mci.edit_response(ctx, edit).await {
+        warn!("Could not answer a share button: {e:#}");
+    }
+}
+
+async fn share(
+    ctx: &serenity::Context,
+    state: &GlobalState,
+    guild_id: serenity::GuildId,
+    mci: &serenity::ComponentInteraction,
+    target: &ResultTarget,
+) -> Result<(), anyhow::Error> {
+    if Utc::now() > target.expires || mci.user.id != target.user_id {
+        return Err(logic::CommandError::ShareUnavailable.into());
+    }
+    let who = logic::Invoker {
+        guild_id,
+        user_id: mci.user.id,
+    };
+    let nsfw = is_nsfw(ctx, mci.channel_id).await?;
+    let summary = {
+        let data = state.data.read().await;
+        logic::check_share_channel(&data, guild_id, mci.channel_id, nsfw)?;
+        let name = match logic::display_name(&data, who) {
+            Some(name) => name.to_string(),
+            None => mci
+                .member
+                .as_ref()
+                .map_or_else(|| mci.user.name.clone(), |m| m.display_name().to_string()),
+        };
+        logic::result_summary(
+            &data,
+            &state.api,
+            &state.cache,
+            who,
+            &subject(&name, &target.headmate),
+            &target.headmate,
+            &target.result_id,
+        )
+        .await?
+    };
+    let message = serenity::CreateMessage::new()
+        .content(summary)
+        .allowed_mentions(serenity::CreateAllowedMentions::new());
+    mci.channel_id.send_message(ctx, message).await?;
+    Ok(())
+}
+
+/// Whether `channel_id` is marked NSFW. Threads go by the channel they are in.
+async fn is_nsfw(
+    ctx: &serenity::Context,
+    channel_id: serenity::ChannelId,
+) -> Result<bool, anyhow::Error> {
+    let Some(channel) = channel_id.to_channel(ctx).await?.guild() else {
+        return Ok(false);
+    };
+    match (channel.thread_metadata, channel.parent_id) {
+        (Some(_), Some(parent)) => Ok(parent
+            .to_channel(ctx)
+            .await?
+            .guild()
+            .is_some_and(|c| c.nsfw)),
+        _ => Ok(channel.nsfw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_expire_after_five_minutes() {
+        let now = "2024-05-01T12:00:00Z".parse().unwrap();
+        let button = button(
+            serenity::UserId::new(42),
+            Some("Ash".into()),
+            "abc123".into(),
+            now,
+        );
+        let custom_id = "share-result:42:1714565100:abc123:Ash";
+        assert_eq!(
+            button,
+            Some(serenity::CreateActionRow::Buttons(vec![
+                serenity::CreateButton::new(custom_id)
+                    .label("Share publicly")
+                    .style(serenity::ButtonStyle::Secondary)
+            ]))
+        );
+        assert_eq!(subject("Me", &Some("Ash".into())), "**Me (Ash)**");
+    }
+}
@@ -0,0 +1,136 @@
+//! Times every command and warns about the ones that take longer than configured. Warnings are
+//! always logged, and can also be posted to a channel at most once per command per interval, so a
+//! slowdown that hits everything doesn't flood it.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use poise::serenity_prelude as serenity;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::Context;
+
+/// When a command started, kept in its invocation data.
+struct Started(Instant);
+
+/// When each command last had a slow run reported to the channel.
+#[derive(Default)]
+pub struct SlowReports {
+    last: Mutex<HashMap<String, Instant>>,
+}
+
+impl SlowReports {
+    /// Whether a slow run of `command` at `now` should be reported, given reports are at most
+    /// `interval` apart. Records the report if so.
+    async fn claim(&self, command: &str, now: Instant, interval: Duration) -> bool {
+        let mut last = self.last.lock().await;
+        match last.get(command) {
+            Some(&at) if now.saturating_duration_since(at) < interval => false,
+            _ => {
+                last.insert(command.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+/// Remembers when the command started. Runs before every command.
+pub async fn start(ctx: Context<'_>) {
+    ctx.set_invocation_data(Started(Instant::now())).await;
+}
+
+/// The one-line note posted about a slow run.
+fn note(command: &str, guild: &str, elapsed: Duration, entries: usize) -> String {
+    format!(
+        "/{command} took {:.1}s in {guild} ({entries} registered entries)",
+        elapsed.as_secs_f64()
+    )
+}
+
+/// Warns if the command took longer than the configured threshold. Runs after every command.
+pub async fn finish(ctx: Context<'_>) {
+    let Some(elapsed) = ctx
+        .invocation_data::<Started>()
+        .await
+        .map(|started| started.0.elapsed())
+    else {
+        return;
+    };
+    let state = ctx.data();
+    let config = &state.config.slow_commands;
+    if elapsed < Duration::from_secs(config.threshold_secs) {
+        return;
+    }
+
+    let command = &ctx.command().qualified_name;
+    let guild = match ctx.guild_id() {
+        Some(id) => ctx
+            .guild()
+            .map_or_else(|| id.to_string(), |g| g.name.clone()),
+        None => "DMs".to_string(),
+    };
+    let entries = match ctx.guild_id() {
+        Some(guild_id) => state
+            .data
+            .read()
+            .await
+            .guilds
+            .get(&guild_id)
+            .map_or(0, |g| g.entries().count()),
+        None => 0,
+    };
+    warn!(
+        command,
+        guild,
+        entries,
+        elapsed_ms = elapsed.as_millis() as u64,
+        "Slow command"
+    );
+
+    let Some(channel) = config.report_channel else {
+        return;
+    };
+    let interval = Duration::from_secs(config.report_interval_secs);
+    if !state.slow.claim(command, Instant::now(), interval).await {
+        return;
+    }
+    let message = serenity::CreateMessage::new().content(note(command, &guild, elapsed, entries));
+    if let Err(e) = serenity::ChannelId::new(channel)
+        .send_message(ctx, message)
+        .await
+    {
+        warn!("Could not report a slow command: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_each_command_once_per_interval() {
+        let reports = SlowReports::default();
+        let start = Instant::now();
+        let hour = Duration::from_secs(60 * 60);
+        assert!(reports.claim("show_result", start, hour).await);
+        assert!(!reports.claim("show_result", start + hour / 2, hour).await);
+        assert!(reports.claim("list_compatibility", start, hour).await);
+        assert!(reports.claim("show_result", start + hour, hour).await);
+    }
+
+    #[test]
+    fn note_fits_on_one_line() {
+        assert_eq!(
+            note(
+                "list_compatibility",
+                "1234",
+                Duration::from_millis(21_460),
+                80
+            ),
+            "/list_compatibility took 21.5s in 1234 (80 registered entries)"
+        );
+    }
+}
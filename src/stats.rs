@@ -0,0 +1,232 @@
+//! Statistics over results: aggregated across many members' results, or over the history of a
+//! single entry.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::api::GetResultScore;
+
+/// The mean score for one archetype across `count` results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchetypeAverage {
+    pub name: String,
+    pub average: f64,
+    pub count: usize,
+}
+
+/// Averages each archetype over the results that include it, highest average first. Ties are
+/// broken by name so the output is stable.
+pub fn archetype_averages<'a, I>(results: I) -> Vec<ArchetypeAverage>
+where
+    I: IntoIterator<Item = &'a [GetResultScore]>,
+{
+    let mut totals: BTreeMap<&str, (u64, usize)> = BTreeMap::new();
+    for scores in results {
+        for score in scores {
+            let (sum, count) = totals.entry(&score.name).or_default();
+            *sum += u64::from(score.score);
+            *count += 1;
+        }
+    }
+    let mut averages: Vec<_> = totals
+        .into_iter()
+        .map(|(name, (sum, count))| ArchetypeAverage {
+            name: name.to_string(),
+            average: sum as f64 / count as f64,
+            count,
+        })
+        .collect();
+    averages.sort_by(|a, b| b.average.total_cmp(&a.average).then(a.name.cmp(&b.name)));
+    averages
+}
+
+/// When an entry's results were taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct History {
+    pub count: usize,
+    pub first: DateTime<Utc>,
+    pub latest: DateTime<Utc>,
+    /// The mean time between consecutive results, if there are at least two.
+    pub average_gap: Option<Duration>,
+}
+
+/// Summarizes when results were taken. Returns `None` without any results.
+pub fn history<I>(dates: I) -> Option<History>
+where
+    I: IntoIterator<Item = DateTime<Utc>>,
+{
+    let mut dates: Vec<_> = dates.into_iter().collect();
+    dates.sort();
+    let (&first, &latest) = (dates.first()?, dates.last()?);
+    let gaps = dates.len() - 1;
+    Some(History {
+        count: dates.len(),
+        first,
+        latest,
+        average_gap: (gaps > 0).then(|| (latest - first) / gaps as i32),
+    })
+}
+
+/// How far one archetype's score moved between two results.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchetypeChange {
+    pub name: String,
+    pub from: u32,
+    pub to: u32,
+}
+
+/// Every archetype in both `oldest` and `newest`, biggest change first in either direction and
+/// unchanged ones last. Ties go to the first name alphabetically.
+pub fn score_changes(oldest: &[GetResultScore], newest: &[GetResultScore]) -> Vec<ArchetypeChange> {
+    let before: BTreeMap<&str, u32> = oldest.iter().map(|s| (s.name.as_str(), s.score)).collect();
+    let mut changes: Vec<_> = newest
+        .iter()
+        .filter_map(|s| {
+            Some(ArchetypeChange {
+                name: s.name.clone(),
+                from: *before.get(s.name.as_str())?,
+                to: s.score,
+            })
+        })
+        .collect();
+    changes.sort_by(|a, b| {
+        b.from
+            .abs_diff(b.to)
+            .cmp(&a.from.abs_diff(a.to))
+            .then(a.name.cmp(&b.name))
+    });
+    changes
+}
+
+/// Every archetype in both `oldest` and `newest` whose score changed, ordered like
+/// [`score_changes`].
+pub fn changes(oldest: &[GetResultScore], newest: &[GetResultScore]) -> Vec<ArchetypeChange> {
+    let mut changes = score_changes(oldest, newest);
+    changes.retain(|c| c.from != c.to);
+    changes
+}
+
+/// The archetype whose score changed the most from `oldest` to `newest`, in either direction.
+/// Only archetypes in both results count, ties go to the first name alphabetically, and `None`
+/// means nothing changed.
+pub fn biggest_change(
+    oldest: &[GetResultScore],
+    newest: &[GetResultScore],
+) -> Option<ArchetypeChange> {
+    changes(oldest, newest).into_iter().next()
+}
+
+/// The `count` biggest increases and the `count` biggest decreases from `oldest` to `newest`,
+/// each biggest first.
+pub fn top_changes(
+    oldest: &[GetResultScore],
+    newest: &[GetResultScore],
+    count: usize,
+) -> (Vec<ArchetypeChange>, Vec<ArchetypeChange>) {
+    let (increases, decreases): (Vec<_>, Vec<_>) = changes(oldest, newest)
+        .into_iter()
+        .partition(|c| c.to > c.from);
+    (
+        increases.into_iter().take(count).collect(),
+        decreases.into_iter().take(count).collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::at;
+
+    fn scores(scores: &[(&str, u32)]) -> Vec<GetResultScore> {
+        scores
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, score))| GetResultScore {
+                id: i as u32,
+                name: name.into(),
+                pairdesc: String::new(),
+                description: String::new(),
+                score,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn averages_each_archetype_over_results_that_have_it() {
+        let a = scores(&[("Switch", 90), ("Rigger", 10)]);
+        let b = scores(&[("Switch", 60), ("Rigger", 50), ("Brat", 50)]);
+        let averages = archetype_averages([a.as_slice(), b.as_slice()]);
+        let summary: Vec<_> = averages
+            .iter()
+            .map(|a| (a.name.as_str(), a.average, a.count))
+            .collect();
+        assert_eq!(
+            summary,
+            [("Switch", 75.0, 2), ("Brat", 50.0, 1), ("Rigger", 30.0, 2)]
+        );
+    }
+
+    #[test]
+    fn no_results_no_averages() {
+        assert!(archetype_averages(std::iter::empty()).is_empty());
+    }
+
+    #[test]
+    fn history_of_several_results() {
+        assert_eq!(
+            history([at(11), at(1), at(5)]),
+            Some(History {
+                count: 3,
+                first: at(1),
+                latest: at(11),
+                average_gap: Some(Duration::days(5)),
+            })
+        );
+    }
+
+    #[test]
+    fn history_of_one_result_has_no_gap() {
+        assert_eq!(
+            history([at(3)]),
+            Some(History {
+                count: 1,
+                first: at(3),
+                latest: at(3),
+                average_gap: None,
+            })
+        );
+        assert_eq!(history(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn biggest_change_in_either_direction() {
+        let oldest = scores(&[("Switch", 90), ("Rigger", 10), ("Brat", 40)]);
+        let newest = scores(&[("Switch", 30), ("Rigger", 70), ("Voyeur", 99)]);
+        assert_eq!(
+            biggest_change(&oldest, &newest),
+            Some(ArchetypeChange {
+                name: "Rigger".into(),
+                from: 10,
+                to: 70,
+            })
+        );
+        assert_eq!(biggest_change(&oldest, &oldest), None);
+        assert_eq!(biggest_change(&oldest, &[]), None);
+    }
+
+    #[test]
+    fn top_changes_split_by_direction() {
+        let oldest = scores(&[("Switch", 90), ("Rigger", 10), ("Brat", 40), ("Owner", 50)]);
+        let newest = scores(&[("Switch", 30), ("Rigger", 70), ("Brat", 45), ("Owner", 50)]);
+        let (increases, decreases) = top_changes(&oldest, &newest, 1);
+        let names = |changes: &[ArchetypeChange]| -> Vec<String> {
+            changes.iter().map(|c| c.name.clone()).collect()
+        };
+        assert_eq!(names(&increases), ["Rigger"]);
+        assert_eq!(names(&decreases), ["Switch"]);
+        let (increases, decreases) = top_changes(&oldest, &newest, 3);
+        assert_eq!(names(&increases), ["Rigger", "Brat"]);
+        assert_eq!(decreases.len(), 1);
+    }
+}
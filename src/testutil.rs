@@ -0,0 +1,79 @@
+//! Synthetic data generators and helpers shared by tests and benchmarks.
+
+use chrono::{DateTime, Duration, Utc};
+use poise::serenity_prelude as serenity;
+
+use crate::{
+    data::{GlobalData, HeadmateData, UserData},
+    format::CompatEntry,
+};
+
+/// Midnight UTC on `day` of January 2024.
+pub fn at(day: u32) -> DateTime<Utc> {
+    format!("2024-01-{day:02}T00:00:00Z").parse().unwrap()
+}
+
+/// The shape of the data produced by [`synthetic_data`].
+#[derive(Clone, Copy, Debug)]
+pub struct Shape {
+    pub guilds: u64,
+    pub users: u64,
+    pub headmates: u64,
+    pub results: u64,
+}
+
+fn synthetic_headmate(seed: u64, results: u64) -> HeadmateData {
+    let start: DateTime<Utc> = "2020-01-01T00:00:00Z".parse().unwrap();
+    HeadmateData {
+        results: (0..results)
+            .map(|i| {
+                (
+                    start + Duration::days((seed * 7 + i * 30) as i64),
+                    format!("{:012x}", seed * 1_000 + i),
+                )
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Deterministic registry data with `shape.users` users in each guild, each with a primary entry
+/// and `shape.headmates` headmates that all have `shape.results` results.
+pub fn synthetic_data(shape: Shape) -> GlobalData {
+    let mut data = GlobalData::default();
+    for g in 1..=shape.guilds {
+        let guild = data.guild_mut(serenity::GuildId::new(g));
+        for u in 1..=shape.users {
+            let seed = g * 1_000_000 + u * 1_000;
+            let user = UserData {
+                primary: Some(synthetic_headmate(seed, shape.results)),
+                headmates: (1..=shape.headmates)
+                    .map(|h| {
+                        (
+                            format!("Headmate {h}"),
+                            synthetic_headmate(seed + h, shape.results),
+                        )
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            guild.users.insert(serenity::UserId::new(u), user);
+        }
+    }
+    data
+}
+
+/// `count` list entries with a spread of scores and an occasional invalid result.
+pub fn synthetic_entries(count: usize) -> Vec<CompatEntry> {
+    (0..count)
+        .map(|i| CompatEntry {
+            user_id: serenity::UserId::new(i as u64 / 4 + 1),
+            member: format!("**Member {}**", i / 4),
+            headmate: Some(format!("Headmate {}", i % 4)),
+            score: (i % 23 != 0).then_some((i * 37 % 101) as u32),
+            estimated: false,
+            temporary: false,
+            taken: None,
+        })
+        .collect()
+}
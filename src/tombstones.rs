@@ -0,0 +1,26 @@
+//! Deletes removed entries for good once their recovery window has passed.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use tracing::info;
+
+use crate::{logic, GlobalState};
+
+/// How often old tombstones are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Sweeps every [`SWEEP_INTERVAL`]. Nothing that is listed changes, so no guild is refreshed.
+pub async fn run(state: Arc<GlobalState>) {
+    let keep = state.config.deletion.recovery_window();
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let mut data = state.data.write().await;
+        let purged = logic::purge_tombstones(&mut data, Utc::now(), keep);
+        if purged == 0 {
+            continue;
+        }
+        info!(purged, "Deleted removed entries past their recovery window");
+        state.saves.request();
+    }
+}
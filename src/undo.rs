@@ -0,0 +1,85 @@
+//! Remembers each member's last removal for a few minutes so /undo can put it back without
+//! picking it from /recover_my_data. Nothing here is ever written to the registry or its backups,
+//! so a restart forgets every removal (their tombstones stay recoverable).
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+
+use crate::logic::{Invoker, Removal};
+
+/// How long a removal can be undone for.
+pub const UNDO_WINDOW: chrono::Duration = chrono::Duration::minutes(10);
+
+/// A removal and when it was made.
+type Record = (DateTime<Utc>, Removal);
+
+/// The last removal of each member, by guild and user.
+#[derive(Default)]
+pub struct UndoLog {
+    removals: std::sync::Mutex<HashMap<(serenity::GuildId, serenity::UserId), Record>>,
+}
+
+impl UndoLog {
+    pub fn new() -> Self {
+        UndoLog::default()
+    }
+
+    /// Remembers `removal`, made at `at`, in place of whatever `who` removed before.
+    pub fn record(&self, who: Invoker, at: DateTime<Utc>, removal: Removal) {
+        self.removals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((who.guild_id, who.user_id), (at, removal));
+    }
+
+    /// Forgets and returns the last removal of `who`, unless it is older than [`UNDO_WINDOW`] at
+    /// `now`.
+    pub fn take(&self, who: Invoker, now: DateTime<Utc>) -> Option<Removal> {
+        let mut removals = self.removals.lock().unwrap_or_else(|e| e.into_inner());
+        // Expired removals are dropped whenever anyone undoes, so the log stays small.
+        removals.retain(|_, (at, _)| now - *at <= UNDO_WINDOW);
+        removals
+            .remove(&(who.guild_id, who.user_id))
+            .map(|(_, removal)| removal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ME: Invoker = Invoker {
+        guild_id: serenity::GuildId::new(1),
+        user_id: serenity::UserId::new(2),
+    };
+
+    fn removal(name: &str) -> Removal {
+        Removal {
+            headmate: Some(name.into()),
+            deleted: "2024-05-01T12:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn only_the_latest_removal_within_the_window_is_undone() {
+        let now: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let log = UndoLog::new();
+        log.record(ME, now, removal("Ash"));
+        log.record(ME, now, removal("Bo"));
+        assert_eq!(log.take(ME, now + UNDO_WINDOW), Some(removal("Bo")));
+        assert_eq!(log.take(ME, now), None);
+
+        log.record(ME, now, removal("Ash"));
+        let elsewhere = Invoker {
+            guild_id: serenity::GuildId::new(3),
+            ..ME
+        };
+        assert_eq!(log.take(elsewhere, now), None);
+        assert_eq!(
+            log.take(ME, now + UNDO_WINDOW + chrono::Duration::seconds(1)),
+            None
+        );
+    }
+}